@@ -24,12 +24,12 @@
 use mail_send::Credentials;
 use smtp_proto::{AUTH_CRAM_MD5, AUTH_LOGIN, AUTH_OAUTHBEARER, AUTH_PLAIN, AUTH_XOAUTH2};
 
-use crate::{DirectoryError, Principal, QueryBy};
+use crate::{DirectoryError, Principal, QueryBy, QueryResult};
 
 use super::{ImapDirectory, ImapError};
 
 impl ImapDirectory {
-    pub async fn query(&self, query: QueryBy<'_>) -> crate::Result<Option<Principal<u32>>> {
+    pub async fn query(&self, query: QueryBy<'_>) -> crate::Result<QueryResult> {
         if let QueryBy::Credentials(credentials) = query {
             let mut client = self.pool.get().await?;
             let mechanism = match credentials {
@@ -57,17 +57,17 @@ impl ImapDirectory {
                         protocol = "imap",
                         "IMAP server does not offer any supported auth mechanisms.",
                     );
-                    return Ok(None);
+                    return Ok(QueryResult::NotFound);
                 }
             };
 
             match client.authenticate(mechanism, credentials).await {
                 Ok(_) => {
                     client.is_valid = false;
-                    Ok(Some(Principal::default()))
+                    Ok(QueryResult::Found(Principal::default()))
                 }
                 Err(err) => match &err {
-                    ImapError::AuthenticationFailed => Ok(None),
+                    ImapError::AuthenticationFailed => Ok(QueryResult::InvalidCredentials),
                     _ => Err(err.into()),
                 },
             }
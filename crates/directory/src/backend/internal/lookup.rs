@@ -27,7 +27,7 @@ use store::{
     IterateParams, Store, ValueKey,
 };
 
-use crate::{Principal, QueryBy, Type};
+use crate::{Principal, QueryBy, QueryResult, Type};
 
 use super::{manage::ManageDirectory, PrincipalIdType};
 
@@ -37,7 +37,16 @@ pub trait DirectoryStore: Sync + Send {
         &self,
         by: QueryBy<'_>,
         return_member_of: bool,
-    ) -> crate::Result<Option<Principal<u32>>>;
+    ) -> crate::Result<Option<Principal<u32>>> {
+        self.query_detailed(by, return_member_of)
+            .await
+            .map(QueryResult::into_principal)
+    }
+    async fn query_detailed(
+        &self,
+        by: QueryBy<'_>,
+        return_member_of: bool,
+    ) -> crate::Result<QueryResult>;
     async fn email_to_ids(&self, email: &str) -> crate::Result<Vec<u32>>;
 
     async fn is_local_domain(&self, domain: &str) -> crate::Result<bool>;
@@ -48,11 +57,11 @@ pub trait DirectoryStore: Sync + Send {
 
 #[async_trait::async_trait]
 impl DirectoryStore for Store {
-    async fn query(
+    async fn query_detailed(
         &self,
         by: QueryBy<'_>,
         return_member_of: bool,
-    ) -> crate::Result<Option<Principal<u32>>> {
+    ) -> crate::Result<QueryResult> {
         let (account_id, secret) = match by {
             QueryBy::Name(name) => (self.get_account_id(name).await?, None),
             QueryBy::Id(account_id) => (account_id.into(), None),
@@ -77,23 +86,27 @@ impl DirectoryStore for Store {
                 .await?,
                 secret,
             ) {
-                (Some(mut principal), Some(secret)) if principal.verify_secret(secret).await => {
-                    if return_member_of {
-                        principal.member_of = self.get_member_of(principal.id).await?;
+                (Some(mut principal), Some(secret)) => {
+                    if principal.verify_secret(secret).await {
+                        if return_member_of {
+                            principal.member_of = self.get_member_of(principal.id).await?;
+                        }
+                        Ok(QueryResult::Found(principal))
+                    } else {
+                        Ok(QueryResult::InvalidCredentials)
                     }
-                    Ok(Some(principal))
                 }
                 (Some(mut principal), None) => {
                     if return_member_of {
                         principal.member_of = self.get_member_of(principal.id).await?;
                     }
 
-                    Ok(Some(principal))
+                    Ok(QueryResult::Found(principal))
                 }
-                _ => Ok(None),
+                (None, _) => Ok(QueryResult::NotFound),
             }
         } else {
-            Ok(None)
+            Ok(QueryResult::NotFound)
         }
     }
 
@@ -202,6 +202,10 @@ impl PrincipalUpdate {
             value,
         }
     }
+
+    pub fn field(&self) -> &PrincipalField {
+        &self.field
+    }
 }
 
 impl Display for PrincipalField {
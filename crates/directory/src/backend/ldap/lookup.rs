@@ -21,11 +21,15 @@
  * for more details.
 */
 
+use ahash::AHashMap;
 use ldap3::{Ldap, LdapConnAsync, LdapError, Scope, SearchEntry};
 use mail_send::Credentials;
 use store::Store;
 
-use crate::{backend::internal::manage::ManageDirectory, DirectoryError, Principal, QueryBy, Type};
+use crate::{
+    backend::internal::manage::ManageDirectory, DirectoryError, Principal, QueryBy, QueryResult,
+    Type,
+};
 
 use super::{LdapDirectory, LdapMappings};
 
@@ -34,7 +38,7 @@ impl LdapDirectory {
         &self,
         by: QueryBy<'_>,
         return_member_of: bool,
-    ) -> crate::Result<Option<Principal<u32>>> {
+    ) -> crate::Result<QueryResult> {
         let mut conn = self.pool.get().await?;
         let mut account_id = None;
         let account_name;
@@ -49,14 +53,14 @@ impl LdapDirectory {
                 {
                     principal
                 } else {
-                    return Ok(None);
+                    return Ok(QueryResult::NotFound);
                 }
             }
             QueryBy::Id(uid) => {
                 if let Some(username) = self.unwrap_id_store().get_account_name(uid).await? {
                     account_name = username;
                 } else {
-                    return Ok(None);
+                    return Ok(QueryResult::NotFound);
                 }
                 account_id = Some(uid);
 
@@ -66,7 +70,7 @@ impl LdapDirectory {
                 {
                     principal
                 } else {
-                    return Ok(None);
+                    return Ok(QueryResult::NotFound);
                 }
             }
             QueryBy::Credentials(credentials) => {
@@ -96,9 +100,9 @@ impl LdapDirectory {
                         Err(DirectoryError::Ldap(LdapError::LdapResult { result }))
                             if [49, 50].contains(&result.rc) =>
                         {
-                            return Ok(None);
+                            return Ok(QueryResult::InvalidCredentials);
                         }
-                        Ok(None) => return Ok(None),
+                        Ok(None) => return Ok(QueryResult::NotFound),
                         Err(err) => return Err(err),
                     }
                 } else if let Some(principal) = self
@@ -115,10 +119,10 @@ impl LdapDirectory {
                             account = username,
                             "Invalid password for account"
                         );
-                        return Ok(None);
+                        return Ok(QueryResult::InvalidCredentials);
                     }
                 } else {
-                    return Ok(None);
+                    return Ok(QueryResult::NotFound);
                 }
             }
         };
@@ -167,13 +171,85 @@ impl LdapDirectory {
             self.unwrap_id_store()
                 .map_group_names(principal, true)
                 .await
-                .map(Some)
+                .map(QueryResult::Found)
         } else {
             principal.member_of.clear();
-            Ok(Some(principal.into()))
+            Ok(QueryResult::Found(principal.into()))
         }
     }
 
+    pub async fn query_many(&self, names: &[&str]) -> crate::Result<Vec<Option<Principal<u32>>>> {
+        // Entries are matched back to names using the attributes compared by the name filter
+        let filter_attrs = self.mappings.filter_name.attributes();
+        if filter_attrs.is_empty() || names.is_empty() {
+            let mut results = Vec::with_capacity(names.len());
+            for name in names {
+                results.push(
+                    self.query(QueryBy::Name(name), false)
+                        .await?
+                        .into_principal(),
+                );
+            }
+            return Ok(results);
+        }
+        let mut attrs = self.mappings.attrs_principal.clone();
+        for attr in &filter_attrs {
+            if !attrs.iter().any(|a| a.eq_ignore_ascii_case(attr)) {
+                attrs.push(attr.to_string());
+            }
+        }
+
+        // Look up all names at once using an OR filter
+        let filter = format!(
+            "(|{})",
+            names
+                .iter()
+                .map(|name| self.mappings.filter_name.build(name))
+                .collect::<String>()
+        );
+        let (rs, _) = self
+            .pool
+            .get()
+            .await?
+            .search(&self.mappings.base_dn, Scope::Subtree, &filter, &attrs)
+            .await?
+            .success()?;
+        let mut found = AHashMap::new();
+        for entry in rs {
+            let entry = SearchEntry::construct(entry);
+            let keys = entry
+                .attrs
+                .iter()
+                .filter(|(attr, _)| filter_attrs.iter().any(|a| a.eq_ignore_ascii_case(attr)))
+                .flat_map(|(_, values)| values.iter().map(|value| value.to_lowercase()))
+                .collect::<Vec<_>>();
+            let principal = self.mappings.entry_to_principal(entry);
+            for key in keys {
+                found.insert(key, principal.clone());
+            }
+        }
+
+        // Map results back to the requested names, preserving their order
+        let mut results = Vec::with_capacity(names.len());
+        for name in names {
+            if let Some(principal) = found.get(&name.to_lowercase()) {
+                let mut principal = Principal::<u32>::from(principal.clone());
+                if self.has_id_store() {
+                    principal.id = self
+                        .unwrap_id_store()
+                        .get_or_create_account_id(name)
+                        .await?;
+                }
+                principal.name = name.to_string();
+                results.push(Some(principal));
+            } else {
+                results.push(None);
+            }
+        }
+
+        Ok(results)
+    }
+
     pub async fn email_to_ids(&self, address: &str) -> crate::Result<Vec<u32>> {
         let rs = self
             .pool
@@ -65,6 +65,20 @@ impl LdapFilter {
         let value = ldap_escape(value);
         self.filter.join(value.as_ref())
     }
+
+    // Attributes compared with the placeholder, such as `uid` in `(&(objectClass=*)(uid=?))`
+    pub fn attributes(&self) -> Vec<&str> {
+        self.filter
+            .iter()
+            .take(self.filter.len().saturating_sub(1))
+            .filter_map(|part| {
+                let attr = part.strip_suffix('=')?;
+                let attr = attr.rsplit_once('(').map_or(attr, |(_, attr)| attr);
+                let attr = attr.trim_end_matches(['~', '>', '<', ':']);
+                (!attr.is_empty()).then_some(attr)
+            })
+            .collect()
+    }
 }
 
 pub(crate) struct LdapConnectionManager {
@@ -23,24 +23,24 @@
 
 use mail_send::Credentials;
 
-use crate::{Principal, QueryBy};
+use crate::{QueryBy, QueryResult};
 
 use super::{EmailType, MemoryDirectory};
 
 impl MemoryDirectory {
-    pub async fn query(&self, by: QueryBy<'_>) -> crate::Result<Option<Principal<u32>>> {
+    pub async fn query(&self, by: QueryBy<'_>) -> crate::Result<QueryResult> {
         match by {
             QueryBy::Name(name) => {
                 for principal in &self.principals {
                     if principal.name == name {
-                        return Ok(Some(principal.clone()));
+                        return Ok(QueryResult::Found(principal.clone()));
                     }
                 }
             }
             QueryBy::Id(uid) => {
                 for principal in &self.principals {
                     if principal.id == uid {
-                        return Ok(Some(principal.clone()));
+                        return Ok(QueryResult::Found(principal.clone()));
                     }
                 }
             }
@@ -54,15 +54,15 @@ impl MemoryDirectory {
                 for principal in &self.principals {
                     if &principal.name == username {
                         return if principal.verify_secret(secret).await {
-                            Ok(Some(principal.clone()))
+                            Ok(QueryResult::Found(principal.clone()))
                         } else {
-                            Ok(None)
+                            Ok(QueryResult::InvalidCredentials)
                         };
                     }
                 }
             }
         }
-        Ok(None)
+        Ok(QueryResult::NotFound)
     }
 
     pub async fn email_to_ids(&self, address: &str) -> crate::Result<Vec<u32>> {
@@ -24,12 +24,12 @@
 use mail_send::{smtp::AssertReply, Credentials};
 use smtp_proto::Severity;
 
-use crate::{DirectoryError, Principal, QueryBy};
+use crate::{DirectoryError, Principal, QueryBy, QueryResult};
 
 use super::{SmtpClient, SmtpDirectory};
 
 impl SmtpDirectory {
-    pub async fn query(&self, query: QueryBy<'_>) -> crate::Result<Option<Principal<u32>>> {
+    pub async fn query(&self, query: QueryBy<'_>) -> crate::Result<QueryResult> {
         if let QueryBy::Credentials(credentials) = query {
             self.pool.get().await?.authenticate(credentials).await
         } else {
@@ -94,17 +94,22 @@ impl SmtpClient {
     async fn authenticate(
         &mut self,
         credentials: &Credentials<String>,
-    ) -> crate::Result<Option<Principal<u32>>> {
+    ) -> crate::Result<QueryResult> {
         match self
             .client
             .authenticate(credentials, &self.capabilities)
             .await
         {
-            Ok(_) => Ok(Some(Principal::default())),
+            Ok(_) => Ok(QueryResult::Found(Principal::default())),
             Err(err) => match &err {
                 mail_send::Error::AuthenticationFailed(err) if err.code() == 535 => {
                     self.num_auth_failures += 1;
-                    Ok(None)
+                    Ok(QueryResult::InvalidCredentials)
+                }
+                mail_send::Error::AuthenticationFailed(err)
+                    if err.code() == 525 || err.esc == [5, 7, 13] =>
+                {
+                    Ok(QueryResult::Disabled)
                 }
                 _ => Err(err.into()),
             },
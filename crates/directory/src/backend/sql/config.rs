@@ -44,6 +44,10 @@ impl SqlDirectory {
             .clone();
 
         let mut mappings = SqlMappings {
+            column_name: config
+                .value((&prefix, "columns.name"))
+                .unwrap_or_default()
+                .to_string(),
             column_description: config
                 .value((&prefix, "columns.description"))
                 .unwrap_or_default()
@@ -60,22 +64,32 @@ impl SqlDirectory {
                 .value((&prefix, "columns.type"))
                 .unwrap_or_default()
                 .to_string(),
+            column_active: config
+                .value((&prefix, "columns.active"))
+                .unwrap_or_default()
+                .to_string(),
             ..Default::default()
         };
 
-        for (query_id, query) in [
-            ("name", &mut mappings.query_name),
-            ("members", &mut mappings.query_members),
-            ("recipients", &mut mappings.query_recipients),
-            ("emails", &mut mappings.query_emails),
-            ("verify", &mut mappings.query_verify),
-            ("expand", &mut mappings.query_expand),
-            ("domains", &mut mappings.query_domains),
+        // Queries are looked up by id, so they can be validated here. Principals
+        // cannot be looked up without a name query, the other queries are optional.
+        for (query_id, query, is_required) in [
+            ("name", &mut mappings.query_name, true),
+            ("members", &mut mappings.query_members, false),
+            ("recipients", &mut mappings.query_recipients, false),
+            ("emails", &mut mappings.query_emails, false),
+            ("verify", &mut mappings.query_verify, false),
+            ("expand", &mut mappings.query_expand, false),
+            ("domains", &mut mappings.query_domains, false),
         ] {
-            *query = config
-                .value(("store", store_id, "query", query_id))
-                .unwrap_or_default()
-                .to_string();
+            let query_id = format!("{store_id}/{query_id}");
+            if is_required || stores.queries.contains_key(&query_id) {
+                *query = stores
+                    .query_template(&query_id, 1)
+                    .map_err(|err| format!("Directory {prefix:?}: {err}"))?
+                    .query
+                    .clone();
+            }
         }
 
         Ok(SqlDirectory {
@@ -21,10 +21,11 @@
  * for more details.
 */
 
+use ahash::AHashMap;
 use mail_send::Credentials;
 use store::{NamedRows, Rows, Store, Value};
 
-use crate::{backend::internal::manage::ManageDirectory, Principal, QueryBy, Type};
+use crate::{backend::internal::manage::ManageDirectory, Principal, QueryBy, QueryResult, Type};
 
 use super::{SqlDirectory, SqlMappings};
 
@@ -33,7 +34,7 @@ impl SqlDirectory {
         &self,
         by: QueryBy<'_>,
         return_member_of: bool,
-    ) -> crate::Result<Option<Principal<u32>>> {
+    ) -> crate::Result<QueryResult> {
         let mut account_id = None;
         let account_name;
         let mut secret = None;
@@ -50,7 +51,7 @@ impl SqlDirectory {
                 if let Some(username) = self.unwrap_id_store().get_account_name(uid).await? {
                     account_name = username;
                 } else {
-                    return Ok(None);
+                    return Ok(QueryResult::NotFound);
                 }
                 account_id = Some(uid);
 
@@ -77,10 +78,11 @@ impl SqlDirectory {
         };
 
         if result.rows.is_empty() {
-            return Ok(None);
+            return Ok(QueryResult::NotFound);
         }
 
         // Map row to principal
+        let is_active = self.mappings.is_active(&result);
         let mut principal = self.mappings.row_to_principal(result)?;
 
         // Validate password
@@ -93,10 +95,22 @@ impl SqlDirectory {
                     account = account_name,
                     "Invalid password for account"
                 );
-                return Ok(None);
+                return Ok(QueryResult::InvalidCredentials);
             }
         }
 
+        // Disabled accounts are reported as such once their credentials have been verified
+        if !is_active {
+            tracing::debug!(
+                context = "directory",
+                event = "account_disabled",
+                protocol = "sql",
+                account = account_name,
+                "Account is disabled"
+            );
+            return Ok(QueryResult::Disabled);
+        }
+
         // Obtain account ID if not available
         if let Some(account_id) = account_id {
             principal.id = account_id;
@@ -143,7 +157,92 @@ impl SqlDirectory {
             }
         }
 
-        Ok(Some(principal))
+        Ok(QueryResult::Found(principal))
+    }
+
+    /// Looks up several principals by name in a single round-trip, which requires the
+    /// name query to compare one column with `= ?` and `columns.name` to be set.
+    /// Other name queries are run once per name. Results follow the order of `names`.
+    pub async fn query_many(&self, names: &[&str]) -> crate::Result<Vec<Option<Principal<u32>>>> {
+        let result = match batch_query(&self.mappings.query_name, names.len()) {
+            Some(query) if !names.is_empty() && !self.mappings.column_name.is_empty() => {
+                self.store
+                    .query::<NamedRows>(
+                        &query,
+                        names.iter().map(|name| Value::from(*name)).collect(),
+                    )
+                    .await?
+            }
+            _ => {
+                let mut results = Vec::with_capacity(names.len());
+                for name in names {
+                    results.push(
+                        self.query(QueryBy::Name(name), false)
+                            .await?
+                            .into_principal(),
+                    );
+                }
+                return Ok(results);
+            }
+        };
+
+        // Group rows by the name they matched
+        let name_pos = result
+            .names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(&self.mappings.column_name))
+            .ok_or_else(|| {
+                store::Error::InternalError(format!(
+                    "Name query does not return the {:?} column",
+                    self.mappings.column_name
+                ))
+            })?;
+        let mut found: AHashMap<String, NamedRows> = AHashMap::new();
+        for row in result.rows {
+            let name = row
+                .values
+                .get(name_pos)
+                .map(|name| name.to_str().to_lowercase())
+                .unwrap_or_default();
+            found
+                .entry(name)
+                .or_insert_with(|| NamedRows {
+                    names: result.names.clone(),
+                    rows: Vec::new(),
+                })
+                .rows
+                .push(row);
+        }
+
+        let mut results = Vec::with_capacity(names.len());
+        for name in names {
+            let rows = match found.get(&name.to_lowercase()) {
+                Some(rows) if self.mappings.is_active(rows) => rows.clone(),
+                _ => {
+                    results.push(None);
+                    continue;
+                }
+            };
+            let mut principal = self.mappings.row_to_principal(rows)?;
+            principal.name = name.to_string();
+            if self.has_id_store() {
+                principal.id = self
+                    .unwrap_id_store()
+                    .get_or_create_account_id(name)
+                    .await?;
+
+                if !self.mappings.query_emails.is_empty() {
+                    principal.emails = self
+                        .store
+                        .query::<Rows>(&self.mappings.query_emails, vec![(*name).into()])
+                        .await?
+                        .into();
+                }
+            }
+            results.push(Some(principal));
+        }
+
+        Ok(results)
     }
 
     pub async fn email_to_ids(&self, address: &str) -> crate::Result<Vec<u32>> {
@@ -248,4 +347,54 @@ impl SqlMappings {
 
         Ok(principal)
     }
+
+    pub fn is_active(&self, rows: &NamedRows) -> bool {
+        if self.column_active.is_empty() {
+            return true;
+        }
+
+        rows.rows
+            .first()
+            .and_then(|row| {
+                rows.names
+                    .iter()
+                    .zip(row.values.iter())
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&self.column_active))
+            })
+            .map_or(true, |(_, value)| match value {
+                Value::Bool(active) => *active,
+                Value::Integer(active) => *active != 0,
+                Value::Text(active) => !matches!(
+                    active.as_ref(),
+                    "0" | "false" | "FALSE" | "f" | "no" | "disabled"
+                ),
+                _ => true,
+            })
+    }
+}
+
+// Rewrites a query comparing a single column with `= ?` or `= $1` to compare it
+// with a list of values instead, returns `None` for any other kind of query.
+fn batch_query(query: &str, num_values: usize) -> Option<String> {
+    [("= ?", false), ("= $1", true)]
+        .into_iter()
+        .find_map(|(placeholder, is_numbered)| {
+            let (prefix, suffix) = query.split_once(placeholder)?;
+            if suffix.contains(placeholder)
+                || (is_numbered && suffix.starts_with(|ch: char| ch.is_ascii_digit()))
+            {
+                return None;
+            }
+            let values = (1..=num_values)
+                .map(|num| {
+                    if is_numbered {
+                        format!("${num}")
+                    } else {
+                        "?".to_string()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            Some(format!("{prefix}IN ({values}){suffix}"))
+        })
 }
@@ -41,8 +41,10 @@ pub(crate) struct SqlMappings {
     query_domains: String,
     query_verify: String,
     query_expand: String,
+    column_name: String,
     column_description: String,
     column_secret: String,
     column_quota: String,
     column_type: String,
+    column_active: String,
 }
@@ -21,27 +21,55 @@
  * for more details.
 */
 
-use std::{
-    borrow::Borrow,
-    hash::Hash,
-    time::{Duration, Instant},
-};
+use std::{borrow::Cow, time::Duration};
 
+use ahash::AHashMap;
+use futures::{
+    channel::oneshot,
+    future::{FutureExt, Shared},
+};
 use parking_lot::Mutex;
 use utils::config::{utils::AsKey, Config};
 
+pub use utils::map::lookup_cache::{EarlyRefresh, LookupCache, LookupCacheStats, ValueCache};
+
+use crate::Principal;
+
 pub struct CachedDirectory {
     cached_domains: Mutex<LookupCache<String>>,
     cached_rcpts: Mutex<LookupCache<String>>,
+    cached_catch_all: Mutex<LookupCache<String>>,
+    cached_member_of: Mutex<ValueCache<u32, Vec<u32>>>,
+    cached_principals: Mutex<ValueCache<String, Option<Principal<u32>>>>,
+    pending_domains: Mutex<PendingLookups>,
+    pending_rcpts: Mutex<PendingLookups>,
+    case_sensitive: bool,
 }
 
-#[allow(clippy::type_complexity)]
-#[derive(Debug)]
-pub struct LookupCache<T: Hash + Eq> {
-    cache_pos: lru_cache::LruCache<T, Instant, ahash::RandomState>,
-    cache_neg: lru_cache::LruCache<T, Instant, ahash::RandomState>,
-    ttl_pos: Duration,
-    ttl_neg: Duration,
+type PendingLookups = AHashMap<String, Shared<oneshot::Receiver<bool>>>;
+
+pub enum CachedLookup<'x> {
+    /// The result was found in the cache.
+    Cached(bool),
+    /// Another task is querying the backend, await its result.
+    Pending(Shared<oneshot::Receiver<bool>>),
+    /// No cached result, the caller has to query the backend.
+    Vacant(PendingLookup<'x>),
+}
+
+pub struct PendingLookup<'x> {
+    key: String,
+    pending: &'x Mutex<PendingLookups>,
+    tx: Option<oneshot::Sender<bool>>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CachedDirectoryStats {
+    pub domains: LookupCacheStats,
+    pub rcpts: LookupCacheStats,
+    pub catch_all: LookupCacheStats,
+    pub member_of: LookupCacheStats,
+    pub principals: LookupCacheStats,
 }
 
 impl CachedDirectory {
@@ -57,93 +85,258 @@ impl CachedDirectory {
             let cache_ttl_negative = config
                 .property((&prefix, "cache.ttl.positive"))?
                 .unwrap_or_else(|| Duration::from_secs(3600));
+            let cache_ttl_catch_all = config
+                .property((&prefix, "cache.ttl.catch-all"))?
+                .unwrap_or(cache_ttl_positive);
+            let cache_ttl_member_of = config
+                .property((&prefix, "cache.ttl.member-of"))?
+                .unwrap_or_else(|| Duration::from_secs(300));
+            let cache_ttl_principal = config
+                .property((&prefix, "cache.ttl.principal"))?
+                .unwrap_or_else(|| Duration::from_secs(60));
+            let early_refresh = if config
+                .property((&prefix, "cache.early-refresh.enable"))?
+                .unwrap_or(false)
+            {
+                Some(EarlyRefresh {
+                    beta: config
+                        .property((&prefix, "cache.early-refresh.beta"))?
+                        .unwrap_or(1.0),
+                    compute_time: config
+                        .property((&prefix, "cache.early-refresh.compute-time"))?
+                        .unwrap_or_else(|| Duration::from_secs(1)),
+                })
+            } else {
+                None
+            };
 
             Ok(Some(CachedDirectory {
-                cached_domains: Mutex::new(LookupCache::new(
-                    cached_entries,
-                    cache_ttl_positive,
-                    cache_ttl_negative,
-                )),
-                cached_rcpts: Mutex::new(LookupCache::new(
+                cached_domains: Mutex::new(
+                    LookupCache::new(cached_entries, cache_ttl_positive, cache_ttl_negative)
+                        .with_early_refresh(early_refresh),
+                ),
+                cached_rcpts: Mutex::new(
+                    LookupCache::new(cached_entries, cache_ttl_positive, cache_ttl_negative)
+                        .with_early_refresh(early_refresh),
+                ),
+                cached_catch_all: Mutex::new(LookupCache::new(
                     cached_entries,
-                    cache_ttl_positive,
-                    cache_ttl_negative,
+                    cache_ttl_catch_all,
+                    cache_ttl_catch_all,
                 )),
+                cached_member_of: Mutex::new(ValueCache::new(cached_entries, cache_ttl_member_of)),
+                cached_principals: Mutex::new(ValueCache::new(cached_entries, cache_ttl_principal)),
+                pending_domains: Mutex::new(AHashMap::new()),
+                pending_rcpts: Mutex::new(AHashMap::new()),
+                case_sensitive: config
+                    .property((&prefix, "cache.case-sensitive"))?
+                    .unwrap_or(true),
             }))
         } else {
             Ok(None)
         }
     }
 
+    pub fn lookup_rcpt(&self, address: &str) -> CachedLookup<'_> {
+        Self::lookup(
+            &self.cached_rcpts,
+            &self.pending_rcpts,
+            self.normalize_address(address).as_ref(),
+        )
+    }
+
+    pub fn lookup_domain(&self, domain: &str) -> CachedLookup<'_> {
+        Self::lookup(
+            &self.cached_domains,
+            &self.pending_domains,
+            normalize_domain(domain).as_ref(),
+        )
+    }
+
+    fn lookup<'x>(
+        cache: &Mutex<LookupCache<String>>,
+        pending: &'x Mutex<PendingLookups>,
+        key: &str,
+    ) -> CachedLookup<'x> {
+        // The pending map is locked first so that a lookup completing concurrently
+        // is either seen in the cache or as pending, never as neither.
+        let mut pending_ = pending.lock();
+        let mut cache = cache.lock();
+        if let Some(rx) = pending_.get(key) {
+            // Keep serving a valid entry while another task refreshes it
+            if let Some(result) = cache.peek(key) {
+                CachedLookup::Cached(result)
+            } else {
+                CachedLookup::Pending(rx.clone())
+            }
+        } else if let Some(result) = cache.get(key) {
+            CachedLookup::Cached(result)
+        } else {
+            let (tx, rx) = oneshot::channel();
+            pending_.insert(key.to_string(), rx.shared());
+            CachedLookup::Vacant(PendingLookup {
+                key: key.to_string(),
+                pending,
+                tx: tx.into(),
+            })
+        }
+    }
+
     pub fn get_rcpt(&self, address: &str) -> Option<bool> {
-        self.cached_rcpts.lock().get(address)
+        self.cached_rcpts
+            .lock()
+            .get(self.normalize_address(address).as_ref())
     }
 
     pub fn set_rcpt(&self, address: &str, exists: bool) {
         if exists {
-            self.cached_rcpts.lock().insert_pos(address.to_string());
+            self.cached_rcpts
+                .lock()
+                .insert_pos(self.normalize_address(address).into_owned());
         } else {
-            self.cached_rcpts.lock().insert_neg(address.to_string());
+            self.cached_rcpts
+                .lock()
+                .insert_neg(self.normalize_address(address).into_owned());
+        }
+    }
+
+    pub fn get_catch_all(&self, domain: &str) -> Option<bool> {
+        self.cached_catch_all
+            .lock()
+            .get(normalize_domain(domain).as_ref())
+    }
+
+    pub fn set_catch_all(&self, domain: &str, is_catch_all: bool) {
+        if is_catch_all {
+            self.cached_catch_all
+                .lock()
+                .insert_pos(normalize_domain(domain).into_owned());
+        } else {
+            self.cached_catch_all
+                .lock()
+                .insert_neg(normalize_domain(domain).into_owned());
         }
     }
 
     pub fn get_domain(&self, domain: &str) -> Option<bool> {
-        self.cached_domains.lock().get(domain)
+        self.cached_domains
+            .lock()
+            .get(normalize_domain(domain).as_ref())
     }
 
     pub fn set_domain(&self, domain: &str, exists: bool) {
         if exists {
-            self.cached_domains.lock().insert_pos(domain.to_string());
+            self.cached_domains
+                .lock()
+                .insert_pos(normalize_domain(domain).into_owned());
         } else {
-            self.cached_domains.lock().insert_neg(domain.to_string());
+            self.cached_domains
+                .lock()
+                .insert_neg(normalize_domain(domain).into_owned());
         }
     }
-}
 
-impl<T: Hash + Eq> LookupCache<T> {
-    pub fn new(capacity: usize, ttl_pos: Duration, ttl_neg: Duration) -> Self {
-        Self {
-            cache_pos: lru_cache::LruCache::with_hasher(capacity, ahash::RandomState::new()),
-            cache_neg: lru_cache::LruCache::with_hasher(capacity, ahash::RandomState::new()),
-            ttl_pos,
-            ttl_neg,
-        }
+    pub fn get_member_of(&self, account_id: u32) -> Option<Vec<u32>> {
+        self.cached_member_of.lock().get(&account_id)
     }
 
-    pub fn get<Q: ?Sized>(&mut self, name: &Q) -> Option<bool>
-    where
-        T: Borrow<Q>,
-        Q: Hash + Eq,
-    {
-        // Check positive cache
-        if let Some(valid_until) = self.cache_pos.get_mut(name) {
-            if *valid_until >= Instant::now() {
-                return Some(true);
-            } else {
-                self.cache_pos.remove(name);
-            }
-        }
+    pub fn set_member_of(&self, account_id: u32, member_of: Vec<u32>) {
+        self.cached_member_of.lock().insert(account_id, member_of);
+    }
+
+    pub fn invalidate_member_of(&self, account_id: u32) {
+        self.cached_member_of.lock().remove(&account_id);
+    }
+
+    pub fn invalidate_all_member_of(&self) {
+        self.cached_member_of.lock().clear();
+    }
 
-        // Check negative cache
-        let valid_until = self.cache_neg.get_mut(name)?;
-        if *valid_until >= Instant::now() {
-            Some(false)
+    /// Discards the cached principal with this id, under any of the names it was looked up by.
+    pub fn invalidate_principal(&self, account_id: u32) {
+        self.cached_principals
+            .lock()
+            .retain(|_, principal| principal.as_ref().map_or(true, |p| p.id != account_id));
+    }
+
+    pub fn invalidate_all_principals(&self) {
+        self.cached_principals.lock().clear();
+    }
+
+    /// Discards every cached entry, used when invalidation events may have been missed.
+    pub fn clear(&self) {
+        self.cached_domains.lock().clear();
+        self.cached_rcpts.lock().clear();
+        self.cached_catch_all.lock().clear();
+        self.cached_member_of.lock().clear();
+        self.cached_principals.lock().clear();
+    }
+
+    pub fn get_principal(&self, name: &str) -> Option<Option<Principal<u32>>> {
+        self.cached_principals.lock().get(name)
+    }
+
+    pub fn set_principal(&self, name: &str, principal: Option<Principal<u32>>) {
+        self.cached_principals
+            .lock()
+            .insert(name.to_string(), principal);
+    }
+
+    /// Returns the cache key for an address: the domain is lowercased and converted
+    /// to its ASCII form. Local parts are case sensitive (RFC 5321) and only lowercased
+    /// when `cache.case-sensitive` is disabled for directories that ignore their case.
+    pub fn normalize_address<'x>(&self, address: &'x str) -> Cow<'x, str> {
+        let (local_part, domain) = address.rsplit_once('@').unwrap_or((address, ""));
+        let local_part = if self.case_sensitive || !local_part.chars().any(|c| c.is_uppercase()) {
+            Cow::Borrowed(local_part)
         } else {
-            self.cache_pos.remove(name);
-            None
+            Cow::Owned(local_part.to_lowercase())
+        };
+        let domain = normalize_domain(domain);
+
+        if matches!((&local_part, &domain), (Cow::Borrowed(_), Cow::Borrowed(_))) {
+            Cow::Borrowed(address)
+        } else if !domain.is_empty() {
+            Cow::Owned(format!("{local_part}@{domain}"))
+        } else {
+            local_part
+        }
+    }
+
+    pub fn stats(&self) -> CachedDirectoryStats {
+        CachedDirectoryStats {
+            domains: self.cached_domains.lock().stats(),
+            rcpts: self.cached_rcpts.lock().stats(),
+            catch_all: self.cached_catch_all.lock().stats(),
+            member_of: self.cached_member_of.lock().stats(),
+            principals: self.cached_principals.lock().stats(),
         }
     }
+}
 
-    pub fn insert_pos(&mut self, item: T) {
-        self.cache_pos.insert(item, Instant::now() + self.ttl_pos);
+fn normalize_domain(domain: &str) -> Cow<'_, str> {
+    if domain.is_ascii() {
+        if domain.bytes().any(|b| b.is_ascii_uppercase()) {
+            Cow::Owned(domain.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(domain)
+        }
+    } else {
+        Cow::Owned(idna::domain_to_ascii(domain).unwrap_or_else(|_| domain.to_lowercase()))
     }
+}
 
-    pub fn insert_neg(&mut self, item: T) {
-        self.cache_neg.insert(item, Instant::now() + self.ttl_neg);
+impl PendingLookup<'_> {
+    pub fn complete(mut self, result: bool) {
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(result);
+        }
     }
+}
 
-    pub fn clear(&mut self) {
-        self.cache_pos.clear();
-        self.cache_neg.clear();
+impl Drop for PendingLookup<'_> {
+    fn drop(&mut self) {
+        // Waiters receive a cancellation if the lookup did not complete
+        self.pending.lock().remove(&self.key);
     }
 }
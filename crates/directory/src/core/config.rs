@@ -43,7 +43,7 @@ use crate::{
     AddressMapping, Directories, Directory, DirectoryInner, Lookup,
 };
 
-use super::cache::CachedDirectory;
+use super::{cache::CachedDirectory, lockout::AccountLockout};
 
 #[async_trait::async_trait]
 pub trait ConfigDirectory {
@@ -66,6 +66,7 @@ impl ConfigDirectory for Config {
             lookups: AHashMap::new(),
         };
         let id_store = id_store.and_then(|id| stores.stores.get(id).cloned());
+        let lockout = AccountLockout::parse(self, stores, id_store.as_ref())?.map(Arc::new);
 
         for id in self.sub_keys("directory") {
             // Parse directory
@@ -133,6 +134,7 @@ impl ConfigDirectory for Config {
                     ("directory", id, "options.subaddressing"),
                 )?,
                 cache: CachedDirectory::try_from_config(self, ("directory", id))?,
+                lockout: lockout.clone(),
             });
 
             // Add lookups
@@ -21,18 +21,125 @@
  * for more details.
 */
 
+use std::borrow::Cow;
+
 use crate::{
     backend::internal::lookup::DirectoryStore, Directory, DirectoryInner, Principal, QueryBy,
+    QueryResult,
 };
 
+use super::cache::{CachedDirectoryStats, CachedLookup};
+
 impl Directory {
     pub async fn query(
         &self,
         by: QueryBy<'_>,
         return_member_of: bool,
     ) -> crate::Result<Option<Principal<u32>>> {
+        self.query_detailed(by, return_member_of)
+            .await
+            .map(QueryResult::into_principal)
+    }
+
+    pub async fn query_detailed(
+        &self,
+        by: QueryBy<'_>,
+        return_member_of: bool,
+    ) -> crate::Result<QueryResult> {
+        // Group memberships are expensive to resolve, serve them from the cache if possible
+        match (&self.cache, by) {
+            (Some(cache), QueryBy::Id(account_id)) if return_member_of => {
+                if let Some(member_of) = cache.get_member_of(account_id) {
+                    Ok(match self.query_(QueryBy::Id(account_id), false).await? {
+                        QueryResult::Found(mut principal) => {
+                            principal.member_of = member_of;
+                            QueryResult::Found(principal)
+                        }
+                        result => result,
+                    })
+                } else {
+                    let result = self.query_(QueryBy::Id(account_id), true).await?;
+                    if let QueryResult::Found(principal) = &result {
+                        cache.set_member_of(account_id, principal.member_of.clone());
+                    }
+                    Ok(result)
+                }
+            }
+            (_, by) => self.query_(by, return_member_of).await,
+        }
+    }
+
+    pub async fn query_many(
+        &self,
+        by: Vec<QueryBy<'_>>,
+    ) -> crate::Result<Vec<Option<Principal<u32>>>> {
+        // Serve names from the cache, keeping track of the misses
+        let mut results = Vec::with_capacity(by.len());
+        let mut misses = Vec::new();
+        let mut others = Vec::new();
+        for (pos, by) in by.into_iter().enumerate() {
+            match by {
+                QueryBy::Name(name) => {
+                    if let Some(result) = self
+                        .cache
+                        .as_ref()
+                        .and_then(|cache| cache.get_principal(name))
+                    {
+                        results.push(result);
+                        continue;
+                    }
+                    misses.push((pos, name));
+                }
+                by => others.push((pos, by)),
+            }
+            results.push(None);
+        }
+
+        // Query the cache misses in a single round-trip when the backend supports it
+        if !misses.is_empty() {
+            let principals = match &self.store {
+                DirectoryInner::Ldap(store) => {
+                    store
+                        .query_many(&misses.iter().map(|(_, name)| *name).collect::<Vec<_>>())
+                        .await?
+                }
+                DirectoryInner::Sql(store) => {
+                    store
+                        .query_many(&misses.iter().map(|(_, name)| *name).collect::<Vec<_>>())
+                        .await?
+                }
+                _ => {
+                    let mut principals = Vec::with_capacity(misses.len());
+                    for (_, name) in &misses {
+                        principals.push(
+                            self.query_(QueryBy::Name(name), false)
+                                .await?
+                                .into_principal(),
+                        );
+                    }
+                    principals
+                }
+            };
+
+            for ((pos, name), principal) in misses.into_iter().zip(principals) {
+                if let Some(cache) = &self.cache {
+                    cache.set_principal(name, principal.clone());
+                }
+                results[pos] = principal;
+            }
+        }
+
+        // Credentials are never cached
+        for (pos, by) in others {
+            results[pos] = self.query(by, false).await?;
+        }
+
+        Ok(results)
+    }
+
+    async fn query_(&self, by: QueryBy<'_>, return_member_of: bool) -> crate::Result<QueryResult> {
         match &self.store {
-            DirectoryInner::Internal(store) => store.query(by, return_member_of).await,
+            DirectoryInner::Internal(store) => store.query_detailed(by, return_member_of).await,
             DirectoryInner::Ldap(store) => store.query(by, return_member_of).await,
             DirectoryInner::Sql(store) => store.query(by, return_member_of).await,
             DirectoryInner::Imap(store) => store.query(by).await,
@@ -66,13 +173,29 @@ impl Directory {
     }
 
     pub async fn is_local_domain(&self, domain: &str) -> crate::Result<bool> {
-        // Check cache
+        // Check cache, coalescing concurrent lookups for the same domain
         if let Some(cache) = &self.cache {
-            if let Some(result) = cache.get_domain(domain) {
-                return Ok(result);
+            match cache.lookup_domain(domain) {
+                CachedLookup::Cached(result) => Ok(result),
+                CachedLookup::Pending(pending) => {
+                    if let Ok(result) = pending.await {
+                        Ok(result)
+                    } else {
+                        self.is_local_domain_(domain).await
+                    }
+                }
+                CachedLookup::Vacant(lookup) => {
+                    let result = self.is_local_domain_(domain).await?;
+                    lookup.complete(result);
+                    Ok(result)
+                }
             }
+        } else {
+            self.is_local_domain_(domain).await
         }
+    }
 
+    async fn is_local_domain_(&self, domain: &str) -> crate::Result<bool> {
         let result = match &self.store {
             DirectoryInner::Internal(store) => store.is_local_domain(domain).await,
             DirectoryInner::Ldap(store) => store.is_local_domain(domain).await,
@@ -92,15 +215,41 @@ impl Directory {
 
     pub async fn rcpt(&self, email: &str) -> crate::Result<bool> {
         // Expand subaddress
-        let mut address = self.subaddressing.to_subaddress(email);
+        let address = self.subaddressing.to_subaddress(email);
 
-        // Check cache
+        // Check cache, coalescing concurrent lookups for the same address
         if let Some(cache) = &self.cache {
-            if let Some(result) = cache.get_rcpt(address.as_ref()) {
-                return Ok(result);
+            match cache.lookup_rcpt(address.as_ref()) {
+                CachedLookup::Cached(result) => Ok(result),
+                CachedLookup::Pending(pending) => {
+                    if let Ok(result) = pending.await {
+                        Ok(result)
+                    } else {
+                        self.rcpt_(email, address).await
+                    }
+                }
+                CachedLookup::Vacant(lookup) => {
+                    let result = self.rcpt_(email, address).await?;
+                    lookup.complete(result);
+                    Ok(result)
+                }
+            }
+        } else {
+            self.rcpt_(email, address).await
+        }
+    }
+
+    async fn rcpt_<'x>(&'x self, email: &'x str, mut address: Cow<'x, str>) -> crate::Result<bool> {
+        // Domains known to accept any local part are resolved from the cache
+        let domain = email.rsplit_once('@').map_or("", |(_, domain)| domain);
+        let mut catch_all = self.catch_all.to_catch_all(email);
+        if let (Some(cache), Some(_)) = (&self.cache, &catch_all) {
+            if let Some(true) = cache.get_catch_all(domain) {
+                return Ok(true);
             }
         }
 
+        let mut is_catch_all = false;
         for _ in 0..2 {
             let result = match &self.store {
                 DirectoryInner::Internal(store) => store.rcpt(address.as_ref()).await,
@@ -111,20 +260,28 @@ impl Directory {
                 DirectoryInner::Memory(store) => store.rcpt(address.as_ref()).await,
             }?;
 
+            if is_catch_all {
+                if let Some(cache) = &self.cache {
+                    cache.set_catch_all(domain, result);
+                }
+            }
+
             if result {
                 // Update cache
                 if let Some(cache) = &self.cache {
                     cache.set_rcpt(address.as_ref(), true);
                 }
                 return Ok(true);
-            } else if let Some(catch_all) = self.catch_all.to_catch_all(email) {
+            } else if let Some(catch_all) = catch_all.take() {
                 // Check cache
                 if let Some(cache) = &self.cache {
                     if let Some(result) = cache.get_rcpt(catch_all.as_ref()) {
+                        cache.set_catch_all(domain, result);
                         return Ok(result);
                     }
                 }
                 address = catch_all;
+                is_catch_all = true;
             } else {
                 break;
             }
@@ -161,4 +318,38 @@ impl Directory {
             DirectoryInner::Memory(store) => store.expn(address.as_ref()).await,
         }
     }
+
+    pub fn invalidate_member_of(&self, account_id: u32) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate_member_of(account_id);
+        }
+    }
+
+    pub fn invalidate_all_member_of(&self) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate_all_member_of();
+        }
+    }
+
+    pub fn invalidate_principal(&self, account_id: u32) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate_principal(account_id);
+        }
+    }
+
+    pub fn invalidate_all_principals(&self) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate_all_principals();
+        }
+    }
+
+    pub fn clear_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+
+    pub fn cache_stats(&self) -> Option<CachedDirectoryStats> {
+        self.cache.as_ref().map(|cache| cache.stats())
+    }
 }
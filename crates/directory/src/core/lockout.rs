@@ -0,0 +1,233 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use mail_send::Credentials;
+use store::{LookupKey, LookupStore, LookupValue, Store, Stores};
+
+use crate::{Directory, QueryBy, QueryResult};
+
+pub struct AccountLockout {
+    pub store: LookupStore,
+    pub max_attempts: u64,
+    pub window: Duration,
+    pub duration: Duration,
+}
+
+impl AccountLockout {
+    pub fn parse(
+        config: &utils::config::Config,
+        stores: &Stores,
+        id_store: Option<&Store>,
+    ) -> utils::config::Result<Option<Self>> {
+        let max_attempts = config
+            .property::<u64>("jmap.auth.lockout.attempts")?
+            .unwrap_or(0);
+        if max_attempts == 0 {
+            return Ok(None);
+        }
+
+        // Failure counters are kept in a lookup store so they are shared across the cluster
+        let store = if let Some(store_id) = config.value("jmap.auth.lockout.store") {
+            stores
+                .lookup_stores
+                .get(store_id)
+                .ok_or_else(|| format!("Unable to find lookup store {store_id:?}"))?
+                .clone()
+        } else {
+            id_store
+                .ok_or_else(|| {
+                    "Missing \"jmap.auth.lockout.store\" and no data store configured".to_string()
+                })?
+                .clone()
+                .into()
+        };
+
+        Ok(Some(AccountLockout {
+            store,
+            max_attempts,
+            window: config
+                .property("jmap.auth.lockout.window")?
+                .unwrap_or(Duration::from_secs(15 * 60)),
+            duration: config
+                .property("jmap.auth.lockout.duration")?
+                .unwrap_or(Duration::from_secs(30 * 60)),
+        }))
+    }
+
+    pub async fn is_locked(&self, username: &str) -> bool {
+        match self
+            .store
+            .key_get::<String>(LookupKey::Key(lock_key(username)))
+            .await
+        {
+            Ok(value) => value.as_value().map_or(false, |value| value != "0"),
+            Err(err) => {
+                tracing::warn!(
+                    context = "lockout",
+                    event = "error",
+                    account = username,
+                    reason = ?err,
+                    "Failed to obtain account lockout status."
+                );
+                false
+            }
+        }
+    }
+
+    // Returns true if the failure caused the account to be locked. Failures are
+    // counted atomically within a window that starts with the first failure.
+    pub async fn register_failure(&self, username: &str) -> bool {
+        let num_failures = match self
+            .store
+            .counter_incr(failures_key(username), 1, self.window.as_secs())
+            .await
+        {
+            Ok(num_failures) => num_failures.max(0) as u64,
+            Err(err) => {
+                tracing::warn!(
+                    context = "lockout",
+                    event = "error",
+                    account = username,
+                    reason = ?err,
+                    "Failed to register authentication failure."
+                );
+                return false;
+            }
+        };
+
+        if num_failures < self.max_attempts {
+            false
+        } else {
+            tracing::info!(
+                context = "lockout",
+                event = "locked",
+                account = username,
+                failures = num_failures,
+                "Account locked after too many authentication failures."
+            );
+
+            let _ = self
+                .store
+                .key_set(
+                    lock_key(username),
+                    LookupValue::Value {
+                        value: b"1".to_vec(),
+                        expires: self.duration.as_secs(),
+                    },
+                )
+                .await;
+            let _ = self.reset_failures(username).await;
+            true
+        }
+    }
+
+    pub async fn reset_failures(&self, username: &str) -> store::Result<()> {
+        if self
+            .store
+            .key_get::<String>(LookupKey::Counter(failures_key(username)))
+            .await?
+            .counter()
+            .map_or(false, |num| num != 0)
+        {
+            self.store
+                .key_delete(LookupKey::Counter(failures_key(username)))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn clear(&self, username: &str) -> store::Result<()> {
+        self.store
+            .key_delete(LookupKey::Key(lock_key(username)))
+            .await?;
+        self.reset_failures(username).await
+    }
+}
+
+impl Directory {
+    /// Verifies a set of credentials, rejecting locked accounts and counting
+    /// failed attempts. All protocols authenticate users through this method.
+    pub async fn authenticate(
+        &self,
+        credentials: &Credentials<String>,
+        return_member_of: bool,
+    ) -> crate::Result<QueryResult> {
+        let (lockout, username) = match (&self.lockout, credentials) {
+            (
+                Some(lockout),
+                Credentials::Plain { username, .. } | Credentials::XOauth2 { username, .. },
+            ) => (lockout, username),
+            _ => {
+                return self
+                    .query_detailed(QueryBy::Credentials(credentials), return_member_of)
+                    .await
+            }
+        };
+
+        // Locked accounts are rejected even if the right credentials are supplied
+        if lockout.is_locked(username).await {
+            tracing::debug!(
+                context = "auth",
+                event = "locked",
+                account = username,
+                "Authentication attempt for locked account"
+            );
+            return Ok(QueryResult::Locked);
+        }
+
+        match self
+            .query_detailed(QueryBy::Credentials(credentials), return_member_of)
+            .await?
+        {
+            QueryResult::Found(principal) => {
+                let _ = lockout.reset_failures(username).await;
+                Ok(QueryResult::Found(principal))
+            }
+            QueryResult::InvalidCredentials if lockout.register_failure(username).await => {
+                Ok(QueryResult::Locked)
+            }
+            // Unknown accounts do not create lockout state in the store,
+            // so that guessing usernames can't be used to fill it up
+            result => Ok(result),
+        }
+    }
+
+    pub async fn clear_lockout(&self, username: &str) -> store::Result<()> {
+        if let Some(lockout) = &self.lockout {
+            lockout.clear(username).await
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn lock_key(username: &str) -> Vec<u8> {
+    format!("auth-lock:{}", username.to_lowercase()).into_bytes()
+}
+
+fn failures_key(username: &str) -> Vec<u8> {
+    format!("auth-fail:{}", username.to_lowercase()).into_bytes()
+}
@@ -26,6 +26,7 @@ use crate::{backend::memory::MemoryDirectory, AddressMapping, Directory, Directo
 pub mod cache;
 pub mod config;
 pub mod dispatch;
+pub mod lockout;
 pub mod secret;
 
 impl Default for Directory {
@@ -35,6 +36,7 @@ impl Default for Directory {
             catch_all: AddressMapping::Disable,
             subaddressing: AddressMapping::Disable,
             cache: None,
+            lockout: None,
         }
     }
 }
@@ -21,6 +21,8 @@
  * for more details.
 */
 
+use std::sync::Once;
+
 use argon2::Argon2;
 use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
@@ -32,6 +34,7 @@ use sha1::Digest;
 use sha1::Sha1;
 use sha2::Sha256;
 use sha2::Sha512;
+use subtle::ConstantTimeEq;
 use tokio::sync::oneshot;
 
 use crate::Principal;
@@ -125,9 +128,10 @@ async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
                     // SHA-1
                     let mut hasher = Sha1::new();
                     hasher.update(secret.as_bytes());
-                    String::from_utf8(base64_encode(&hasher.finalize()[..]).unwrap_or_default())
-                        .unwrap()
-                        == hashed_secret
+                    ct_eq(
+                        &base64_encode(&hasher.finalize()[..]).unwrap_or_default(),
+                        hashed_secret.as_bytes(),
+                    )
                 }
                 "SSHA" => {
                     // Salted SHA-1
@@ -137,15 +141,16 @@ async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
                     let mut hasher = Sha1::new();
                     hasher.update(secret.as_bytes());
                     hasher.update(salt);
-                    &hasher.finalize()[..] == hash
+                    ct_eq(&hasher.finalize()[..], hash)
                 }
                 "SHA256" => {
                     // Verify hash
                     let mut hasher = Sha256::new();
                     hasher.update(secret.as_bytes());
-                    String::from_utf8(base64_encode(&hasher.finalize()[..]).unwrap_or_default())
-                        .unwrap()
-                        == hashed_secret
+                    ct_eq(
+                        &base64_encode(&hasher.finalize()[..]).unwrap_or_default(),
+                        hashed_secret.as_bytes(),
+                    )
                 }
                 "SSHA256" => {
                     // Salted SHA-256
@@ -155,15 +160,16 @@ async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
                     let mut hasher = Sha256::new();
                     hasher.update(secret.as_bytes());
                     hasher.update(salt);
-                    &hasher.finalize()[..] == hash
+                    ct_eq(&hasher.finalize()[..], hash)
                 }
                 "SHA512" => {
                     // SHA-512
                     let mut hasher = Sha512::new();
                     hasher.update(secret.as_bytes());
-                    String::from_utf8(base64_encode(&hasher.finalize()[..]).unwrap_or_default())
-                        .unwrap()
-                        == hashed_secret
+                    ct_eq(
+                        &base64_encode(&hasher.finalize()[..]).unwrap_or_default(),
+                        hashed_secret.as_bytes(),
+                    )
                 }
                 "SSHA512" => {
                     // Salted SHA-512
@@ -173,13 +179,15 @@ async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
                     let mut hasher = Sha512::new();
                     hasher.update(secret.as_bytes());
                     hasher.update(salt);
-                    &hasher.finalize()[..] == hash
+                    ct_eq(&hasher.finalize()[..], hash)
                 }
                 "MD5" => {
                     // MD5
                     let digest = md5::compute(secret.as_bytes());
-                    String::from_utf8(base64_encode(&digest[..]).unwrap_or_default()).unwrap()
-                        == hashed_secret
+                    ct_eq(
+                        &base64_encode(&digest[..]).unwrap_or_default(),
+                        hashed_secret.as_bytes(),
+                    )
                 }
                 "CRYPT" | "crypt" => {
                     if hashed_secret.starts_with('$') {
@@ -189,7 +197,7 @@ async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
                         unix_crypt::verify(secret, hashed_secret)
                     }
                 }
-                "PLAIN" | "plain" | "CLEAR" | "clear" => hashed_secret == secret,
+                "PLAIN" | "plain" | "CLEAR" | "clear" => verify_plain(hashed_secret, secret),
                 _ => {
                     tracing::warn!(
                         context = "directory",
@@ -210,6 +218,24 @@ async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
             false
         }
     } else {
-        hashed_secret == secret
+        verify_plain(hashed_secret, secret)
     }
 }
+
+fn verify_plain(stored_secret: &str, secret: &str) -> bool {
+    // Only warn the first time, this runs on every login of an affected account
+    static DEPRECATION_WARNING: Once = Once::new();
+    DEPRECATION_WARNING.call_once(|| {
+        tracing::warn!(
+            context = "directory",
+            event = "deprecated",
+            "Plain text secrets are deprecated, please store a password hash instead."
+        );
+    });
+    ct_eq(stored_secret.as_bytes(), secret.as_bytes())
+}
+
+#[inline(always)]
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
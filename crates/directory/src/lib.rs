@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use core::cache::CachedDirectory;
+use core::{cache::CachedDirectory, lockout::AccountLockout};
 use std::{borrow::Cow, fmt::Debug, sync::Arc};
 
 use ahash::AHashMap;
@@ -47,6 +47,7 @@ pub struct Directory {
     catch_all: AddressMapping,
     subaddressing: AddressMapping,
     cache: Option<CachedDirectory>,
+    lockout: Option<Arc<AccountLockout>>,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -125,6 +126,35 @@ pub enum QueryBy<'x> {
     Credentials(&'x Credentials<String>),
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryResult {
+    Found(Principal<u32>),
+    NotFound,
+    /// Only the SQL (`columns.active`) and SMTP backends can tell that an account is
+    /// disabled. The internal directory has no notion of disabled accounts, and LDAP
+    /// reports them as `NotFound` when the filter excludes them or as
+    /// `InvalidCredentials` when the server refuses to bind.
+    Disabled,
+    InvalidCredentials,
+    Locked,
+}
+
+impl QueryResult {
+    // Used by `query`, where every outcome other than `Found` is `None`
+    pub fn into_principal(self) -> Option<Principal<u32>> {
+        match self {
+            QueryResult::Found(principal) => Some(principal),
+            _ => None,
+        }
+    }
+}
+
+impl From<Option<Principal<u32>>> for QueryResult {
+    fn from(principal: Option<Principal<u32>>) -> Self {
+        principal.map_or(QueryResult::NotFound, QueryResult::Found)
+    }
+}
+
 impl<T: serde::Serialize + serde::de::DeserializeOwned> Principal<T> {
     pub fn name(&self) -> &str {
         &self.name
@@ -237,7 +267,10 @@ impl From<store::Error> for DirectoryError {
             "Directory error"
         );
 
-        DirectoryError::Store(error)
+        match error {
+            store::Error::Timeout(_) => DirectoryError::TimedOut,
+            error => DirectoryError::Store(error),
+        }
     }
 }
 
@@ -28,6 +28,7 @@ use imap_proto::{
     receiver::{self, Request},
     Command, ResponseCode, StatusResponse,
 };
+use jmap::auth::AuthResult;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
 use tokio::io::AsyncRead;
@@ -126,7 +127,7 @@ impl<T: AsyncRead> Session<T> {
                     .validate_access_token("access_token", &token)
                     .await
                 {
-                    Ok((account_id, _, _)) => self.jmap.get_access_token(account_id).await,
+                    Ok((account_id, _, _)) => self.jmap.get_access_token(account_id).await.into(),
                     Err(err) => {
                         tracing::debug!(
                             parent: &self.span,
@@ -134,13 +135,14 @@ impl<T: AsyncRead> Session<T> {
                             err = err,
                             "Failed to validate access token."
                         );
-                        None
+                        AuthResult::Failure
                     }
                 }
             }
         };
+        let is_locked = matches!(access_token, AuthResult::Locked);
 
-        if let Some(access_token) = access_token {
+        if let AuthResult::Success(access_token) = access_token {
             // Enforce concurrency limits
             let in_flight = self
                 .imap
@@ -180,10 +182,14 @@ impl<T: AsyncRead> Session<T> {
             }
         } else {
             self.write_bytes(
-                StatusResponse::no("Authentication failed")
-                    .with_tag(tag)
-                    .with_code(ResponseCode::AuthenticationFailed)
-                    .into_bytes(),
+                StatusResponse::no(if is_locked {
+                    "Account temporarily locked"
+                } else {
+                    "Authentication failed"
+                })
+                .with_tag(tag)
+                .with_code(ResponseCode::AuthenticationFailed)
+                .into_bytes(),
             )
             .await?;
 
@@ -41,6 +41,16 @@ pub enum Collection {
     None = 8,
 }
 
+impl Collection {
+    // Unlike `From<u8>`, unknown values are rejected instead of being mapped to `Collection::None`
+    pub fn try_from_u8(value: u8) -> Option<Self> {
+        match Collection::from(value) {
+            Collection::None if value != Collection::None as u8 => None,
+            collection => Some(collection),
+        }
+    }
+}
+
 impl From<u8> for Collection {
     fn from(v: u8) -> Self {
         match v {
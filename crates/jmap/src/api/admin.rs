@@ -22,7 +22,9 @@
 */
 
 use directory::{
-    backend::internal::{lookup::DirectoryStore, manage::ManageDirectory, PrincipalUpdate},
+    backend::internal::{
+        lookup::DirectoryStore, manage::ManageDirectory, PrincipalField, PrincipalUpdate,
+    },
     DirectoryError, ManagementError, Principal, QueryBy, Type,
 };
 use http_body_util::combinators::BoxBody;
@@ -118,6 +120,16 @@ impl JMAP {
                     Err(err) => map_directory_error(err),
                 }
             }
+            ("lockout", Some(name), &Method::DELETE) => {
+                // Clear account lockout
+                match self.directory.clear_lockout(name).await {
+                    Ok(_) => JsonResponse::new(json!({
+                        "data": [],
+                    }))
+                    .into_http_response(),
+                    Err(err) => map_directory_error(err.into()),
+                }
+            }
             ("principal", Some(name), method) => {
                 // Fetch, update or delete principal
                 let account_id = match self.store.get_account_id(name).await {
@@ -135,7 +147,7 @@ impl JMAP {
                     }
                 };
 
-                match *method {
+                match method {
                     Method::GET => {
                         let result = match self.store.query(QueryBy::Id(account_id), true).await {
                             Ok(Some(principal)) => self.store.map_group_ids(principal).await,
@@ -196,10 +208,15 @@ impl JMAP {
 
                         // Delete account
                         match self.store.delete_account(QueryBy::Id(account_id)).await {
-                            Ok(_) => JsonResponse::new(json!({
-                                "data": [],
-                            }))
-                            .into_http_response(),
+                            Ok(_) => {
+                                // Deleting a group changes the membership of its members
+                                self.invalidate_all_principals().await;
+
+                                JsonResponse::new(json!({
+                                    "data": [],
+                                }))
+                                .into_http_response()
+                            }
                             Err(err) => map_directory_error(err),
                         }
                     }
@@ -207,15 +224,28 @@ impl JMAP {
                         if let Some(changes) = body.and_then(|body| {
                             serde_json::from_slice::<Vec<PrincipalUpdate>>(&body).ok()
                         }) {
+                            // Changing the members of a group affects other accounts
+                            let members_changed = changes
+                                .iter()
+                                .any(|change| change.field() == &PrincipalField::Members);
+
                             match self
                                 .store
                                 .update_account(QueryBy::Id(account_id), changes)
                                 .await
                             {
-                                Ok(account_id) => JsonResponse::new(json!({
-                                    "data": account_id,
-                                }))
-                                .into_http_response(),
+                                Ok(result) => {
+                                    if members_changed {
+                                        self.invalidate_all_principals().await;
+                                    } else {
+                                        self.invalidate_principal(account_id).await;
+                                    }
+
+                                    JsonResponse::new(json!({
+                                        "data": result,
+                                    }))
+                                    .into_http_response()
+                                }
                                 Err(err) => map_directory_error(err),
                             }
                         } else {
@@ -279,7 +309,7 @@ impl JMAP {
             }
             ("store", Some("maintenance"), &Method::GET) => {
                 match self.store.purge_blobs(self.blob_store.clone()).await {
-                    Ok(_) => match self.store.purge_bitmaps().await {
+                    Ok(_) => match self.store.maintain().await {
                         Ok(_) => JsonResponse::new(json!({
                             "data": [],
                         }))
@@ -299,6 +329,19 @@ impl JMAP {
                     .into_http_response(),
                 }
             }
+            ("store", Some("read-only"), method) => {
+                match *method {
+                    Method::POST => self.store.set_read_only(true),
+                    Method::DELETE => self.store.set_read_only(false),
+                    Method::GET => (),
+                    _ => return RequestError::not_found().into_http_response(),
+                }
+
+                JsonResponse::new(json!({
+                    "data": self.store.is_read_only(),
+                }))
+                .into_http_response()
+            }
             (path_1 @ ("queue" | "report"), Some(path_2), &Method::GET) => {
                 self.smtp
                     .handle_manage_request(req.uri(), req.method(), path_1, path_2)
@@ -24,6 +24,7 @@
 use std::{str::FromStr, time::Duration};
 
 use nlp::language::Language;
+use smtp::config::IpAddrMask;
 use store::rand::{distributions::Alphanumeric, thread_rng, Rng};
 
 use super::session::BaseCapabilities;
@@ -53,6 +54,7 @@ impl crate::Config {
             request_max_concurrent: settings
                 .property("jmap.protocol.request.max-concurrent")?
                 .unwrap_or(4),
+            request_timeout: settings.property("jmap.protocol.request.timeout")?,
             get_max_objects: settings
                 .property("jmap.protocol.get.max-objects")?
                 .unwrap_or(500),
@@ -97,6 +99,9 @@ impl crate::Config {
             session_cache_ttl: settings
                 .property("jmap.session.cache.ttl")?
                 .unwrap_or(Duration::from_secs(3600)),
+            principal_cache_ttl: settings
+                .property("jmap.principal.cache.ttl")?
+                .unwrap_or(Duration::from_secs(30)),
             rate_authenticated: settings
                 .property_or_static("jmap.rate-limit.account", "1000/1m")?,
             rate_authenticate_req: settings
@@ -105,6 +110,33 @@ impl crate::Config {
             rate_use_forwarded: settings
                 .property("jmap.rate-limit.use-forwarded")?
                 .unwrap_or(false),
+            rate_use_fingerprint: settings
+                .property("jmap.rate-limit.use-fingerprint")?
+                .unwrap_or(false),
+            auth_client_cert: settings
+                .property("jmap.auth.client-certificate")?
+                .unwrap_or(false),
+            auth_trusted_header: settings
+                .value("jmap.auth.trusted-header.name")
+                .map(|name| {
+                    hyper::header::HeaderName::from_str(name.trim()).map_err(|err| {
+                        format!(
+                            "Invalid header found in property \"jmap.auth.trusted-header.name\": {}",
+                            err
+                        )
+                    })
+                })
+                .transpose()?,
+            auth_trusted_proxies: settings
+                .properties::<IpAddrMask>("jmap.auth.trusted-header.proxies")
+                .map(|result| result.map(|(_, mask)| mask))
+                .collect::<Result<Vec<_>, _>>()?,
+            session_cookie_enable: settings
+                .property("jmap.auth.session-cookie.enable")?
+                .unwrap_or(false),
+            session_cookie_expiry: settings
+                .property_or_static::<Duration>("jmap.auth.session-cookie.expiry", "8h")?
+                .as_secs(),
             oauth_key: settings
                 .text_file_contents("oauth.key")?
                 .unwrap_or_else(|| {
@@ -176,6 +208,12 @@ impl crate::Config {
                 })
                 .collect::<Result<Vec<_>, String>>()?,
         };
+        if config.auth_trusted_header.is_some() && config.auth_trusted_proxies.is_empty() {
+            return Err(
+                "Property \"jmap.auth.trusted-header.proxies\" is required when trusted header authentication is enabled."
+                    .to_string(),
+            );
+        }
         config.add_capabilites(settings);
         Ok(config)
     }
@@ -42,7 +42,10 @@ use tokio::{
     io::{AsyncRead, AsyncWrite},
     net::TcpStream,
 };
-use utils::listener::{ServerInstance, SessionData, SessionManager};
+use utils::{
+    config::certificate::ClientCertificate,
+    listener::{ServerInstance, SessionData, SessionManager},
+};
 
 use crate::{
     auth::{oauth::OAuthMetadata, AccessToken},
@@ -200,6 +203,26 @@ pub async fn parse_jmap_request(
                     Err(err) => err.into_http_response(),
                 };
             }
+            ("acme-challenge", &Method::GET) => {
+                // Answer ACME HTTP-01 challenges
+                let token = path.next().unwrap_or_default();
+                return match instance
+                    .acme_providers
+                    .iter()
+                    .find_map(|provider| provider.http_challenge(token))
+                {
+                    Some(key_authorization) => hyper::Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, "text/plain")
+                        .body(
+                            Full::new(Bytes::from(key_authorization))
+                                .map_err(|never| match never {})
+                                .boxed(),
+                        )
+                        .unwrap(),
+                    None => RequestError::not_found().into_http_response(),
+                };
+            }
             (_, &Method::OPTIONS) => {
                 return ().into_http_response();
             }
@@ -251,6 +274,15 @@ pub async fn parse_jmap_request(
                         Err(err) => err.into_http_response(),
                     }
                 }
+                ("session", &Method::POST) if jmap.config.session_cookie_enable => {
+                    return match jmap.is_auth_allowed_soft(&remote_addr) {
+                        Ok(_) => jmap.handle_session_login(&mut req, &remote_addr).await,
+                        Err(err) => err.into_http_response(),
+                    }
+                }
+                ("session", &Method::DELETE) if jmap.config.session_cookie_enable => {
+                    return jmap.handle_session_logout(&req).await;
+                }
                 (_, &Method::OPTIONS) => {
                     return ().into_http_response();
                 }
@@ -299,6 +331,15 @@ impl SessionManager for JmapSessionManager {
                 let span = session.span;
                 match tls_acceptor.accept(session.stream).await {
                     Ok(stream) => {
+                        // Obtain verified client certificate
+                        let client_cert = stream
+                            .get_ref()
+                            .1
+                            .peer_certificates()
+                            .and_then(|certs| certs.first())
+                            .and_then(ClientCertificate::parse)
+                            .map(Arc::new);
+
                         handle_request(
                             jmap,
                             SessionData {
@@ -310,6 +351,7 @@ impl SessionManager for JmapSessionManager {
                                 in_flight: session.in_flight,
                                 instance: session.instance,
                             },
+                            client_cert,
                         )
                         .await;
                     }
@@ -324,7 +366,7 @@ impl SessionManager for JmapSessionManager {
                     }
                 }
             } else {
-                handle_request(jmap, session).await;
+                handle_request(jmap, session, None).await;
             }
         });
     }
@@ -340,6 +382,7 @@ impl SessionManager for JmapSessionManager {
 async fn handle_request<T: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
     jmap: Arc<JMAP>,
     session: SessionData<T>,
+    client_cert: Option<Arc<ClientCertificate>>,
 ) {
     let span = session.span;
     let _in_flight = session.in_flight;
@@ -348,10 +391,13 @@ async fn handle_request<T: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
         .keep_alive(true)
         .serve_connection(
             TokioIo::new(session.stream),
-            service_fn(|req: hyper::Request<body::Incoming>| {
+            service_fn(|mut req: hyper::Request<body::Incoming>| {
                 let jmap = jmap.clone();
                 let span = span.clone();
                 let instance = session.instance.clone();
+                if let Some(client_cert) = &client_cert {
+                    req.extensions_mut().insert(client_cert.clone());
+                }
 
                 async move {
                     tracing::debug!(
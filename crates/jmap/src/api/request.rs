@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use jmap_proto::{
     error::{method::MethodError, request::RequestError},
@@ -33,6 +33,7 @@ use jmap_proto::{
     response::{Response, ResponseMethod},
     types::collection::Collection,
 };
+use store::dispatch::{deadline::with_deadline, scope::with_read_scope};
 use utils::listener::ServerInstance;
 
 use crate::{auth::AccessToken, JMAP};
@@ -43,6 +44,28 @@ impl JMAP {
         request: Request,
         access_token: Arc<AccessToken>,
         instance: &Arc<ServerInstance>,
+    ) -> Result<Response, RequestError> {
+        // Reads issued while handling the request may share a read version
+        with_read_scope(async {
+            if let Some(timeout) = self.config.request_timeout {
+                // Store operations still pending when the request runs out of time fail with a timeout
+                with_deadline(
+                    Instant::now() + timeout,
+                    self.handle_request_(request, access_token, instance),
+                )
+                .await
+            } else {
+                self.handle_request_(request, access_token, instance).await
+            }
+        })
+        .await
+    }
+
+    async fn handle_request_(
+        &self,
+        request: Request,
+        access_token: Arc<AccessToken>,
+        instance: &Arc<ServerInstance>,
     ) -> Result<Response, RequestError> {
         let mut response = Response::new(
             access_token.state(),
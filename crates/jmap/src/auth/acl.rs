@@ -46,6 +46,7 @@ use super::AccessToken;
 
 impl JMAP {
     pub async fn update_access_token(&self, mut access_token: AccessToken) -> Option<AccessToken> {
+        let version = self.access_token_version.load(Ordering::Relaxed);
         for &grant_account_id in [access_token.primary_id]
             .iter()
             .chain(access_token.member_of.clone().iter())
@@ -102,7 +103,10 @@ impl JMAP {
                 }
             }
         }
-        access_token.into()
+        access_token
+            .with_effective_access()
+            .with_version(version)
+            .into()
     }
 
     pub async fn shared_documents(
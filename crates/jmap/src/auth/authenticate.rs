@@ -23,20 +23,26 @@
 
 use std::{
     net::{IpAddr, Ipv4Addr},
-    sync::Arc,
+    sync::{atomic::Ordering, Arc},
     time::Instant,
 };
 
-use directory::QueryBy;
-use hyper::header;
+use directory::{QueryBy, QueryResult};
+use hyper::{header, StatusCode};
 use jmap_proto::error::request::RequestError;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
-use utils::{listener::limiter::InFlight, map::ttl_dashmap::TtlMap};
+use store::dispatch::invalidation::InvalidationEvent;
+use utils::{
+    config::certificate::ClientCertificate, listener::limiter::InFlight, map::ttl_dashmap::TtlMap,
+};
 
 use crate::JMAP;
 
-use super::{rate_limit::RemoteAddress, AccessToken};
+use super::{
+    geoip::GeoLocation, rate_limit::RemoteAddress, AccessToken, AuthResult, CachedSession,
+    SessionKey,
+};
 
 impl JMAP {
     pub async fn authenticate_headers(
@@ -44,14 +50,16 @@ impl JMAP {
         req: &hyper::Request<hyper::body::Incoming>,
         remote_ip: IpAddr,
     ) -> Result<Option<(InFlight, Arc<AccessToken>)>, RequestError> {
+        // Explicit credentials take precedence over the trusted proxy header
         if let Some((mechanism, token)) = req
             .headers()
             .get(header::AUTHORIZATION)
             .and_then(|h| h.to_str().ok())
             .and_then(|h| h.split_once(' ').map(|(l, t)| (l, t.trim().to_string())))
         {
-            let session = if let Some(account_id) = self.sessions.get_with_ttl(&token) {
-                self.get_cached_access_token(account_id).await
+            let session_id = SessionKey::Token(token.clone());
+            let session = if let Some(session) = self.sessions.get_with_ttl(&session_id) {
+                self.get_cached_access_token(session.account_id).await
             } else {
                 let addr = self.build_remote_addr(req, remote_ip);
                 if mechanism.eq_ignore_ascii_case("basic") {
@@ -67,7 +75,17 @@ impl JMAP {
                             })
                         })
                     {
-                        self.authenticate_plain(&account, &secret, &addr).await
+                        match self.authenticate_plain(&account, &secret, &addr).await {
+                            AuthResult::Success(access_token) => Some(access_token),
+                            AuthResult::Failure => None,
+                            AuthResult::Locked => {
+                                return Err(RequestError::blank(
+                                    StatusCode::FORBIDDEN.as_u16(),
+                                    "Account locked",
+                                    "Too many authentication failures, try again later.",
+                                ))
+                            }
+                        }
                     } else {
                         tracing::debug!(
                             context = "authenticate_headers",
@@ -98,7 +116,11 @@ impl JMAP {
                 }
                 .map(|access_token| {
                     let access_token = Arc::new(access_token);
-                    self.cache_session(token, &access_token);
+                    self.cache_session(
+                        session_id,
+                        &access_token,
+                        self.locate_session(&access_token, &addr),
+                    );
                     self.cache_access_token(access_token.clone());
                     access_token
                 })
@@ -110,6 +132,15 @@ impl JMAP {
             } else {
                 Ok(None)
             }
+        } else if let Some(session) = self.authenticate_trusted_header(req, remote_ip).await {
+            // Enforce authenticated rate limit
+            Ok(Some((self.is_account_allowed(&session)?, session)))
+        } else if let Some(session) = self.authenticate_session_cookie(req, remote_ip).await? {
+            // Enforce authenticated rate limit
+            Ok(Some((self.is_account_allowed(&session)?, session)))
+        } else if let Some(session) = self.authenticate_client_cert(req, remote_ip).await {
+            // Enforce authenticated rate limit
+            Ok(Some((self.is_account_allowed(&session)?, session)))
         } else {
             // Enforce anonymous rate limit
             self.is_anonymous_allowed(&self.build_remote_addr(req, remote_ip))?;
@@ -118,10 +149,126 @@ impl JMAP {
         }
     }
 
-    pub fn cache_session(&self, session_id: String, access_token: &AccessToken) {
+    async fn authenticate_trusted_header(
+        &self,
+        req: &hyper::Request<hyper::body::Incoming>,
+        remote_ip: IpAddr,
+    ) -> Option<Arc<AccessToken>> {
+        let header = self.config.auth_trusted_header.as_ref()?;
+        let name = req
+            .headers()
+            .get(header)
+            .and_then(|h| h.to_str().ok())
+            .map(|h| h.trim())
+            .filter(|h| !h.is_empty())?;
+
+        // The header is only honored when the connection comes straight from a trusted proxy,
+        // otherwise any client could impersonate any user by setting it
+        if !self
+            .config
+            .auth_trusted_proxies
+            .iter()
+            .any(|proxy| proxy.matches(&remote_ip))
+        {
+            tracing::debug!(
+                context = "authenticate_headers",
+                event = "untrusted-proxy",
+                remote_ip = remote_ip.to_string(),
+                header = header,
+                "Ignoring trusted authentication header sent by an untrusted host."
+            );
+            return None;
+        }
+
+        let session_id = SessionKey::Proxy(name.to_string());
+        if let Some(session) = self.sessions.get_with_ttl(&session_id) {
+            return self.get_cached_access_token(session.account_id).await;
+        }
+
+        match self.directory.query(QueryBy::Name(name), true).await {
+            Ok(Some(principal)) => {
+                let access_token = self
+                    .update_access_token(AccessToken::new(principal))
+                    .await?;
+                let access_token = Arc::new(access_token);
+                self.cache_session(
+                    session_id,
+                    &access_token,
+                    self.locate_session(&access_token, &self.build_remote_addr(req, remote_ip)),
+                );
+                self.cache_access_token(access_token.clone());
+                Some(access_token)
+            }
+            Ok(None) => {
+                tracing::debug!(
+                    context = "authenticate_headers",
+                    account = name,
+                    "No principal found for trusted authentication header."
+                );
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    async fn authenticate_client_cert(
+        &self,
+        req: &hyper::Request<hyper::body::Incoming>,
+        remote_ip: IpAddr,
+    ) -> Option<Arc<AccessToken>> {
+        if !self.config.auth_client_cert {
+            return None;
+        }
+
+        // Map the verified client certificate to a principal, sessions cached here
+        // are only looked up after the TLS layer verified a certificate for the name
+        let client_cert = req.extensions().get::<Arc<ClientCertificate>>()?;
+        for name in client_cert.names() {
+            let session_id = SessionKey::ClientCert(name.to_string());
+            if let Some(session) = self.sessions.get_with_ttl(&session_id) {
+                return self.get_cached_access_token(session.account_id).await;
+            }
+
+            match self.directory.query(QueryBy::Name(name), true).await {
+                Ok(Some(principal)) => {
+                    let access_token = self
+                        .update_access_token(AccessToken::new(principal))
+                        .await?;
+                    let access_token = Arc::new(access_token);
+                    self.cache_session(
+                        session_id,
+                        &access_token,
+                        self.locate_session(&access_token, &self.build_remote_addr(req, remote_ip)),
+                    );
+                    self.cache_access_token(access_token.clone());
+                    return Some(access_token);
+                }
+                Ok(None) => (),
+                Err(_) => return None,
+            }
+        }
+
+        tracing::debug!(
+            context = "authenticate_headers",
+            subject = ?client_cert.subject,
+            "No principal found for client certificate."
+        );
+
+        None
+    }
+
+    pub fn cache_session(
+        &self,
+        session_id: SessionKey,
+        access_token: &AccessToken,
+        location: Option<Arc<GeoLocation>>,
+    ) {
         self.sessions.insert_with_ttl(
             session_id,
-            access_token.primary_id(),
+            CachedSession {
+                account_id: access_token.primary_id(),
+                location,
+            },
             Instant::now() + self.config.session_cache_ttl,
         );
     }
@@ -135,7 +282,11 @@ impl JMAP {
     }
 
     pub async fn get_cached_access_token(&self, primary_id: u32) -> Option<Arc<AccessToken>> {
-        if let Some(access_token) = self.access_tokens.get_with_ttl(&primary_id) {
+        if let Some(access_token) = self
+            .access_tokens
+            .get_with_ttl(&primary_id)
+            .filter(|access_token| self.is_access_token_current(access_token))
+        {
             access_token.into()
         } else {
             // Refresh ACL token
@@ -152,7 +303,7 @@ impl JMAP {
         req: &hyper::Request<hyper::body::Incoming>,
         remote_ip: IpAddr,
     ) -> RemoteAddress {
-        if !self.config.rate_use_forwarded {
+        let addr = if !self.config.rate_use_forwarded {
             RemoteAddress::IpAddress(remote_ip)
         } else if let Some(forwarded_for) = req
             .headers()
@@ -163,6 +314,16 @@ impl JMAP {
         } else {
             tracing::debug!("Warning: No remote address found in request, using loopback.");
             RemoteAddress::IpAddress(Ipv4Addr::new(127, 0, 0, 1).into())
+        };
+
+        if self.config.rate_use_fingerprint {
+            addr.with_fingerprint(
+                req.headers()
+                    .get(header::USER_AGENT)
+                    .and_then(|h| h.to_str().ok()),
+            )
+        } else {
+            addr
         }
     }
 
@@ -171,35 +332,109 @@ impl JMAP {
         username: &str,
         secret: &str,
         remote_addr: &RemoteAddress,
-    ) -> Option<AccessToken> {
+    ) -> AuthResult<AccessToken> {
         match self
             .directory
-            .query(
-                QueryBy::Credentials(&Credentials::Plain {
+            .authenticate(
+                &Credentials::Plain {
                     username: username.to_string(),
                     secret: secret.to_string(),
-                }),
+                },
                 true,
             )
             .await
         {
-            Ok(Some(principal)) => AccessToken::new(principal).into(),
-            Ok(None) => {
+            Ok(QueryResult::Found(principal)) => AuthResult::Success(AccessToken::new(principal)),
+            Ok(QueryResult::Disabled) => {
+                // The credentials were valid, do not penalize the client
+                tracing::debug!(
+                    context = "auth",
+                    event = "disabled",
+                    account = username,
+                    "Authentication attempt for disabled account"
+                );
+                AuthResult::Failure
+            }
+            Ok(QueryResult::Locked) => {
                 let _ = self.is_auth_allowed_hard(remote_addr);
-                None
+                AuthResult::Locked
             }
-            Err(_) => None,
+            Ok(QueryResult::InvalidCredentials | QueryResult::NotFound) => {
+                let _ = self.is_auth_allowed_hard(remote_addr);
+                AuthResult::Failure
+            }
+            Err(_) => AuthResult::Failure,
         }
     }
 
     pub async fn get_access_token(&self, account_id: u32) -> Option<AccessToken> {
-        // Create access token
-        self.update_access_token(AccessToken::new(
-            self.directory
+        // Reuse recently fetched principals
+        let principal = if let Some(principal) = self.principals.get_with_ttl(&account_id) {
+            principal
+        } else {
+            let principal = self
+                .directory
                 .query(QueryBy::Id(account_id), true)
                 .await
-                .ok()??,
-        ))
-        .await
+                .ok()??;
+            self.principals.insert_with_ttl(
+                account_id,
+                principal,
+                Instant::now() + self.config.principal_cache_ttl,
+            )
+        };
+
+        // Create access token
+        self.update_access_token(AccessToken::new(principal)).await
+    }
+
+    pub async fn invalidate_principal(&self, account_id: u32) {
+        self.evict_principal(account_id);
+        self.publish_invalidation(InvalidationEvent::Principal {
+            account_id: Some(account_id),
+        })
+        .await;
+    }
+
+    pub async fn invalidate_all_principals(&self) {
+        self.evict_all_principals();
+        self.publish_invalidation(InvalidationEvent::Principal { account_id: None })
+            .await;
+    }
+
+    pub fn evict_principal(&self, account_id: u32) {
+        self.principals.remove(&account_id);
+        self.access_tokens.remove(&account_id);
+        self.sessions
+            .retain(|_, item| item.item().account_id != account_id);
+        self.locations.remove(&account_id);
+        self.directory.invalidate_member_of(account_id);
+        self.directory.invalidate_principal(account_id);
+    }
+
+    pub fn evict_all_principals(&self) {
+        // Cached access tokens are rebuilt lazily once their version is outdated,
+        // sessions are dropped as the names they were cached under may have changed
+        self.principals.clear();
+        self.sessions.clear();
+        self.locations.clear();
+        self.access_token_version.fetch_add(1, Ordering::Relaxed);
+        self.directory.invalidate_all_member_of();
+        self.directory.invalidate_all_principals();
+    }
+
+    async fn publish_invalidation(&self, event: InvalidationEvent) {
+        if let Err(err) = self.store.publish_invalidation(event).await {
+            tracing::warn!(
+                context = "invalidation",
+                event = "error",
+                reason = ?err,
+                "Failed to publish cache invalidation event."
+            );
+        }
+    }
+
+    pub fn is_access_token_current(&self, access_token: &AccessToken) -> bool {
+        access_token.version == self.access_token_version.load(Ordering::Relaxed)
     }
 }
@@ -0,0 +1,381 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+//! Cookie based sessions for browser clients such as the web admin.
+//!
+//! The session cookie carries a token that is signed and encrypted exactly like
+//! an OAuth bearer token (bound to the account's secret and with an expiry) and
+//! is marked `HttpOnly`, `Secure` and `SameSite=Strict`. Because browsers attach
+//! cookies automatically, requests authenticated by cookie are exposed to CSRF:
+//! `SameSite=Strict` stops most cross-site requests on modern browsers, and in
+//! addition every state changing request (anything other than GET, HEAD or
+//! OPTIONS) must echo the CSRF token in the `X-CSRF-Token` header. The CSRF token
+//! is issued by the login endpoint both in the response body and in a readable
+//! (non `HttpOnly`) cookie, following the double-submit pattern. It is derived
+//! from the session token using a keyed hash, so an attacker able to plant a
+//! cookie cannot forge a matching pair.
+//!
+//! Session tokens embed the account's session version, which the logout endpoint
+//! increments. This revokes every cookie issued for the account, including copies
+//! of the token that outlive the browser cookie, and evicts the cached sessions
+//! on all nodes through the principal invalidation event.
+
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use directory::QueryBy;
+use hyper::{
+    header::{self, HeaderValue},
+    Method, StatusCode,
+};
+use jmap_proto::{
+    error::request::RequestError,
+    types::{collection::Collection, property::Property},
+};
+use store::{
+    blake3,
+    write::{BatchBuilder, ValueClass},
+    ValueKey,
+};
+use subtle::ConstantTimeEq;
+use utils::map::ttl_dashmap::TtlMap;
+
+use crate::{
+    api::{http::ToHttpResponse, HttpRequest, HttpResponse, JsonResponse},
+    JMAP,
+};
+
+use super::{
+    oauth::FormData, rate_limit::RemoteAddress, AccessToken, AuthResult, CachedSession, SessionKey,
+};
+
+pub const SESSION_COOKIE: &str = "stalwart_session";
+pub const CSRF_COOKIE: &str = "stalwart_csrf";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+const SESSION_GRANT_TYPE: &str = "session";
+const MAX_POST_LEN: usize = 2048;
+
+#[derive(Debug, serde::Serialize)]
+struct SessionResponse {
+    #[serde(rename = "csrfToken")]
+    csrf_token: String,
+    #[serde(rename = "expiresIn")]
+    expires_in: u64,
+}
+
+impl JMAP {
+    // Login endpoint
+    pub async fn handle_session_login(
+        &self,
+        req: &mut HttpRequest,
+        remote_addr: &RemoteAddress,
+    ) -> HttpResponse {
+        let params = match FormData::from_request(req, MAX_POST_LEN).await {
+            Ok(params) => params,
+            Err(err) => return err,
+        };
+
+        let access_token =
+            if let (Some(login), Some(password)) = (params.get("email"), params.get("password")) {
+                match self
+                    .authenticate_plain(&login.trim().to_lowercase(), password, remote_addr)
+                    .await
+                {
+                    AuthResult::Success(access_token) => access_token,
+                    AuthResult::Failure => {
+                        return RequestError::unauthorized().into_http_response();
+                    }
+                    AuthResult::Locked => {
+                        return RequestError::blank(
+                            StatusCode::FORBIDDEN.as_u16(),
+                            "Account locked",
+                            "Too many authentication failures, try again later.",
+                        )
+                        .into_http_response();
+                    }
+                }
+            } else {
+                return RequestError::blank(
+                    StatusCode::BAD_REQUEST.as_u16(),
+                    "Invalid parameters",
+                    "Missing email or password.",
+                )
+                .into_http_response();
+            };
+
+        // Issue session token
+        let session_version = match self.session_version(access_token.primary_id()).await {
+            Ok(session_version) => session_version,
+            Err(_) => {
+                return RequestError::internal_server_error().into_http_response();
+            }
+        };
+        let password_hash = self
+            .directory
+            .query(QueryBy::Id(access_token.primary_id()), false)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|principal| principal.secrets.into_iter().next());
+        let session_token = match password_hash.and_then(|password_hash| {
+            self.encode_access_token(
+                SESSION_GRANT_TYPE,
+                access_token.primary_id(),
+                &password_hash,
+                &format!("{SESSION_GRANT_TYPE}:{session_version}"),
+                self.config.session_cookie_expiry,
+            )
+            .ok()
+        }) {
+            Some(session_token) => session_token,
+            None => {
+                return RequestError::internal_server_error().into_http_response();
+            }
+        };
+        let csrf_token = self.csrf_token(&session_token);
+
+        // Cache session
+        let access_token = Arc::new(access_token);
+        self.cache_cookie_session(
+            &session_token,
+            &access_token,
+            remote_addr,
+            self.config.session_cookie_expiry,
+        );
+        self.cache_access_token(access_token);
+
+        let max_age = self.config.session_cookie_expiry;
+        let mut response = JsonResponse::new(SessionResponse {
+            csrf_token: csrf_token.clone(),
+            expires_in: max_age,
+        })
+        .into_http_response();
+        let headers = response.headers_mut();
+        for cookie in [
+            format!(
+                "{SESSION_COOKIE}={session_token}; Path=/; Max-Age={max_age}; HttpOnly; Secure; SameSite=Strict"
+            ),
+            format!("{CSRF_COOKIE}={csrf_token}; Path=/; Max-Age={max_age}; Secure; SameSite=Strict"),
+        ] {
+            if let Ok(value) = HeaderValue::from_str(&cookie) {
+                headers.append(header::SET_COOKIE, value);
+            }
+        }
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+        response
+    }
+
+    // Logout endpoint
+    pub async fn handle_session_logout(&self, req: &HttpRequest) -> HttpResponse {
+        if let Some(session_token) = cookie_value(req, SESSION_COOKIE) {
+            self.sessions
+                .remove(&SessionKey::Cookie(session_token.to_string()));
+
+            // Revoke all sessions of the account
+            if let Ok((account_id, _, _)) = self
+                .validate_access_token(SESSION_GRANT_TYPE, session_token)
+                .await
+            {
+                let mut batch = BatchBuilder::new();
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(Collection::Principal)
+                    .update_document(0)
+                    .add(ValueClass::Property(Property::Secret.into()), 1);
+                if let Err(err) = self.store.write(batch.build()).await {
+                    tracing::warn!(
+                        context = "session_logout",
+                        event = "error",
+                        account_id = account_id,
+                        reason = ?err,
+                        "Failed to revoke session."
+                    );
+                    return RequestError::internal_server_error().into_http_response();
+                }
+                self.invalidate_principal(account_id).await;
+            }
+        }
+
+        let mut response = ().into_http_response();
+        let headers = response.headers_mut();
+        for cookie in [
+            format!("{SESSION_COOKIE}=; Path=/; Max-Age=0; HttpOnly; Secure; SameSite=Strict"),
+            format!("{CSRF_COOKIE}=; Path=/; Max-Age=0; Secure; SameSite=Strict"),
+        ] {
+            if let Ok(value) = HeaderValue::from_str(&cookie) {
+                headers.append(header::SET_COOKIE, value);
+            }
+        }
+
+        response
+    }
+
+    pub(crate) async fn authenticate_session_cookie(
+        &self,
+        req: &HttpRequest,
+        remote_ip: IpAddr,
+    ) -> Result<Option<Arc<AccessToken>>, RequestError> {
+        if !self.config.session_cookie_enable {
+            return Ok(None);
+        }
+        let session_token = if let Some(session_token) = cookie_value(req, SESSION_COOKIE) {
+            session_token
+        } else {
+            return Ok(None);
+        };
+
+        // Double-submit CSRF check for state changing requests
+        if !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+            let csrf_token = self.csrf_token(session_token);
+            let is_valid = req
+                .headers()
+                .get(CSRF_HEADER)
+                .and_then(|h| h.to_str().ok())
+                .map_or(false, |header| {
+                    header.as_bytes().ct_eq(csrf_token.as_bytes()).into()
+                })
+                && cookie_value(req, CSRF_COOKIE).map_or(false, |cookie| {
+                    cookie.as_bytes().ct_eq(csrf_token.as_bytes()).into()
+                });
+            if !is_valid {
+                tracing::debug!(
+                    context = "authenticate_headers",
+                    event = "csrf-failed",
+                    "Missing or invalid CSRF token."
+                );
+                return Err(RequestError::blank(
+                    StatusCode::FORBIDDEN.as_u16(),
+                    "Invalid CSRF token",
+                    "A valid CSRF token is required for this request.",
+                ));
+            }
+        }
+
+        if let Some(session) = self
+            .sessions
+            .get_with_ttl(&SessionKey::Cookie(session_token.to_string()))
+        {
+            return Ok(self.get_cached_access_token(session.account_id).await);
+        }
+
+        // Enforce anonymous rate limit for session validation
+        let addr = self.build_remote_addr(req, remote_ip);
+        self.is_anonymous_allowed(&addr)?;
+
+        match self
+            .validate_access_token(SESSION_GRANT_TYPE, session_token)
+            .await
+        {
+            Ok((account_id, client_id, expires_in)) => {
+                let session_version = client_id
+                    .strip_prefix(SESSION_GRANT_TYPE)
+                    .and_then(|version| version.strip_prefix(':'))
+                    .and_then(|version| version.parse::<i64>().ok());
+                match self.session_version(account_id).await {
+                    Ok(current_version) if Some(current_version) == session_version => {}
+                    Ok(_) => {
+                        tracing::debug!(
+                            context = "authenticate_headers",
+                            event = "session-revoked",
+                            account_id = account_id,
+                            "Session cookie has been revoked."
+                        );
+                        return Ok(None);
+                    }
+                    Err(_) => return Err(RequestError::internal_server_error()),
+                }
+
+                Ok(self.get_access_token(account_id).await.map(|access_token| {
+                    let access_token = Arc::new(access_token);
+                    self.cache_cookie_session(session_token, &access_token, &addr, expires_in);
+                    self.cache_access_token(access_token.clone());
+                    access_token
+                }))
+            }
+            Err(err) => {
+                tracing::debug!(
+                    context = "authenticate_headers",
+                    err = err,
+                    "Failed to validate session cookie."
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    async fn session_version(&self, account_id: u32) -> store::Result<i64> {
+        self.store
+            .get_counter(ValueKey {
+                account_id,
+                collection: Collection::Principal.into(),
+                document_id: 0,
+                class: ValueClass::Property(Property::Secret.into()),
+            })
+            .await
+    }
+
+    // Cached sessions must not outlive the cookie
+    fn cache_cookie_session(
+        &self,
+        session_token: &str,
+        access_token: &AccessToken,
+        addr: &RemoteAddress,
+        expires_in: u64,
+    ) {
+        self.sessions.insert_with_ttl(
+            SessionKey::Cookie(session_token.to_string()),
+            CachedSession {
+                account_id: access_token.primary_id(),
+                location: self.locate_session(access_token, addr),
+            },
+            Instant::now()
+                + std::cmp::min(
+                    self.config.session_cache_ttl,
+                    Duration::from_secs(expires_in),
+                ),
+        );
+    }
+
+    fn csrf_token(&self, session_token: &str) -> String {
+        let key = blake3::derive_key("stalwart session csrf", self.config.oauth_key.as_bytes());
+        blake3::keyed_hash(&key, session_token.as_bytes())
+            .to_hex()
+            .to_string()
+    }
+}
+
+pub fn cookie_value<'x>(req: &'x HttpRequest, name: &str) -> Option<&'x str> {
+    req.headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value.trim()))
+        .filter(|value| !value.is_empty())
+}
@@ -0,0 +1,176 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, sync::Arc, time::Instant};
+
+use utils::map::ttl_dashmap::TtlMap;
+
+use super::{rate_limit::RemoteAddress, AccessToken};
+use crate::JMAP;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoLocation {
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+}
+
+pub struct GeoLookup {
+    #[cfg(feature = "geoip")]
+    city: Option<maxminddb::Reader<maxminddb::Mmap>>,
+    #[cfg(feature = "geoip")]
+    asn: Option<maxminddb::Reader<maxminddb::Mmap>>,
+}
+
+impl GeoLookup {
+    pub fn parse(config: &utils::config::Config) -> Result<Option<Self>, String> {
+        let city_db = config.value("jmap.geoip.city");
+        let asn_db = config.value("jmap.geoip.asn");
+        if city_db.is_none() && asn_db.is_none() {
+            return Ok(None);
+        }
+
+        #[cfg(feature = "geoip")]
+        {
+            let open = |path: Option<&str>| {
+                path.map(|path| {
+                    maxminddb::Reader::open_mmap(path)
+                        .map_err(|err| format!("Failed to open GeoIP database {path:?}: {err}"))
+                })
+                .transpose()
+            };
+
+            Ok(Some(GeoLookup {
+                city: open(city_db)?,
+                asn: open(asn_db)?,
+            }))
+        }
+
+        #[cfg(not(feature = "geoip"))]
+        {
+            Err("GeoIP databases are configured but this server was built without the geoip feature."
+                .to_string())
+        }
+    }
+
+    #[cfg(feature = "geoip")]
+    pub fn lookup(&self, ip: IpAddr) -> Option<GeoLocation> {
+        use maxminddb::geoip2;
+
+        let mut location = GeoLocation::default();
+
+        if let Some(city) = self
+            .city
+            .as_ref()
+            .and_then(|db| db.lookup::<geoip2::City>(ip).ok())
+        {
+            location.country = city
+                .country
+                .and_then(|country| country.iso_code)
+                .map(|code| code.to_string());
+            location.city = city
+                .city
+                .and_then(|city| city.names)
+                .and_then(|names| names.get("en").map(|name| name.to_string()));
+        }
+
+        if let Some(asn) = self
+            .asn
+            .as_ref()
+            .and_then(|db| db.lookup::<geoip2::Asn>(ip).ok())
+        {
+            location.asn = asn.autonomous_system_number;
+            location.as_org = asn
+                .autonomous_system_organization
+                .map(|org| org.to_string());
+        }
+
+        if location != GeoLocation::default() {
+            Some(location)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    pub fn lookup(&self, _ip: IpAddr) -> Option<GeoLocation> {
+        None
+    }
+}
+
+impl JMAP {
+    // Locates the client of a freshly authenticated session
+    pub fn locate_session(
+        &self,
+        access_token: &AccessToken,
+        addr: &RemoteAddress,
+    ) -> Option<Arc<GeoLocation>> {
+        let location = self.geoip.as_ref()?.lookup(addr.ip()?)?;
+        Some(self.track_location(access_token, addr, location))
+    }
+
+    // Records the location of a session, logging an event when it differs
+    // from the last location seen for the account
+    pub fn track_location(
+        &self,
+        access_token: &AccessToken,
+        addr: &RemoteAddress,
+        location: GeoLocation,
+    ) -> Arc<GeoLocation> {
+        let location = Arc::new(location);
+        if let Some(previous) = self.locations.get_with_ttl(&access_token.primary_id()) {
+            if previous.country != location.country || previous.asn != location.asn {
+                tracing::warn!(
+                    context = "auth",
+                    event = "new-location",
+                    account = access_token.name,
+                    remote = ?addr,
+                    country = location.country,
+                    asn = location.asn,
+                    previous_country = previous.country,
+                    previous_asn = previous.asn,
+                    "Account authenticated from a new location."
+                );
+            }
+        }
+
+        tracing::debug!(
+            context = "auth",
+            event = "located",
+            account = access_token.name,
+            remote = ?addr,
+            country = location.country,
+            city = location.city,
+            asn = location.asn,
+            as_org = location.as_org,
+            "Session authenticated."
+        );
+
+        self.locations.insert_with_ttl(
+            access_token.primary_id(),
+            location,
+            Instant::now() + self.config.session_cache_ttl,
+        )
+    }
+}
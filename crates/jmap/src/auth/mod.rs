@@ -24,6 +24,7 @@
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
+    sync::Arc,
 };
 
 use aes_gcm_siv::{
@@ -39,8 +40,12 @@ use jmap_proto::{
 use store::blake3;
 use utils::map::bitmap::Bitmap;
 
+use self::geoip::GeoLocation;
+
 pub mod acl;
 pub mod authenticate;
+pub mod cookie;
+pub mod geoip;
 pub mod oauth;
 pub mod rate_limit;
 
@@ -53,6 +58,48 @@ pub struct AccessToken {
     pub description: Option<String>,
     pub quota: u32,
     pub is_superuser: bool,
+    pub version: u64,
+    effective_access: Vec<(u32, Bitmap<Collection>)>,
+}
+
+// Cached sessions are namespaced by the method that authenticated them, so that
+// a session created by one method can never be looked up by another.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SessionKey {
+    Token(String),
+    ClientCert(String),
+    Proxy(String),
+    Cookie(String),
+}
+
+// Access tokens are shared by all sessions of an account, per session data
+// such as the client location is kept on the cached session instead.
+#[derive(Debug, Clone)]
+pub struct CachedSession {
+    pub account_id: u32,
+    pub location: Option<Arc<GeoLocation>>,
+}
+
+#[derive(Debug)]
+pub enum AuthResult<T> {
+    Success(T),
+    Failure,
+    Locked,
+}
+
+impl<T> AuthResult<T> {
+    pub fn into_option(self) -> Option<T> {
+        match self {
+            AuthResult::Success(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl<T> From<Option<T>> for AuthResult<T> {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(AuthResult::Failure, AuthResult::Success)
+    }
 }
 
 impl AccessToken {
@@ -65,11 +112,52 @@ impl AccessToken {
             description: principal.description,
             quota: principal.quota,
             is_superuser: principal.typ == Type::Superuser,
+            version: 0,
+            effective_access: Vec::new(),
         }
+        .with_effective_access()
     }
 
     pub fn with_access_to(self, access_to: Vec<(u32, Bitmap<Collection>)>) -> Self {
-        Self { access_to, ..self }
+        Self { access_to, ..self }.with_effective_access()
+    }
+
+    pub fn with_version(self, version: u64) -> Self {
+        Self { version, ..self }
+    }
+
+    /// Flattens memberships and shares into a sorted list of per-account
+    /// collection bitmaps, so that access checks do not need to walk them.
+    pub fn with_effective_access(mut self) -> Self {
+        let mut effective_access: Vec<(u32, Bitmap<Collection>)> =
+            Vec::with_capacity(1 + self.member_of.len() + self.access_to.len());
+        for &account_id in [self.primary_id].iter().chain(self.member_of.iter()) {
+            effective_access.push((account_id, Bitmap::all()));
+        }
+        for (account_id, collections) in &self.access_to {
+            effective_access.push((*account_id, *collections));
+        }
+        effective_access.sort_unstable_by_key(|(account_id, _)| *account_id);
+        effective_access.dedup_by(|(account_id, collections), (prev_id, prev_collections)| {
+            if account_id == prev_id {
+                prev_collections.union(collections);
+                true
+            } else {
+                false
+            }
+        });
+        self.effective_access = effective_access;
+        self
+    }
+
+    pub fn effective_access(&self, account_id: u32) -> Bitmap<Collection> {
+        if self.is_superuser {
+            Bitmap::all()
+        } else {
+            self.effective_access
+                .binary_search_by_key(&account_id, |(account_id, _)| *account_id)
+                .map_or_else(|_| Bitmap::new(), |idx| self.effective_access[idx].1)
+        }
     }
 
     pub fn state(&self) -> u32 {
@@ -120,11 +208,8 @@ impl AccessToken {
     }
 
     pub fn has_access(&self, to_account_id: u32, to_collection: impl Into<Collection>) -> bool {
-        let to_collection = to_collection.into();
-        self.is_member(to_account_id)
-            || self.access_to.iter().any(|(id, collections)| {
-                *id == to_account_id && collections.contains(to_collection)
-            })
+        self.effective_access(to_account_id)
+            .contains(to_collection.into())
     }
 
     pub fn assert_has_access(
@@ -41,6 +41,7 @@ use crate::{
             OAUTH_HTML_LOGIN_SUCCESS, STATUS_AUTHORIZED,
         },
         rate_limit::RemoteAddress,
+        AuthResult,
     },
     JMAP,
 };
@@ -177,7 +178,9 @@ impl JMAP {
             {
                 if let (Some(email), Some(password)) = (fields.get("email"), fields.get("password"))
                 {
-                    if let Some(id) = self.authenticate_plain(email, password, remote_addr).await {
+                    if let AuthResult::Success(id) =
+                        self.authenticate_plain(email, password, remote_addr).await
+                    {
                         oauth
                             .account_id
                             .store(id.primary_id(), atomic::Ordering::Relaxed);
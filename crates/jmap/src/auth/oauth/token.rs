@@ -217,7 +217,7 @@ impl JMAP {
         })
     }
 
-    fn encode_access_token(
+    pub(crate) fn encode_access_token(
         &self,
         grant_type: &str,
         account_id: u32,
@@ -37,7 +37,7 @@ use utils::map::ttl_dashmap::TtlMap;
 
 use crate::{
     api::{http::ToHttpResponse, HtmlResponse, HttpRequest, HttpResponse},
-    auth::rate_limit::RemoteAddress,
+    auth::{rate_limit::RemoteAddress, AuthResult},
     JMAP,
 };
 
@@ -137,7 +137,8 @@ impl JMAP {
 
         // Authenticate user
         if let (Some(email), Some(password)) = (params.get("email"), params.get("password")) {
-            if let Some(access_token) = self.authenticate_plain(email, password, remote_addr).await
+            if let AuthResult::Success(access_token) =
+                self.authenticate_plain(email, password, remote_addr).await
             {
                 // Generate client code
                 let client_code = thread_rng()
@@ -21,7 +21,11 @@
  * for more details.
 */
 
-use std::{net::IpAddr, sync::Arc};
+use std::{
+    hash::{Hash, Hasher},
+    net::IpAddr,
+    sync::Arc,
+};
 
 use jmap_proto::error::request::{RequestError, RequestLimitError};
 use store::parking_lot::Mutex;
@@ -35,6 +39,9 @@ use super::AccessToken;
 pub enum RemoteAddress {
     IpAddress(IpAddr),
     IpAddressFwd(String),
+    Fingerprint(u64),
+    // Rate limited both by address and by client fingerprint
+    Fingerprinted(Box<RemoteAddress>, u64),
 }
 
 pub struct AuthenticatedLimiter {
@@ -112,12 +119,16 @@ impl JMAP {
     }
 
     pub fn is_anonymous_allowed(&self, addr: &RemoteAddress) -> Result<(), RequestError> {
-        if self
-            .get_anonymous_limiter(addr)
-            .lock()
-            .request_limiter
-            .is_allowed()
-        {
+        let mut is_allowed = true;
+        for addr in addr.limiter_keys() {
+            is_allowed &= self
+                .get_anonymous_limiter(&addr)
+                .lock()
+                .request_limiter
+                .is_allowed();
+        }
+
+        if is_allowed {
             Ok(())
         } else {
             Err(RequestError::too_many_requests())
@@ -140,21 +151,29 @@ impl JMAP {
     }
 
     pub fn is_auth_allowed_soft(&self, addr: &RemoteAddress) -> Result<(), RequestError> {
-        match self.rate_limit_unauth.get(addr) {
-            Some(limiter) if !limiter.lock().auth_limiter.is_allowed_soft() => {
-                Err(RequestError::too_many_auth_attempts())
+        for addr in addr.limiter_keys() {
+            match self.rate_limit_unauth.get(&addr) {
+                Some(limiter) if !limiter.lock().auth_limiter.is_allowed_soft() => {
+                    return Err(RequestError::too_many_auth_attempts());
+                }
+                _ => (),
             }
-            _ => Ok(()),
         }
+
+        Ok(())
     }
 
     pub fn is_auth_allowed_hard(&self, addr: &RemoteAddress) -> Result<(), RequestError> {
-        if self
-            .get_anonymous_limiter(addr)
-            .lock()
-            .auth_limiter
-            .is_allowed()
-        {
+        let mut is_allowed = true;
+        for addr in addr.limiter_keys() {
+            is_allowed &= self
+                .get_anonymous_limiter(&addr)
+                .lock()
+                .auth_limiter
+                .is_allowed();
+        }
+
+        if is_allowed {
             Ok(())
         } else {
             Err(RequestError::too_many_auth_attempts())
@@ -162,6 +181,44 @@ impl JMAP {
     }
 }
 
+impl RemoteAddress {
+    /// Adds a limiter shared by the clients with the same user agent within the network
+    /// of this address. Clients without a user agent are only limited by address.
+    pub fn with_fingerprint(self, user_agent: Option<&str>) -> Self {
+        let user_agent = match user_agent {
+            Some(user_agent) if !user_agent.is_empty() => user_agent,
+            _ => return self,
+        };
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        user_agent.hash(&mut hasher);
+        match self.ip() {
+            Some(IpAddr::V4(ip)) => (u32::from(ip) & 0xffff_ff00).hash(&mut hasher),
+            Some(IpAddr::V6(ip)) => (u128::from(ip) >> 72).hash(&mut hasher),
+            None => self.hash(&mut hasher),
+        }
+        RemoteAddress::Fingerprinted(Box::new(self), hasher.finish())
+    }
+
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            RemoteAddress::IpAddress(ip) => Some(*ip),
+            RemoteAddress::IpAddressFwd(ip) => ip.trim().parse().ok(),
+            RemoteAddress::Fingerprinted(addr, _) => addr.ip(),
+            RemoteAddress::Fingerprint(_) => None,
+        }
+    }
+
+    pub fn limiter_keys(&self) -> Vec<RemoteAddress> {
+        match self {
+            RemoteAddress::Fingerprinted(addr, fingerprint) => {
+                vec![(**addr).clone(), RemoteAddress::Fingerprint(*fingerprint)]
+            }
+            addr => vec![addr.clone()],
+        }
+    }
+}
+
 impl AuthenticatedLimiter {
     pub fn is_active(&self) -> bool {
         self.request_limiter.is_active()
@@ -30,10 +30,7 @@ use jmap_proto::{
     types::blob::BlobId,
 };
 
-use store::{
-    write::{now, BatchBuilder, BlobOp},
-    BlobClass, Serialize,
-};
+use store::{write::now, BlobClass};
 use utils::map::vec_map::VecMap;
 
 use crate::{auth::AccessToken, JMAP};
@@ -54,34 +51,43 @@ impl JMAP {
 
         for blob_id in request.blob_ids {
             if self.has_access_blob(&blob_id, access_token).await? {
-                let mut batch = BatchBuilder::new();
-                let until = now() + self.config.upload_tmp_ttl;
-                batch.with_account_id(account_id).set(
-                    BlobOp::Reserve {
-                        until,
-                        hash: blob_id.hash.clone(),
-                    },
-                    0u32.serialize(),
-                );
-                self.write_batch(batch).await?;
-                let dest_blob_id = BlobId {
-                    hash: blob_id.hash.clone(),
-                    class: BlobClass::Reserved {
-                        account_id,
-                        expires: until,
-                    },
-                    section: blob_id.section.clone(),
+                let class = BlobClass::Reserved {
+                    account_id,
+                    expires: now() + self.config.upload_tmp_ttl,
                 };
+                match self
+                    .blob_store
+                    .copy(&self.store, &blob_id.hash, class.clone())
+                    .await
+                {
+                    Ok(()) => {
+                        let dest_blob_id = BlobId {
+                            hash: blob_id.hash.clone(),
+                            class,
+                            section: blob_id.section.clone(),
+                        };
 
-                response.copied.append(blob_id, dest_blob_id);
-            } else {
-                response.not_copied.append(
-                    blob_id,
-                    SetError::new(SetErrorType::BlobNotFound).with_description(
-                        "blobId does not exist or not enough permissions to access it.",
-                    ),
-                );
+                        response.copied.append(blob_id, dest_blob_id);
+                        continue;
+                    }
+                    Err(store::Error::NotFound(_)) => (),
+                    Err(err) => {
+                        tracing::error!(
+                            event = "error",
+                            context = "blob_copy",
+                            error = ?err,
+                            "Failed to copy blob.");
+                        return Err(MethodError::ServerPartialFail);
+                    }
+                }
             }
+
+            response.not_copied.append(
+                blob_id,
+                SetError::new(SetErrorType::BlobNotFound).with_description(
+                    "blobId does not exist or not enough permissions to access it.",
+                ),
+            );
         }
 
         Ok(response)
@@ -25,7 +25,7 @@ use std::{borrow::Cow, collections::BTreeSet, fmt::Display, io::Cursor};
 
 use crate::{
     api::{http::ToHttpResponse, HtmlResponse, HttpRequest, HttpResponse},
-    auth::{oauth::FormData, rate_limit::RemoteAddress},
+    auth::{oauth::FormData, rate_limit::RemoteAddress, AuthResult},
     JMAP,
 };
 use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
@@ -686,10 +686,15 @@ impl JMAP {
             }
 
             // Authenticate
-            let token = self
-                .authenticate_plain(email, password, remote_addr)
-                .await
-                .ok_or_else(|| Cow::from("Invalid login or password"))?;
+            let token = match self.authenticate_plain(email, password, remote_addr).await {
+                AuthResult::Success(token) => token,
+                AuthResult::Failure => return Err(Cow::from("Invalid login or password")),
+                AuthResult::Locked => {
+                    return Err(Cow::from(
+                        "Account locked due to too many failed attempts, try again later",
+                    ))
+                }
+            };
             if encryption != "disable" {
                 let (method, certs) =
                     try_parse_certs(certificate.unwrap_or_default()).map_err(Cow::from)?;
@@ -21,17 +21,23 @@
  * for more details.
 */
 
-use std::{collections::hash_map::RandomState, fmt::Display, sync::Arc, time::Duration};
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Display,
+    sync::{atomic::AtomicU64, Arc},
+    time::Duration,
+};
 
 use ::sieve::{Compiler, Runtime};
 use api::session::BaseCapabilities;
 use auth::{
+    geoip::{GeoLocation, GeoLookup},
     oauth::OAuthCode,
     rate_limit::{AnonymousLimiter, AuthenticatedLimiter, RemoteAddress},
-    AccessToken,
+    AccessToken, CachedSession, SessionKey,
 };
 use dashmap::DashMap;
-use directory::{Directories, Directory, QueryBy};
+use directory::{Directories, Directory, Principal, QueryBy};
 use jmap_proto::{
     error::method::MethodError,
     method::{
@@ -45,9 +51,10 @@ use nlp::language::Language;
 use services::{
     delivery::spawn_delivery_manager,
     housekeeper::{self, init_housekeeper, spawn_housekeeper},
+    invalidation::spawn_invalidation_listener,
     state::{self, init_state_manager, spawn_state_manager},
 };
-use smtp::core::SMTP;
+use smtp::{config::IpAddrMask, core::SMTP};
 use store::{
     fts::FtsFilter,
     parking_lot::Mutex,
@@ -91,12 +98,16 @@ pub struct JMAP {
     pub config: Config,
     pub directory: Arc<Directory>,
 
-    pub sessions: TtlDashMap<String, u32>,
+    pub sessions: TtlDashMap<SessionKey, CachedSession>,
     pub access_tokens: TtlDashMap<u32, Arc<AccessToken>>,
+    pub principals: TtlDashMap<u32, Principal<u32>>,
+    pub access_token_version: AtomicU64,
     pub snowflake_id: SnowflakeIdGenerator,
 
     pub rate_limit_auth: DashMap<u32, Arc<Mutex<AuthenticatedLimiter>>>,
     pub rate_limit_unauth: DashMap<RemoteAddress, Arc<Mutex<AnonymousLimiter>>>,
+    pub geoip: Option<GeoLookup>,
+    pub locations: TtlDashMap<u32, Arc<GeoLocation>>,
 
     pub oauth_codes: TtlDashMap<String, Arc<OAuthCode>>,
 
@@ -117,6 +128,7 @@ pub struct Config {
     pub request_max_size: usize,
     pub request_max_calls: usize,
     pub request_max_concurrent: u64,
+    pub request_timeout: Option<Duration>,
 
     pub get_max_objects: usize,
     pub set_max_objects: usize,
@@ -138,10 +150,17 @@ pub struct Config {
     pub sieve_max_scripts: usize,
 
     pub session_cache_ttl: Duration,
+    pub principal_cache_ttl: Duration,
     pub rate_authenticated: Rate,
     pub rate_authenticate_req: Rate,
     pub rate_anonymous: Rate,
     pub rate_use_forwarded: bool,
+    pub rate_use_fingerprint: bool,
+    pub auth_client_cert: bool,
+    pub auth_trusted_header: Option<hyper::header::HeaderName>,
+    pub auth_trusted_proxies: Vec<IpAddrMask>,
+    pub session_cookie_enable: bool,
+    pub session_cookie_expiry: u64,
 
     pub event_source_throttle: Duration,
     pub push_max_total: usize,
@@ -235,6 +254,11 @@ impl JMAP {
                 ))
                 .clone(),
             config: Config::new(config).failed("Invalid configuration file"),
+            geoip: GeoLookup::parse(config)?,
+            locations: TtlDashMap::with_capacity(
+                config.property("jmap.session.cache.size")?.unwrap_or(100),
+                shard_amount,
+            ),
             sessions: TtlDashMap::with_capacity(
                 config.property("jmap.session.cache.size")?.unwrap_or(100),
                 shard_amount,
@@ -243,6 +267,11 @@ impl JMAP {
                 config.property("jmap.session.cache.size")?.unwrap_or(100),
                 shard_amount,
             ),
+            principals: TtlDashMap::with_capacity(
+                config.property("jmap.principal.cache.size")?.unwrap_or(100),
+                shard_amount,
+            ),
+            access_token_version: AtomicU64::new(0),
             rate_limit_auth: DashMap::with_capacity_and_hasher_and_shard_amount(
                 config
                     .property("jmap.rate-limit.cache.size")?
@@ -422,6 +451,9 @@ impl JMAP {
         // Spawn housekeeper
         spawn_housekeeper(jmap_server.clone(), config, housekeeper_rx);
 
+        // Spawn cache invalidation listener
+        spawn_invalidation_listener(jmap_server.clone());
+
         Ok(jmap_server)
     }
 
@@ -519,6 +551,36 @@ impl JMAP {
         }
     }
 
+    pub async fn get_collection_properties<U>(
+        &self,
+        account_id: u32,
+        collection: Collection,
+        property: impl AsRef<Property>,
+    ) -> Result<Vec<(u32, U)>, MethodError>
+    where
+        U: Deserialize + 'static,
+    {
+        let property = property.as_ref();
+
+        match self
+            .store
+            .get_collection_values::<U>(account_id, collection.into(), property.into())
+            .await
+        {
+            Ok(value) => Ok(value),
+            Err(err) => {
+                tracing::error!(event = "error",
+                                context = "store",
+                                account_id = account_id,
+                                collection = ?collection,
+                                property = ?property,
+                                error = ?err,
+                                "Failed to retrieve collection properties");
+                Err(MethodError::ServerPartialFail)
+            }
+        }
+    }
+
     pub async fn get_document_ids(
         &self,
         account_id: u32,
@@ -773,6 +835,46 @@ impl JMAP {
                     );
                     MethodError::ServerUnavailable
                 }
+                store::Error::ReadOnly => {
+                    tracing::warn!(
+                        event = "error",
+                        context = "write_batch",
+                        "Failed to write batch, store is in read-only mode."
+                    );
+                    MethodError::ServerUnavailable
+                }
+                store::Error::StaleEpoch => {
+                    tracing::warn!(
+                        event = "error",
+                        context = "write_batch",
+                        "Failed to write batch, fencing epoch is stale."
+                    );
+                    MethodError::ServerUnavailable
+                }
+                store::Error::Timeout(_) | store::Error::Unavailable(_) => {
+                    tracing::warn!(
+                        event = "error",
+                        context = "write_batch",
+                        error = ?err,
+                        "Store temporarily unavailable, failed to write batch.");
+                    MethodError::ServerUnavailable
+                }
+                store::Error::NotFound(_) => {
+                    tracing::error!(
+                        event = "error",
+                        context = "write_batch",
+                        error = ?err,
+                        "Failed to write batch.");
+                    MethodError::ServerPartialFail
+                }
+                store::Error::PartialCommit { .. } => {
+                    tracing::error!(
+                        event = "error",
+                        context = "write_batch",
+                        error = ?err,
+                        "Batch was only partially written.");
+                    MethodError::ServerPartialFail
+                }
             }
         })
     }
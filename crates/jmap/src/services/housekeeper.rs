@@ -113,6 +113,8 @@ pub fn spawn_housekeeper(core: Arc<JMAP>, settings: &Config, mut rx: mpsc::Recei
                     tracing::info!("Purging session cache.");
                     core.sessions.cleanup();
                     core.access_tokens.cleanup();
+                    core.locations.cleanup();
+                    core.principals.cleanup();
                     core.oauth_codes.cleanup();
                     core.rate_limit_auth
                         .retain(|_, limiter| limiter.lock().is_active());
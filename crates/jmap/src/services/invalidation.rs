@@ -0,0 +1,59 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use store::dispatch::invalidation::InvalidationEvent;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::JMAP;
+
+pub fn spawn_invalidation_listener(core: Arc<JMAP>) {
+    let mut invalidation_rx = if let Some(invalidation_rx) = core.store.subscribe_invalidations() {
+        invalidation_rx
+    } else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        loop {
+            match invalidation_rx.recv().await {
+                Ok(InvalidationEvent::Principal {
+                    account_id: Some(account_id),
+                }) => {
+                    core.evict_principal(account_id);
+                }
+                Ok(InvalidationEvent::Principal { account_id: None }) => {
+                    core.evict_all_principals();
+                }
+                Ok(InvalidationEvent::FlushAll) | Err(RecvError::Lagged(_)) => {
+                    // Events were missed, nothing cached can be trusted
+                    core.access_tokens.clear();
+                    core.evict_all_principals();
+                    core.directory.clear_cache();
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
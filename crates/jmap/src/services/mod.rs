@@ -25,6 +25,7 @@ pub mod delivery;
 pub mod housekeeper;
 pub mod index;
 pub mod ingest;
+pub mod invalidation;
 pub mod state;
 
 pub const IPC_CHANNEL_BUFFER: usize = 1024;
@@ -254,21 +254,13 @@ impl JMAP {
                     .await?
                     .hash;
                 let blob_id = obj.changes_mut().unwrap().blob_id_mut().unwrap();
-                blob_id.hash = hash;
+                blob_id.hash = hash.clone();
                 blob_id.class = BlobClass::Linked {
                     account_id,
                     collection: Collection::SieveScript.into(),
                     document_id,
                 };
 
-                // Link blob
-                batch.set(
-                    BlobOp::Link {
-                        hash: blob_id.hash.clone(),
-                    },
-                    Vec::new(),
-                );
-
                 let script_size = blob_id.section.as_ref().unwrap().size as i64;
 
                 if let Some(current) = obj.current() {
@@ -301,6 +293,9 @@ impl JMAP {
                 } else {
                     batch.add(DirectoryClass::UsedQuota(account_id), script_size);
                 }
+
+                // Link blob, the previous one is unlinked first as both may share the same hash
+                batch.set(BlobOp::Link { hash }, Vec::new());
             };
 
             // Write changes
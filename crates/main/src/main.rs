@@ -62,6 +62,17 @@ async fn main() -> std::io::Result<()> {
 
     // Parse stores and directories
     let stores = config.parse_stores().await.failed("Invalid configuration");
+    stores
+        .verify()
+        .await
+        .map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|(id, err)| format!("{id}: {err}"))
+                .collect::<Vec<_>>()
+                .join("; ")
+        })
+        .failed("Failed to connect to stores");
     let directory = config
         .parse_directory(&stores, config.value("jmap.store.data"))
         .await
@@ -128,5 +139,18 @@ async fn main() -> std::io::Result<()> {
     // Wait for services to finish
     tokio::time::sleep(Duration::from_secs(1)).await;
 
+    // Persist any buffered writes before exiting
+    if let Err(errors) = stores.flush().await {
+        for (id, err) in errors {
+            tracing::warn!(
+                context = "store",
+                event = "error",
+                id = id,
+                reason = err,
+                "Failed to flush store on shutdown."
+            );
+        }
+    }
+
     Ok(())
 }
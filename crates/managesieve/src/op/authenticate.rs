@@ -28,6 +28,7 @@ use imap_proto::{
     protocol::authenticate::Mechanism,
     receiver::{self, Request},
 };
+use jmap::auth::AuthResult;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -99,7 +100,7 @@ impl<T: AsyncRead + AsyncWrite + IsTls> Session<T> {
                     .validate_access_token("access_token", &token)
                     .await
                 {
-                    Ok((account_id, _, _)) => self.jmap.get_access_token(account_id).await,
+                    Ok((account_id, _, _)) => self.jmap.get_access_token(account_id).await.into(),
                     Err(err) => {
                         tracing::debug!(
                             parent: &self.span,
@@ -107,13 +108,14 @@ impl<T: AsyncRead + AsyncWrite + IsTls> Session<T> {
                             err = err,
                             "Failed to validate access token."
                         );
-                        None
+                        AuthResult::Failure
                     }
                 }
             }
         };
+        let is_locked = matches!(access_token, AuthResult::Locked);
 
-        if let Some(access_token) = access_token {
+        if let AuthResult::Success(access_token) = access_token {
             // Enforce concurrency limits
             let in_flight = self
                 .imap
@@ -148,7 +150,12 @@ impl<T: AsyncRead + AsyncWrite + IsTls> Session<T> {
                     self.state = State::NotAuthenticated {
                         auth_failures: auth_failures + 1,
                     };
-                    Ok(StatusResponse::no("Authentication failed").into_bytes())
+                    Ok(StatusResponse::no(if is_locked {
+                        "Account temporarily locked"
+                    } else {
+                        "Authentication failed"
+                    })
+                    .into_bytes())
                 }
                 _ => {
                     tracing::debug!(
@@ -24,6 +24,7 @@
 use std::net::IpAddr;
 
 use regex::Regex;
+use store::LookupStore;
 
 use crate::config::StringMatch;
 
@@ -218,6 +219,10 @@ impl ConfigCondition for Config {
                             if let Some(lookup) = ctx.directory.lookups.get(value_str) {
                                 ConditionMatch::Lookup(lookup.clone().into())
                             } else if let Some(lookup) = ctx.stores.lookup_stores.get(value_str) {
+                                // Queries are called with the value being matched
+                                if matches!(lookup, LookupStore::Query(_)) {
+                                    ctx.stores.query_template(value_str, 1)?;
+                                }
                                 ConditionMatch::Lookup(lookup.clone().into())
                             } else {
                                 return Err(format!(
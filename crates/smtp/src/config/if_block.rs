@@ -339,3 +339,9 @@ impl<T> IfBlock<Vec<T>> {
         self.default.is_empty() || self.if_then.iter().any(|v| v.then.is_empty())
     }
 }
+
+impl IfBlock<usize> {
+    pub fn has_zero(&self) -> bool {
+        self.default == 0 || self.if_then.iter().any(|v| v.then == 0)
+    }
+}
@@ -66,6 +66,7 @@ pub struct Host {
     pub tls_allow_invalid_certs: bool,
     pub username: Option<String>,
     pub secret: Option<String>,
+    pub srv: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -321,6 +322,7 @@ pub struct RelayHost {
     pub auth: Option<Credentials<String>>,
     pub tls_implicit: bool,
     pub tls_allow_invalid_certs: bool,
+    pub srv: Option<String>,
 }
 
 pub struct QueueConfig {
@@ -329,6 +331,7 @@ pub struct QueueConfig {
 
     // Schedule
     pub retry: IfBlock<Vec<Duration>>,
+    pub retry_dns: QueueDnsRetry,
     pub notify: IfBlock<Vec<Duration>>,
     pub expire: IfBlock<Duration>,
 
@@ -338,6 +341,9 @@ pub struct QueueConfig {
     pub max_mx: IfBlock<usize>,
     pub max_multihomed: IfBlock<usize>,
     pub ip_strategy: IfBlock<IpLookupStrategy>,
+    pub disable_ipv4: bool,
+    pub disable_ipv6: bool,
+    pub ip_feedback_ttl: Duration,
     pub source_ip: QueueOutboundSourceIp,
     pub tls: QueueOutboundTls,
     pub dsn: Dsn,
@@ -351,9 +357,22 @@ pub struct QueueConfig {
     pub management_lookup: Arc<Directory>,
 }
 
+pub struct QueueDnsRetry {
+    pub server_failure: IfBlock<Vec<Duration>>,
+    pub unreachable: IfBlock<Vec<Duration>>,
+}
+
 pub struct QueueOutboundSourceIp {
     pub ipv4: IfBlock<Vec<Ipv4Addr>>,
     pub ipv6: IfBlock<Vec<Ipv6Addr>>,
+    pub selection: SourceIpSelection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SourceIpSelection {
+    #[default]
+    Random,
+    RoundRobin,
 }
 
 pub struct ReportConfig {
@@ -559,6 +578,7 @@ impl std::fmt::Debug for RelayHost {
             .field("protocol", &self.protocol)
             .field("tls_implicit", &self.tls_implicit)
             .field("tls_allow_invalid_certs", &self.tls_allow_invalid_certs)
+            .field("srv", &self.srv)
             .finish()
     }
 }
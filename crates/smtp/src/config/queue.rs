@@ -105,6 +105,40 @@ impl ConfigQueue for Config {
                         Duration::from_secs(2 * 3600),
                     ])
                 }),
+            retry_dns: QueueDnsRetry {
+                server_failure: self
+                    .parse_if_block(
+                        "queue.schedule.retry-dns.server-failure",
+                        ctx,
+                        &host_envelope_keys,
+                    )?
+                    .unwrap_or_else(|| {
+                        IfBlock::new(vec![
+                            Duration::from_secs(30),
+                            Duration::from_secs(60),
+                            Duration::from_secs(2 * 60),
+                            Duration::from_secs(5 * 60),
+                            Duration::from_secs(10 * 60),
+                            Duration::from_secs(30 * 60),
+                        ])
+                    }),
+                unreachable: self
+                    .parse_if_block(
+                        "queue.schedule.retry-dns.unreachable",
+                        ctx,
+                        &host_envelope_keys,
+                    )?
+                    .unwrap_or_else(|| {
+                        IfBlock::new(vec![
+                            Duration::from_secs(5 * 60),
+                            Duration::from_secs(15 * 60),
+                            Duration::from_secs(30 * 60),
+                            Duration::from_secs(3600),
+                            Duration::from_secs(2 * 3600),
+                            Duration::from_secs(4 * 3600),
+                        ])
+                    }),
+            },
             notify: self
                 .parse_if_block("queue.schedule.notify", ctx, &rcpt_envelope_keys)?
                 .unwrap_or_else(|| {
@@ -128,6 +162,15 @@ impl ConfigQueue for Config {
             ip_strategy: self
                 .parse_if_block("queue.outbound.ip-strategy", ctx, &sender_envelope_keys)?
                 .unwrap_or_else(|| IfBlock::new(IpLookupStrategy::Ipv4thenIpv6)),
+            disable_ipv4: self
+                .property("queue.outbound.disable-ipv4")?
+                .unwrap_or(false),
+            disable_ipv6: self
+                .property("queue.outbound.disable-ipv6")?
+                .unwrap_or(false),
+            ip_feedback_ttl: self
+                .property("queue.outbound.ip-feedback.ttl")?
+                .unwrap_or(Duration::from_secs(5 * 60)),
             source_ip: QueueOutboundSourceIp {
                 ipv4: self
                     .parse_if_block("queue.outbound.source-ip.v4", ctx, &mx_envelope_keys)?
@@ -135,6 +178,9 @@ impl ConfigQueue for Config {
                 ipv6: self
                     .parse_if_block("queue.outbound.source-ip.v6", ctx, &mx_envelope_keys)?
                     .unwrap_or_else(|| IfBlock::new(Vec::new())),
+                selection: self
+                    .property("queue.outbound.source-ip.selection")?
+                    .unwrap_or_default(),
             },
             next_hop: next_hop.into_relay_host(ctx)?,
             tls: QueueOutboundTls {
@@ -218,8 +264,21 @@ impl ConfigQueue for Config {
 
         if config.retry.has_empty_list() {
             Err("Property \"queue.schedule.retry\" cannot contain empty lists.".to_string())
+        } else if config.retry_dns.server_failure.has_empty_list()
+            || config.retry_dns.unreachable.has_empty_list()
+        {
+            Err("Property \"queue.schedule.retry-dns\" cannot contain empty lists.".to_string())
         } else if config.notify.has_empty_list() {
             Err("Property \"queue.schedule.notify\" cannot contain empty lists.".to_string())
+        } else if config.max_mx.has_zero() {
+            Err("Property \"queue.outbound.limits.mx\" must be at least 1.".to_string())
+        } else if config.max_multihomed.has_zero() {
+            Err("Property \"queue.outbound.limits.multihomed\" must be at least 1.".to_string())
+        } else if config.disable_ipv4 && config.disable_ipv6 {
+            Err(
+                "Properties \"queue.outbound.disable-ipv4\" and \"queue.outbound.disable-ipv6\" cannot both be enabled."
+                    .to_string(),
+            )
         } else {
             Ok(config)
         }
@@ -449,6 +508,21 @@ impl From<&Host> for RelayHost {
             },
             tls_implicit: host.tls_implicit,
             tls_allow_invalid_certs: host.tls_allow_invalid_certs,
+            srv: host.srv.clone(),
+        }
+    }
+}
+
+impl ParseValue for SourceIpSelection {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "random" => Ok(SourceIpSelection::Random),
+            "round-robin" => Ok(SourceIpSelection::RoundRobin),
+            _ => Err(format!(
+                "Invalid source IP selection value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
         }
     }
 }
@@ -55,6 +55,7 @@ impl ConfigHost for Config {
                 .unwrap_or(false),
             username: self.property(("remote", id, "auth.username"))?,
             secret: self.property(("remote", id, "auth.secret"))?,
+            srv: self.property(("remote", id, "srv"))?,
             timeout: self
                 .property(("remote", id, "timeout"))?
                 .unwrap_or(Duration::from_secs(60)),
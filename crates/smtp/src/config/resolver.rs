@@ -21,52 +21,105 @@
  * for more details.
 */
 
-use std::io::Read;
+use std::{
+    io::Read,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
 
 use mail_auth::{
     common::lru::{DnsCache, LruCache},
     flate2::read::GzDecoder,
     hickory_resolver::{
-        config::{ResolverConfig, ResolverOpts},
+        config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
         system_conf::read_system_conf,
+        AsyncResolver,
     },
     Resolver,
 };
 
-use crate::{core::Resolvers, outbound::dane::DnssecResolver};
+use crate::{
+    core::{NamedResolver, Resolvers},
+    outbound::{dane::DnssecResolver, ecs::EcsResolver},
+};
 use utils::{config::Config, suffixlist::PublicSuffix};
 
 pub trait ConfigResolver {
     fn build_resolvers(&self) -> super::Result<Resolvers>;
+    fn parse_resolver_options(&self, prefix: &str)
+        -> super::Result<(ResolverConfig, ResolverOpts)>;
     fn parse_public_suffix(&self) -> super::Result<PublicSuffix>;
 }
 
 impl ConfigResolver for Config {
     fn build_resolvers(&self) -> super::Result<Resolvers> {
-        let (config, mut opts) = match self.value_require("resolver.type")? {
-            "cloudflare" => (ResolverConfig::cloudflare(), ResolverOpts::default()),
-            "cloudflare-tls" => (ResolverConfig::cloudflare_tls(), ResolverOpts::default()),
-            "quad9" => (ResolverConfig::quad9(), ResolverOpts::default()),
-            "quad9-tls" => (ResolverConfig::quad9_tls(), ResolverOpts::default()),
-            "google" => (ResolverConfig::google(), ResolverOpts::default()),
-            "system" => read_system_conf()
-                .map_err(|err| format!("Failed to read system DNS config: {err}"))?,
-            other => return Err(format!("Unknown resolver type {other:?}.")),
+        let (config, opts) = self.parse_resolver_options("resolver")?;
+
+        // ECS hints are sent through the same name servers as regular lookups
+        let ecs_prefixes = if self.property("resolver.ecs.enable")?.unwrap_or(false) {
+            let ipv4_prefix = self
+                .property::<u16>("resolver.ecs.ipv4-prefix")?
+                .unwrap_or(24);
+            let ipv6_prefix = self
+                .property::<u16>("resolver.ecs.ipv6-prefix")?
+                .unwrap_or(56);
+            if ipv4_prefix > 32 || ipv6_prefix > 128 {
+                return Err("Invalid ECS source prefix length.".to_string());
+            }
+            Some((ipv4_prefix as u8, ipv6_prefix as u8))
+        } else {
+            None
         };
-        if let Some(concurrency) = self.property("resolver.concurrency")? {
-            opts.num_concurrent_reqs = concurrency;
-        }
-        if let Some(timeout) = self.property("resolver.timeout")? {
-            opts.timeout = timeout;
-        }
-        if let Some(preserve) = self.property("resolver.preserve-intermediates")? {
-            opts.preserve_intermediates = preserve;
-        }
-        if let Some(try_tcp_on_error) = self.property("resolver.try-tcp-on-error")? {
-            opts.try_tcp_on_error = try_tcp_on_error;
-        }
-        if let Some(attempts) = self.property("resolver.attempts")? {
-            opts.attempts = attempts;
+        let ecs_capacity = self.property("resolver.cache.ecs")?.unwrap_or(1024);
+        let build_ecs = |config: &ResolverConfig, opts: &ResolverOpts| {
+            ecs_prefixes.map(|(ipv4_prefix, ipv6_prefix)| {
+                EcsResolver::new(
+                    config.clone(),
+                    opts.clone(),
+                    ipv4_prefix,
+                    ipv6_prefix,
+                    ecs_capacity,
+                )
+            })
+        };
+
+        // Build named resolvers
+        let mut named = Vec::new();
+        for id in self.sub_keys("resolver.named") {
+            let prefix = format!("resolver.named.{id}");
+            let (config, opts) = self.parse_resolver_options(&prefix)?;
+            let mut domains = Vec::new();
+            for (_, domain) in self.values((prefix.as_str(), "domains")) {
+                domains.push(domain.trim().trim_end_matches('.').to_lowercase());
+            }
+            if domains.is_empty() {
+                return Err(format!(
+                    "No domains specified for named resolver {id:?} ({prefix}.domains)."
+                ));
+            }
+
+            let mut capacities = [1024usize; 5];
+            for (pos, key) in ["txt", "mx", "ipv4", "ipv6", "ptr"].into_iter().enumerate() {
+                if let Some(capacity) = self.property((prefix.as_str(), "cache", key))? {
+                    capacities[pos] = capacity;
+                }
+            }
+
+            named.push(NamedResolver {
+                id: id.to_string(),
+                domains,
+                ecs: build_ecs(&config, &opts),
+                resolver: Resolver::with_capacities(
+                    config,
+                    opts,
+                    capacities[0],
+                    capacities[1],
+                    capacities[2],
+                    capacities[3],
+                    capacities[4],
+                )
+                .map_err(|err| format!("Failed to build DNS resolver {id:?}: {err}"))?,
+            });
         }
 
         // Prepare DNSSEC resolver options
@@ -74,6 +127,13 @@ impl ConfigResolver for Config {
         let mut opts_dnssec = opts.clone();
         opts_dnssec.validate = true;
 
+        // Build ECS resolver
+        let ecs = build_ecs(&config, &opts);
+
+        // Prepare SRV resolver options
+        let config_srv = config.clone();
+        let opts_srv = opts.clone();
+
         let mut capacities = [1024usize; 5];
         for (pos, key) in ["txt", "mx", "ipv4", "ipv6", "ptr"].into_iter().enumerate() {
             if let Some(capacity) = self.property(("resolver.cache", key))? {
@@ -92,8 +152,11 @@ impl ConfigResolver for Config {
                 capacities[4],
             )
             .map_err(|err| format!("Failed to build DNS resolver: {err}"))?,
+            named,
             dnssec: DnssecResolver::with_capacity(config_dnssec, opts_dnssec)
                 .map_err(|err| format!("Failed to build DNSSEC resolver: {err}"))?,
+            srv: AsyncResolver::tokio(config_srv, opts_srv),
+            ecs,
             cache: crate::core::DnsCache {
                 tlsa: LruCache::with_capacity(
                     self.property("resolver.cache.tlsa")?.unwrap_or(1024),
@@ -101,10 +164,103 @@ impl ConfigResolver for Config {
                 mta_sts: LruCache::with_capacity(
                     self.property("resolver.cache.mta-sts")?.unwrap_or(1024),
                 ),
+                srv: LruCache::with_capacity(self.property("resolver.cache.srv")?.unwrap_or(1024)),
+                dnssec: LruCache::with_capacity(
+                    self.property("resolver.cache.dnssec")?.unwrap_or(1024),
+                ),
             },
         })
     }
 
+    fn parse_resolver_options(
+        &self,
+        prefix: &str,
+    ) -> super::Result<(ResolverConfig, ResolverOpts)> {
+        let (config, mut opts) = match self.value_require((prefix, "type"))? {
+            "cloudflare" => (ResolverConfig::cloudflare(), ResolverOpts::default()),
+            "cloudflare-tls" => (ResolverConfig::cloudflare_tls(), ResolverOpts::default()),
+            "quad9" => (ResolverConfig::quad9(), ResolverOpts::default()),
+            "quad9-tls" => (ResolverConfig::quad9_tls(), ResolverOpts::default()),
+            "google" => (ResolverConfig::google(), ResolverOpts::default()),
+            "system" => read_system_conf()
+                .map_err(|err| format!("Failed to read system DNS config: {err}"))?,
+            "custom" => {
+                let mut config = ResolverConfig::new();
+                for (_, address) in self.values((prefix, "address")) {
+                    let address = address.parse::<SocketAddr>().or_else(|_| {
+                        address
+                            .parse::<IpAddr>()
+                            .map(|ip| SocketAddr::new(ip, 53))
+                            .map_err(|_| {
+                                format!(
+                                    "Invalid resolver address {address:?} for property {prefix}.address."
+                                )
+                            })
+                    })?;
+                    config.add_name_server(NameServerConfig::new(address, Protocol::Udp));
+                    config.add_name_server(NameServerConfig::new(address, Protocol::Tcp));
+                }
+                if config.name_servers().is_empty() {
+                    return Err(format!(
+                        "No name servers specified for custom resolver ({prefix}.address)."
+                    ));
+                }
+                (config, ResolverOpts::default())
+            }
+            other => return Err(format!("Unknown resolver type {other:?}.")),
+        };
+        if let Some(concurrency) = self.property((prefix, "concurrency"))? {
+            opts.num_concurrent_reqs = concurrency;
+        }
+        if let Some(timeout) = self.property((prefix, "timeout"))? {
+            opts.timeout = timeout;
+        }
+        if let Some(preserve) = self.property((prefix, "preserve-intermediates"))? {
+            opts.preserve_intermediates = preserve;
+        }
+        if let Some(try_tcp_on_error) = self.property((prefix, "try-tcp-on-error"))? {
+            opts.try_tcp_on_error = try_tcp_on_error;
+        }
+        if let Some(attempts) = self.property((prefix, "attempts"))? {
+            opts.attempts = attempts;
+        }
+
+        // Observed TTLs are clamped before caching, both for records and for negative responses
+        for (class, default_min, default_max) in [
+            (
+                "positive",
+                Duration::from_secs(30),
+                Duration::from_secs(86400),
+            ),
+            (
+                "negative",
+                Duration::from_secs(30),
+                Duration::from_secs(3600),
+            ),
+        ] {
+            let min = self
+                .property((prefix, "ttl", class, "min"))?
+                .unwrap_or(default_min);
+            let max = self
+                .property((prefix, "ttl", class, "max"))?
+                .unwrap_or(default_max);
+            if min > max {
+                return Err(format!(
+                    "Property {prefix}.ttl.{class}.min cannot be greater than {prefix}.ttl.{class}.max."
+                ));
+            }
+            if class == "positive" {
+                opts.positive_min_ttl = min.into();
+                opts.positive_max_ttl = max.into();
+            } else {
+                opts.negative_min_ttl = min.into();
+                opts.negative_max_ttl = max.into();
+            }
+        }
+
+        Ok((config, opts))
+    }
+
     fn parse_public_suffix(&self) -> super::Result<PublicSuffix> {
         let mut has_values = false;
         for (_, value) in self.values("resolver.public-suffix") {
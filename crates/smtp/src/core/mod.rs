@@ -31,7 +31,9 @@ use std::{
 use ahash::AHashMap;
 use dashmap::DashMap;
 use directory::Directory;
-use mail_auth::{common::lru::LruCache, IprevOutput, Resolver, SpfOutput};
+use mail_auth::{
+    common::lru::LruCache, hickory_resolver::TokioAsyncResolver, IprevOutput, Resolver, SpfOutput,
+};
 use sieve::{runtime::Variable, Runtime, Sieve};
 use smtp_proto::{
     request::receiver::{
@@ -59,7 +61,8 @@ use crate::{
     },
     inbound::auth::SaslToken,
     outbound::{
-        dane::{DnssecResolver, Tlsa},
+        dane::{DnssecResolver, DnssecStatus, Tlsa},
+        lookup::{SourceIpSelector, SrvRecord},
         mta_sts,
     },
     queue::{self, DomainPart, QueueId, QuotaLimiter},
@@ -67,11 +70,15 @@ use crate::{
     scripts::plugins::lookup::VariableExists,
 };
 
-use self::throttle::{Limiter, ThrottleKey, ThrottleKeyHasherBuilder};
+use self::{
+    rng::SelectionRng,
+    throttle::{Limiter, ThrottleKey, ThrottleKeyHasherBuilder},
+};
 
 pub mod if_block;
 pub mod management;
 pub mod params;
+pub mod rng;
 pub mod throttle;
 pub mod worker;
 
@@ -105,6 +112,7 @@ pub struct SMTP {
     pub mail_auth: MailAuthConfig,
     pub report: ReportCore,
     pub sieve: SieveCore,
+    pub rng: SelectionRng,
     #[cfg(feature = "local_delivery")]
     pub delivery_tx: mpsc::Sender<DeliveryEvent>,
 }
@@ -125,13 +133,25 @@ pub struct SieveCore {
 
 pub struct Resolvers {
     pub dns: Resolver,
+    pub named: Vec<NamedResolver>,
     pub dnssec: DnssecResolver,
+    pub srv: TokioAsyncResolver,
+    pub ecs: Option<EcsResolver>,
     pub cache: DnsCache,
 }
 
+pub struct NamedResolver {
+    pub id: String,
+    pub domains: Vec<String>,
+    pub resolver: Resolver,
+    pub ecs: Option<EcsResolver>,
+}
+
 pub struct DnsCache {
     pub tlsa: LruCache<String, Arc<Tlsa>>,
     pub mta_sts: LruCache<String, Arc<mta_sts::Policy>>,
+    pub srv: LruCache<String, Arc<Vec<SrvRecord>>>,
+    pub dnssec: LruCache<String, DnssecStatus>,
 }
 
 pub struct SessionCore {
@@ -145,6 +165,8 @@ pub struct QueueCore {
     pub quota: DashMap<ThrottleKey, Arc<QuotaLimiter>, ThrottleKeyHasherBuilder>,
     pub tx: mpsc::Sender<queue::Event>,
     pub id_seq: AtomicU32,
+    pub source_ip: Box<dyn SourceIpSelector>,
+    pub ip_feedback: LruCache<IpAddr, bool>,
     pub connectors: TlsConnectors,
 }
 
@@ -287,11 +309,7 @@ impl SessionData {
 impl Lookup {
     pub async fn contains(&self, item: &str) -> Option<bool> {
         match self {
-            Lookup::Store(LookupStore::Query(lookup)) => lookup
-                .store
-                .query::<bool>(&lookup.query, vec![item.into()])
-                .await
-                .ok(),
+            Lookup::Store(LookupStore::Query(lookup)) => lookup.exists(item).await.ok(),
             Lookup::Store(store) => store
                 .key_get::<VariableExists>(LookupKey::Key(item.to_string().into_bytes()))
                 .await
@@ -336,10 +354,32 @@ pub fn into_sieve_value(value: Value) -> Variable {
         Value::Float(v) => Variable::Float(v),
         Value::Text(v) => Variable::String(v.into_owned().into()),
         Value::Blob(v) => Variable::String(v.into_owned().into_string().into()),
+        Value::Json(v) => json_into_sieve_value(v),
         Value::Null => Variable::default(),
     }
 }
 
+// Arrays become Sieve arrays, objects are passed as their JSON text
+fn json_into_sieve_value(value: serde_json::Value) -> Variable {
+    match value {
+        serde_json::Value::String(v) => Variable::String(v.into()),
+        serde_json::Value::Bool(v) => Variable::Integer(i64::from(v)),
+        serde_json::Value::Number(v) => v
+            .as_i64()
+            .map(Variable::Integer)
+            .or_else(|| v.as_f64().map(Variable::Float))
+            .unwrap_or_else(|| Variable::String(v.to_string().into())),
+        serde_json::Value::Array(v) => Variable::Array(
+            v.into_iter()
+                .map(json_into_sieve_value)
+                .collect::<Vec<_>>()
+                .into(),
+        ),
+        serde_json::Value::Object(_) => Variable::String(value.to_string().into()),
+        serde_json::Value::Null => Variable::default(),
+    }
+}
+
 pub fn into_store_value(value: Variable) -> Value<'static> {
     match value {
         Variable::String(v) => Value::Text(v.to_string().into()),
@@ -485,6 +525,7 @@ static ref SIEVE: Arc<ServerInstance> = Arc::new(utils::listener::ServerInstance
     is_tls_implicit: true,
     limiter: utils::listener::limiter::ConcurrencyLimiter::new(0),
     shutdown_rx: tokio::sync::watch::channel(false).1,
+    acme_providers: vec![],
 });
 }
 
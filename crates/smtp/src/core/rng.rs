@@ -0,0 +1,49 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+/// Source of randomness used when selecting outbound hosts and source addresses.
+/// Uses the thread-local generator unless seeded, in which case selections are
+/// reproducible.
+#[derive(Default)]
+pub struct SelectionRng {
+    seeded: Option<Mutex<StdRng>>,
+}
+
+impl SelectionRng {
+    pub fn seeded(seed: u64) -> Self {
+        SelectionRng {
+            seeded: Some(Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+
+    pub fn with<T>(&self, f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        if let Some(rng) = &self.seeded {
+            f(&mut *rng.lock())
+        } else {
+            f(&mut rand::thread_rng())
+        }
+    }
+}
@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use directory::QueryBy;
+use directory::QueryResult;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
 use smtp_proto::{IntoString, AUTH_LOGIN, AUTH_OAUTHBEARER, AUTH_PLAIN, AUTH_XOAUTH2};
@@ -181,30 +181,45 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                 | Credentials::XOauth2 { username, .. }
                 | Credentials::OAuthBearer { token: username } => username.to_string(),
             };
-            if let Ok(principal) = lookup
-                .query(QueryBy::Credentials(&credentials), false)
-                .await
-            {
+            if let Ok(result) = lookup.authenticate(&credentials, false).await {
                 tracing::debug!(
                     parent: &self.span,
                     context = "auth",
                     event = "authenticate",
-                    result = if principal.is_some() {"success"} else {"failed"}
+                    result = match &result {
+                        QueryResult::Found(_) => "success",
+                        QueryResult::Disabled => "disabled",
+                        QueryResult::Locked => "locked",
+                        QueryResult::NotFound | QueryResult::InvalidCredentials => "failed",
+                    }
                 );
-                return if let Some(principal) = principal {
-                    self.data.authenticated_as = authenticated_as.to_lowercase();
-                    self.data.authenticated_emails = principal
-                        .emails
-                        .into_iter()
-                        .map(|e| e.trim().to_lowercase())
-                        .collect();
-                    self.eval_post_auth_params().await;
-                    self.write(b"235 2.7.0 Authentication succeeded.\r\n")
-                        .await?;
-                    Ok(false)
-                } else {
-                    self.auth_error(b"535 5.7.8 Authentication credentials invalid.\r\n")
+                return match result {
+                    QueryResult::Found(principal) => {
+                        self.data.authenticated_as = authenticated_as.to_lowercase();
+                        self.data.authenticated_emails = principal
+                            .emails
+                            .into_iter()
+                            .map(|e| e.trim().to_lowercase())
+                            .collect();
+                        self.eval_post_auth_params().await;
+                        self.write(b"235 2.7.0 Authentication succeeded.\r\n")
+                            .await?;
+                        Ok(false)
+                    }
+                    QueryResult::Disabled => {
+                        self.auth_error(b"525 5.7.13 User account disabled.\r\n")
+                            .await
+                    }
+                    QueryResult::Locked => {
+                        self.auth_error(
+                            b"454 4.7.0 Too many authentication failures, try again later.\r\n",
+                        )
                         .await
+                    }
+                    QueryResult::NotFound | QueryResult::InvalidCredentials => {
+                        self.auth_error(b"535 5.7.8 Authentication credentials invalid.\r\n")
+                            .await
+                    }
                 };
             }
         } else {
@@ -22,7 +22,8 @@
 */
 
 use crate::core::{
-    throttle::ThrottleKeyHasherBuilder, QueueCore, ReportCore, SessionCore, TlsConnectors, SMTP,
+    rng::SelectionRng, throttle::ThrottleKeyHasherBuilder, QueueCore, ReportCore, SessionCore,
+    TlsConnectors, SMTP,
 };
 use std::sync::Arc;
 
@@ -32,6 +33,7 @@ use config::{
 };
 use dashmap::DashMap;
 use directory::Directories;
+use mail_auth::common::lru::{DnsCache, LruCache};
 use mail_send::smtp::tls::build_tls_connector;
 use queue::manager::SpawnQueue;
 use reporting::scheduler::SpawnReport;
@@ -84,6 +86,7 @@ impl SMTP {
                     tls_allow_invalid_certs: Default::default(),
                     username: Default::default(),
                     secret: Default::default(),
+                    srv: Default::default(),
                 },
             );
         }
@@ -93,6 +96,7 @@ impl SMTP {
         let sieve_config = config.parse_sieve(&mut config_ctx)?;
         let session_config = config.parse_session_config(&config_ctx)?;
         let queue_config = config.parse_queue(&config_ctx)?;
+        let source_ip = queue_config.source_ip.selection.selector();
         let mail_auth_config = config.parse_mail_auth(&config_ctx)?;
         let report_config = config.parse_reports(&config_ctx)?;
 
@@ -132,6 +136,12 @@ impl SMTP {
                         .next_power_of_two() as usize,
                 ),
                 id_seq: 0.into(),
+                source_ip,
+                ip_feedback: LruCache::with_capacity(
+                    config
+                        .property("queue.outbound.ip-feedback.size")?
+                        .unwrap_or(1024),
+                ),
                 quota: DashMap::with_capacity_and_hasher_and_shard_amount(
                     config.property("global.shared-map.capacity")?.unwrap_or(2),
                     ThrottleKeyHasherBuilder::default(),
@@ -152,6 +162,7 @@ impl SMTP {
             },
             mail_auth: mail_auth_config,
             sieve: sieve_config,
+            rng: SelectionRng::default(),
             #[cfg(feature = "local_delivery")]
             delivery_tx,
         });
@@ -28,16 +28,26 @@ use mail_auth::{
         error::{ResolveError, ResolveErrorKind},
         proto::{
             error::ProtoErrorKind,
-            rr::rdata::tlsa::{CertUsage, Matching, Selector},
+            op::ResponseCode,
+            rr::{
+                rdata::tlsa::{CertUsage, Matching, Selector},
+                RecordType,
+            },
         },
         AsyncResolver,
     },
 };
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::core::Resolvers;
 
-use super::{DnssecResolver, Tlsa, TlsaEntry};
+use super::{DnssecResolver, DnssecStatus, Tlsa, TlsaEntry};
+
+// Unsigned answers carry no TTL, their status is cached for this long instead
+const INSECURE_TTL: Duration = Duration::from_secs(5 * 60);
 
 impl DnssecResolver {
     pub fn with_capacity(
@@ -123,6 +133,69 @@ impl Resolvers {
         )))
     }
 
+    /// Returns the DNSSEC status of a record set according to the validating resolver.
+    /// Statuses are cached for the TTL of the validated answer, so the record set is only
+    /// queried again once it would have expired. Failures that say nothing about the zone,
+    /// such as timeouts or SERVFAIL, return `Unknown` and are not cached.
+    pub async fn dnssec_status<'x>(
+        &self,
+        key: impl IntoFqdn<'x>,
+        record_type: RecordType,
+    ) -> DnssecStatus {
+        let key = key.into_fqdn();
+        let cache_key = format!("{record_type}:{key}");
+        if let Some(status) = self.cache.dnssec.get(cache_key.as_str()) {
+            return status;
+        }
+
+        #[cfg(any(test, feature = "test_mode"))]
+        if true {
+            return DnssecStatus::Unknown;
+        }
+
+        let (status, valid_until) =
+            match self.dnssec.resolver.lookup(key.as_ref(), record_type).await {
+                Ok(lookup) => (DnssecStatus::Secure, lookup.valid_until()),
+                Err(err) => match err.kind() {
+                    ResolveErrorKind::Proto(proto_err)
+                        if matches!(proto_err.kind(), ProtoErrorKind::RrsigsNotPresent { .. }) =>
+                    {
+                        (DnssecStatus::Insecure, Instant::now() + INSECURE_TTL)
+                    }
+                    // The records used for delivery could not be found by the validating resolver
+                    ResolveErrorKind::NoRecordsFound {
+                        response_code: ResponseCode::NXDomain | ResponseCode::NoError,
+                        negative_ttl,
+                        ..
+                    } => (
+                        DnssecStatus::Insecure,
+                        Instant::now()
+                            + negative_ttl.map_or(INSECURE_TTL, |ttl| {
+                                Duration::from_secs(ttl as u64).min(INSECURE_TTL)
+                            }),
+                    ),
+                    _ => return DnssecStatus::Unknown,
+                },
+            };
+
+        self.cache.dnssec.insert(cache_key, status, valid_until)
+    }
+
+    #[cfg(feature = "test_mode")]
+    pub fn dnssec_add<'x>(
+        &self,
+        key: impl IntoFqdn<'x>,
+        record_type: RecordType,
+        status: DnssecStatus,
+        valid_until: std::time::Instant,
+    ) {
+        self.cache.dnssec.insert(
+            format!("{record_type}:{}", key.into_fqdn()),
+            status,
+            valid_until,
+        );
+    }
+
     #[cfg(feature = "test_mode")]
     pub fn tlsa_add<'x>(
         &self,
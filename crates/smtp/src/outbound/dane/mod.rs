@@ -30,6 +30,17 @@ pub struct DnssecResolver {
     pub resolver: TokioAsyncResolver,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DnssecStatus {
+    /// The answer was authenticated by the validating resolver.
+    Secure,
+    /// The answer could not be authenticated (unsigned zone or missing RRSIGs).
+    Insecure,
+    /// The DNSSEC status could not be determined.
+    #[default]
+    Unknown,
+}
+
 #[derive(Debug, Hash, PartialEq, Eq)]
 pub struct TlsaEntry {
     pub is_end_entity: bool,
@@ -33,24 +33,24 @@ use mail_auth::{
 };
 use mail_send::SmtpClient;
 use smtp_proto::MAIL_REQUIRETLS;
-use utils::config::ServerProtocol;
+use utils::config::{KeyLookup, ServerProtocol};
 
 use crate::{
-    config::{AggregateFrequency, TlsStrategy},
+    config::{AggregateFrequency, EnvelopeKey, QueueConfig, TlsStrategy},
     core::SMTP,
     queue::ErrorDetails,
     reporting::{tls::TlsRptOptions, PolicyType, TlsEvent},
 };
 
 use super::{
-    lookup::ToNextHop,
+    lookup::{ToNextHop, ToSrvNextHop},
     mta_sts,
     session::{read_greeting, say_helo, try_start_tls, SessionParams, StartTlsResult},
     NextHop,
 };
 use crate::queue::{
-    manager::Queue, throttle, DeliveryAttempt, Domain, Error, Event, OnHold, QueueEnvelope,
-    Schedule, Status, WorkerResult,
+    manager::Queue, throttle, DeliveryAttempt, DnsErrorKind, Domain, Error, Event, OnHold,
+    QueueEnvelope, Schedule, Status, WorkerResult,
 };
 
 impl DeliveryAttempt {
@@ -315,10 +315,74 @@ impl DeliveryAttempt {
 
                 // Obtain remote hosts list
                 let mx_list;
-                if is_smtp && remote_hosts.is_empty() {
+                let srv_list;
+                let srv_relay = match remote_hosts.first() {
+                    Some(NextHop::Relay(relay)) if relay.srv.is_some() => Some(*relay),
+                    _ => None,
+                };
+                if let Some(relay) = srv_relay {
+                    // Lookup SRV
+                    let service = relay.srv.as_deref().unwrap_or_default();
+                    srv_list = match core
+                        .resolvers
+                        .srv_lookup(format!(
+                            "{service}.{}.",
+                            relay.address.trim_end_matches('.')
+                        ))
+                        .await
+                    {
+                        Ok(srv) => srv,
+                        Err(err) => {
+                            tracing::info!(
+                                parent: &span,
+                                context = "dns",
+                                event = "srv-lookup-failed",
+                                service = service,
+                                reason = %err,
+                            );
+                            let status = Status::from_dns_error(&relay.address, err);
+                            let schedule = queue_config.retry_schedule(&status, &envelope).await;
+                            domain.set_status(status, schedule);
+                            continue 'next_domain;
+                        }
+                    };
+
+                    let max_mx = *queue_config.max_mx.eval(&envelope).await;
+                    if let Some(remote_hosts_) = core
+                        .rng
+                        .with(|rng| srv_list.to_srv_hosts(relay, max_mx, rng))
+                    {
+                        remote_hosts = remote_hosts_;
+                    } else {
+                        tracing::info!(
+                            parent: &span,
+                            context = "dns",
+                            event = "null-srv",
+                            service = service,
+                            reason = "Service not available at domain (null SRV)",
+                        );
+                        domain.set_status(
+                            Status::PermanentFailure(Error::DnsError(format!(
+                                "Service {service:?} not available at {:?} (null SRV)",
+                                relay.address
+                            ))),
+                            queue_config.retry.eval(&envelope).await,
+                        );
+                        continue 'next_domain;
+                    }
+                } else if is_smtp && remote_hosts.is_empty() {
                     // Lookup MX
-                    mx_list = match core.resolvers.dns.mx_lookup(&domain.domain).await {
-                        Ok(mx) => mx,
+                    mx_list = match core.mx_lookup(&domain.domain, &envelope).await {
+                        Ok(mx_list) => {
+                            tracing::debug!(
+                                parent: &span,
+                                context = "dns",
+                                event = "mx-lookup",
+                                dnssec = ?mx_list.dnssec,
+                            );
+
+                            mx_list
+                        }
                         Err(err) => {
                             tracing::info!(
                                 parent: &span,
@@ -326,13 +390,17 @@ impl DeliveryAttempt {
                                 event = "mx-lookup-failed",
                                 reason = %err,
                             );
-                            domain.set_status(err, queue_config.retry.eval(&envelope).await);
+                            let status = Status::from_dns_error(&domain.domain, err);
+                            let schedule = queue_config.retry_schedule(&status, &envelope).await;
+                            domain.set_status(status, schedule);
                             continue 'next_domain;
                         }
                     };
 
-                    if let Some(remote_hosts_) = mx_list
-                        .to_remote_hosts(&domain.domain, *queue_config.max_mx.eval(&envelope).await)
+                    let max_mx = *queue_config.max_mx.eval(&envelope).await;
+                    if let Some(remote_hosts_) = core
+                        .rng
+                        .with(|rng| mx_list.mx.to_remote_hosts(&domain.domain, max_mx, rng))
                     {
                         remote_hosts = remote_hosts_;
                     } else {
@@ -397,7 +465,17 @@ impl DeliveryAttempt {
                         .resolve_host(remote_host, &envelope, max_multihomed)
                         .await
                     {
-                        Ok(result) => result,
+                        Ok(result) => {
+                            tracing::debug!(
+                                parent: &span,
+                                context = "dns",
+                                event = "ip-lookup",
+                                mx = envelope.mx,
+                                dnssec = ?result.dnssec,
+                            );
+
+                            result
+                        }
                         Err(status) => {
                             tracing::info!(
                                 parent: &span,
@@ -580,6 +658,21 @@ impl DeliveryAttempt {
                             }
                         }
 
+                        // Log the egress path of this attempt
+                        tracing::info!(
+                            parent: &span,
+                            context = "connect",
+                            event = "attempt",
+                            domain = envelope.domain,
+                            mx = envelope.mx,
+                            next_hop = ?remote_host,
+                            remote_ip = %remote_ip,
+                            remote_port = remote_host.port(),
+                            source_ip = %source_ip.unwrap_or(no_ip),
+                            source_ipv4 = ?resolve_result.source_ipv4,
+                            source_ipv6 = ?resolve_result.source_ipv6,
+                        );
+
                         // Connect
                         let mut smtp_client = match if let Some(ip_addr) = source_ip {
                             SmtpClient::connect_using(
@@ -596,6 +689,7 @@ impl DeliveryAttempt {
                             .await
                         } {
                             Ok(smtp_client) => {
+                                core.record_ip_feedback(remote_ip, true);
                                 tracing::debug!(
                                     parent: &span,
                                     context = "connect",
@@ -609,6 +703,7 @@ impl DeliveryAttempt {
                                 smtp_client
                             }
                             Err(err) => {
+                                core.record_ip_feedback(remote_ip, false);
                                 tracing::info!(
                                     parent: &span,
                                     context = "connect",
@@ -927,7 +1022,8 @@ impl DeliveryAttempt {
 
                 // Update status
                 domain.disable_tls = disable_tls;
-                domain.set_status(last_status, queue_config.retry.eval(&envelope).await);
+                let schedule = queue_config.retry_schedule(&last_status, &envelope).await;
+                domain.set_status(last_status, schedule);
             }
             self.message.domains = domains;
             self.message.recipients = recipients;
@@ -1056,6 +1152,23 @@ impl DeliveryAttempt {
     }
 }
 
+impl QueueConfig {
+    pub async fn retry_schedule(
+        &self,
+        status: &Status<(), Error>,
+        envelope: &impl KeyLookup<Key = EnvelopeKey>,
+    ) -> &[Duration] {
+        match status {
+            Status::TemporaryFailure(Error::DnsLookupError(err)) => match err.kind {
+                DnsErrorKind::ServerFailure => self.retry_dns.server_failure.eval(envelope).await,
+                DnsErrorKind::Unreachable => self.retry_dns.unreachable.eval(envelope).await,
+                DnsErrorKind::NotFound => self.retry.eval(envelope).await,
+            },
+            _ => self.retry.eval(envelope).await,
+        }
+    }
+}
+
 impl Domain {
     pub fn set_status(&mut self, status: impl Into<Status<(), Error>>, schedule: &[Duration]) {
         self.status = status.into();
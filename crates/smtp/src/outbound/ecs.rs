@@ -0,0 +1,232 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use mail_auth::{
+    common::{
+        lru::{DnsCache, LruCache},
+        resolver::IntoFqdn,
+    },
+    hickory_resolver::{
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+        error::ResolveError,
+        name_server::{NameServerPool, TokioConnectionProvider},
+        proto::{
+            op::{Edns, Message, MessageType, OpCode, Query},
+            rr::{
+                rdata::opt::{ClientSubnet, EdnsOption},
+                Name, RData, RecordType,
+            },
+            xfer::{DnsHandle, DnsRequest, DnsRequestOptions, FirstAnswer},
+        },
+    },
+};
+
+pub struct EcsResolver {
+    pub pool: NameServerPool<TokioConnectionProvider>,
+    pub ipv4_prefix: u8,
+    pub ipv6_prefix: u8,
+    pub cache_ipv4: LruCache<String, Arc<Vec<Ipv4Addr>>>,
+    pub cache_ipv6: LruCache<String, Arc<Vec<Ipv6Addr>>>,
+    pub min_ttl: Duration,
+    pub max_ttl: Duration,
+}
+
+struct EcsSubnet {
+    address: IpAddr,
+    source_prefix: u8,
+}
+
+impl EcsResolver {
+    pub fn new(
+        config: ResolverConfig,
+        options: ResolverOpts,
+        ipv4_prefix: u8,
+        ipv6_prefix: u8,
+        capacity: usize,
+    ) -> Self {
+        EcsResolver {
+            min_ttl: options.positive_min_ttl.unwrap_or(Duration::ZERO),
+            max_ttl: options
+                .positive_max_ttl
+                .unwrap_or(Duration::from_secs(86400)),
+            pool: NameServerPool::from_config(
+                NameServerConfigGroup::from(config.name_servers().to_vec()),
+                options,
+                TokioConnectionProvider::default(),
+            ),
+            ipv4_prefix: ipv4_prefix.min(32),
+            ipv6_prefix: ipv6_prefix.min(128),
+            cache_ipv4: LruCache::with_capacity(capacity),
+            cache_ipv6: LruCache::with_capacity(capacity),
+        }
+    }
+
+    pub async fn ipv4_lookup<'x>(
+        &self,
+        key: impl IntoFqdn<'x>,
+        source: IpAddr,
+    ) -> mail_auth::Result<Arc<Vec<Ipv4Addr>>> {
+        let key = key.into_fqdn();
+        let subnet = self.client_subnet(source);
+        let cache_key = format!("{key}@{}/{}", subnet.address, subnet.source_prefix);
+        if let Some(value) = self.cache_ipv4.get(&cache_key) {
+            return Ok(value);
+        }
+
+        #[cfg(any(test, feature = "test_mode"))]
+        if true {
+            return mail_auth::common::resolver::mock_resolve(&cache_key);
+        }
+
+        let (records, valid_until) = self.query(key.as_ref(), RecordType::A, subnet).await?;
+        let ips = records
+            .into_iter()
+            .filter_map(|ip| match ip {
+                IpAddr::V4(ip) => Some(ip),
+                IpAddr::V6(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        Ok(self
+            .cache_ipv4
+            .insert(cache_key, Arc::new(ips), valid_until))
+    }
+
+    pub async fn ipv6_lookup<'x>(
+        &self,
+        key: impl IntoFqdn<'x>,
+        source: IpAddr,
+    ) -> mail_auth::Result<Arc<Vec<Ipv6Addr>>> {
+        let key = key.into_fqdn();
+        let subnet = self.client_subnet(source);
+        let cache_key = format!("{key}@{}/{}", subnet.address, subnet.source_prefix);
+        if let Some(value) = self.cache_ipv6.get(&cache_key) {
+            return Ok(value);
+        }
+
+        #[cfg(any(test, feature = "test_mode"))]
+        if true {
+            return mail_auth::common::resolver::mock_resolve(&cache_key);
+        }
+
+        let (records, valid_until) = self.query(key.as_ref(), RecordType::AAAA, subnet).await?;
+        let ips = records
+            .into_iter()
+            .filter_map(|ip| match ip {
+                IpAddr::V6(ip) => Some(ip),
+                IpAddr::V4(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        Ok(self
+            .cache_ipv6
+            .insert(cache_key, Arc::new(ips), valid_until))
+    }
+
+    async fn query(
+        &self,
+        key: &str,
+        record_type: RecordType,
+        subnet: EcsSubnet,
+    ) -> mail_auth::Result<(Vec<IpAddr>, Instant)> {
+        let mut edns = Edns::new();
+        edns.set_max_payload(1232).set_version(0);
+        edns.options_mut()
+            .insert(EdnsOption::Subnet(ClientSubnet::new(
+                subnet.address,
+                subnet.source_prefix,
+                0,
+            )));
+
+        let mut message = Message::new();
+        message
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(
+                Name::from_str_relaxed(key).map_err(ResolveError::from)?,
+                record_type,
+            ))
+            .set_edns(edns);
+
+        // Failed response codes are classified by the name server pool, the same way
+        // they are for lookups without ECS
+        let response = self
+            .pool
+            .send(DnsRequest::new(message, DnsRequestOptions::default()))
+            .first_answer()
+            .await?;
+
+        let mut ips = Vec::new();
+        let mut ttl = u32::MAX;
+        for record in response.answers() {
+            let ip = match record.data() {
+                Some(RData::A(a)) => IpAddr::V4(a.0),
+                Some(RData::AAAA(aaaa)) => IpAddr::V6(aaaa.0),
+                _ => continue,
+            };
+            ips.push(ip);
+            ttl = ttl.min(record.ttl());
+        }
+
+        if !ips.is_empty() {
+            let ttl = Duration::from_secs(ttl as u64).clamp(self.min_ttl, self.max_ttl);
+            Ok((ips, Instant::now() + ttl))
+        } else {
+            Err(mail_auth::Error::DnsRecordNotFound(
+                response.response_code(),
+            ))
+        }
+    }
+
+    fn client_subnet(&self, source: IpAddr) -> EcsSubnet {
+        // Only disclose the network portion of the source address
+        match source {
+            IpAddr::V4(ip) => EcsSubnet {
+                address: IpAddr::V4(Ipv4Addr::from(
+                    u32::from(ip)
+                        & u32::MAX
+                            .checked_shl(32 - self.ipv4_prefix as u32)
+                            .unwrap_or(0),
+                )),
+                source_prefix: self.ipv4_prefix,
+            },
+            IpAddr::V6(ip) => EcsSubnet {
+                address: IpAddr::V6(Ipv6Addr::from(
+                    u128::from(ip)
+                        & u128::MAX
+                            .checked_shl(128 - self.ipv6_prefix as u32)
+                            .unwrap_or(0),
+                )),
+                source_prefix: self.ipv6_prefix,
+            },
+        }
+    }
+}
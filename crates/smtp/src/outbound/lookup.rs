@@ -21,33 +21,160 @@
  * for more details.
 */
 
-use std::{net::IpAddr, sync::Arc};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
-use mail_auth::{IpLookupStrategy, MX};
+use mail_auth::{
+    common::{lru::DnsCache, resolver::IntoFqdn},
+    hickory_resolver::proto::rr::RecordType,
+    IpLookupStrategy, Resolver, MX,
+};
 use rand::{seq::SliceRandom, Rng};
 use utils::config::KeyLookup;
 
 use crate::{
-    config::EnvelopeKey,
-    core::SMTP,
-    queue::{Error, ErrorDetails, Status},
+    config::{EnvelopeKey, RelayHost, RequireOptional, SourceIpSelection},
+    core::{rng::SelectionRng, NamedResolver, Resolvers, SMTP},
+    queue::{Error, Status},
 };
 
-use super::NextHop;
+use super::{dane::DnssecStatus, ecs::EcsResolver, NextHop};
 
 pub struct IpLookupResult {
     pub source_ipv4: Option<IpAddr>,
     pub source_ipv6: Option<IpAddr>,
     pub remote_ips: Vec<IpAddr>,
+    pub dnssec: DnssecStatus,
+}
+
+pub struct MxLookupResult {
+    pub mx: Arc<Vec<MX>>,
+    pub dnssec: DnssecStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+impl Resolvers {
+    pub fn dns_for(&self, domain: &str) -> &Resolver {
+        self.named_for(domain)
+            .map_or(&self.dns, |named| &named.resolver)
+    }
+
+    /// Returns the ECS resolver sharing its name servers with the one returned by `dns_for`.
+    pub fn ecs_for(&self, domain: &str) -> Option<&EcsResolver> {
+        match self.named_for(domain) {
+            Some(named) => named.ecs.as_ref(),
+            None => self.ecs.as_ref(),
+        }
+    }
+
+    fn named_for(&self, domain: &str) -> Option<&NamedResolver> {
+        let domain = domain.strip_suffix('.').unwrap_or(domain);
+        self.named.iter().find(|named| {
+            named
+                .domains
+                .iter()
+                .any(|pattern| domain_matches(pattern, domain))
+        })
+    }
+
+    pub async fn srv_lookup<'x>(
+        &self,
+        key: impl IntoFqdn<'x>,
+    ) -> mail_auth::Result<Arc<Vec<SrvRecord>>> {
+        let key = key.into_fqdn();
+        if let Some(value) = self.cache.srv.get(key.as_ref()) {
+            return Ok(value);
+        }
+
+        #[cfg(any(test, feature = "test_mode"))]
+        if true {
+            return mail_auth::common::resolver::mock_resolve(key.as_ref());
+        }
+
+        let srv_lookup = self.srv.srv_lookup(key.as_ref()).await?;
+        let records = srv_lookup
+            .iter()
+            .map(|srv| SrvRecord {
+                priority: srv.priority(),
+                weight: srv.weight(),
+                port: srv.port(),
+                target: srv.target().to_lowercase().to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(self.cache.srv.insert(
+            key.into_owned(),
+            Arc::new(records),
+            srv_lookup.as_lookup().valid_until(),
+        ))
+    }
+
+    #[cfg(feature = "test_mode")]
+    pub fn srv_add<'x>(
+        &self,
+        key: impl IntoFqdn<'x>,
+        value: Vec<SrvRecord>,
+        valid_until: std::time::Instant,
+    ) {
+        self.cache
+            .srv
+            .insert(key.into_fqdn().into_owned(), Arc::new(value), valid_until);
+    }
 }
 
 impl SMTP {
+    pub async fn mx_lookup(
+        &self,
+        domain: &str,
+        envelope: &impl KeyLookup<Key = EnvelopeKey>,
+    ) -> mail_auth::Result<MxLookupResult> {
+        let mx = self.resolvers.dns_for(domain).mx_lookup(domain).await?;
+
+        // Obtain DNSSEC status, only needed when DANE is enabled
+        let dnssec = if !matches!(
+            self.queue.config.tls.dane.eval(envelope).await,
+            RequireOptional::Disable
+        ) {
+            self.resolvers.dnssec_status(domain, RecordType::MX).await
+        } else {
+            DnssecStatus::Unknown
+        };
+
+        Ok(MxLookupResult { mx, dnssec })
+    }
+
     pub async fn ip_lookup(
         &self,
         key: &str,
         strategy: IpLookupStrategy,
         max_results: usize,
+        source_ipv4: Option<IpAddr>,
+        source_ipv6: Option<IpAddr>,
     ) -> mail_auth::Result<Vec<IpAddr>> {
+        let resolver = self.resolvers.dns_for(key);
+        let ecs = self.resolvers.ecs_for(key);
+
+        // Address families unavailable on this host are never looked up
+        let strategy = if self.queue.config.disable_ipv4 {
+            IpLookupStrategy::Ipv6Only
+        } else if self.queue.config.disable_ipv6 {
+            IpLookupStrategy::Ipv4Only
+        } else {
+            strategy
+        };
         let (has_ipv4, has_ipv6, v4_first) = match strategy {
             IpLookupStrategy::Ipv4Only => (true, false, false),
             IpLookupStrategy::Ipv6Only => (false, true, false),
@@ -55,7 +182,11 @@ impl SMTP {
             IpLookupStrategy::Ipv6thenIpv4 => (true, true, false),
         };
         let ipv4_addrs = if has_ipv4 {
-            match self.resolvers.dns.ipv4_lookup(key).await {
+            let result = match (ecs, source_ipv4) {
+                (Some(ecs), Some(source_ip)) => ecs.ipv4_lookup(key, source_ip).await,
+                _ => resolver.ipv4_lookup(key).await,
+            };
+            match result {
                 Ok(addrs) => addrs,
                 Err(_) if has_ipv6 => Arc::new(Vec::new()),
                 Err(err) => return Err(err),
@@ -65,11 +196,16 @@ impl SMTP {
         };
 
         if has_ipv6 {
-            let ipv6_addrs = match self.resolvers.dns.ipv6_lookup(key).await {
+            let result = match (ecs, source_ipv6) {
+                (Some(ecs), Some(source_ip)) => ecs.ipv6_lookup(key, source_ip).await,
+                _ => resolver.ipv6_lookup(key).await,
+            };
+            let ipv6_addrs = match result {
                 Ok(addrs) => addrs,
                 Err(_) if !ipv4_addrs.is_empty() => Arc::new(Vec::new()),
                 Err(err) => return Err(err),
             };
+            log_dropped_ips(key, ipv4_addrs.len() + ipv6_addrs.len(), max_results);
             if v4_first {
                 Ok(ipv4_addrs
                     .iter()
@@ -88,6 +224,7 @@ impl SMTP {
                     .collect())
             }
         } else {
+            log_dropped_ips(key, ipv4_addrs.len(), max_results);
             Ok(ipv4_addrs
                 .iter()
                 .take(max_results)
@@ -97,69 +234,95 @@ impl SMTP {
         }
     }
 
+    /// Resolves the addresses of a remote host, returning at most `max_multihomed`
+    /// addresses, which must be at least 1.
     pub async fn resolve_host(
         &self,
         remote_host: &NextHop<'_>,
         envelope: &impl KeyLookup<Key = EnvelopeKey>,
         max_multihomed: usize,
     ) -> Result<IpLookupResult, Status<(), Error>> {
-        let remote_ips = self
+        // Source addresses are selected before the lookup so they can be sent as ECS hints.
+        // Obtain source IPv4 addresses
+        let source_ipv4 = if !self.queue.config.disable_ipv4 {
+            self.queue
+                .config
+                .source_ip
+                .ipv4
+                .eval(envelope)
+                .await
+                .as_slice()
+        } else {
+            &[]
+        };
+
+        // Obtain source IPv6 addresses
+        let source_ipv6 = if !self.queue.config.disable_ipv6 {
+            self.queue
+                .config
+                .source_ip
+                .ipv6
+                .eval(envelope)
+                .await
+                .as_slice()
+        } else {
+            &[]
+        };
+        let (source_ipv4, source_ipv6) =
+            select_source_ips(source_ipv4, source_ipv6, self.queue.source_ip.as_ref());
+
+        let fqdn_hostname = remote_host.fqdn_hostname();
+        let mut remote_ips = self
             .ip_lookup(
-                remote_host.fqdn_hostname().as_ref(),
+                fqdn_hostname.as_ref(),
                 *self.queue.config.ip_strategy.eval(envelope).await,
-                max_multihomed,
+                usize::MAX,
+                source_ipv4,
+                source_ipv6,
             )
             .await
-            .map_err(|err| {
-                if let mail_auth::Error::DnsRecordNotFound(_) = &err {
-                    Status::PermanentFailure(Error::ConnectionError(ErrorDetails {
-                        entity: remote_host.hostname().to_string(),
-                        details: "record not found for MX".to_string(),
-                    }))
-                } else {
-                    Status::TemporaryFailure(Error::ConnectionError(ErrorDetails {
-                        entity: remote_host.hostname().to_string(),
-                        details: format!("lookup error: {err}"),
-                    }))
-                }
-            })?;
+            .map_err(|err| Status::from_dns_error(remote_host.hostname(), err))?;
 
         if !remote_ips.is_empty() {
-            let mut result = IpLookupResult {
-                source_ipv4: None,
-                source_ipv6: None,
-                remote_ips,
-            };
-
-            // Obtain source IPv4 address
-            let source_ips = self.queue.config.source_ip.ipv4.eval(envelope).await;
-            match source_ips.len().cmp(&1) {
-                std::cmp::Ordering::Equal => {
-                    result.source_ipv4 = IpAddr::from(*source_ips.first().unwrap()).into();
-                }
-                std::cmp::Ordering::Greater => {
-                    result.source_ipv4 =
-                        IpAddr::from(source_ips[rand::thread_rng().gen_range(0..source_ips.len())])
-                            .into();
-                }
-                std::cmp::Ordering::Less => (),
+            // Try addresses that recently accepted connections first and the ones that
+            // recently failed last, keeping the order given by the lookup strategy otherwise.
+            // Ranking happens before truncation so that addresses beyond the multihomed
+            // limit can replace the ones that recently failed.
+            if remote_ips.len() > 1 {
+                remote_ips.sort_by_key(|ip| match self.queue.ip_feedback.get(ip) {
+                    Some(true) => 0,
+                    None => 1,
+                    Some(false) => 2,
+                });
             }
+            log_dropped_ips(fqdn_hostname.as_ref(), remote_ips.len(), max_multihomed);
+            remote_ips.truncate(max_multihomed);
 
-            // Obtain source IPv6 address
-            let source_ips = self.queue.config.source_ip.ipv6.eval(envelope).await;
-            match source_ips.len().cmp(&1) {
-                std::cmp::Ordering::Equal => {
-                    result.source_ipv6 = IpAddr::from(*source_ips.first().unwrap()).into();
-                }
-                std::cmp::Ordering::Greater => {
-                    result.source_ipv6 =
-                        IpAddr::from(source_ips[rand::thread_rng().gen_range(0..source_ips.len())])
-                            .into();
-                }
-                std::cmp::Ordering::Less => (),
-            }
+            // Obtain DNSSEC status, only needed when DANE is enabled
+            let dnssec = if !matches!(
+                self.queue.config.tls.dane.eval(envelope).await,
+                RequireOptional::Disable
+            ) {
+                self.resolvers
+                    .dnssec_status(
+                        fqdn_hostname.as_ref(),
+                        if remote_ips[0].is_ipv4() {
+                            RecordType::A
+                        } else {
+                            RecordType::AAAA
+                        },
+                    )
+                    .await
+            } else {
+                DnssecStatus::Unknown
+            };
 
-            Ok(result)
+            Ok(IpLookupResult {
+                source_ipv4,
+                source_ipv6,
+                remote_ips,
+                dnssec,
+            })
         } else {
             Err(Status::TemporaryFailure(Error::DnsError(format!(
                 "No IP addresses found for {:?}.",
@@ -169,11 +332,123 @@ impl SMTP {
     }
 }
 
+impl SMTP {
+    /// Records whether a connection to a remote address succeeded, used to order the
+    /// addresses of multi-homed hosts on later deliveries.
+    pub fn record_ip_feedback(&self, remote_ip: IpAddr, success: bool) {
+        let ttl = self.queue.config.ip_feedback_ttl;
+        if !ttl.is_zero() {
+            self.queue
+                .ip_feedback
+                .insert(remote_ip, success, Instant::now() + ttl);
+        }
+    }
+}
+
+/// Chooses which of the configured source addresses is used for a connection.
+pub trait SourceIpSelector: Send + Sync {
+    /// Returns the positions of the IPv4 and IPv6 addresses to use out of `num_ipv4` and
+    /// `num_ipv6` addresses. It is called once per lookup, only when at least one of the
+    /// families has more than one address, and the position of a family with a single
+    /// address is ignored.
+    fn select(&self, num_ipv4: usize, num_ipv6: usize) -> (usize, usize);
+}
+
+#[derive(Default)]
+pub struct RandomSourceIp(pub SelectionRng);
+
+/// Advances a shared position once per lookup, for both address families.
+#[derive(Default)]
+pub struct RoundRobinSourceIp(pub AtomicUsize);
+
+impl SourceIpSelector for RandomSourceIp {
+    fn select(&self, num_ipv4: usize, num_ipv6: usize) -> (usize, usize) {
+        self.0.with(|rng| {
+            let mut select = |num_ips: usize| {
+                if num_ips > 1 {
+                    rng.gen_range(0..num_ips)
+                } else {
+                    0
+                }
+            };
+            (select(num_ipv4), select(num_ipv6))
+        })
+    }
+}
+
+impl SourceIpSelector for RoundRobinSourceIp {
+    fn select(&self, num_ipv4: usize, num_ipv6: usize) -> (usize, usize) {
+        let seq = self.0.fetch_add(1, Ordering::Relaxed);
+        (seq % num_ipv4.max(1), seq % num_ipv6.max(1))
+    }
+}
+
+impl SourceIpSelection {
+    pub fn selector(&self) -> Box<dyn SourceIpSelector> {
+        match self {
+            SourceIpSelection::Random => Box::<RandomSourceIp>::default(),
+            SourceIpSelection::RoundRobin => Box::<RoundRobinSourceIp>::default(),
+        }
+    }
+}
+
+pub fn select_source_ips(
+    ipv4: &[Ipv4Addr],
+    ipv6: &[Ipv6Addr],
+    selector: &(impl SourceIpSelector + ?Sized),
+) -> (Option<IpAddr>, Option<IpAddr>) {
+    let (pos_ipv4, pos_ipv6) = if ipv4.len() > 1 || ipv6.len() > 1 {
+        selector.select(ipv4.len(), ipv6.len())
+    } else {
+        (0, 0)
+    };
+
+    (
+        select_source_ip(ipv4, pos_ipv4),
+        select_source_ip(ipv6, pos_ipv6),
+    )
+}
+
+fn select_source_ip<T: Copy + Into<IpAddr>>(source_ips: &[T], pos: usize) -> Option<IpAddr> {
+    match source_ips.len() {
+        0 => None,
+        1 => Some(source_ips[0].into()),
+        _ => Some(source_ips[pos].into()),
+    }
+}
+
+fn log_dropped_ips(key: &str, num_ips: usize, max_results: usize) {
+    if num_ips > max_results {
+        tracing::debug!(
+            context = "dns",
+            event = "truncated",
+            hostname = key,
+            limit = max_results,
+            dropped = num_ips - max_results,
+            "Dropped {} addresses exceeding the multihomed limit.",
+            num_ips - max_results
+        );
+    }
+}
+
+fn domain_matches(pattern: &str, domain: &str) -> bool {
+    if let Some(suffix) = pattern.strip_prefix("*.") {
+        domain.len() > suffix.len()
+            && domain.as_bytes()[domain.len() - suffix.len() - 1] == b'.'
+            && domain.as_bytes()[domain.len() - suffix.len()..]
+                .eq_ignore_ascii_case(suffix.as_bytes())
+    } else {
+        pattern.eq_ignore_ascii_case(domain)
+    }
+}
+
 pub trait ToNextHop {
+    /// Returns at most `max_mx` hosts, which must be at least 1, or `None` for a Null MX.
     fn to_remote_hosts<'x, 'y: 'x>(
         &'x self,
         domain: &'y str,
         max_mx: usize,
+        rng: &mut (impl Rng + ?Sized),
     ) -> Option<Vec<NextHop<'_>>>;
 }
 
@@ -182,6 +457,7 @@ impl ToNextHop for Vec<MX> {
         &'x self,
         domain: &'y str,
         max_mx: usize,
+        rng: &mut (impl Rng + ?Sized),
     ) -> Option<Vec<NextHop<'_>>> {
         if !self.is_empty() {
             // Obtain max number of MX hosts to process
@@ -190,7 +466,7 @@ impl ToNextHop for Vec<MX> {
             'outer: for mx in self.iter() {
                 if mx.exchanges.len() > 1 {
                     let mut slice = mx.exchanges.iter().collect::<Vec<_>>();
-                    slice.shuffle(&mut rand::thread_rng());
+                    slice.shuffle(rng);
                     for remote_host in slice {
                         remote_hosts.push(NextHop::MX(remote_host.as_str()));
                         if remote_hosts.len() == max_mx {
@@ -216,3 +492,68 @@ impl ToNextHop for Vec<MX> {
         }
     }
 }
+
+pub trait ToSrvNextHop {
+    fn to_srv_hosts<'x>(
+        &'x self,
+        relay: &'x RelayHost,
+        max_hosts: usize,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Option<Vec<NextHop<'x>>>;
+}
+
+impl ToSrvNextHop for Vec<SrvRecord> {
+    fn to_srv_hosts<'x>(
+        &'x self,
+        relay: &'x RelayHost,
+        max_hosts: usize,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Option<Vec<NextHop<'x>>> {
+        // A single record with a target of "." means that the service
+        // is decidedly not available at this domain (RFC 2782)
+        let mut records = self
+            .iter()
+            .filter(|srv| srv.target != ".")
+            .collect::<Vec<_>>();
+        records.sort_by_key(|srv| srv.priority);
+
+        let mut remote_hosts = Vec::with_capacity(std::cmp::min(records.len(), max_hosts));
+        let mut pos = 0;
+        while pos < records.len() {
+            // Obtain all records with the same priority, placing
+            // the ones with weight 0 at the beginning of the list.
+            let priority = records[pos].priority;
+            let mut group = records[pos..]
+                .iter()
+                .take_while(|srv| srv.priority == priority)
+                .copied()
+                .collect::<Vec<_>>();
+            pos += group.len();
+            group.sort_by_key(|srv| srv.weight != 0);
+
+            // Weighted random selection
+            while !group.is_empty() {
+                let total_weight = group.iter().map(|srv| srv.weight as u32).sum::<u32>();
+                let selected_weight = rng.gen_range(0..=total_weight);
+                let mut running_weight = 0;
+                let selected = group
+                    .iter()
+                    .position(|srv| {
+                        running_weight += srv.weight as u32;
+                        running_weight >= selected_weight
+                    })
+                    .unwrap_or(0);
+                remote_hosts.push(NextHop::Srv(relay, group.remove(selected)));
+                if remote_hosts.len() == max_hosts {
+                    return remote_hosts.into();
+                }
+            }
+        }
+
+        if !remote_hosts.is_empty() {
+            remote_hosts.into()
+        } else {
+            None
+        }
+    }
+}
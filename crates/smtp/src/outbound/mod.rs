@@ -23,17 +23,27 @@
 
 use std::borrow::Cow;
 
+use mail_auth::hickory_resolver::{
+    error::{ResolveError, ResolveErrorKind},
+    proto::op::ResponseCode,
+};
 use mail_send::Credentials;
 use smtp_proto::{Response, Severity};
 use utils::config::ServerProtocol;
 
 use crate::{
     config::RelayHost,
-    queue::{DeliveryAttempt, Error, ErrorDetails, HostResponse, Message, Status},
+    queue::{
+        DeliveryAttempt, DnsErrorDetails, DnsErrorKind, Error, ErrorDetails, HostResponse, Message,
+        Status,
+    },
 };
 
+use self::lookup::SrvRecord;
+
 pub mod dane;
 pub mod delivery;
+pub mod ecs;
 #[cfg(feature = "local_delivery")]
 pub mod local;
 pub mod lookup;
@@ -149,6 +159,51 @@ impl Status<(), Error> {
         }))
     }
 
+    pub fn from_dns_error(entity: &str, err: mail_auth::Error) -> Self {
+        match &err {
+            mail_auth::Error::DnsRecordNotFound(code) => {
+                let rcode = Some(u16::from(*code));
+                if matches!(code, ResponseCode::NXDomain | ResponseCode::NoError) {
+                    Status::PermanentFailure(Error::DnsLookupError(DnsErrorDetails {
+                        kind: DnsErrorKind::NotFound,
+                        rcode,
+                        details: ErrorDetails {
+                            entity: entity.to_string(),
+                            details: format!("Record not found ({code})"),
+                        },
+                    }))
+                } else {
+                    Status::TemporaryFailure(Error::DnsLookupError(DnsErrorDetails {
+                        kind: DnsErrorKind::ServerFailure,
+                        rcode,
+                        details: ErrorDetails {
+                            entity: entity.to_string(),
+                            details: format!("Server failure ({code})"),
+                        },
+                    }))
+                }
+            }
+            mail_auth::Error::DnsError(reason) => {
+                // The resolver error is flattened to its description, and hickory reports
+                // both I/O and protocol timeouts as ResolveErrorKind::Timeout
+                let kind = if *reason == ResolveError::from(ResolveErrorKind::Timeout).to_string() {
+                    DnsErrorKind::ServerFailure
+                } else {
+                    DnsErrorKind::Unreachable
+                };
+                Status::TemporaryFailure(Error::DnsLookupError(DnsErrorDetails {
+                    kind,
+                    rcode: None,
+                    details: ErrorDetails {
+                        entity: entity.to_string(),
+                        details: reason.to_string(),
+                    },
+                }))
+            }
+            _ => Status::TemporaryFailure(Error::DnsError(err.to_string())),
+        }
+    }
+
     #[cfg(feature = "local_delivery")]
     pub fn local_error() -> Self {
         Status::TemporaryFailure(Error::ConnectionError(ErrorDetails {
@@ -235,6 +290,7 @@ impl From<Box<Message>> for DeliveryAttempt {
 pub enum NextHop<'x> {
     Relay(&'x RelayHost),
     MX(&'x str),
+    Srv(&'x RelayHost, &'x SrvRecord),
 }
 
 impl<'x> NextHop<'x> {
@@ -249,9 +305,13 @@ impl<'x> NextHop<'x> {
                 }
             }
             NextHop::Relay(host) => host.address.as_str(),
+            NextHop::Srv(_, srv) => srv.target.strip_suffix('.').unwrap_or(&srv.target),
         }
     }
 
+    /// Returns the hostname with a trailing dot. Names obtained from DNS (MX exchanges and
+    /// SRV targets) are already fully qualified and borrowed as is, only the implicit MX
+    /// of a domain without MX records requires an allocation.
     #[inline(always)]
     fn fqdn_hostname(&self) -> Cow<'_, str> {
         match self {
@@ -263,6 +323,13 @@ impl<'x> NextHop<'x> {
                 }
             }
             NextHop::Relay(host) => host.address.as_str().into(),
+            NextHop::Srv(_, srv) => {
+                if !srv.target.ends_with('.') {
+                    format!("{}.", srv.target).into()
+                } else {
+                    srv.target.as_str().into()
+                }
+            }
         }
     }
 
@@ -274,6 +341,7 @@ impl<'x> NextHop<'x> {
             #[cfg(not(feature = "test_mode"))]
             NextHop::MX(_) => 25,
             NextHop::Relay(host) => host.port,
+            NextHop::Srv(_, srv) => srv.port,
         }
     }
 
@@ -281,7 +349,7 @@ impl<'x> NextHop<'x> {
     fn credentials(&self) -> Option<&Credentials<String>> {
         match self {
             NextHop::MX(_) => None,
-            NextHop::Relay(host) => host.auth.as_ref(),
+            NextHop::Relay(host) | NextHop::Srv(host, _) => host.auth.as_ref(),
         }
     }
 
@@ -294,7 +362,7 @@ impl<'x> NextHop<'x> {
         #[cfg(not(feature = "test_mode"))]
         match self {
             NextHop::MX(_) => false,
-            NextHop::Relay(host) => host.tls_allow_invalid_certs,
+            NextHop::Relay(host) | NextHop::Srv(host, _) => host.tls_allow_invalid_certs,
         }
     }
 
@@ -302,7 +370,7 @@ impl<'x> NextHop<'x> {
     fn implicit_tls(&self) -> bool {
         match self {
             NextHop::MX(_) => false,
-            NextHop::Relay(host) => host.tls_implicit,
+            NextHop::Relay(host) | NextHop::Srv(host, _) => host.tls_implicit,
         }
     }
 
@@ -310,7 +378,7 @@ impl<'x> NextHop<'x> {
     fn is_smtp(&self) -> bool {
         match self {
             NextHop::MX(_) => true,
-            NextHop::Relay(host) => host.protocol == ServerProtocol::Smtp,
+            NextHop::Relay(host) | NextHop::Srv(host, _) => host.protocol == ServerProtocol::Smtp,
         }
     }
 }
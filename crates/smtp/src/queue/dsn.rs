@@ -438,6 +438,13 @@ impl Error {
             Error::DnsError(err) => {
                 let _ = write!(dsn, "<{addr}> (failed to lookup '{domain}': {err})\r\n",);
             }
+            Error::DnsLookupError(err) => {
+                let _ = write!(
+                    dsn,
+                    "<{}> (failed to lookup '{}': {})\r\n",
+                    addr, err.details.entity, err.details.details
+                );
+            }
             Error::ConnectionError(details) => {
                 let _ = write!(
                     dsn,
@@ -140,6 +140,7 @@ pub struct HostResponse<T> {
 #[derive(Debug, PartialEq, Eq)]
 pub enum Error {
     DnsError(String),
+    DnsLookupError(DnsErrorDetails),
     UnexpectedResponse(HostResponse<ErrorDetails>),
     ConnectionError(ErrorDetails),
     TlsError(ErrorDetails),
@@ -156,6 +157,23 @@ pub struct ErrorDetails {
     pub details: String,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub struct DnsErrorDetails {
+    pub kind: DnsErrorKind,
+    pub rcode: Option<u16>,
+    pub details: ErrorDetails,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsErrorKind {
+    /// The domain does not exist (NXDOMAIN) or has no records of the requested type.
+    NotFound,
+    /// The server failed to answer (SERVFAIL, REFUSED) or the query timed out.
+    ServerFailure,
+    /// No name server could be reached.
+    Unreachable,
+}
+
 pub struct DeliveryAttempt {
     pub span: tracing::Span,
     pub in_flight: Vec<InFlight>,
@@ -420,6 +438,13 @@ impl Display for Error {
             Error::DnsError(err) => {
                 write!(f, "DNS lookup failed: {err}")
             }
+            Error::DnsLookupError(err) => {
+                write!(
+                    f,
+                    "DNS lookup for '{}' failed: {}",
+                    err.details.entity, err.details.details
+                )
+            }
             Error::ConnectionError(details) => {
                 write!(
                     f,
@@ -32,8 +32,8 @@ use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
 use super::{
-    instant_to_timestamp, Domain, DomainPart, Error, ErrorDetails, HostResponse,
-    InstantFromTimestamp, Message, Recipient, Schedule, Status, RCPT_STATUS_CHANGED,
+    instant_to_timestamp, DnsErrorDetails, DnsErrorKind, Domain, DomainPart, Error, ErrorDetails,
+    HostResponse, InstantFromTimestamp, Message, Recipient, Schedule, Status, RCPT_STATUS_CHANGED,
 };
 
 pub trait QueueSerializer: Sized {
@@ -385,6 +385,41 @@ impl QueueSerializer for i16 {
     }
 }
 
+impl QueueSerializer for DnsErrorDetails {
+    fn serialize(&self, buf: &mut String) {
+        match self.kind {
+            DnsErrorKind::NotFound => 0usize,
+            DnsErrorKind::ServerFailure => 1,
+            DnsErrorKind::Unreachable => 2,
+        }
+        .serialize(buf);
+        self.rcode
+            .map_or(0, |rcode| rcode as usize + 1)
+            .serialize(buf);
+        self.details.serialize(buf);
+    }
+
+    fn deserialize(bytes: &mut Iter<'_, u8>) -> Option<Self> {
+        let kind = match usize::deserialize(bytes)? {
+            0 => DnsErrorKind::NotFound,
+            1 => DnsErrorKind::ServerFailure,
+            2 => DnsErrorKind::Unreachable,
+            _ => return None,
+        };
+        let rcode = match usize::deserialize(bytes)? {
+            0 => None,
+            rcode => Some((rcode - 1) as u16),
+        };
+
+        DnsErrorDetails {
+            kind,
+            rcode,
+            details: ErrorDetails::deserialize(bytes)?,
+        }
+        .into()
+    }
+}
+
 impl QueueSerializer for ErrorDetails {
     fn serialize(&self, buf: &mut String) {
         self.entity.serialize(buf);
@@ -515,6 +550,10 @@ impl QueueSerializer for Error {
                 buf.push('8');
                 e.serialize(buf);
             }
+            Error::DnsLookupError(e) => {
+                buf.push('9');
+                e.serialize(buf);
+            }
         }
     }
 
@@ -529,6 +568,7 @@ impl QueueSerializer for Error {
             b'6' => Error::RateLimited.into(),
             b'7' => Error::ConcurrencyLimited.into(),
             b'8' => Error::Io(String::deserialize(bytes)?).into(),
+            b'9' => Error::DnsLookupError(DnsErrorDetails::deserialize(bytes)?).into(),
             _ => None,
         }
     }
@@ -345,7 +345,7 @@ impl LookupOrInsert for BayesTokenCache {
                     .finalize(),
             )),
         ) {
-            if let LookupValue::Counter { num } = result {
+            if let Some(num) = result.counter() {
                 let weights = Weights::from(num);
                 self.insert_positive(hash, weights);
                 weights
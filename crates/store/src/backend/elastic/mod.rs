@@ -28,7 +28,7 @@ use elasticsearch::{
         transport::{BuildError, SingleNodeConnectionPool, Transport, TransportBuilder},
         StatusCode, Url,
     },
-    indices::{IndicesCreateParts, IndicesExistsParts},
+    indices::{IndicesCreateParts, IndicesExistsParts, IndicesFlushParts},
     Elasticsearch, Error,
 };
 use serde_json::json;
@@ -175,6 +175,37 @@ impl ElasticSearchStore {
 
         Ok(())
     }
+
+    pub(crate) async fn flush(&self) -> crate::Result<()> {
+        let response = self
+            .index
+            .indices()
+            .flush(IndicesFlushParts::Index(INDEX_NAMES))
+            .send()
+            .await?;
+
+        if response.status_code().is_success() {
+            Ok(())
+        } else {
+            Err(crate::Error::InternalError(format!(
+                "ElasticSearch flush failed with status {}",
+                response.status_code()
+            )))
+        }
+    }
+
+    pub(crate) async fn ping(&self) -> crate::Result<()> {
+        let response = self.index.ping().send().await?;
+
+        if response.status_code().is_success() {
+            Ok(())
+        } else {
+            Err(crate::Error::Unavailable(format!(
+                "ElasticSearch ping failed with status {}",
+                response.status_code()
+            )))
+        }
+    }
 }
 
 impl From<Error> for crate::Error {
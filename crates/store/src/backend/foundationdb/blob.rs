@@ -51,7 +51,7 @@ impl FdbStore {
             .write(block_end as u16)
             .finalize();
         let key_len = begin.len();
-        let trx = self.db.create_trx()?;
+        let trx = self.read_trx().await?;
         let mut values = trx.get_ranges(
             RangeOption {
                 begin: KeySelector::first_greater_or_equal(begin),
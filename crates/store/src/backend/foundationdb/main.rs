@@ -26,7 +26,8 @@ use std::time::Duration;
 use foundationdb::{options::DatabaseOption, Database};
 use utils::config::{utils::AsKey, Config};
 
-use super::FdbStore;
+use super::{FdbStore, MAX_READ_VERSION_AGE};
+use crate::dispatch::options::StoreOptions;
 
 impl FdbStore {
     pub async fn open(config: &Config, prefix: impl AsKey) -> crate::Result<Self> {
@@ -54,6 +55,32 @@ impl FdbStore {
             db.set_option(DatabaseOption::DatacenterId(value))?;
         }
 
-        Ok(Self { guard, db })
+        // Reads within a request scope may share a read version up to this age
+        let read_version_max_age = config
+            .property::<Duration>((&prefix, "transaction.read-version.max-age"))?
+            .filter(|max_age| !max_age.is_zero());
+        if read_version_max_age.map_or(false, |max_age| max_age > MAX_READ_VERSION_AGE) {
+            return Err(format!(
+                "Property {prefix}.transaction.read-version.max-age cannot exceed {} seconds.",
+                MAX_READ_VERSION_AGE.as_secs()
+            )
+            .into());
+        }
+
+        Ok(Self {
+            guard,
+            db,
+            options: StoreOptions::parse(config, &prefix)?,
+            read_version_max_age,
+        })
+    }
+
+    pub(crate) async fn ping(&self) -> crate::Result<()> {
+        self.db
+            .create_trx()?
+            .get_read_version()
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
     }
 }
@@ -21,25 +21,46 @@
  * for more details.
 */
 
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
 use foundationdb::{api::NetworkAutoStop, Database, FdbError};
 
+use crate::dispatch::options::StoreOptions;
 use crate::Error;
 
 pub mod blob;
 pub mod main;
 pub mod read;
+pub mod snapshot;
 pub mod write;
 
 const MAX_VALUE_SIZE: usize = 100000;
+// FDB rejects transactions above 10MB, leave room for conflict ranges and bitmaps
+const MAX_TRANSACTION_SIZE: usize = 8_000_000;
+// FDB rejects read versions older than five seconds
+const MAX_READ_VERSION_AGE: Duration = Duration::from_secs(4);
 
 #[allow(dead_code)]
 pub struct FdbStore {
     db: Database,
     guard: NetworkAutoStop,
+    pub(crate) options: Arc<StoreOptions>,
+    pub(crate) read_version_max_age: Option<Duration>,
 }
 
 impl From<FdbError> for Error {
     fn from(error: FdbError) -> Self {
-        Self::InternalError(format!("FoundationDB error: {}", error.message()))
+        match error.code() {
+            // timed_out, transaction_timed_out
+            1004 | 1031 => Self::Timeout(format!("FoundationDB error: {}", error.message())),
+            // process_behind, database_locked, cluster_version_changed
+            1037 | 1038 | 1039 => {
+                Self::Unavailable(format!("FoundationDB error: {}", error.message()))
+            }
+            _ => Self::InternalError(format!("FoundationDB error: {}", error.message())),
+        }
     }
 }
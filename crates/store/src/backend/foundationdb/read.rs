@@ -21,24 +21,27 @@
  * for more details.
 */
 
+use std::time::Instant;
+
 use foundationdb::{
     future::FdbSlice,
     options::{self, StreamingMode},
     KeySelector, RangeOption, Transaction,
 };
-use futures::StreamExt;
+use futures::{future::try_join_all, StreamExt};
 use roaring::RoaringBitmap;
 
 use crate::{
     write::{
         bitmap::DeserializeBlock,
         key::{DeserializeBigEndian, KeySerializer},
-        BitmapClass, ValueClass,
+        AnyKey, BitmapClass, ValueClass,
     },
     BitmapKey, Deserialize, IterateParams, Key, ValueKey, U32_LEN, WITH_SUBSPACE,
 };
 
-use super::{FdbStore, MAX_VALUE_SIZE};
+use super::{FdbStore, MAX_READ_VERSION_AGE, MAX_VALUE_SIZE};
+use crate::dispatch::scope::{cached_read_version, set_read_version};
 
 #[cfg(feature = "fdb-chunked-bm")]
 pub(crate) enum ChunkedBitmap {
@@ -55,12 +58,28 @@ pub(crate) enum ChunkedValue {
 }
 
 impl FdbStore {
+    // Reuses the read version of the current read scope while it is fresh enough,
+    // saving a round-trip to the GRV proxies on every read
+    pub(crate) async fn read_trx(&self) -> crate::Result<Transaction> {
+        let trx = self.db.create_trx()?;
+
+        if let Some(max_age) = self.read_version_max_age {
+            if let Some(version) = cached_read_version(max_age) {
+                trx.set_read_version(version);
+            } else {
+                set_read_version(trx.get_read_version().await?);
+            }
+        }
+
+        Ok(trx)
+    }
+
     pub(crate) async fn get_value<U>(&self, key: impl Key) -> crate::Result<Option<U>>
     where
         U: Deserialize,
     {
         let key = key.serialize(WITH_SUBSPACE);
-        let trx = self.db.create_trx()?;
+        let trx = self.read_trx().await?;
 
         match read_chunked_value(&key, &trx, true).await? {
             ChunkedValue::Single(bytes) => U::deserialize(&bytes).map(Some),
@@ -69,49 +88,21 @@ impl FdbStore {
         }
     }
 
+    // Chunked values store their first chunk under the key itself
+    pub(crate) async fn key_exists(&self, key: impl Key) -> crate::Result<bool> {
+        let key = key.serialize(WITH_SUBSPACE);
+        let trx = self.read_trx().await?;
+        trx.get(&key, true)
+            .await
+            .map(|value| value.is_some())
+            .map_err(Into::into)
+    }
+
     pub(crate) async fn get_bitmap(
         &self,
-        mut key: BitmapKey<BitmapClass>,
+        key: BitmapKey<BitmapClass>,
     ) -> crate::Result<Option<RoaringBitmap>> {
-        #[cfg(feature = "fdb-chunked-bm")]
-        {
-            read_chunked_bitmap(&key.serialize(WITH_SUBSPACE), &self.db.create_trx()?, true)
-                .await
-                .map(Into::into)
-        }
-
-        #[cfg(not(feature = "fdb-chunked-bm"))]
-        {
-            let mut bm = RoaringBitmap::new();
-            let begin = key.serialize(WITH_SUBSPACE);
-            key.block_num = u32::MAX;
-            let end = key.serialize(WITH_SUBSPACE);
-            let key_len = begin.len();
-            let trx = self.db.create_trx()?;
-            let mut values = trx.get_ranges(
-                RangeOption {
-                    begin: KeySelector::first_greater_or_equal(begin),
-                    end: KeySelector::first_greater_or_equal(end),
-                    mode: StreamingMode::WantAll,
-                    reverse: false,
-                    ..RangeOption::default()
-                },
-                true,
-            );
-
-            while let Some(values) = values.next().await {
-                for value in values? {
-                    let key = value.key();
-                    if key.len() == key_len {
-                        bm.deserialize_block(
-                            value.value(),
-                            key.deserialize_be_u32(key.len() - U32_LEN)?,
-                        );
-                    }
-                }
-            }
-            Ok(if !bm.is_empty() { Some(bm) } else { None })
-        }
+        read_bitmap(key, &self.read_trx().await?).await
     }
 
     pub(crate) async fn iterate<T: Key>(
@@ -122,13 +113,14 @@ impl FdbStore {
         let begin = params.begin.serialize(WITH_SUBSPACE);
         let end = params.end.serialize(WITH_SUBSPACE);
 
-        let trx = self.db.create_trx()?;
+        let trx = self.read_trx().await?;
         let mut iter = trx.get_ranges(
             RangeOption {
                 begin: KeySelector::first_greater_or_equal(&begin),
                 end: KeySelector::first_greater_than(&end),
+                limit: if params.first { Some(1) } else { None },
                 mode: if params.first {
-                    options::StreamingMode::Small
+                    options::StreamingMode::Exact
                 } else {
                     options::StreamingMode::Iterator
                 },
@@ -152,12 +144,58 @@ impl FdbStore {
         Ok(())
     }
 
+    // FDB keeps no key count statistics, so keys are counted batch by batch
+    // and the transaction is renewed before its read version becomes too old
+    pub(crate) async fn estimate_count(
+        &self,
+        subspace: u8,
+        begin: Vec<u8>,
+        end: Option<Vec<u8>>,
+    ) -> crate::Result<u64> {
+        let begin = AnyKey {
+            subspace,
+            key: begin,
+        }
+        .serialize(WITH_SUBSPACE);
+        let end = match end {
+            Some(end) => KeySelector::first_greater_or_equal(
+                AnyKey { subspace, key: end }.serialize(WITH_SUBSPACE),
+            ),
+            None => KeySelector::first_greater_or_equal(vec![subspace + 1]),
+        };
+
+        let mut count = 0;
+        let mut trx = self.db.create_trx()?;
+        let mut trx_start = Instant::now();
+        let mut iteration = 1;
+        let mut range = Some(RangeOption {
+            begin: KeySelector::first_greater_or_equal(begin),
+            end,
+            mode: StreamingMode::Iterator,
+            ..Default::default()
+        });
+
+        while let Some(opt) = range {
+            if trx_start.elapsed() >= MAX_READ_VERSION_AGE {
+                trx = self.db.create_trx()?;
+                trx_start = Instant::now();
+                iteration = 1;
+            }
+            let values = trx.get_range(&opt, iteration, true).await?;
+            count += values.len() as u64;
+            iteration += 1;
+            range = opt.next_range(&values);
+        }
+
+        Ok(count)
+    }
+
     pub(crate) async fn get_counter(
         &self,
         key: impl Into<ValueKey<ValueClass>> + Sync + Send,
     ) -> crate::Result<i64> {
         let key = key.into().serialize(WITH_SUBSPACE);
-        if let Some(bytes) = self.db.create_trx()?.get(&key, true).await? {
+        if let Some(bytes) = self.read_trx().await?.get(&key, true).await? {
             Ok(i64::from_le_bytes(bytes[..].try_into().map_err(|_| {
                 crate::Error::InternalError("Invalid counter value.".to_string())
             })?))
@@ -165,6 +203,75 @@ impl FdbStore {
             Ok(0)
         }
     }
+
+    pub(crate) async fn get_counters(
+        &self,
+        keys: Vec<ValueKey<ValueClass>>,
+    ) -> crate::Result<Vec<i64>> {
+        let keys = keys
+            .into_iter()
+            .map(|key| key.serialize(WITH_SUBSPACE))
+            .collect::<Vec<_>>();
+        let trx = self.read_trx().await?;
+
+        try_join_all(keys.iter().map(|key| trx.get(key, true)))
+            .await?
+            .into_iter()
+            .map(|bytes| {
+                Ok(if let Some(bytes) = bytes {
+                    i64::from_le_bytes(bytes[..].try_into().map_err(|_| {
+                        crate::Error::InternalError("Invalid counter value.".to_string())
+                    })?)
+                } else {
+                    0
+                })
+            })
+            .collect()
+    }
+}
+
+pub(crate) async fn read_bitmap(
+    mut key: BitmapKey<BitmapClass>,
+    trx: &Transaction,
+) -> crate::Result<Option<RoaringBitmap>> {
+    #[cfg(feature = "fdb-chunked-bm")]
+    {
+        read_chunked_bitmap(&key.serialize(WITH_SUBSPACE), trx, true)
+            .await
+            .map(Into::into)
+    }
+
+    #[cfg(not(feature = "fdb-chunked-bm"))]
+    {
+        let mut bm = RoaringBitmap::new();
+        let begin = key.serialize(WITH_SUBSPACE);
+        key.block_num = u32::MAX;
+        let end = key.serialize(WITH_SUBSPACE);
+        let key_len = begin.len();
+        let mut values = trx.get_ranges(
+            RangeOption {
+                begin: KeySelector::first_greater_or_equal(begin),
+                end: KeySelector::first_greater_or_equal(end),
+                mode: StreamingMode::WantAll,
+                reverse: false,
+                ..RangeOption::default()
+            },
+            true,
+        );
+
+        while let Some(values) = values.next().await {
+            for value in values? {
+                let key = value.key();
+                if key.len() == key_len {
+                    bm.deserialize_block(
+                        value.value(),
+                        key.deserialize_be_u32(key.len() - U32_LEN)?,
+                    );
+                }
+            }
+        }
+        Ok(if !bm.is_empty() { Some(bm) } else { None })
+    }
 }
 
 pub(crate) async fn read_chunked_value(
@@ -183,8 +290,23 @@ pub(crate) async fn read_chunked_value(
                 .write(0u8)
                 .finalize();
 
+            let mut last_len = bytes.len();
             while let Some(bytes) = trx.get(&key, snapshot).await? {
+                // Only the last chunk can be shorter than the maximum size, anything
+                // else means the value was not written completely.
+                if last_len != MAX_VALUE_SIZE || bytes.len() > MAX_VALUE_SIZE {
+                    return Err(crate::Error::InternalError(format!(
+                        "Chunked value {key:?} is incomplete or corrupted"
+                    )));
+                }
+                last_len = bytes.len();
                 value.extend_from_slice(&bytes);
+
+                if key.last() == Some(&u8::MAX) {
+                    return Err(crate::Error::InternalError(format!(
+                        "Chunked value {key:?} has too many chunks"
+                    )));
+                }
                 *key.last_mut().unwrap() += 1;
             }
 
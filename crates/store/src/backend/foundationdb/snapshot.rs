@@ -0,0 +1,67 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use foundationdb::Transaction;
+use roaring::RoaringBitmap;
+
+use crate::{write::BitmapClass, BitmapKey, Deserialize, Key, WITH_SUBSPACE};
+
+use super::{
+    read::{read_bitmap, read_chunked_value, ChunkedValue},
+    FdbStore,
+};
+
+// All reads share the read version of a single transaction.
+pub struct FdbSnapshot {
+    trx: Transaction,
+}
+
+impl FdbStore {
+    pub(crate) fn snapshot(&self) -> crate::Result<FdbSnapshot> {
+        Ok(FdbSnapshot {
+            trx: self.db.create_trx()?,
+        })
+    }
+}
+
+impl FdbSnapshot {
+    pub(crate) async fn get_value<U>(&mut self, key: impl Key) -> crate::Result<Option<U>>
+    where
+        U: Deserialize,
+    {
+        let key = key.serialize(WITH_SUBSPACE);
+
+        match read_chunked_value(&key, &self.trx, true).await? {
+            ChunkedValue::Single(bytes) => U::deserialize(&bytes).map(Some),
+            ChunkedValue::Chunked { bytes, .. } => U::deserialize(&bytes).map(Some),
+            ChunkedValue::None => Ok(None),
+        }
+    }
+
+    pub(crate) async fn get_bitmap(
+        &mut self,
+        key: BitmapKey<BitmapClass>,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        read_bitmap(key, &self.trx).await
+    }
+}
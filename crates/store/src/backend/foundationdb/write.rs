@@ -35,17 +35,20 @@ use futures::StreamExt;
 use rand::Rng;
 
 use crate::{
+    dispatch::scope::set_read_version,
     write::{
+        assert::AssertValue,
         bitmap::{block_contains, DenseBitmap},
         key::KeySerializer,
-        Batch, BitmapClass, Operation, ValueClass, ValueOp, MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME,
+        Batch, BatchBuilder, BitmapClass, Operation, ValueClass, ValueOp, MAX_COMMIT_ATTEMPTS,
+        MAX_COMMIT_TIME,
     },
     BitmapKey, IndexKey, Key, LogKey, ValueKey, SUBSPACE_BITMAPS, SUBSPACE_VALUES, WITH_SUBSPACE,
 };
 
 use super::{
     read::{read_chunked_value, ChunkedValue},
-    FdbStore, MAX_VALUE_SIZE,
+    FdbStore, MAX_TRANSACTION_SIZE, MAX_VALUE_SIZE,
 };
 
 #[cfg(feature = "fdb-chunked-bm")]
@@ -69,6 +72,28 @@ impl BitmapOp {
 
 impl FdbStore {
     pub(crate) async fn write(&self, batch: Batch) -> crate::Result<()> {
+        // Large batches are written in several transactions to stay within FDB limits,
+        // a failure after the first commit leaves the earlier transactions in place
+        let batches = batch.split(MAX_TRANSACTION_SIZE)?;
+        let total = batches.len();
+        for (committed, batch) in batches.into_iter().enumerate() {
+            match self.write_(batch).await {
+                Ok(()) => (),
+                Err(err) if committed > 0 => {
+                    return Err(crate::Error::PartialCommit {
+                        committed,
+                        total,
+                        reason: err.to_string(),
+                    });
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn write_(&self, batch: Batch) -> crate::Result<()> {
         let start = Instant::now();
         let mut retry_count = 0;
         #[cfg(not(feature = "fdb-chunked-bm"))]
@@ -128,6 +153,20 @@ impl FdbStore {
 
                         if let ValueOp::Set(value) = op {
                             if !value.is_empty() && do_chunk {
+                                if value.len() >= MAX_VALUE_SIZE {
+                                    // Remove any chunks left by a previous, larger value
+                                    // so they are not read back as part of this one.
+                                    trx.clear_range(
+                                        &KeySerializer::new(key.len() + 1)
+                                            .write(key.as_slice())
+                                            .write(0u8)
+                                            .finalize(),
+                                        &KeySerializer::new(key.len() + 1)
+                                            .write(key.as_slice())
+                                            .write(u8::MAX)
+                                            .finalize(),
+                                    );
+                                }
                                 for (pos, chunk) in value.chunks(MAX_VALUE_SIZE).enumerate() {
                                     match pos.cmp(&1) {
                                         Ordering::Less => {}
@@ -152,7 +191,7 @@ impl FdbStore {
                             }
 
                             if matches!(class, ValueClass::ReservedId) {
-                                let block_num = DenseBitmap::block_num(document_id);
+                                let block_num = BitmapKey::block_for(document_id);
                                 if let Ok(Some(bytes)) = trx
                                     .get(
                                         &BitmapKey {
@@ -202,6 +241,8 @@ impl FdbStore {
                     }
                     Operation::Bitmap { class, set } => {
                         if retry_count == 0 {
+                            #[cfg(not(feature = "fdb-chunked-bm"))]
+                            let block_num = BitmapKey::block_for(document_id);
                             #[cfg(not(feature = "fdb-chunked-bm"))]
                             if *set {
                                 &mut set_bitmaps
@@ -213,11 +254,11 @@ impl FdbStore {
                                     account_id,
                                     collection,
                                     class,
-                                    block_num: DenseBitmap::block_num(document_id),
+                                    block_num,
                                 }
                                 .serialize(WITH_SUBSPACE),
                             )
-                            .or_insert_with(DenseBitmap::empty)
+                            .or_insert_with(|| DenseBitmap::for_block(block_num))
                             .set(document_id);
 
                             #[cfg(feature = "fdb-chunked-bm")]
@@ -260,13 +301,23 @@ impl FdbStore {
                         }
                         .serialize(WITH_SUBSPACE);
 
-                        let matches = match read_chunked_value(&key, &trx, false).await {
-                            Ok(ChunkedValue::Single(bytes)) => assert_value.matches(bytes.as_ref()),
-                            Ok(ChunkedValue::Chunked { bytes, .. }) => {
-                                assert_value.matches(bytes.as_ref())
-                            }
-                            Ok(ChunkedValue::None) => assert_value.is_none(),
-                            Err(_) => false,
+                        let matches = match assert_value {
+                            // Counters are stored as is, never chunked
+                            AssertValue::Counter(value) => match trx.get(&key, false).await {
+                                Ok(Some(bytes)) => assert_value.matches(&bytes),
+                                Ok(None) => *value == 0,
+                                Err(_) => false,
+                            },
+                            _ => match read_chunked_value(&key, &trx, false).await {
+                                Ok(ChunkedValue::Single(bytes)) => {
+                                    assert_value.matches(bytes.as_ref())
+                                }
+                                Ok(ChunkedValue::Chunked { bytes, .. }) => {
+                                    assert_value.matches(bytes.as_ref())
+                                }
+                                Ok(ChunkedValue::None) => assert_value.is_none(),
+                                Err(_) => false,
+                            },
                         };
 
                         if !matches {
@@ -372,7 +423,16 @@ impl FdbStore {
             }
 
             match trx.commit().await {
-                Ok(_) => {
+                Ok(committed) => {
+                    // Later reads in the same scope must observe this commit,
+                    // read-only transactions report a committed version of -1
+                    if let Some(version) = committed
+                        .committed_version()
+                        .ok()
+                        .filter(|version| *version > 0)
+                    {
+                        set_read_version(version);
+                    }
                     return Ok(());
                 }
                 Err(err) => {
@@ -389,6 +449,27 @@ impl FdbStore {
         }
     }
 
+    // Compare-and-set on an empty key, a failed assertion means the key exists
+    pub(crate) async fn put_if_absent(
+        &self,
+        key: ValueKey<ValueClass>,
+        value: Vec<u8>,
+    ) -> crate::Result<bool> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(key.account_id)
+            .with_collection(key.collection)
+            .update_document(key.document_id)
+            .assert_value(key.class.clone(), ())
+            .set(key.class, value);
+
+        match self.write(batch.build()).await {
+            Ok(_) => Ok(true),
+            Err(crate::Error::AssertValueFailed) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
     pub(crate) async fn purge_bitmaps(&self) -> crate::Result<()> {
         // Obtain all empty bitmaps
         let trx = self.db.create_trx()?;
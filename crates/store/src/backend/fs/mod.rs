@@ -115,6 +115,15 @@ impl FsStore {
         }
     }
 
+    pub(crate) async fn ping(&self) -> crate::Result<()> {
+        fs::metadata(&self.path).await.map(|_| ()).map_err(|err| {
+            crate::Error::Unavailable(format!(
+                "Blob store path {:?} is not accessible: {}",
+                self.path, err
+            ))
+        })
+    }
+
     fn build_path(&self, key: &[u8]) -> PathBuf {
         let mut path = self.path.clone();
 
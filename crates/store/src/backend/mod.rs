@@ -51,6 +51,15 @@ pub const ID_ASSIGNMENT_EXPIRY: u64 = 60 * 60; // seconds
 
 impl From<std::io::Error> for crate::Error {
     fn from(err: std::io::Error) -> Self {
-        Self::InternalError(format!("IO error: {}", err))
+        match err.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFound(format!("IO error: {}", err)),
+            std::io::ErrorKind::TimedOut => Self::Timeout(format!("IO error: {}", err)),
+            std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::NotConnected
+            | std::io::ErrorKind::BrokenPipe => Self::Unavailable(format!("IO error: {}", err)),
+            _ => Self::InternalError(format!("IO error: {}", err)),
+        }
     }
 }
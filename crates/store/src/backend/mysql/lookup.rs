@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use mysql_async::{prelude::Queryable, Params, Row};
+use mysql_async::{consts::ColumnType, prelude::Queryable, Params, Row, TxOpts};
 
 use crate::{IntoRows, QueryResult, QueryType, Value};
 
@@ -56,6 +56,31 @@ impl MysqlStore {
                 .map_or_else(|e| Err(e.into()), |r| Ok(T::from_query_all(r))),
         }
     }
+
+    pub(crate) async fn query_batch(
+        &self,
+        queries: Vec<(&str, Vec<Value<'_>>)>,
+    ) -> crate::Result<Vec<crate::Rows>> {
+        let mut conn = self.conn_pool.get_conn().await?;
+        let mut trx = conn.start_transaction(TxOpts::default()).await?;
+        let mut results = Vec::with_capacity(queries.len());
+
+        for (query, params) in queries {
+            let s = trx.prep(query).await?;
+            let params = Params::Positional(params.into_iter().map(Into::into).collect());
+            results.push(trx.exec::<Row, _, _>(s, params).await?.into_rows());
+        }
+
+        trx.commit().await?;
+
+        Ok(results)
+    }
+
+    pub(crate) async fn prepare(&self, query: &str) -> crate::Result<()> {
+        let mut conn = self.conn_pool.get_conn().await?;
+        conn.prep(query).await?;
+        Ok(())
+    }
 }
 
 impl From<crate::Value<'_>> for mysql_async::Value {
@@ -66,6 +91,7 @@ impl From<crate::Value<'_>> for mysql_async::Value {
             crate::Value::Float(f) => mysql_async::Value::Double(f),
             crate::Value::Text(t) => mysql_async::Value::Bytes(t.into_owned().into_bytes()),
             crate::Value::Blob(b) => mysql_async::Value::Bytes(b.into_owned()),
+            crate::Value::Json(v) => mysql_async::Value::Bytes(v.to_string().into_bytes()),
             crate::Value::Null => mysql_async::Value::NULL,
         }
     }
@@ -95,12 +121,7 @@ impl IntoRows for Vec<mysql_async::Row> {
             rows: self
                 .into_iter()
                 .map(|r| crate::Row {
-                    values: r
-                        .unwrap_raw()
-                        .into_iter()
-                        .flatten()
-                        .map(Into::into)
-                        .collect(),
+                    values: row_values(r),
                 })
                 .collect(),
         }
@@ -115,12 +136,7 @@ impl IntoRows for Vec<mysql_async::Row> {
             rows: self
                 .into_iter()
                 .map(|r| crate::Row {
-                    values: r
-                        .unwrap_raw()
-                        .into_iter()
-                        .flatten()
-                        .map(Into::into)
-                        .collect(),
+                    values: row_values(r),
                 })
                 .collect(),
         }
@@ -134,12 +150,7 @@ impl IntoRows for Vec<mysql_async::Row> {
 impl IntoRows for Option<mysql_async::Row> {
     fn into_row(self) -> Option<crate::Row> {
         self.map(|row| crate::Row {
-            values: row
-                .unwrap_raw()
-                .into_iter()
-                .flatten()
-                .map(Into::into)
-                .collect(),
+            values: row_values(row),
         })
     }
 
@@ -151,3 +162,23 @@ impl IntoRows for Option<mysql_async::Row> {
         unreachable!()
     }
 }
+
+// JSON columns are returned as text, the column type tells them apart
+fn row_values(row: mysql_async::Row) -> Vec<crate::Value<'static>> {
+    let columns = row.columns();
+    row.unwrap_raw()
+        .into_iter()
+        .zip(columns.iter())
+        .filter_map(|(value, column)| match value? {
+            mysql_async::Value::Bytes(bytes)
+                if column.column_type() == ColumnType::MYSQL_TYPE_JSON =>
+            {
+                Some(serde_json::from_slice(&bytes).map_or_else(
+                    |_| mysql_async::Value::Bytes(bytes).into(),
+                    crate::Value::Json,
+                ))
+            }
+            value => Some(value.into()),
+        })
+        .collect()
+}
@@ -21,6 +21,8 @@
  * for more details.
 */
 
+use std::time::Duration;
+
 use mysql_async::{prelude::Queryable, OptsBuilder, Pool, PoolConstraints, PoolOpts, SslOpts};
 use utils::config::utils::AsKey;
 
@@ -30,6 +32,7 @@ use crate::{
 };
 
 use super::MysqlStore;
+use crate::dispatch::options::StoreOptions;
 
 impl MysqlStore {
     pub async fn open(config: &utils::config::Config, prefix: impl AsKey) -> crate::Result<Self> {
@@ -64,12 +67,26 @@ impl MysqlStore {
         if let Some(n_size) = config.property::<usize>((&prefix, "pool.max-connections"))? {
             pool_max = n_size;
         }
+        // Connections past their lifetime are closed once returned to the pool, idle
+        // connections above the minimum are closed after the idle timeout
+        let max_lifetime =
+            config.property_or_static::<Duration>((&prefix, "pool.max-lifetime"), "30m")?;
+        let idle_timeout =
+            config.property_or_static::<Duration>((&prefix, "pool.idle-timeout"), "10m")?;
         opts = opts.pool_opts(
-            PoolOpts::default().with_constraints(PoolConstraints::new(pool_min, pool_max).unwrap()),
+            PoolOpts::default()
+                .with_constraints(PoolConstraints::new(pool_min, pool_max).unwrap())
+                .with_abs_conn_ttl(Some(max_lifetime))
+                .with_inactive_connection_ttl(idle_timeout),
         );
 
         let db = Self {
             conn_pool: Pool::new(opts),
+            options: StoreOptions::parse(config, &prefix)?,
+            slow_query: config
+                .property::<u64>((&prefix, "slow-query-ms"))?
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
         };
 
         db.create_tables().await?;
@@ -77,6 +94,15 @@ impl MysqlStore {
         Ok(db)
     }
 
+    pub(crate) async fn ping(&self) -> crate::Result<()> {
+        self.conn_pool
+            .get_conn()
+            .await?
+            .ping()
+            .await
+            .map_err(Into::into)
+    }
+
     pub(super) async fn create_tables(&self) -> crate::Result<()> {
         let mut conn = self.conn_pool.get_conn().await?;
 
@@ -21,21 +21,36 @@
  * for more details.
 */
 
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::dispatch::options::StoreOptions;
 use mysql_async::Pool;
 
 pub mod blob;
 pub mod lookup;
 pub mod main;
 pub mod read;
+pub mod snapshot;
 pub mod write;
 
 pub struct MysqlStore {
     pub(crate) conn_pool: Pool,
+    pub(crate) options: Arc<StoreOptions>,
+    pub(crate) slow_query: Option<Duration>,
 }
 
 impl From<mysql_async::Error> for crate::Error {
     fn from(err: mysql_async::Error) -> Self {
-        Self::InternalError(format!("mySQL error: {}", err))
+        match err {
+            mysql_async::Error::Io(_)
+            | mysql_async::Error::Driver(mysql_async::DriverError::PoolDisconnected) => {
+                Self::Unavailable(format!("mySQL error: {}", err))
+            }
+            _ => Self::InternalError(format!("mySQL error: {}", err)),
+        }
     }
 }
 
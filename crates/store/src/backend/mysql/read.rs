@@ -21,8 +21,9 @@
  * for more details.
 */
 
+use ahash::AHashMap;
 use futures::TryStreamExt;
-use mysql_async::{prelude::Queryable, Row};
+use mysql_async::{prelude::Queryable, Params, Row};
 use roaring::RoaringBitmap;
 
 use crate::{
@@ -37,46 +38,31 @@ impl MysqlStore {
     where
         U: Deserialize + 'static,
     {
+        let mut conn = self.conn_pool.get_conn().await?;
+        read_value(&mut conn, key).await
+    }
+
+    pub(crate) async fn key_exists(&self, key: impl Key) -> crate::Result<bool> {
         let mut conn = self.conn_pool.get_conn().await?;
         let s = conn
             .prep(&format!(
-                "SELECT v FROM {} WHERE k = ?",
+                "SELECT 1 FROM {} WHERE k = ?",
                 char::from(key.subspace())
             ))
             .await?;
         let key = key.serialize(0);
-        conn.exec_first::<Vec<u8>, _, _>(&s, (key,))
+        conn.exec_first::<i64, _, _>(&s, (key,))
             .await
+            .map(|r| r.is_some())
             .map_err(Into::into)
-            .and_then(|r| {
-                if let Some(r) = r {
-                    Ok(Some(U::deserialize(&r)?))
-                } else {
-                    Ok(None)
-                }
-            })
     }
 
     pub(crate) async fn get_bitmap(
         &self,
-        mut key: BitmapKey<BitmapClass>,
+        key: BitmapKey<BitmapClass>,
     ) -> crate::Result<Option<RoaringBitmap>> {
-        let begin = key.serialize(0);
-        key.block_num = u32::MAX;
-        let key_len = begin.len();
-        let end = key.serialize(0);
         let mut conn = self.conn_pool.get_conn().await?;
-
-        let mut bm = RoaringBitmap::new();
-        let s = conn.prep("SELECT k FROM b WHERE k >= ? AND k <= ?").await?;
-        let mut rows = conn.exec_stream::<Vec<u8>, _, _>(&s, (begin, end)).await?;
-
-        while let Some(key) = rows.try_next().await? {
-            if key.len() == key_len {
-                bm.insert(key.as_slice().deserialize_be_u32(key.len() - U32_LEN)?);
-            }
-        }
-        Ok(if !bm.is_empty() { Some(bm) } else { None })
+        read_bitmap(&mut conn, key).await
     }
 
     pub(crate) async fn iterate<T: Key>(
@@ -140,6 +126,36 @@ impl MysqlStore {
         Ok(())
     }
 
+    pub(crate) async fn estimate_count(
+        &self,
+        subspace: u8,
+        begin: Vec<u8>,
+        end: Option<Vec<u8>>,
+    ) -> crate::Result<u64> {
+        let mut conn = self.conn_pool.get_conn().await?;
+        let table = char::from(subspace);
+        if let Some(end) = end {
+            let s = conn
+                .prep(&format!(
+                    "SELECT COUNT(*) FROM {table} WHERE k >= ? AND k < ?"
+                ))
+                .await?;
+            conn.exec_first::<u64, _, _>(&s, (begin, end))
+                .await
+                .map(|count| count.unwrap_or(0))
+                .map_err(Into::into)
+        } else {
+            // InnoDB keeps an approximate row count in the table statistics
+            let s = conn
+                .prep("SELECT TABLE_ROWS FROM information_schema.TABLES WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ?")
+                .await?;
+            conn.exec_first::<Option<u64>, _, _>(&s, (table.to_string(),))
+                .await
+                .map(|count| count.flatten().unwrap_or(0))
+                .map_err(Into::into)
+        }
+    }
+
     pub(crate) async fn get_counter(
         &self,
         key: impl Into<ValueKey<ValueClass>> + Sync + Send,
@@ -153,4 +169,84 @@ impl MysqlStore {
             Err(e) => Err(e.into()),
         }
     }
+
+    pub(crate) async fn get_counters(
+        &self,
+        keys: Vec<ValueKey<ValueClass>>,
+    ) -> crate::Result<Vec<i64>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys = keys
+            .into_iter()
+            .map(|key| key.serialize(0))
+            .collect::<Vec<_>>();
+        let mut conn = self.conn_pool.get_conn().await?;
+        let s = conn
+            .prep(format!(
+                "SELECT k, v FROM c WHERE k IN ({})",
+                vec!["?"; keys.len()].join(",")
+            ))
+            .await?;
+        let counters = conn
+            .exec::<(Vec<u8>, i64), _, _>(
+                &s,
+                Params::Positional(keys.iter().map(|key| key.clone().into()).collect()),
+            )
+            .await?
+            .into_iter()
+            .collect::<AHashMap<_, _>>();
+
+        Ok(keys
+            .iter()
+            .map(|key| counters.get(key).copied().unwrap_or(0))
+            .collect())
+    }
+}
+
+pub(crate) async fn read_value<U>(
+    conn: &mut impl Queryable,
+    key: impl Key,
+) -> crate::Result<Option<U>>
+where
+    U: Deserialize + 'static,
+{
+    let s = conn
+        .prep(&format!(
+            "SELECT v FROM {} WHERE k = ?",
+            char::from(key.subspace())
+        ))
+        .await?;
+    let key = key.serialize(0);
+    conn.exec_first::<Vec<u8>, _, _>(&s, (key,))
+        .await
+        .map_err(Into::into)
+        .and_then(|r| {
+            if let Some(r) = r {
+                Ok(Some(U::deserialize(&r)?))
+            } else {
+                Ok(None)
+            }
+        })
+}
+
+pub(crate) async fn read_bitmap(
+    conn: &mut impl Queryable,
+    mut key: BitmapKey<BitmapClass>,
+) -> crate::Result<Option<RoaringBitmap>> {
+    let begin = key.serialize(0);
+    key.block_num = u32::MAX;
+    let key_len = begin.len();
+    let end = key.serialize(0);
+
+    let mut bm = RoaringBitmap::new();
+    let s = conn.prep("SELECT k FROM b WHERE k >= ? AND k <= ?").await?;
+    let mut rows = conn.exec_stream::<Vec<u8>, _, _>(&s, (begin, end)).await?;
+
+    while let Some(key) = rows.try_next().await? {
+        if key.len() == key_len {
+            bm.insert(key.as_slice().deserialize_be_u32(key.len() - U32_LEN)?);
+        }
+    }
+    Ok(if !bm.is_empty() { Some(bm) } else { None })
 }
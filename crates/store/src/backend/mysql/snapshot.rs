@@ -0,0 +1,70 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mysql_async::{Transaction, TxOpts};
+use roaring::RoaringBitmap;
+
+use crate::{write::BitmapClass, BitmapKey, Deserialize, Key};
+
+use super::{
+    read::{read_bitmap, read_value},
+    MysqlStore,
+};
+
+pub struct MysqlSnapshot {
+    trx: Transaction<'static>,
+}
+
+impl MysqlStore {
+    pub(crate) async fn snapshot(&self) -> crate::Result<MysqlSnapshot> {
+        let mut tx_opts = TxOpts::default();
+        tx_opts
+            .with_consistent_snapshot(true)
+            .with_readonly(Some(true));
+        let trx = self.conn_pool.start_transaction(tx_opts).await?;
+
+        Ok(MysqlSnapshot { trx })
+    }
+}
+
+impl MysqlSnapshot {
+    pub(crate) async fn get_value<U>(&mut self, key: impl Key) -> crate::Result<Option<U>>
+    where
+        U: Deserialize + 'static,
+    {
+        read_value(&mut self.trx, key).await
+    }
+
+    pub(crate) async fn get_bitmap(
+        &mut self,
+        key: BitmapKey<BitmapClass>,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        read_bitmap(&mut self.trx, key).await
+    }
+
+    // Dropping an unfinished snapshot (e.g. a cancelled future) rolls the
+    // transaction back before the connection is returned to the pool
+    pub(crate) async fn finish(self) -> crate::Result<()> {
+        self.trx.commit().await.map_err(Into::into)
+    }
+}
@@ -29,7 +29,8 @@ use rand::Rng;
 
 use crate::{
     write::{
-        Batch, BitmapClass, Operation, ValueClass, ValueOp, MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME,
+        assert::AssertValue, Batch, BitmapClass, Operation, ValueClass, ValueOp,
+        MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME,
     },
     BitmapKey, IndexKey, Key, LogKey, ValueKey,
 };
@@ -237,6 +238,25 @@ impl MysqlStore {
                         .await?;
                     trx.exec_drop(&s, (key, set)).await?;
                 }
+                Operation::AssertValue {
+                    class,
+                    assert_value: AssertValue::Counter(value),
+                } => {
+                    let key = ValueKey {
+                        account_id,
+                        collection,
+                        document_id,
+                        class,
+                    }
+                    .serialize(0);
+
+                    let s = trx.prep("SELECT v FROM c WHERE k = ? FOR UPDATE").await?;
+                    let counter = trx.exec_first::<i64, _, _>(&s, (&key,)).await?.unwrap_or(0);
+                    if counter != *value {
+                        trx.rollback().await?;
+                        return Ok(false);
+                    }
+                }
                 Operation::AssertValue {
                     class,
                     assert_value,
@@ -270,6 +290,24 @@ impl MysqlStore {
         trx.commit().await.map(|_| true)
     }
 
+    pub(crate) async fn put_if_absent(
+        &self,
+        key: ValueKey<ValueClass>,
+        value: Vec<u8>,
+    ) -> crate::Result<bool> {
+        let mut conn = self.conn_pool.get_conn().await?;
+
+        let s = conn
+            .prep(&format!(
+                "INSERT IGNORE INTO {} (k, v) VALUES (?, ?)",
+                char::from(key.subspace()),
+            ))
+            .await?;
+        conn.exec_drop(&s, (key.serialize(0), value)).await?;
+
+        Ok(conn.affected_rows() > 0)
+    }
+
     pub(crate) async fn purge_bitmaps(&self) -> crate::Result<()> {
         // Not needed for PostgreSQL
         Ok(())
@@ -24,7 +24,7 @@
 use crate::{QueryResult, QueryType};
 
 use bytes::BytesMut;
-use futures::{pin_mut, TryStreamExt};
+use futures::{future::try_join_all, pin_mut, TryStreamExt};
 use tokio_postgres::types::{FromSql, ToSql, Type};
 
 use crate::IntoRows;
@@ -66,6 +66,45 @@ impl PostgresStore {
                 .map_or_else(|e| Err(e.into()), |r| Ok(T::from_query_all(r))),
         }
     }
+
+    pub(crate) async fn query_batch(
+        &self,
+        queries: Vec<(&str, Vec<crate::Value<'_>>)>,
+    ) -> crate::Result<Vec<crate::Rows>> {
+        let mut conn = self.conn_pool.get().await?;
+        let trx = conn.transaction().await?;
+        let mut statements = Vec::with_capacity(queries.len());
+        for (query, _) in &queries {
+            statements.push(trx.prepare_cached(query).await?);
+        }
+        let params = queries
+            .iter()
+            .map(|(_, params)| {
+                params
+                    .iter()
+                    .map(|v| v as &(dyn tokio_postgres::types::ToSql + Sync))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // Queries issued concurrently are pipelined over the same connection
+        let results = try_join_all(
+            statements
+                .iter()
+                .zip(params.iter())
+                .map(|(s, params)| trx.query(s, params.as_slice())),
+        )
+        .await?;
+        trx.commit().await?;
+
+        Ok(results.into_iter().map(IntoRows::into_rows).collect())
+    }
+
+    pub(crate) async fn prepare(&self, query: &str) -> crate::Result<()> {
+        let conn = self.conn_pool.get().await?;
+        conn.prepare(query).await?;
+        Ok(())
+    }
 }
 
 impl ToSql for crate::Value<'_> {
@@ -94,6 +133,16 @@ impl ToSql for crate::Value<'_> {
             }
             crate::Value::Text(v) => v.to_sql(ty, out),
             crate::Value::Blob(v) => v.to_sql(ty, out),
+            crate::Value::Json(v) => {
+                // The binary jsonb format is the text format prefixed with a version
+                if matches!(ty, &Type::JSONB) {
+                    out.extend_from_slice(&[1]);
+                } else if !matches!(ty, &Type::JSON) {
+                    return v.to_string().to_sql(ty, out);
+                }
+                out.extend_from_slice(v.to_string().as_bytes());
+                Ok(tokio_postgres::types::IsNull::No)
+            }
             crate::Value::Null => None::<String>.to_sql(ty, out),
         }
     }
@@ -127,6 +176,7 @@ impl ToSql for crate::Value<'_> {
             }
             crate::Value::Text(v) => v.to_sql_checked(ty, out),
             crate::Value::Blob(v) => v.to_sql_checked(ty, out),
+            crate::Value::Json(_) => self.to_sql(ty, out),
             crate::Value::Null => None::<String>.to_sql_checked(ty, out),
         }
     }
@@ -201,6 +251,19 @@ impl FromSql<'_> for crate::Value<'static> {
             &Type::INT4 => i32::from_sql(ty, raw).map(|v| crate::Value::Integer(v as i64)),
             &Type::INT8 | &Type::OID => i64::from_sql(ty, raw).map(crate::Value::Integer),
             &Type::FLOAT4 | &Type::FLOAT8 => f64::from_sql(ty, raw).map(crate::Value::Float),
+            &Type::JSON | &Type::JSONB => {
+                let raw = if matches!(ty, &Type::JSONB) {
+                    match raw.split_first() {
+                        Some((1, raw)) => raw,
+                        _ => return Err("Unsupported jsonb version".into()),
+                    }
+                } else {
+                    raw
+                };
+                serde_json::from_slice(raw)
+                    .map(crate::Value::Json)
+                    .map_err(Into::into)
+            }
             ty if (ty.name() == "citext"
                 || ty.name() == "ltree"
                 || ty.name() == "lquery"
@@ -21,17 +21,24 @@
  * for more details.
 */
 
+use std::time::Duration;
+
 use crate::{
     backend::postgres::tls::MakeRustlsConnect, SUBSPACE_BITMAPS, SUBSPACE_BLOBS, SUBSPACE_COUNTERS,
     SUBSPACE_INDEXES, SUBSPACE_LOGS, SUBSPACE_VALUES,
 };
 
-use super::PostgresStore;
+use super::{notify::InvalidationChannel, PostgresStore};
 
+use crate::dispatch::options::StoreOptions;
 use deadpool_postgres::{
-    Config, CreatePoolError, ManagerConfig, PoolConfig, RecyclingMethod, Runtime,
+    Config, CreatePoolError, Hook, HookError, ManagerConfig, Pool, PoolConfig, RecyclingMethod,
+    Runtime,
+};
+use tokio_postgres::{
+    tls::{MakeTlsConnect, TlsConnect},
+    NoTls, Socket,
 };
-use tokio_postgres::NoTls;
 use utils::{config::utils::AsKey, rustls_client_config};
 
 impl PostgresStore {
@@ -53,17 +60,60 @@ impl PostgresStore {
         if let Some(max_conn) = config.property::<usize>((&prefix, "pool.max-connections"))? {
             cfg.pool = PoolConfig::new(max_conn).into();
         }
+        let max_lifetime =
+            config.property_or_static::<Duration>((&prefix, "pool.max-lifetime"), "30m")?;
+        let idle_timeout =
+            config.property_or_static::<Duration>((&prefix, "pool.idle-timeout"), "10m")?;
+        let tls = if config.property_or_static::<bool>((&prefix, "tls.enable"), "false")? {
+            Some(MakeRustlsConnect::new(rustls_client_config(
+                config.property_or_static((&prefix, "tls.allow-invalid-certs"), "false")?,
+            )))
+        } else {
+            None
+        };
+
+        // Cache invalidation events are exchanged between nodes using LISTEN/NOTIFY
+        let invalidation =
+            if config.property_or_static::<bool>((&prefix, "invalidation.enable"), "false")? {
+                let channel = config
+                    .value((&prefix, "invalidation.channel"))
+                    .unwrap_or("stalwart_invalidation");
+                if channel.is_empty()
+                    || !channel
+                        .chars()
+                        .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+                {
+                    return Err(format!(
+                    "Invalid channel name {channel:?} for property {prefix}.invalidation.channel."
+                )
+                    .into());
+                }
+                let invalidation = InvalidationChannel::new(channel.to_string());
+                let pg_config = cfg.get_pg_config().map_err(|err| {
+                    crate::Error::InternalError(format!("Invalid PostgreSQL configuration: {err}"))
+                })?;
+                if let Some(tls) = &tls {
+                    invalidation.spawn_listener(pg_config, tls.clone());
+                } else {
+                    invalidation.spawn_listener(pg_config, NoTls);
+                }
+                Some(invalidation)
+            } else {
+                None
+            };
+
         let db = Self {
-            conn_pool: if config.property_or_static::<bool>((&prefix, "tls.enable"), "false")? {
-                cfg.create_pool(
-                    Some(Runtime::Tokio1),
-                    MakeRustlsConnect::new(rustls_client_config(
-                        config.property_or_static((&prefix, "tls.allow-invalid-certs"), "false")?,
-                    )),
-                )?
+            conn_pool: if let Some(tls) = tls {
+                create_pool(&cfg, tls, max_lifetime, idle_timeout)?
             } else {
-                cfg.create_pool(Some(Runtime::Tokio1), NoTls)?
+                create_pool(&cfg, NoTls, max_lifetime, idle_timeout)?
             },
+            options: StoreOptions::parse(config, &prefix)?,
+            invalidation,
+            slow_query: config
+                .property::<u64>((&prefix, "slow-query-ms"))?
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
         };
 
         db.create_tables().await?;
@@ -71,6 +121,16 @@ impl PostgresStore {
         Ok(db)
     }
 
+    pub(crate) async fn ping(&self) -> crate::Result<()> {
+        self.conn_pool
+            .get()
+            .await?
+            .simple_query("SELECT 1")
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
     pub(super) async fn create_tables(&self) -> crate::Result<()> {
         let conn = self.conn_pool.get().await?;
 
@@ -117,6 +177,40 @@ impl PostgresStore {
     }
 }
 
+// Expired connections are discarded when they are next taken from the pool,
+// connections in use are never interrupted.
+fn create_pool<T>(
+    cfg: &Config,
+    tls: T,
+    max_lifetime: Duration,
+    idle_timeout: Duration,
+) -> crate::Result<Pool>
+where
+    T: MakeTlsConnect<Socket> + Clone + Sync + Send + 'static,
+    T::Stream: Sync + Send,
+    T::TlsConnect: Sync + Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+{
+    cfg.builder(tls)
+        .map_err(CreatePoolError::Config)?
+        .runtime(Runtime::Tokio1)
+        .pre_recycle(Hook::sync_fn(move |_, metrics| {
+            if metrics.age() >= max_lifetime {
+                Err(HookError::StaticMessage(
+                    "Connection reached its maximum lifetime",
+                ))
+            } else if metrics.last_used() >= idle_timeout {
+                Err(HookError::StaticMessage(
+                    "Connection exceeded its idle timeout",
+                ))
+            } else {
+                Ok(())
+            }
+        }))
+        .build()
+        .map_err(|err| CreatePoolError::Build(err).into())
+}
+
 impl From<CreatePoolError> for crate::Error {
     fn from(err: CreatePoolError) -> Self {
         crate::Error::InternalError(format!("Failed to create connection pool: {}", err))
@@ -21,27 +21,51 @@
  * for more details.
 */
 
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::dispatch::options::StoreOptions;
 use deadpool_postgres::{Pool, PoolError};
 
+use self::notify::InvalidationChannel;
+
 pub mod blob;
 pub mod lookup;
 pub mod main;
+pub mod notify;
 pub mod read;
+pub mod snapshot;
 pub mod tls;
 pub mod write;
 
 pub struct PostgresStore {
     pub(crate) conn_pool: Pool,
+    pub(crate) options: Arc<StoreOptions>,
+    pub(crate) invalidation: Option<Arc<InvalidationChannel>>,
+    pub(crate) slow_query: Option<Duration>,
 }
 
 impl From<PoolError> for crate::Error {
     fn from(err: PoolError) -> Self {
-        Self::InternalError(format!("Connection pool error: {}", err))
+        match err {
+            PoolError::Backend(err) => err.into(),
+            PoolError::Timeout(_) => Self::Timeout(format!("Connection pool error: {}", err)),
+            PoolError::Closed => Self::Unavailable(format!("Connection pool error: {}", err)),
+            _ => Self::InternalError(format!("Connection pool error: {}", err)),
+        }
     }
 }
 
 impl From<tokio_postgres::Error> for crate::Error {
     fn from(err: tokio_postgres::Error) -> Self {
-        Self::InternalError(format!("PostgreSQL error: {}", err))
+        if err.is_closed() {
+            Self::Unavailable(format!("PostgreSQL error: {}", err))
+        } else if err.code() == Some(&tokio_postgres::error::SqlState::QUERY_CANCELED) {
+            Self::Timeout(format!("PostgreSQL error: {}", err))
+        } else {
+            Self::InternalError(format!("PostgreSQL error: {}", err))
+        }
     }
 }
@@ -0,0 +1,186 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use futures::{stream, StreamExt};
+use tokio::sync::{broadcast, mpsc};
+use tokio_postgres::{
+    tls::{MakeTlsConnect, TlsConnect},
+    AsyncMessage, Socket,
+};
+
+use crate::dispatch::invalidation::InvalidationEvent;
+
+use super::PostgresStore;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const LIVENESS_CHECK: Duration = Duration::from_secs(60);
+
+pub struct InvalidationChannel {
+    pub(crate) channel: String,
+    pub(crate) tx: broadcast::Sender<InvalidationEvent>,
+}
+
+impl InvalidationChannel {
+    pub(crate) fn new(channel: String) -> Arc<Self> {
+        Arc::new(InvalidationChannel {
+            channel,
+            tx: broadcast::channel(1024).0,
+        })
+    }
+
+    /// Listens for notifications on a dedicated connection, reconnecting when
+    /// it drops. Subscribers are asked to flush their caches after a reconnect
+    /// as any notifications sent in the meantime were lost.
+    pub(crate) fn spawn_listener<T>(self: &Arc<Self>, config: tokio_postgres::Config, tls: T)
+    where
+        T: MakeTlsConnect<Socket> + Clone + Send + 'static,
+        T::Stream: Send,
+        T::TlsConnect: Send,
+        <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+    {
+        let channel = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut is_reconnect = false;
+            loop {
+                match listen(&channel, &config, tls.clone(), is_reconnect).await {
+                    Ok(true) => {
+                        tracing::warn!(
+                            context = "postgres",
+                            event = "invalidation-disconnected",
+                            "Invalidation channel connection closed, reconnecting."
+                        );
+                    }
+                    Ok(false) => {
+                        return;
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            context = "postgres",
+                            event = "error",
+                            reason = %err,
+                            "Invalidation channel failed, reconnecting."
+                        );
+                    }
+                }
+                is_reconnect = true;
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                if channel.strong_count() == 0 {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+// Returns Ok(false) once the store has been dropped and the listener should exit
+async fn listen<T>(
+    channel: &Weak<InvalidationChannel>,
+    config: &tokio_postgres::Config,
+    tls: T,
+    is_reconnect: bool,
+) -> Result<bool, tokio_postgres::Error>
+where
+    T: MakeTlsConnect<Socket> + Send + 'static,
+    T::Stream: Send,
+    T::TlsConnect: Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+{
+    let listen = if let Some(channel) = channel.upgrade() {
+        format!("LISTEN \"{}\"", channel.channel)
+    } else {
+        return Ok(false);
+    };
+    let (client, mut connection) = config.connect(tls).await?;
+
+    // Drive the connection in the background, it stops once the client is dropped
+    let (message_tx, mut message_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            if message_tx.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    client.batch_execute(&listen).await?;
+
+    if is_reconnect {
+        if let Some(channel) = channel.upgrade() {
+            let _ = channel.tx.send(InvalidationEvent::FlushAll);
+        }
+    }
+
+    loop {
+        match tokio::time::timeout(LIVENESS_CHECK, message_rx.recv()).await {
+            Ok(Some(Ok(AsyncMessage::Notification(notification)))) => {
+                let channel = if let Some(channel) = channel.upgrade() {
+                    channel
+                } else {
+                    return Ok(false);
+                };
+                if let Some(event) = InvalidationEvent::parse(notification.payload()) {
+                    let _ = channel.tx.send(event);
+                } else {
+                    tracing::debug!(
+                        context = "postgres",
+                        event = "invalidation-invalid",
+                        payload = notification.payload(),
+                        "Ignoring invalid invalidation event."
+                    );
+                }
+            }
+            Ok(Some(Ok(_))) => {}
+            Ok(Some(Err(err))) => return Err(err),
+            Ok(None) => return Ok(true),
+            Err(_) => {
+                if channel.strong_count() == 0 {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+}
+
+impl PostgresStore {
+    pub(crate) async fn publish_invalidation(&self, event: InvalidationEvent) -> crate::Result<()> {
+        if let Some(invalidation) = &self.invalidation {
+            let conn = self.conn_pool.get().await?;
+            let s = conn.prepare_cached("SELECT pg_notify($1, $2)").await?;
+            conn.execute(&s, &[&invalidation.channel, &event.serialize()])
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn subscribe_invalidations(&self) -> Option<broadcast::Receiver<InvalidationEvent>> {
+        self.invalidation
+            .as_ref()
+            .map(|invalidation| invalidation.tx.subscribe())
+    }
+}
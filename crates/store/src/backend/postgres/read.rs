@@ -21,6 +21,8 @@
  * for more details.
 */
 
+use ahash::AHashMap;
+use deadpool_postgres::ClientWrapper;
 use futures::{pin_mut, TryStreamExt};
 use roaring::RoaringBitmap;
 
@@ -36,51 +38,31 @@ impl PostgresStore {
     where
         U: Deserialize + 'static,
     {
+        let conn = self.conn_pool.get().await?;
+        read_value(&conn, key).await
+    }
+
+    pub(crate) async fn key_exists(&self, key: impl Key) -> crate::Result<bool> {
         let conn = self.conn_pool.get().await?;
         let s = conn
             .prepare_cached(&format!(
-                "SELECT v FROM {} WHERE k = $1",
+                "SELECT 1 FROM {} WHERE k = $1",
                 char::from(key.subspace())
             ))
             .await?;
         let key = key.serialize(0);
         conn.query_opt(&s, &[&key])
             .await
+            .map(|r| r.is_some())
             .map_err(Into::into)
-            .and_then(|r| {
-                if let Some(r) = r {
-                    Ok(Some(U::deserialize(r.get(0))?))
-                } else {
-                    Ok(None)
-                }
-            })
     }
 
     pub(crate) async fn get_bitmap(
         &self,
-        mut key: BitmapKey<BitmapClass>,
+        key: BitmapKey<BitmapClass>,
     ) -> crate::Result<Option<RoaringBitmap>> {
-        let begin = key.serialize(0);
-        key.block_num = u32::MAX;
-        let key_len = begin.len();
-        let end = key.serialize(0);
         let conn = self.conn_pool.get().await?;
-
-        let mut bm = RoaringBitmap::new();
-        let s = conn
-            .prepare_cached("SELECT k FROM b WHERE k >= $1 AND k <= $2")
-            .await?;
-        let rows = conn.query_raw(&s, &[&begin, &end]).await?;
-
-        pin_mut!(rows);
-
-        while let Some(row) = rows.try_next().await? {
-            let key: &[u8] = row.try_get(0)?;
-            if key.len() == key_len {
-                bm.insert(key.deserialize_be_u32(key.len() - U32_LEN)?);
-            }
-        }
-        Ok(if !bm.is_empty() { Some(bm) } else { None })
+        read_bitmap(&conn, key).await
     }
 
     pub(crate) async fn iterate<T: Key>(
@@ -138,6 +120,51 @@ impl PostgresStore {
         Ok(())
     }
 
+    pub(crate) async fn estimate_count(
+        &self,
+        subspace: u8,
+        begin: Vec<u8>,
+        end: Option<Vec<u8>>,
+    ) -> crate::Result<u64> {
+        let conn = self.conn_pool.get().await?;
+        let table = char::from(subspace);
+        if let Some(end) = end {
+            let s = conn
+                .prepare_cached(&format!(
+                    "SELECT COUNT(*) FROM {table} WHERE k >= $1 AND k < $2"
+                ))
+                .await?;
+            conn.query_one(&s, &[&begin, &end])
+                .await
+                .and_then(|row| row.try_get::<_, i64>(0))
+                .map(|count| count as u64)
+                .map_err(Into::into)
+        } else {
+            // Use the planner statistics, a negative value means the table was never analyzed
+            let s = conn
+                .prepare_cached("SELECT reltuples::BIGINT FROM pg_class WHERE relname = $1")
+                .await?;
+            match conn
+                .query_opt(&s, &[&table.to_string()])
+                .await?
+                .map(|row| row.try_get::<_, i64>(0))
+                .transpose()?
+            {
+                Some(count) if count >= 0 => Ok(count as u64),
+                _ => {
+                    let s = conn
+                        .prepare_cached(&format!("SELECT COUNT(*) FROM {table}"))
+                        .await?;
+                    conn.query_one(&s, &[])
+                        .await
+                        .and_then(|row| row.try_get::<_, i64>(0))
+                        .map(|count| count as u64)
+                        .map_err(Into::into)
+                }
+            }
+        }
+    }
+
     pub(crate) async fn get_counter(
         &self,
         key: impl Into<ValueKey<ValueClass>> + Sync + Send,
@@ -151,4 +178,76 @@ impl PostgresStore {
             Err(e) => Err(e.into()),
         }
     }
+
+    pub(crate) async fn get_counters(
+        &self,
+        keys: Vec<ValueKey<ValueClass>>,
+    ) -> crate::Result<Vec<i64>> {
+        let keys = keys
+            .into_iter()
+            .map(|key| key.serialize(0))
+            .collect::<Vec<_>>();
+        let conn = self.conn_pool.get().await?;
+        let s = conn
+            .prepare_cached("SELECT k, v FROM c WHERE k = ANY($1)")
+            .await?;
+        let mut counters = AHashMap::with_capacity(keys.len());
+        for row in conn.query(&s, &[&keys]).await? {
+            counters.insert(row.try_get::<_, Vec<u8>>(0)?, row.try_get::<_, i64>(1)?);
+        }
+
+        Ok(keys
+            .iter()
+            .map(|key| counters.get(key).copied().unwrap_or(0))
+            .collect())
+    }
+}
+
+pub(crate) async fn read_value<U>(conn: &ClientWrapper, key: impl Key) -> crate::Result<Option<U>>
+where
+    U: Deserialize + 'static,
+{
+    let s = conn
+        .prepare_cached(&format!(
+            "SELECT v FROM {} WHERE k = $1",
+            char::from(key.subspace())
+        ))
+        .await?;
+    let key = key.serialize(0);
+    conn.query_opt(&s, &[&key])
+        .await
+        .map_err(Into::into)
+        .and_then(|r| {
+            if let Some(r) = r {
+                Ok(Some(U::deserialize(r.get(0))?))
+            } else {
+                Ok(None)
+            }
+        })
+}
+
+pub(crate) async fn read_bitmap(
+    conn: &ClientWrapper,
+    mut key: BitmapKey<BitmapClass>,
+) -> crate::Result<Option<RoaringBitmap>> {
+    let begin = key.serialize(0);
+    key.block_num = u32::MAX;
+    let key_len = begin.len();
+    let end = key.serialize(0);
+
+    let mut bm = RoaringBitmap::new();
+    let s = conn
+        .prepare_cached("SELECT k FROM b WHERE k >= $1 AND k <= $2")
+        .await?;
+    let rows = conn.query_raw(&s, &[&begin, &end]).await?;
+
+    pin_mut!(rows);
+
+    while let Some(row) = rows.try_next().await? {
+        let key: &[u8] = row.try_get(0)?;
+        if key.len() == key_len {
+            bm.insert(key.deserialize_be_u32(key.len() - U32_LEN)?);
+        }
+    }
+    Ok(if !bm.is_empty() { Some(bm) } else { None })
 }
@@ -0,0 +1,83 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use deadpool_postgres::Object;
+use roaring::RoaringBitmap;
+
+use crate::{write::BitmapClass, BitmapKey, Deserialize, Key};
+
+use super::{
+    read::{read_bitmap, read_value},
+    PostgresStore,
+};
+
+pub struct PostgresSnapshot {
+    conn: Option<Object>,
+}
+
+impl PostgresStore {
+    pub(crate) async fn snapshot(&self) -> crate::Result<PostgresSnapshot> {
+        let conn = self.conn_pool.get().await?;
+        conn.batch_execute("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY")
+            .await?;
+
+        Ok(PostgresSnapshot { conn: Some(conn) })
+    }
+}
+
+impl PostgresSnapshot {
+    pub(crate) async fn get_value<U>(&mut self, key: impl Key) -> crate::Result<Option<U>>
+    where
+        U: Deserialize + 'static,
+    {
+        read_value(self.conn(), key).await
+    }
+
+    pub(crate) async fn get_bitmap(
+        &mut self,
+        key: BitmapKey<BitmapClass>,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        read_bitmap(self.conn(), key).await
+    }
+
+    pub(crate) async fn finish(mut self) -> crate::Result<()> {
+        if let Some(conn) = self.conn.take() {
+            conn.batch_execute("COMMIT").await?;
+        }
+        Ok(())
+    }
+
+    fn conn(&self) -> &Object {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for PostgresSnapshot {
+    fn drop(&mut self) {
+        // The transaction is still open, detach the connection from the pool
+        // so it gets closed instead of being handed out mid-transaction.
+        if let Some(conn) = self.conn.take() {
+            drop(Object::take(conn));
+        }
+    }
+}
@@ -30,7 +30,8 @@ use tokio_postgres::{error::SqlState, IsolationLevel};
 
 use crate::{
     write::{
-        Batch, BitmapClass, Operation, ValueClass, ValueOp, MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME,
+        assert::AssertValue, Batch, BitmapClass, Operation, ValueClass, ValueOp,
+        MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME,
     },
     BitmapKey, IndexKey, Key, LogKey, ValueKey,
 };
@@ -252,6 +253,29 @@ impl PostgresStore {
                         .await?;
                     trx.execute(&s, &[&key, set]).await?;
                 }
+                Operation::AssertValue {
+                    class,
+                    assert_value: AssertValue::Counter(value),
+                } => {
+                    let key = ValueKey {
+                        account_id,
+                        collection,
+                        document_id,
+                        class,
+                    }
+                    .serialize(0);
+
+                    let s = trx
+                        .prepare_cached("SELECT v FROM c WHERE k = $1 FOR UPDATE")
+                        .await?;
+                    let counter = match trx.query_opt(&s, &[&key]).await? {
+                        Some(row) => row.try_get::<_, i64>(0)?,
+                        None => 0,
+                    };
+                    if counter != *value {
+                        return Ok(false);
+                    }
+                }
                 Operation::AssertValue {
                     class,
                     assert_value,
@@ -287,6 +311,25 @@ impl PostgresStore {
         trx.commit().await.map(|_| true)
     }
 
+    pub(crate) async fn put_if_absent(
+        &self,
+        key: ValueKey<ValueClass>,
+        value: Vec<u8>,
+    ) -> crate::Result<bool> {
+        let conn = self.conn_pool.get().await?;
+
+        let s = conn
+            .prepare_cached(&format!(
+                "INSERT INTO {} (k, v) VALUES ($1, $2) ON CONFLICT (k) DO NOTHING",
+                char::from(key.subspace()),
+            ))
+            .await?;
+        conn.execute(&s, &[&key.serialize(0), &value])
+            .await
+            .map(|inserted| inserted > 0)
+            .map_err(Into::into)
+    }
+
     pub(crate) async fn purge_bitmaps(&self) -> crate::Result<()> {
         // Not needed for PostgreSQL
         Ok(())
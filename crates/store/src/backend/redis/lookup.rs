@@ -52,7 +52,12 @@ impl RedisStore {
     ) -> crate::Result<LookupValue<T>> {
         match key {
             LookupKey::Key(key) => {
-                if let Some(value) = conn.get::<_, Option<Vec<u8>>>(key).await? {
+                if let Some(value) = conn.get::<_, Option<Vec<u8>>>(&key).await? {
+                    let value = if let Some(integrity) = &self.integrity {
+                        integrity.verify(&key, value)?
+                    } else {
+                        value
+                    };
                     T::deserialize(&value).map(|value| LookupValue::Value { value, expires: 0 })
                 } else {
                     Ok(LookupValue::None)
@@ -67,6 +72,145 @@ impl RedisStore {
         }
     }
 
+    pub async fn key_delete(&self, key: LookupKey) -> crate::Result<()> {
+        let key = match key {
+            LookupKey::Key(key) | LookupKey::Counter(key) => key,
+        };
+        match &self.pool {
+            RedisPool::Single(pool) => pool.get().await?.as_mut().del::<_, ()>(key).await,
+            RedisPool::Cluster(pool) => pool.get().await?.as_mut().del::<_, ()>(key).await,
+        }
+        .map_err(Into::into)
+    }
+
+    pub async fn ping(&self) -> crate::Result<()> {
+        match &self.pool {
+            RedisPool::Single(pool) => self.ping_(pool.get().await?.as_mut()).await,
+            RedisPool::Cluster(pool) => self.ping_(pool.get().await?.as_mut()).await,
+        }
+    }
+
+    async fn ping_(&self, conn: &mut impl AsyncCommands) -> crate::Result<()> {
+        redis::cmd("PING")
+            .query_async::<_, ()>(conn)
+            .await
+            .map_err(Into::into)
+    }
+
+    pub async fn counter_incr(&self, key: Vec<u8>, value: i64, expires: u64) -> crate::Result<i64> {
+        match &self.pool {
+            RedisPool::Single(pool) => {
+                self.counter_incr_(pool.get().await?.as_mut(), key, value, expires)
+                    .await
+            }
+            RedisPool::Cluster(pool) => {
+                self.counter_incr_(pool.get().await?.as_mut(), key, value, expires)
+                    .await
+            }
+        }
+    }
+
+    async fn counter_incr_(
+        &self,
+        conn: &mut impl AsyncCommands,
+        key: Vec<u8>,
+        value: i64,
+        expires: u64,
+    ) -> crate::Result<i64> {
+        if expires == 0 {
+            return conn.incr(key, value).await.map_err(Into::into);
+        }
+
+        let (num, ttl): (i64, i64) = redis::pipe()
+            .atomic()
+            .incr(&key, value)
+            .ttl(&key)
+            .query_async(conn)
+            .await?;
+
+        // Only set the expiration when the window starts, a TTL of -1 also
+        // covers counters left without one after an interrupted increment.
+        if ttl == -1 {
+            conn.expire::<_, ()>(&key, expires as i64).await?;
+        }
+
+        Ok(num)
+    }
+
+    pub async fn get_counters(&self, keys: Vec<LookupKey>) -> crate::Result<Vec<Option<i64>>> {
+        match &self.pool {
+            RedisPool::Single(pool) => self.get_counters_(pool.get().await?.as_mut(), keys).await,
+            RedisPool::Cluster(pool) => self.get_counters_(pool.get().await?.as_mut(), keys).await,
+        }
+    }
+
+    async fn get_counters_(
+        &self,
+        conn: &mut impl AsyncCommands,
+        keys: Vec<LookupKey>,
+    ) -> crate::Result<Vec<Option<i64>>> {
+        let counter_keys = keys
+            .iter()
+            .filter_map(|key| match key {
+                LookupKey::Counter(key) => Some(key.as_slice()),
+                LookupKey::Key(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let mut counters = if !counter_keys.is_empty() {
+            redis::cmd("MGET")
+                .arg(&counter_keys)
+                .query_async::<_, Vec<Option<i64>>>(conn)
+                .await?
+                .into_iter()
+        } else {
+            Vec::new().into_iter()
+        };
+
+        Ok(keys
+            .iter()
+            .map(|key| match key {
+                LookupKey::Counter(_) => Some(counters.next().flatten().unwrap_or(0)),
+                LookupKey::Key(_) => None,
+            })
+            .collect())
+    }
+
+    pub async fn filter_members(&self, keys: &[LookupKey]) -> crate::Result<Vec<bool>> {
+        match &self.pool {
+            RedisPool::Single(pool) => self.filter_members_(pool.get().await?.as_mut(), keys).await,
+            RedisPool::Cluster(pool) => {
+                self.filter_members_(pool.get().await?.as_mut(), keys).await
+            }
+        }
+    }
+
+    async fn filter_members_(
+        &self,
+        conn: &mut impl AsyncCommands,
+        keys: &[LookupKey],
+    ) -> crate::Result<Vec<bool>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // EXISTS replies with 0 or 1 and counters with their count, so both
+        // are members when the reply is a non-zero integer.
+        let mut pipe = redis::pipe();
+        for key in keys {
+            match key {
+                LookupKey::Key(key) => pipe.exists(key),
+                LookupKey::Counter(key) => pipe.get(key),
+            };
+        }
+
+        Ok(pipe
+            .query_async::<_, Vec<Option<i64>>>(conn)
+            .await?
+            .into_iter()
+            .map(|num| num.unwrap_or(0) != 0)
+            .collect())
+    }
+
     async fn key_set_(
         &self,
         conn: &mut impl AsyncCommands,
@@ -75,6 +219,12 @@ impl RedisStore {
     ) -> crate::Result<()> {
         match value {
             LookupValue::Value { value, expires } => {
+                // Counters are updated in place by the server and cannot be signed
+                let value = if let Some(integrity) = &self.integrity {
+                    integrity.sign(&key, &value)
+                } else {
+                    value
+                };
                 if expires > 0 {
                     conn.set_ex(key, value, expires).await?;
                 } else {
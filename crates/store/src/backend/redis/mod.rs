@@ -33,11 +33,14 @@ use redis::{
 };
 use utils::config::{utils::AsKey, Config};
 
+use crate::write::integrity::ValueIntegrity;
+
 pub mod lookup;
 pub mod pool;
 
 pub struct RedisStore {
     pool: RedisPool,
+    integrity: Option<ValueIntegrity>,
 }
 
 struct RedisConnectionManager {
@@ -58,6 +61,7 @@ enum RedisPool {
 impl RedisStore {
     pub async fn open(config: &Config, prefix: impl AsKey) -> crate::Result<Self> {
         let prefix = prefix.as_key();
+        let integrity = ValueIntegrity::parse(config, &prefix)?;
 
         let db = if let Some(url) = config.value((&prefix, "url")) {
             Self {
@@ -69,6 +73,7 @@ impl RedisStore {
                         timeout: config.property_or_static((&prefix, "timeout"), "10s")?,
                     },
                 )?),
+                integrity,
             }
         } else {
             let addresses = config
@@ -108,6 +113,7 @@ impl RedisStore {
                         timeout: config.property_or_static((&prefix, "timeout"), "10s")?,
                     },
                 )?),
+                integrity,
             }
         };
 
@@ -142,7 +148,12 @@ fn build_pool<M: Manager>(
 
 impl From<PoolError<RedisError>> for crate::Error {
     fn from(value: PoolError<RedisError>) -> Self {
-        crate::Error::InternalError(format!("Redis pool error: {}", value))
+        match value {
+            PoolError::Backend(err) => err.into(),
+            PoolError::Timeout(_) => crate::Error::Timeout(format!("Redis pool error: {}", value)),
+            PoolError::Closed => crate::Error::Unavailable(format!("Redis pool error: {}", value)),
+            _ => crate::Error::InternalError(format!("Redis pool error: {}", value)),
+        }
     }
 }
 
@@ -154,6 +165,12 @@ impl From<PoolError<crate::Error>> for crate::Error {
 
 impl From<RedisError> for crate::Error {
     fn from(value: RedisError) -> Self {
-        crate::Error::InternalError(format!("Redis error: {}", value))
+        if value.is_timeout() {
+            crate::Error::Timeout(format!("Redis error: {}", value))
+        } else if value.is_connection_refusal() || value.is_connection_dropped() {
+            crate::Error::Unavailable(format!("Redis error: {}", value))
+        } else {
+            crate::Error::InternalError(format!("Redis error: {}", value))
+        }
     }
 }
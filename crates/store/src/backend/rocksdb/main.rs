@@ -38,6 +38,7 @@ use utils::{
 use crate::{Deserialize, Error};
 
 use super::{RocksDbStore, CF_BITMAPS, CF_BLOBS, CF_COUNTERS, CF_INDEXES, CF_LOGS, CF_VALUES};
+use crate::dispatch::options::StoreOptions;
 
 impl RocksDbStore {
     pub async fn open(config: &Config, prefix: impl AsKey) -> crate::Result<Self> {
@@ -110,9 +111,28 @@ impl RocksDbStore {
                 .map_err(|err| {
                     crate::Error::InternalError(format!("Failed to build worker pool: {}", err))
                 })?,
+            options: StoreOptions::parse(config, &prefix)?,
         })
     }
 
+    pub(crate) async fn flush(&self) -> crate::Result<()> {
+        let db = self.db.clone();
+        self.spawn_worker(move || {
+            for cf in [
+                CF_BITMAPS,
+                CF_BLOBS,
+                CF_COUNTERS,
+                CF_INDEXES,
+                CF_LOGS,
+                CF_VALUES,
+            ] {
+                db.flush_cf(&db.cf_handle(cf).unwrap())?;
+            }
+            db.flush_wal(true).map_err(Into::into)
+        })
+        .await
+    }
+
     pub async fn spawn_worker<U, V>(&self, mut f: U) -> crate::Result<V>
     where
         U: FnMut() -> crate::Result<V> + Send,
@@ -25,6 +25,7 @@ use std::sync::Arc;
 
 use rocksdb::{MultiThreaded, OptimisticTransactionDB};
 
+use crate::dispatch::options::StoreOptions;
 use crate::{
     SUBSPACE_BITMAPS, SUBSPACE_BLOBS, SUBSPACE_COUNTERS, SUBSPACE_INDEXES, SUBSPACE_LOGS,
     SUBSPACE_VALUES,
@@ -34,6 +35,7 @@ pub mod bitmap;
 pub mod blob;
 pub mod main;
 pub mod read;
+pub mod snapshot;
 pub mod write;
 
 static CF_BITMAPS: &str = unsafe { std::str::from_utf8_unchecked(&[SUBSPACE_BITMAPS]) };
@@ -45,11 +47,18 @@ static CF_COUNTERS: &str = unsafe { std::str::from_utf8_unchecked(&[SUBSPACE_COU
 
 impl From<rocksdb::Error> for crate::Error {
     fn from(value: rocksdb::Error) -> Self {
-        Self::InternalError(format!("RocksDB error: {}", value))
+        match value.kind() {
+            rocksdb::ErrorKind::TimedOut => Self::Timeout(format!("RocksDB error: {}", value)),
+            rocksdb::ErrorKind::Busy | rocksdb::ErrorKind::TryAgain => {
+                Self::Unavailable(format!("RocksDB error: {}", value))
+            }
+            _ => Self::InternalError(format!("RocksDB error: {}", value)),
+        }
     }
 }
 
 pub struct RocksDbStore {
     db: Arc<OptimisticTransactionDB<MultiThreaded>>,
     worker_pool: rayon::ThreadPool,
+    pub(crate) options: Arc<StoreOptions>,
 }
@@ -55,6 +55,20 @@ impl RocksDbStore {
         .await
     }
 
+    pub(crate) async fn key_exists(&self, key: impl Key) -> crate::Result<bool> {
+        let db = self.db.clone();
+        self.spawn_worker(move || {
+            db.get_pinned_cf(
+                &db.cf_handle(std::str::from_utf8(&[key.subspace()]).unwrap())
+                    .unwrap(),
+                &key.serialize(0),
+            )
+            .map(|value| value.is_some())
+            .map_err(Into::into)
+        })
+        .await
+    }
+
     pub(crate) async fn get_bitmap(
         &self,
         key: BitmapKey<BitmapClass>,
@@ -96,6 +110,26 @@ impl RocksDbStore {
                 .unwrap();
             let begin = params.begin.serialize(0);
             let end = params.end.serialize(0);
+
+            // Fetch a single key with one seek instead of opening a full range iterator
+            if params.first {
+                let mut it = db.raw_iterator_cf(&cf);
+                if params.ascending {
+                    it.seek(&begin);
+                } else {
+                    it.seek_for_prev(&end);
+                }
+                it.status()?;
+
+                if let (Some(key), Some(value)) = (it.key(), it.value()) {
+                    if key >= begin.as_slice() && key <= end.as_slice() {
+                        cb(key, value)?;
+                    }
+                }
+
+                return Ok(());
+            }
+
             let it_mode = if params.ascending {
                 IteratorMode::From(&begin, Direction::Forward)
             } else {
@@ -107,7 +141,6 @@ impl RocksDbStore {
                 if key.as_ref() < begin.as_slice()
                     || key.as_ref() > end.as_slice()
                     || !cb(&key, &value)?
-                    || params.first
                 {
                     break;
                 }
@@ -118,6 +151,37 @@ impl RocksDbStore {
         .await
     }
 
+    pub(crate) async fn estimate_count(
+        &self,
+        subspace: u8,
+        begin: Vec<u8>,
+        end: Option<Vec<u8>>,
+    ) -> crate::Result<u64> {
+        let db = self.db.clone();
+        self.spawn_worker(move || {
+            let cf = db
+                .cf_handle(std::str::from_utf8(&[subspace]).unwrap())
+                .unwrap();
+
+            if let Some(end) = end {
+                let mut count = 0;
+                for row in db.iterator_cf(&cf, IteratorMode::From(&begin, Direction::Forward)) {
+                    let (key, _) = row?;
+                    if key.as_ref() >= end.as_slice() {
+                        break;
+                    }
+                    count += 1;
+                }
+                Ok(count)
+            } else {
+                db.property_int_value_cf(&cf, "rocksdb.estimate-num-keys")
+                    .map(|count| count.unwrap_or(0))
+                    .map_err(Into::into)
+            }
+        })
+        .await
+    }
+
     pub(crate) async fn get_counter(
         &self,
         key: impl Into<ValueKey<ValueClass>> + Sync + Send,
@@ -139,4 +203,31 @@ impl RocksDbStore {
         })
         .await
     }
+
+    pub(crate) async fn get_counters(
+        &self,
+        keys: Vec<ValueKey<ValueClass>>,
+    ) -> crate::Result<Vec<i64>> {
+        let keys = keys
+            .into_iter()
+            .map(|key| key.serialize(0))
+            .collect::<Vec<_>>();
+        let db = self.db.clone();
+        self.spawn_worker(move || {
+            let cf = db.cf_handle(CF_COUNTERS).unwrap();
+            db.multi_get_cf(keys.iter().map(|key| (&cf, key)))
+                .into_iter()
+                .map(|bytes| {
+                    Ok(if let Some(bytes) = bytes? {
+                        i64::from_le_bytes(bytes[..].try_into().map_err(|_| {
+                            crate::Error::InternalError("Invalid counter value.".to_string())
+                        })?)
+                    } else {
+                        0
+                    })
+                })
+                .collect()
+        })
+        .await
+    }
 }
@@ -0,0 +1,150 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::mpsc;
+
+use roaring::RoaringBitmap;
+use rocksdb::{MultiThreaded, OptimisticTransactionDB, SnapshotWithThreadMode};
+use tokio::sync::oneshot;
+
+use crate::{write::BitmapClass, BitmapKey, Deserialize, Key, WITHOUT_BLOCK_NUM};
+
+use super::{RocksDbStore, CF_BITMAPS};
+
+type RocksDb = OptimisticTransactionDB<MultiThreaded>;
+type SnapshotJob =
+    Box<dyn for<'x> FnOnce(&'x RocksDb, &'x SnapshotWithThreadMode<'x, RocksDb>) + Send>;
+
+// RocksDB snapshots borrow the database, so they are owned by a dedicated thread
+// that runs every read of the snapshot and releases it once the handle is dropped.
+pub struct RocksDbSnapshot {
+    tx: mpsc::Sender<SnapshotJob>,
+}
+
+impl RocksDbStore {
+    pub(crate) async fn snapshot(&self) -> crate::Result<RocksDbSnapshot> {
+        let db = self.db.clone();
+        let (tx, rx) = mpsc::channel::<SnapshotJob>();
+        let (ready_tx, ready_rx) = oneshot::channel();
+
+        std::thread::Builder::new()
+            .name("rocksdb-snapshot".to_string())
+            .spawn(move || {
+                let snapshot = db.snapshot();
+                ready_tx.send(()).ok();
+                while let Ok(job) = rx.recv() {
+                    job(&db, &snapshot);
+                }
+            })
+            .map_err(|err| {
+                crate::Error::InternalError(format!("Failed to spawn snapshot thread: {err}"))
+            })?;
+
+        // Reads must not start before the snapshot is taken
+        ready_rx.await.map_err(|_| {
+            crate::Error::InternalError("Snapshot thread exited unexpectedly".to_string())
+        })?;
+
+        Ok(RocksDbSnapshot { tx })
+    }
+}
+
+impl RocksDbSnapshot {
+    pub(crate) async fn get_value<U>(&mut self, key: impl Key) -> crate::Result<Option<U>>
+    where
+        U: Deserialize + 'static,
+    {
+        let subspace = key.subspace();
+        let key = key.serialize(0);
+        self.run(move |db, snapshot| {
+            snapshot
+                .get_pinned_cf(
+                    &db.cf_handle(std::str::from_utf8(&[subspace]).unwrap())
+                        .unwrap(),
+                    &key,
+                )
+                .map_err(Into::into)
+                .and_then(|value| {
+                    if let Some(value) = value {
+                        U::deserialize(&value).map(Some)
+                    } else {
+                        Ok(None)
+                    }
+                })
+        })
+        .await
+    }
+
+    pub(crate) async fn get_bitmap(
+        &mut self,
+        key: BitmapKey<BitmapClass>,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        let key = key.serialize(WITHOUT_BLOCK_NUM);
+        self.run(move |db, snapshot| {
+            snapshot
+                .get_pinned_cf(&db.cf_handle(CF_BITMAPS).unwrap(), &key)
+                .map_err(Into::into)
+                .and_then(|value| {
+                    if let Some(value) = value {
+                        RoaringBitmap::deserialize(&value).map(|rb| {
+                            if !rb.is_empty() {
+                                Some(rb)
+                            } else {
+                                None
+                            }
+                        })
+                    } else {
+                        Ok(None)
+                    }
+                })
+        })
+        .await
+    }
+
+    async fn run<V>(
+        &self,
+        f: impl for<'x> FnOnce(&'x RocksDb, &'x SnapshotWithThreadMode<'x, RocksDb>) -> crate::Result<V>
+            + Send
+            + 'static,
+    ) -> crate::Result<V>
+    where
+        V: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(Box::new(
+                move |db: &RocksDb, snapshot: &SnapshotWithThreadMode<'_, RocksDb>| {
+                    tx.send(f(db, snapshot)).ok();
+                },
+            ))
+            .map_err(|_| {
+                crate::Error::InternalError("Snapshot thread exited unexpectedly".to_string())
+            })?;
+
+        rx.await.unwrap_or_else(|_| {
+            Err(crate::Error::InternalError(
+                "Snapshot thread failed".to_string(),
+            ))
+        })
+    }
+}
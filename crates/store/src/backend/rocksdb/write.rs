@@ -40,7 +40,8 @@ use super::{
 };
 use crate::{
     write::{
-        Batch, BitmapClass, Operation, ValueClass, ValueOp, MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME,
+        assert::AssertValue, Batch, BatchBuilder, BitmapClass, Operation, ValueClass, ValueOp,
+        MAX_COMMIT_ATTEMPTS, MAX_COMMIT_TIME,
     },
     BitmapKey, Deserialize, IndexKey, Key, LogKey, ValueKey, WITHOUT_BLOCK_NUM,
 };
@@ -88,6 +89,27 @@ impl RocksDbStore {
         .await
     }
 
+    // Compare-and-set on an empty key, a failed assertion means the key exists
+    pub(crate) async fn put_if_absent(
+        &self,
+        key: ValueKey<ValueClass>,
+        value: Vec<u8>,
+    ) -> crate::Result<bool> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(key.account_id)
+            .with_collection(key.collection)
+            .update_document(key.document_id)
+            .assert_value(key.class.clone(), ())
+            .set(key.class, value);
+
+        match self.write(batch.build()).await {
+            Ok(_) => Ok(true),
+            Err(crate::Error::AssertValueFailed) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
     pub(crate) async fn delete_range(&self, from: impl Key, to: impl Key) -> crate::Result<()> {
         let db = self.db.clone();
         self.spawn_worker(move || {
@@ -288,10 +310,15 @@ impl<'x> RocksDBTransaction<'x> {
                             class,
                         }
                         .serialize(0);
-                        let matches = txn
-                            .get_pinned_for_update_cf(&self.cf_values, &key, true)?
-                            .map(|value| assert_value.matches(&value))
-                            .unwrap_or_else(|| assert_value.is_none());
+                        let matches = match assert_value {
+                            AssertValue::Counter(value) => txn
+                                .get_pinned_for_update_cf(&self.cf_counters, &key, true)?
+                                .map_or(*value == 0, |bytes| assert_value.matches(&bytes)),
+                            _ => txn
+                                .get_pinned_for_update_cf(&self.cf_values, &key, true)?
+                                .map(|value| assert_value.matches(&value))
+                                .unwrap_or_else(|| assert_value.is_none()),
+                        };
 
                         if !matches {
                             txn.rollback()?;
@@ -33,8 +33,11 @@ use utils::{
     config::{utils::AsKey, Config},
 };
 
+use crate::write::integrity::ValueIntegrity;
+
 pub struct S3Store {
     bucket: Bucket,
+    integrity: Option<ValueIntegrity>,
 }
 
 impl S3Store {
@@ -67,6 +70,7 @@ impl S3Store {
             )?
             .with_path_style()
             .with_request_timeout(timeout),
+            integrity: ValueIntegrity::parse(config, &prefix)?,
         })
     }
 
@@ -75,6 +79,23 @@ impl S3Store {
         key: &[u8],
         range: Range<u32>,
     ) -> crate::Result<Option<Vec<u8>>> {
+        if let Some(integrity) = &self.integrity {
+            // Signed blobs are verified as a whole before returning the requested range,
+            // so ranged reads cost as much as downloading the entire blob
+            if let Some(blob) = self.get_object(key, 0..u32::MAX).await? {
+                let mut blob = integrity.verify(key, blob)?;
+                blob.truncate(range.end as usize);
+                blob.drain(..std::cmp::min(range.start as usize, blob.len()));
+                Ok(Some(blob))
+            } else {
+                Ok(None)
+            }
+        } else {
+            self.get_object(key, range).await
+        }
+    }
+
+    async fn get_object(&self, key: &[u8], range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
         let path = Base32Writer::from_bytes(key).finalize();
         let response = if range.start != 0 || range.end != u32::MAX {
             self.bucket
@@ -102,6 +123,14 @@ impl S3Store {
     }
 
     pub(crate) async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
+        let signed;
+        let data = if let Some(integrity) = &self.integrity {
+            signed = integrity.sign(key, data);
+            signed.as_slice()
+        } else {
+            data
+        };
+
         match self
             .bucket
             .put_object(Base32Writer::from_bytes(key).finalize(), data)
@@ -124,6 +153,21 @@ impl S3Store {
             .map(|response| (200..300).contains(&response.status_code()))
             .map_err(|e| e.into())
     }
+
+    pub(crate) async fn ping(&self) -> crate::Result<()> {
+        match self
+            .bucket
+            .list_page(String::new(), None, None, None, Some(1))
+            .await
+        {
+            Ok((_, code)) if (200..300).contains(&code) => Ok(()),
+            Ok((_, code)) => Err(crate::Error::Unavailable(format!(
+                "S3 bucket check failed with code {}",
+                code
+            ))),
+            Err(e) => Err(e.into()),
+        }
+    }
 }
 
 impl From<S3Error> for crate::Error {
@@ -33,7 +33,8 @@ impl SqliteStore {
         key: &[u8],
         range: Range<u32>,
     ) -> crate::Result<Option<Vec<u8>>> {
-        let conn = self.conn_pool.get()?;
+        let key = key.to_vec();
+        let conn = self.read_pool.get()?;
         self.spawn_worker(move || {
             let mut result = conn.prepare_cached("SELECT v FROM t WHERE k = ?")?;
             result
@@ -60,7 +61,8 @@ impl SqliteStore {
     }
 
     pub(crate) async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
-        let conn = self.conn_pool.get()?;
+        let (key, data) = (key.to_vec(), data.to_vec());
+        let conn = self.write_pool.get()?;
         self.spawn_worker(move || {
             conn.prepare_cached("INSERT OR REPLACE INTO t (k, v) VALUES (?, ?)")?
                 .execute([key, data])
@@ -71,7 +73,8 @@ impl SqliteStore {
     }
 
     pub(crate) async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
-        let conn = self.conn_pool.get()?;
+        let key = key.to_vec();
+        let conn = self.write_pool.get()?;
         self.spawn_worker(move || {
             conn.prepare_cached("DELETE FROM t WHERE k = ?")?
                 .execute([key])
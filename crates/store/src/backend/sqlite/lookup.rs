@@ -33,9 +33,18 @@ impl SqliteStore {
         query: &str,
         params_: Vec<Value<'_>>,
     ) -> crate::Result<T> {
-        let conn = self.conn_pool.get()?;
+        // Statements that modify the database must go through the writer
+        let conn = match T::query_type() {
+            QueryType::Execute => self.write_pool.get()?,
+            _ => self.read_pool.get()?,
+        };
+        let query = query.to_string();
+        let params_ = params_
+            .into_iter()
+            .map(Value::into_owned)
+            .collect::<Vec<_>>();
         self.spawn_worker(move || {
-            let mut s = conn.prepare_cached(query)?;
+            let mut s = conn.prepare_cached(&query)?;
             let params = params_
                 .iter()
                 .map(|v| v as &(dyn rusqlite::types::ToSql))
@@ -58,6 +67,48 @@ impl SqliteStore {
         })
         .await
     }
+
+    pub(crate) async fn query_batch(
+        &self,
+        queries: Vec<(&str, Vec<Value<'_>>)>,
+    ) -> crate::Result<Vec<crate::Rows>> {
+        let mut conn = self.write_pool.get()?;
+        let queries = queries
+            .into_iter()
+            .map(|(query, params)| {
+                (
+                    query.to_string(),
+                    params
+                        .into_iter()
+                        .map(Value::into_owned)
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        self.spawn_worker(move || {
+            let trx = conn.transaction()?;
+            let mut results = Vec::with_capacity(queries.len());
+
+            for (query, params_) in &queries {
+                let mut s = trx.prepare_cached(query)?;
+                let params = params_
+                    .iter()
+                    .map(|v| v as &(dyn rusqlite::types::ToSql))
+                    .collect::<Vec<_>>();
+                results.push(s.query(params.as_slice())?.into_rows());
+            }
+
+            trx.commit().map(|_| results).map_err(Into::into)
+        })
+        .await
+    }
+
+    pub(crate) async fn prepare(&self, query: &str) -> crate::Result<()> {
+        let conn = self.read_pool.get()?;
+        let query = query.to_string();
+        self.spawn_worker(move || conn.prepare(&query).map(|_| ()).map_err(Into::into))
+            .await
+    }
 }
 
 impl ToSql for Value<'_> {
@@ -68,6 +119,9 @@ impl ToSql for Value<'_> {
             Value::Float(value) => value.to_sql(),
             Value::Text(value) => value.to_sql(),
             Value::Blob(value) => value.to_sql(),
+            Value::Json(value) => Ok(rusqlite::types::ToSqlOutput::Owned(
+                rusqlite::types::Value::Text(value.to_string()),
+            )),
             Value::Null => Ok(rusqlite::types::ToSqlOutput::Owned(
                 rusqlite::types::Value::Null,
             )),
@@ -21,7 +21,10 @@
  * for more details.
 */
 
+use std::{sync::Arc, time::Duration};
+
 use r2d2::Pool;
+use rusqlite::OpenFlags;
 use tokio::sync::oneshot;
 use utils::{
     config::{utils::AsKey, Config},
@@ -33,51 +36,130 @@ use crate::{
     SUBSPACE_VALUES,
 };
 
-use super::{pool::SqliteConnectionManager, SqliteStore};
+use super::{pool::SqliteConnectionManager, CheckpointMode, SqliteStore};
+use crate::dispatch::options::StoreOptions;
 
 impl SqliteStore {
     pub async fn open(config: &Config, prefix: impl AsKey) -> crate::Result<Self> {
         let prefix = prefix.as_key();
-        let db = Self {
-            conn_pool: Pool::builder()
-                .max_size(
-                    config
-                        .property((&prefix, "pool.max-connections"))?
-                        .unwrap_or_else(|| (num_cpus::get() * 4) as u32),
+
+        // page_size only takes effect on a fresh database, as it cannot be
+        // changed once the database is in WAL mode.
+        let page_size = config
+            .property::<u32>((&prefix, "sqlite.page-size"))?
+            .unwrap_or(4096);
+        if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+            return Err(format!(
+                "Invalid page size {page_size} for property {prefix}.sqlite.page-size, expected a power of two between 512 and 65536."
+            )
+            .into());
+        }
+        let synchronous = config
+            .value((&prefix, "sqlite.synchronous"))
+            .unwrap_or("normal")
+            .to_ascii_uppercase();
+        if !matches!(synchronous.as_str(), "OFF" | "NORMAL" | "FULL" | "EXTRA") {
+            return Err(format!(
+                "Invalid value {synchronous:?} for property {prefix}.sqlite.synchronous, expected off, normal, full or extra."
+            )
+            .into());
+        }
+        let pragmas = format!(
+            concat!(
+                "PRAGMA page_size = {}; ",
+                "PRAGMA journal_mode = WAL; ",
+                "PRAGMA synchronous = {}; ",
+                "PRAGMA temp_store = memory; ",
+                "PRAGMA busy_timeout = 30000; ",
+                "PRAGMA mmap_size = {}; ",
+                "PRAGMA cache_size = -{}; ",
+                "PRAGMA wal_autocheckpoint = {};"
+            ),
+            page_size,
+            synchronous,
+            config
+                .property::<u64>((&prefix, "sqlite.mmap-size"))?
+                .unwrap_or(256 * 1024 * 1024),
+            // Negative values are interpreted by SQLite as KiB
+            config
+                .property::<u64>((&prefix, "sqlite.cache-size"))?
+                .unwrap_or(64 * 1024 * 1024)
+                / 1024,
+            config
+                .property::<u32>((&prefix, "sqlite.wal-autocheckpoint"))?
+                .unwrap_or(1000),
+        );
+
+        let path = config
+            .value_require((&prefix, "path"))
+            .failed("Invalid configuration file");
+
+        // The writer is built first so the database file exists and is in WAL mode
+        // before any read-only connection is opened.
+        let write_pool =
+            Pool::builder()
+                .max_size(1)
+                .build(SqliteConnectionManager::file(path).with_init({
+                    let pragmas = pragmas.clone();
+                    move |c| c.execute_batch(&pragmas)
+                }))?;
+        let max_connections = config
+            .property((&prefix, "pool.max-connections"))?
+            .unwrap_or_else(|| (num_cpus::get() * 4) as u32);
+        let read_pool = Pool::builder().max_size(max_connections).build(
+            SqliteConnectionManager::file(path)
+                .with_flags(
+                    OpenFlags::SQLITE_OPEN_READ_ONLY
+                        | OpenFlags::SQLITE_OPEN_URI
+                        | OpenFlags::SQLITE_OPEN_NO_MUTEX,
                 )
-                .build(
-                    SqliteConnectionManager::file(
-                        config
-                            .value_require((&prefix, "path"))
-                            .failed("Invalid configuration file"),
-                    )
-                    .with_init(|c| {
-                        c.execute_batch(concat!(
-                            "PRAGMA journal_mode = WAL; ",
-                            "PRAGMA synchronous = NORMAL; ",
-                            "PRAGMA temp_store = memory;",
-                            "PRAGMA busy_timeout = 30000;"
-                        ))
-                    }),
-                )?,
+                .with_init(move |c| c.execute_batch(&pragmas)),
+        )?;
+
+        // By default there is one worker per connection, including the writer
+        let num_workers = config
+            .property::<usize>((&prefix, "pool.workers"))?
+            .filter(|v| *v > 0)
+            .unwrap_or(max_connections as usize + 1);
+        let max_queued = config
+            .property::<usize>((&prefix, "pool.max-queued"))?
+            .filter(|v| *v > 0)
+            .unwrap_or(num_workers * 4);
+
+        let db = Self {
+            read_pool,
+            write_pool,
             worker_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(
-                    config
-                        .property::<usize>((&prefix, "pool.workers"))?
-                        .filter(|v| *v > 0)
-                        .unwrap_or_else(num_cpus::get),
-                )
+                .num_threads(num_workers)
                 .build()
                 .map_err(|err| {
                     crate::Error::InternalError(format!("Failed to build worker pool: {}", err))
                 })?,
+            worker_permits: Arc::new(tokio::sync::Semaphore::new(max_queued)),
+            options: StoreOptions::parse(config, &prefix)?,
+            checkpoint_mode: config
+                .property_or_static((&prefix, "sqlite.checkpoint.mode"), "passive")?,
+            checkpoint_interval: config.property((&prefix, "sqlite.checkpoint.interval"))?,
+            slow_query: config
+                .property::<u64>((&prefix, "slow-query-ms"))?
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
         };
         db.create_tables()?;
         Ok(db)
     }
 
+    pub(crate) async fn ping(&self) -> crate::Result<()> {
+        let conn = self.read_pool.get()?;
+        self.spawn_worker(move || {
+            conn.query_row("SELECT 1", [], |_| Ok(()))
+                .map_err(Into::into)
+        })
+        .await
+    }
+
     pub(super) fn create_tables(&self) -> crate::Result<()> {
-        let conn = self.conn_pool.get()?;
+        let conn = self.write_pool.get()?;
 
         for table in [SUBSPACE_VALUES, SUBSPACE_LOGS, SUBSPACE_BLOBS] {
             let table = char::from(table);
@@ -118,17 +200,89 @@ impl SqliteStore {
         Ok(())
     }
 
-    pub async fn spawn_worker<U, V>(&self, mut f: U) -> crate::Result<V>
+    /// Copies the WAL contents back into the database, in TRUNCATE mode the WAL
+    /// file is also reset to zero bytes.
+    pub async fn checkpoint(&self, mode: CheckpointMode) -> crate::Result<()> {
+        let conn = self.write_pool.get()?;
+        self.spawn_worker(move || {
+            let (busy, log_frames, checkpointed_frames) = conn.query_row(
+                &format!("PRAGMA wal_checkpoint({})", mode.as_str()),
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, i64>(2)?,
+                    ))
+                },
+            )?;
+
+            tracing::debug!(
+                context = "sqlite",
+                event = "checkpoint",
+                mode = mode.as_str(),
+                busy = busy != 0,
+                log_frames = log_frames,
+                checkpointed_frames = checkpointed_frames,
+                "WAL checkpoint completed."
+            );
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Checkpoints the WAL on the configured interval, until the store is dropped.
+    pub(crate) fn spawn_checkpoint_task(self: &Arc<Self>) {
+        let interval = if let Some(interval) = self.checkpoint_interval {
+            interval
+        } else {
+            return;
+        };
+        let store = Arc::downgrade(self);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let store = if let Some(store) = store.upgrade() {
+                    store
+                } else {
+                    return;
+                };
+                if let Err(err) = store.checkpoint(store.checkpoint_mode).await {
+                    tracing::warn!(
+                        context = "sqlite",
+                        event = "error",
+                        reason = ?err,
+                        "WAL checkpoint failed."
+                    );
+                }
+            }
+        });
+    }
+
+    // Jobs own everything they touch, so the calling task only waits on the channel
+    // and never blocks its runtime thread. The permit is held until the job completes,
+    // even if the caller stops waiting for it.
+    pub async fn spawn_worker<U, V>(&self, f: U) -> crate::Result<V>
     where
-        U: FnMut() -> crate::Result<V> + Send,
-        V: Sync + Send + 'static,
+        U: FnOnce() -> crate::Result<V> + Send + 'static,
+        V: Send + 'static,
     {
+        // Wait for room in the queue rather than piling up work on the pool
+        let permit = self
+            .worker_permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| {
+                crate::Error::InternalError("Worker pool is no longer available".to_string())
+            })?;
         let (tx, rx) = oneshot::channel();
 
-        self.worker_pool.scope(|s| {
-            s.spawn(|_| {
-                tx.send(f()).ok();
-            });
+        self.worker_pool.spawn(move || {
+            tx.send(f()).ok();
+            drop(permit);
         });
 
         match rx.await {
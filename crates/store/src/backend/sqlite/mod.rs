@@ -21,26 +21,54 @@
  * for more details.
 */
 
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
 use r2d2::Pool;
+use utils::config::utils::{AsKey, ParseValue};
 
 use self::pool::SqliteConnectionManager;
+use crate::dispatch::options::StoreOptions;
 
 pub mod blob;
 pub mod lookup;
 pub mod main;
 pub mod pool;
 pub mod read;
+pub mod snapshot;
 pub mod write;
 
 impl From<r2d2::Error> for crate::Error {
     fn from(err: r2d2::Error) -> Self {
-        Self::InternalError(format!("Connection pool error: {}", err))
+        // r2d2 only fails when no connection could be obtained in time
+        Self::Timeout(format!("Connection pool error: {}", err))
     }
 }
 
 impl From<rusqlite::Error> for crate::Error {
     fn from(err: rusqlite::Error) -> Self {
-        Self::InternalError(format!("SQLite error: {}", err))
+        match &err {
+            rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error {
+                    code: rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked,
+                    ..
+                },
+                _,
+            ) => Self::Timeout(format!("SQLite error: {}", err)),
+            rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error {
+                    code: rusqlite::ErrorCode::CannotOpen,
+                    ..
+                },
+                _,
+            ) => Self::Unavailable(format!("SQLite error: {}", err)),
+            rusqlite::Error::QueryReturnedNoRows => {
+                Self::NotFound(format!("SQLite error: {}", err))
+            }
+            _ => Self::InternalError(format!("SQLite error: {}", err)),
+        }
     }
 }
 
@@ -51,6 +79,45 @@ impl From<rusqlite::types::FromSqlError> for crate::Error {
 }
 
 pub struct SqliteStore {
-    pub(crate) conn_pool: Pool<SqliteConnectionManager>,
+    // Read-only connections, SQLite allows any number of concurrent readers
+    pub(crate) read_pool: Pool<SqliteConnectionManager>,
+    // Single connection used for all writes, as SQLite only allows one writer
+    pub(crate) write_pool: Pool<SqliteConnectionManager>,
     pub(crate) worker_pool: rayon::ThreadPool,
+    // Bounds the operations queued on the worker pool, callers wait for a permit
+    pub(crate) worker_permits: Arc<tokio::sync::Semaphore>,
+    pub(crate) options: Arc<StoreOptions>,
+    pub(crate) checkpoint_mode: CheckpointMode,
+    pub(crate) checkpoint_interval: Option<Duration>,
+    pub(crate) slow_query: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointMode {
+    // Copies as many frames as possible without blocking readers or writers
+    Passive,
+    // Waits for readers to finish, then checkpoints and truncates the WAL file
+    Truncate,
+}
+
+impl CheckpointMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CheckpointMode::Passive => "PASSIVE",
+            CheckpointMode::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+impl ParseValue for CheckpointMode {
+    fn parse_value(key: impl AsKey, value: &str) -> utils::config::Result<Self> {
+        match value {
+            "passive" => Ok(CheckpointMode::Passive),
+            "truncate" => Ok(CheckpointMode::Truncate),
+            _ => Err(format!(
+                "Invalid value for checkpoint mode {key:?}: {value:?}",
+                key = key.as_key(),
+            )),
+        }
+    }
 }
@@ -22,7 +22,8 @@
 */
 
 use roaring::RoaringBitmap;
-use rusqlite::OptionalExtension;
+use rusqlite::{Connection, OptionalExtension};
+use tokio::sync::mpsc;
 
 use crate::{
     write::{key::DeserializeBigEndian, BitmapClass, ValueClass},
@@ -31,24 +32,27 @@ use crate::{
 
 use super::SqliteStore;
 
+const ITERATE_CHUNK_SIZE: usize = 256;
+
 impl SqliteStore {
     pub(crate) async fn get_value<U>(&self, key: impl Key) -> crate::Result<Option<U>>
     where
         U: Deserialize + 'static,
     {
-        let conn = self.conn_pool.get()?;
+        let subspace = key.subspace();
+        let key = key.serialize(0);
+        let conn = self.read_pool.get()?;
+        self.spawn_worker(move || read_value(&conn, subspace, &key))
+            .await
+    }
+
+    pub(crate) async fn key_exists(&self, key: impl Key) -> crate::Result<bool> {
+        let table = char::from(key.subspace());
+        let key = key.serialize(0);
+        let conn = self.read_pool.get()?;
         self.spawn_worker(move || {
-            let mut result = conn.prepare_cached(&format!(
-                "SELECT v FROM {} WHERE k = ?",
-                char::from(key.subspace())
-            ))?;
-            let key = key.serialize(0);
-            result
-                .query_row([&key], |row| {
-                    U::deserialize(row.get_ref(0)?.as_bytes()?)
-                        .map_err(|err| rusqlite::Error::ToSqlConversionFailure(err.into()))
-                })
-                .optional()
+            conn.prepare_cached(&format!("SELECT 1 FROM {table} WHERE k = ?"))?
+                .exists([&key])
                 .map_err(Into::into)
         })
         .await
@@ -60,40 +64,31 @@ impl SqliteStore {
     ) -> crate::Result<Option<RoaringBitmap>> {
         let begin = key.serialize(0);
         key.block_num = u32::MAX;
-        let key_len = begin.len();
         let end = key.serialize(0);
-        let conn = self.conn_pool.get()?;
+        let conn = self.read_pool.get()?;
 
-        self.spawn_worker(move || {
-            let mut bm = RoaringBitmap::new();
-            let mut query = conn.prepare_cached("SELECT k FROM b WHERE k >= ? AND k <= ?")?;
-            let mut rows = query.query([&begin, &end])?;
-
-            while let Some(row) = rows.next()? {
-                let key = row.get_ref(0)?.as_bytes()?;
-                if key.len() == key_len {
-                    bm.insert(key.deserialize_be_u32(key.len() - U32_LEN)?);
-                }
-            }
-            Ok(if !bm.is_empty() { Some(bm) } else { None })
-        })
-        .await
+        self.spawn_worker(move || read_bitmap(&conn, &begin, &end))
+            .await
     }
 
+    // Rows are read on the worker pool and handed to the callback in chunks, so
+    // that the callback does not need to be moved to the worker thread.
     pub(crate) async fn iterate<T: Key>(
         &self,
         params: IterateParams<T>,
         mut cb: impl for<'x> FnMut(&'x [u8], &'x [u8]) -> crate::Result<bool> + Sync + Send,
     ) -> crate::Result<()> {
-        let conn = self.conn_pool.get()?;
+        let conn = self.read_pool.get()?;
+        let table = char::from(params.begin.subspace());
+        let begin = params.begin.serialize(0);
+        let end = params.end.serialize(0);
+        let (first, ascending, values) = (params.first, params.ascending, params.values);
+        let (tx, mut rx) = mpsc::channel::<Vec<(Vec<u8>, Vec<u8>)>>(2);
 
-        self.spawn_worker(move || {
-            let table = char::from(params.begin.subspace());
-            let begin = params.begin.serialize(0);
-            let end = params.end.serialize(0);
-            let keys = if params.values { "k, v" } else { "k" };
+        let reader = self.spawn_worker(move || {
+            let keys = if values { "k, v" } else { "k" };
 
-            let mut query = conn.prepare_cached(&match (params.first, params.ascending) {
+            let mut query = conn.prepare_cached(&match (first, ascending) {
                 (true, true) => {
                     format!(
                         "SELECT {keys} FROM {table} WHERE k >= ? AND k <= ? ORDER BY k ASC LIMIT 1"
@@ -112,25 +107,66 @@ impl SqliteStore {
                 }
             })?;
             let mut rows = query.query([&begin, &end])?;
+            let mut chunk = Vec::with_capacity(ITERATE_CHUNK_SIZE);
 
-            if params.values {
-                while let Some(row) = rows.next()? {
-                    let key = row.get_ref(0)?.as_bytes()?;
-                    let value = row.get_ref(1)?.as_bytes()?;
+            while let Some(row) = rows.next()? {
+                let key = row.get_ref(0)?.as_bytes()?.to_vec();
+                let value = if values {
+                    row.get_ref(1)?.as_bytes()?.to_vec()
+                } else {
+                    Vec::new()
+                };
+                chunk.push((key, value));
 
-                    if !cb(key, value)? {
-                        break;
+                if chunk.len() == ITERATE_CHUNK_SIZE {
+                    let chunk =
+                        std::mem::replace(&mut chunk, Vec::with_capacity(ITERATE_CHUNK_SIZE));
+                    if tx.blocking_send(chunk).is_err() {
+                        // The callback stopped the iteration
+                        return Ok(());
                     }
                 }
-            } else {
-                while let Some(row) = rows.next()? {
-                    if !cb(row.get_ref(0)?.as_bytes()?, b"")? {
-                        break;
+            }
+            if !chunk.is_empty() {
+                let _ = tx.blocking_send(chunk);
+            }
+
+            Ok(())
+        });
+        let consumer = async move {
+            while let Some(chunk) = rx.recv().await {
+                for (key, value) in &chunk {
+                    if !cb(key, value)? {
+                        return Ok(());
                     }
                 }
             }
-
             Ok(())
+        };
+
+        let (read_result, cb_result) = tokio::join!(reader, consumer);
+        cb_result.and(read_result)
+    }
+
+    pub(crate) async fn estimate_count(
+        &self,
+        subspace: u8,
+        begin: Vec<u8>,
+        end: Option<Vec<u8>>,
+    ) -> crate::Result<u64> {
+        let conn = self.read_pool.get()?;
+        self.spawn_worker(move || {
+            let table = char::from(subspace);
+            let count: i64 = if let Some(end) = end {
+                conn.prepare_cached(&format!(
+                    "SELECT COUNT(*) FROM {table} WHERE k >= ? AND k < ?"
+                ))?
+                .query_row([&begin, &end], |row| row.get(0))?
+            } else {
+                conn.prepare_cached(&format!("SELECT COUNT(*) FROM {table}"))?
+                    .query_row([], |row| row.get(0))?
+            };
+            Ok(count as u64)
         })
         .await
     }
@@ -140,7 +176,7 @@ impl SqliteStore {
         key: impl Into<ValueKey<ValueClass>> + Sync + Send,
     ) -> crate::Result<i64> {
         let key = key.into().serialize(0);
-        let conn = self.conn_pool.get()?;
+        let conn = self.read_pool.get()?;
         self.spawn_worker(move || {
             match conn
                 .prepare_cached("SELECT v FROM c WHERE k = ?")?
@@ -153,4 +189,61 @@ impl SqliteStore {
         })
         .await
     }
+
+    pub(crate) async fn get_counters(
+        &self,
+        keys: Vec<ValueKey<ValueClass>>,
+    ) -> crate::Result<Vec<i64>> {
+        let keys = keys
+            .into_iter()
+            .map(|key| key.serialize(0))
+            .collect::<Vec<_>>();
+        let conn = self.read_pool.get()?;
+        self.spawn_worker(move || {
+            let mut s = conn.prepare_cached("SELECT v FROM c WHERE k = ?")?;
+            keys.iter()
+                .map(|key| match s.query_row([key], |row| row.get::<_, i64>(0)) {
+                    Ok(value) => Ok(value),
+                    Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+                    Err(e) => Err(e.into()),
+                })
+                .collect()
+        })
+        .await
+    }
+}
+
+pub(crate) fn read_value<U>(conn: &Connection, subspace: u8, key: &[u8]) -> crate::Result<Option<U>>
+where
+    U: Deserialize + 'static,
+{
+    let mut result = conn.prepare_cached(&format!(
+        "SELECT v FROM {} WHERE k = ?",
+        char::from(subspace)
+    ))?;
+    result
+        .query_row([key], |row| {
+            U::deserialize(row.get_ref(0)?.as_bytes()?)
+                .map_err(|err| rusqlite::Error::ToSqlConversionFailure(err.into()))
+        })
+        .optional()
+        .map_err(Into::into)
+}
+
+pub(crate) fn read_bitmap(
+    conn: &Connection,
+    begin: &[u8],
+    end: &[u8],
+) -> crate::Result<Option<RoaringBitmap>> {
+    let mut bm = RoaringBitmap::new();
+    let mut query = conn.prepare_cached("SELECT k FROM b WHERE k >= ? AND k <= ?")?;
+    let mut rows = query.query([begin, end])?;
+
+    while let Some(row) = rows.next()? {
+        let key = row.get_ref(0)?.as_bytes()?;
+        if key.len() == begin.len() {
+            bm.insert(key.deserialize_be_u32(key.len() - U32_LEN)?);
+        }
+    }
+    Ok(if !bm.is_empty() { Some(bm) } else { None })
 }
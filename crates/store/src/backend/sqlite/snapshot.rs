@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use r2d2::PooledConnection;
+use roaring::RoaringBitmap;
+
+use crate::{write::BitmapClass, BitmapKey, Deserialize, Key};
+
+use super::{
+    pool::SqliteConnectionManager,
+    read::{read_bitmap, read_value},
+    SqliteStore,
+};
+
+// The connection is shared with the worker running the current read, which
+// may outlive a caller that stopped waiting for it.
+pub struct SqliteSnapshot {
+    store: Arc<SqliteStore>,
+    conn: Arc<Mutex<PooledConnection<SqliteConnectionManager>>>,
+}
+
+impl SqliteStore {
+    pub(crate) fn snapshot(self: &Arc<Self>) -> crate::Result<SqliteSnapshot> {
+        let conn = self.read_pool.get()?;
+
+        // Deferred transactions take their read snapshot on the first statement,
+        // all subsequent reads on this connection see the same database state.
+        conn.execute_batch("BEGIN DEFERRED")?;
+
+        Ok(SqliteSnapshot {
+            store: self.clone(),
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+}
+
+impl SqliteSnapshot {
+    pub(crate) async fn get_value<U>(&mut self, key: impl Key) -> crate::Result<Option<U>>
+    where
+        U: Deserialize + 'static,
+    {
+        let subspace = key.subspace();
+        let key = key.serialize(0);
+        let conn = self.conn.clone();
+        self.store
+            .spawn_worker(move || read_value(&conn.lock(), subspace, &key))
+            .await
+    }
+
+    pub(crate) async fn get_bitmap(
+        &mut self,
+        mut key: BitmapKey<BitmapClass>,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        let begin = key.serialize(0);
+        key.block_num = u32::MAX;
+        let end = key.serialize(0);
+        let conn = self.conn.clone();
+
+        self.store
+            .spawn_worker(move || read_bitmap(&conn.lock(), &begin, &end))
+            .await
+    }
+}
+
+impl Drop for SqliteSnapshot {
+    fn drop(&mut self) {
+        // Nothing was written, rolling back just releases the read snapshot
+        let _ = self.conn.lock().execute_batch("ROLLBACK");
+    }
+}
@@ -24,7 +24,7 @@
 use rusqlite::{params, OptionalExtension, TransactionBehavior};
 
 use crate::{
-    write::{Batch, BitmapClass, Operation, ValueClass, ValueOp},
+    write::{assert::AssertValue, Batch, BitmapClass, Operation, ValueClass, ValueOp},
     BitmapKey, IndexKey, Key, LogKey, ValueKey,
 };
 
@@ -32,7 +32,7 @@ use super::SqliteStore;
 
 impl SqliteStore {
     pub(crate) async fn write(&self, batch: Batch) -> crate::Result<()> {
-        let mut conn = self.conn_pool.get()?;
+        let mut conn = self.write_pool.get()?;
         self.spawn_worker(move || {
             let mut account_id = u32::MAX;
             let mut collection = u8::MAX;
@@ -170,6 +170,28 @@ impl SqliteStore {
                         trx.prepare_cached("INSERT OR REPLACE INTO l (k, v) VALUES (?, ?)")?
                             .execute([&key, set])?;
                     }
+                    Operation::AssertValue {
+                        class,
+                        assert_value: AssertValue::Counter(value),
+                    } => {
+                        let key = ValueKey {
+                            account_id,
+                            collection,
+                            document_id,
+                            class,
+                        }
+                        .serialize(0);
+
+                        let counter = trx
+                            .prepare_cached("SELECT v FROM c WHERE k = ?")?
+                            .query_row([&key], |row| row.get::<_, i64>(0))
+                            .optional()?
+                            .unwrap_or(0);
+                        if counter != *value {
+                            trx.rollback()?;
+                            return Err(crate::Error::AssertValueFailed);
+                        }
+                    }
                     Operation::AssertValue {
                         class,
                         assert_value,
@@ -203,18 +225,35 @@ impl SqliteStore {
         .await
     }
 
+    pub(crate) async fn put_if_absent(
+        &self,
+        key: ValueKey<ValueClass>,
+        value: Vec<u8>,
+    ) -> crate::Result<bool> {
+        let conn = self.write_pool.get()?;
+        self.spawn_worker(move || {
+            conn.prepare_cached(&format!(
+                "INSERT INTO {} (k, v) VALUES (?, ?) ON CONFLICT(k) DO NOTHING",
+                char::from(key.subspace()),
+            ))?
+            .execute(params![key.serialize(0), value])
+            .map(|inserted| inserted > 0)
+            .map_err(Into::into)
+        })
+        .await
+    }
+
     pub(crate) async fn purge_bitmaps(&self) -> crate::Result<()> {
         Ok(())
     }
 
     pub(crate) async fn delete_range(&self, from: impl Key, to: impl Key) -> crate::Result<()> {
-        let conn = self.conn_pool.get()?;
+        let table = char::from(from.subspace());
+        let (from, to) = (from.serialize(0), to.serialize(0));
+        let conn = self.write_pool.get()?;
         self.spawn_worker(move || {
-            conn.prepare_cached(&format!(
-                "DELETE FROM {} WHERE k >= ? AND k < ?",
-                char::from(from.subspace()),
-            ))?
-            .execute([from.serialize(0), to.serialize(0)])?;
+            conn.prepare_cached(&format!("DELETE FROM {table} WHERE k >= ? AND k < ?"))?
+                .execute([from, to])?;
 
             Ok(())
         })
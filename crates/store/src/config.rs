@@ -21,15 +21,19 @@
  * for more details.
 */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use utils::config::{cron::SimpleCron, Config};
+use parking_lot::Mutex;
+use utils::{
+    config::{cron::SimpleCron, Config},
+    map::lookup_cache::{LookupCache, ValueCache},
+};
 
 use crate::{
     backend::{fs::FsStore, memory::MemoryStore},
     write::purge::{PurgeSchedule, PurgeStore},
-    LookupStore, QueryStore, Store, Stores,
+    LookupStore, QueryCache, QueryStore, QueryTemplate, Store, Stores,
 };
 
 #[cfg(feature = "s3")]
@@ -139,7 +143,9 @@ impl ConfigStore for Config {
                 }
                 #[cfg(feature = "sqlite")]
                 "sqlite" => {
-                    let db: Store = SqliteStore::open(self, prefix).await?.into();
+                    let db = Arc::new(SqliteStore::open(self, prefix).await?);
+                    db.spawn_checkpoint_task();
+                    let db = Store::SQLite(db);
                     config.stores.insert(store_id.clone(), db.clone());
                     config
                         .fts_stores
@@ -193,11 +199,17 @@ impl ConfigStore for Config {
             // Add queries as lookup stores
             let lookup_store: LookupStore = lookup_store.into();
             for lookup_id in self.sub_keys(("store", id, "query")) {
+                let query: String = self.property_require(("store", id, "query", lookup_id))?;
+                config.queries.insert(
+                    format!("{store_id}/{lookup_id}"),
+                    Arc::new(QueryTemplate::new(query.clone())),
+                );
                 config.lookup_stores.insert(
                     format!("{store_id}/{lookup_id}"),
                     LookupStore::Query(Arc::new(QueryStore {
                         store: lookup_store.clone(),
-                        query: self.property_require(("store", id, "query", lookup_id))?,
+                        query,
+                        cache: parse_query_cache(self, id)?,
                     })),
                 );
             }
@@ -209,6 +221,26 @@ impl ConfigStore for Config {
                     tracing::warn!("Failed to initialize store {id:?}: {err}");
                 }
             }
+
+            // Prepare queries once the schema exists, so broken queries fail at startup
+            // rather than on first use
+            if self.property_or_static::<bool>(("store", id, "validate-queries"), "false")? {
+                for lookup_id in self.sub_keys(("store", id, "query")) {
+                    let query = self.value_require(("store", id, "query", lookup_id))?;
+                    lookup_store
+                        .validate_query(query)
+                        .await
+                        .map_err(|err| format!("Invalid query \"{id}/{lookup_id}\": {err}"))?;
+                }
+            }
+        }
+
+        // Make sure change logs are read with the format they were written with
+        for (id, store) in &config.stores {
+            store
+                .check_log_format()
+                .await
+                .map_err(|err| format!("Failed to open store {id:?}: {err}"))?;
         }
 
         Ok(config)
@@ -269,11 +301,166 @@ impl ConfigStore for Config {
     }
 }
 
+impl Stores {
+    /// Returns the named query, failing if it does not exist or if it does not
+    /// take the number of parameters the caller is going to provide.
+    pub fn query_template(
+        &self,
+        id: &str,
+        num_params: usize,
+    ) -> utils::config::Result<Arc<QueryTemplate>> {
+        let template = self
+            .queries
+            .get(id)
+            .ok_or_else(|| format!("Query {id:?} does not exist"))?;
+        if template.num_params == num_params {
+            Ok(template.clone())
+        } else {
+            Err(format!(
+                "Query {id:?} expects {} parameter(s) but {num_params} are provided",
+                template.num_params
+            ))
+        }
+    }
+}
+
+impl QueryTemplate {
+    pub fn new(query: String) -> Self {
+        QueryTemplate {
+            num_params: count_query_params(&query),
+            query,
+        }
+    }
+}
+
+// Counts '?' placeholders plus the highest '$n' placeholder, ignoring
+// anything within quotes.
+fn count_query_params(query: &str) -> usize {
+    let mut num_positional = 0;
+    let mut max_numbered = 0;
+    let mut quote = None;
+    let mut chars = query.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match (ch, quote) {
+            ('\'' | '"' | '`', None) => quote = Some(ch),
+            (_, Some(q)) if ch == q => quote = None,
+            (_, Some(_)) => (),
+            ('?', None) => num_positional += 1,
+            ('$', None) => {
+                let mut num = 0usize;
+                while let Some(digit) = chars.peek().and_then(|ch| ch.to_digit(10)) {
+                    num = num.saturating_mul(10).saturating_add(digit as usize);
+                    chars.next();
+                }
+                max_numbered = max_numbered.max(num);
+            }
+            _ => (),
+        }
+    }
+
+    num_positional + max_numbered
+}
+
+// Checks that the query is not empty, that quotes are terminated and that
+// parentheses are balanced.
+pub(crate) fn check_query_syntax(query: &str) -> Result<(), String> {
+    if query.trim().is_empty() {
+        return Err("Query is empty".to_string());
+    }
+
+    let mut depth = 0usize;
+    let mut quote = None;
+    for ch in query.chars() {
+        match (ch, quote) {
+            ('\'' | '"' | '`', None) => quote = Some(ch),
+            (_, Some(q)) if ch == q => quote = None,
+            (_, Some(_)) => (),
+            ('(', None) => depth += 1,
+            (')', None) => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| "Unbalanced closing parenthesis".to_string())?;
+            }
+            _ => (),
+        }
+    }
+
+    if let Some(quote) = quote {
+        Err(format!("Unterminated {quote} quote"))
+    } else if depth > 0 {
+        Err("Unbalanced opening parenthesis".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_query_cache(config: &Config, id: &str) -> utils::config::Result<Option<QueryCache>> {
+    if let Some(entries) = config.property::<usize>(("store", id, "query-cache.entries"))? {
+        let ttl_positive = config
+            .property(("store", id, "query-cache.ttl.positive"))?
+            .unwrap_or(Duration::from_secs(3600));
+        let ttl_negative = config
+            .property(("store", id, "query-cache.ttl.negative"))?
+            .unwrap_or(Duration::from_secs(300));
+
+        Ok(Some(QueryCache {
+            exists: Mutex::new(LookupCache::new(entries, ttl_positive, ttl_negative)),
+            values: Mutex::new(ValueCache::new(entries, ttl_positive)),
+            ttl_negative,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
 impl From<crate::Error> for String {
     fn from(err: crate::Error) -> Self {
         match err {
             crate::Error::InternalError(err) => err,
             crate::Error::AssertValueFailed => unimplemented!(),
+            err => err.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_query_syntax, count_query_params};
+
+    #[test]
+    fn query_params() {
+        for (query, expected) in [
+            ("SELECT 1", 0),
+            ("SELECT name FROM accounts WHERE name = ? AND active = true", 1),
+            (
+                "SELECT address FROM emails WHERE address LIKE '%' || ? || '%' AND type = 'primary'",
+                1,
+            ),
+            ("SELECT a FROM b WHERE c = $1 AND d = $2 OR e = $1", 2),
+            ("SELECT '?' FROM b WHERE c = ? AND d = \"x?\"", 1),
+            ("INSERT INTO t (a, b) VALUES (?, ?)", 2),
+        ] {
+            assert_eq!(count_query_params(query), expected, "{query}");
+        }
+    }
+    #[test]
+    fn query_syntax() {
+        for query in [
+            "SELECT 1",
+            "SELECT name FROM accounts WHERE name = ? AND (active = true OR type = ')')",
+            "SELECT address FROM emails WHERE address LIKE '%' || ? || '%'",
+        ] {
+            assert!(check_query_syntax(query).is_ok(), "{query}");
+        }
+        for query in [
+            "",
+            "   ",
+            "SELECT name FROM accounts WHERE name = 'x",
+            "SELECT name FROM accounts WHERE (name = ?",
+            "SELECT name FROM accounts WHERE name = ?)",
+        ] {
+            assert!(check_query_syntax(query).is_err(), "{query}");
         }
     }
 }
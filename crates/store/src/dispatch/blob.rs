@@ -23,66 +23,90 @@
 
 use std::ops::Range;
 
-use crate::{BlobStore, Store};
+use super::deadline::{bounded, bounded_write};
+use crate::{BlobStore, ErrorContext, Store};
 
 impl BlobStore {
     pub async fn get_blob(&self, key: &[u8], range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
-        match self {
-            Self::Store(store) => match store {
-                #[cfg(feature = "sqlite")]
-                Store::SQLite(store) => store.get_blob(key, range).await,
-                #[cfg(feature = "foundation")]
-                Store::FoundationDb(store) => store.get_blob(key, range).await,
-                #[cfg(feature = "postgres")]
-                Store::PostgreSQL(store) => store.get_blob(key, range).await,
-                #[cfg(feature = "mysql")]
-                Store::MySQL(store) => store.get_blob(key, range).await,
-                #[cfg(feature = "rocks")]
-                Store::RocksDb(store) => store.get_blob(key, range).await,
-            },
-            Self::Fs(store) => store.get_blob(key, range).await,
-            #[cfg(feature = "s3")]
-            Self::S3(store) => store.get_blob(key, range).await,
-        }
+        let context = ErrorContext::new("get_blob");
+        let result = bounded(async {
+            match self {
+                Self::Store(store) => match store {
+                    #[cfg(feature = "sqlite")]
+                    Store::SQLite(store) => store.get_blob(key, range).await,
+                    #[cfg(feature = "foundation")]
+                    Store::FoundationDb(store) => store.get_blob(key, range).await,
+                    #[cfg(feature = "postgres")]
+                    Store::PostgreSQL(store) => store.get_blob(key, range).await,
+                    #[cfg(feature = "mysql")]
+                    Store::MySQL(store) => store.get_blob(key, range).await,
+                    #[cfg(feature = "rocks")]
+                    Store::RocksDb(store) => store.get_blob(key, range).await,
+                },
+                Self::Fs(store) => store.get_blob(key, range).await,
+                #[cfg(feature = "s3")]
+                Self::S3(store) => store.get_blob(key, range).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
     }
 
     pub async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
-        match self {
-            Self::Store(store) => match store {
-                #[cfg(feature = "sqlite")]
-                Store::SQLite(store) => store.put_blob(key, data).await,
-                #[cfg(feature = "foundation")]
-                Store::FoundationDb(store) => store.put_blob(key, data).await,
-                #[cfg(feature = "postgres")]
-                Store::PostgreSQL(store) => store.put_blob(key, data).await,
-                #[cfg(feature = "mysql")]
-                Store::MySQL(store) => store.put_blob(key, data).await,
-                #[cfg(feature = "rocks")]
-                Store::RocksDb(store) => store.put_blob(key, data).await,
-            },
-            Self::Fs(store) => store.put_blob(key, data).await,
-            #[cfg(feature = "s3")]
-            Self::S3(store) => store.put_blob(key, data).await,
+        if let Self::Store(store) = self {
+            store.assert_writable()?;
         }
+
+        let context = ErrorContext::new("put_blob");
+        let result = bounded_write(async {
+            match self {
+                Self::Store(store) => match store {
+                    #[cfg(feature = "sqlite")]
+                    Store::SQLite(store) => store.put_blob(key, data).await,
+                    #[cfg(feature = "foundation")]
+                    Store::FoundationDb(store) => store.put_blob(key, data).await,
+                    #[cfg(feature = "postgres")]
+                    Store::PostgreSQL(store) => store.put_blob(key, data).await,
+                    #[cfg(feature = "mysql")]
+                    Store::MySQL(store) => store.put_blob(key, data).await,
+                    #[cfg(feature = "rocks")]
+                    Store::RocksDb(store) => store.put_blob(key, data).await,
+                },
+                Self::Fs(store) => store.put_blob(key, data).await,
+                #[cfg(feature = "s3")]
+                Self::S3(store) => store.put_blob(key, data).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
     }
 
     pub async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
-        match self {
-            Self::Store(store) => match store {
-                #[cfg(feature = "sqlite")]
-                Store::SQLite(store) => store.delete_blob(key).await,
-                #[cfg(feature = "foundation")]
-                Store::FoundationDb(store) => store.delete_blob(key).await,
-                #[cfg(feature = "postgres")]
-                Store::PostgreSQL(store) => store.delete_blob(key).await,
-                #[cfg(feature = "mysql")]
-                Store::MySQL(store) => store.delete_blob(key).await,
-                #[cfg(feature = "rocks")]
-                Store::RocksDb(store) => store.delete_blob(key).await,
-            },
-            Self::Fs(store) => store.delete_blob(key).await,
-            #[cfg(feature = "s3")]
-            Self::S3(store) => store.delete_blob(key).await,
+        if let Self::Store(store) = self {
+            store.assert_writable()?;
         }
+
+        let context = ErrorContext::new("delete_blob");
+        let result = bounded_write(async {
+            match self {
+                Self::Store(store) => match store {
+                    #[cfg(feature = "sqlite")]
+                    Store::SQLite(store) => store.delete_blob(key).await,
+                    #[cfg(feature = "foundation")]
+                    Store::FoundationDb(store) => store.delete_blob(key).await,
+                    #[cfg(feature = "postgres")]
+                    Store::PostgreSQL(store) => store.delete_blob(key).await,
+                    #[cfg(feature = "mysql")]
+                    Store::MySQL(store) => store.delete_blob(key).await,
+                    #[cfg(feature = "rocks")]
+                    Store::RocksDb(store) => store.delete_blob(key).await,
+                },
+                Self::Fs(store) => store.delete_blob(key).await,
+                #[cfg(feature = "s3")]
+                Self::S3(store) => store.delete_blob(key).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
     }
 }
@@ -0,0 +1,218 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use ahash::AHashSet;
+use parking_lot::Mutex;
+use utils::{config::Config, map::lookup_cache::ValueCache};
+
+use crate::{
+    write::{Batch, Operation, ValueClass},
+    Key, ValueKey, WITH_SUBSPACE,
+};
+
+/// Read-through cache for property values of the configured collections.
+/// Entries are invalidated by writes issued through the same store.
+pub struct ValueReadCache {
+    entries: Mutex<ValueCache<Vec<u8>, Option<Arc<Vec<u8>>>>>,
+    collections: AHashSet<u8>,
+    // Incremented on every invalidation, reads that overlap with a write
+    // do not populate the cache as they might have fetched the old value.
+    generation: AtomicU64,
+}
+
+impl ValueReadCache {
+    pub fn parse(config: &Config, prefix: &str) -> utils::config::Result<Option<Self>> {
+        let mut collections = AHashSet::new();
+        for collection in config.properties::<u8>((prefix, "value-cache.collections")) {
+            collections.insert(collection?.1);
+        }
+        if collections.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(ValueReadCache {
+            entries: Mutex::new(ValueCache::new(
+                config.property_or_static((prefix, "value-cache.entries"), "1024")?,
+                config.property_or_static::<Duration>((prefix, "value-cache.ttl"), "5m")?,
+            )),
+            collections,
+            generation: AtomicU64::new(0),
+        }))
+    }
+
+    pub(crate) fn is_cached(&self, key: &impl Key) -> bool {
+        key.property_collection()
+            .map_or(false, |collection| self.collections.contains(&collection))
+    }
+
+    pub(crate) fn get(&self, key: &[u8]) -> Option<Option<Arc<Vec<u8>>>> {
+        self.entries.lock().get(key)
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn insert(&self, key: Vec<u8>, value: Option<Arc<Vec<u8>>>, generation: u64) {
+        let mut entries = self.entries.lock();
+        if self.generation.load(Ordering::Acquire) == generation {
+            entries.insert(key, value);
+        }
+    }
+
+    pub(crate) fn invalidate(&self, key: &[u8]) {
+        let mut entries = self.entries.lock();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        entries.remove(key);
+    }
+
+    /// Returns the serialized keys of the cached values modified by a batch.
+    pub(crate) fn batch_keys(&self, batch: &Batch) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        let mut account_id = u32::MAX;
+        let mut collection = u8::MAX;
+        let mut document_id = u32::MAX;
+
+        for op in &batch.ops {
+            match op {
+                Operation::AccountId {
+                    account_id: account_id_,
+                } => {
+                    account_id = *account_id_;
+                }
+                Operation::Collection {
+                    collection: collection_,
+                } => {
+                    collection = *collection_;
+                }
+                Operation::DocumentId {
+                    document_id: document_id_,
+                } => {
+                    document_id = *document_id_;
+                }
+                Operation::Value {
+                    class: class @ ValueClass::Property(_),
+                    ..
+                } if self.collections.contains(&collection) => {
+                    keys.push(
+                        ValueKey {
+                            account_id,
+                            collection,
+                            document_id,
+                            class,
+                        }
+                        .serialize(WITH_SUBSPACE),
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        keys
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut entries = self.entries.lock();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use utils::config::Config;
+
+    use crate::{
+        write::{BatchBuilder, ValueClass},
+        Key, ValueKey, WITH_SUBSPACE,
+    };
+
+    use super::ValueReadCache;
+
+    #[test]
+    fn value_read_cache() {
+        // Caching is disabled unless collections are configured
+        assert!(ValueReadCache::parse(
+            &Config::new("[store.a]\ntype = \"sqlite\"").unwrap(),
+            "store.a"
+        )
+        .unwrap()
+        .is_none());
+        let cache = ValueReadCache::parse(
+            &Config::new("[store.a]\nvalue-cache.collections = [1]").unwrap(),
+            "store.a",
+        )
+        .unwrap()
+        .unwrap();
+
+        // Only properties of the configured collections are cached
+        let key = ValueKey {
+            account_id: 1,
+            collection: 1,
+            document_id: 2,
+            class: ValueClass::Property(3),
+        };
+        assert!(cache.is_cached(&key));
+        assert!(!cache.is_cached(&ValueKey {
+            collection: 2,
+            ..key.clone()
+        }));
+        assert!(!cache.is_cached(&ValueKey {
+            class: ValueClass::ReservedId,
+            ..key.clone()
+        }));
+
+        // Reads overlapping with an invalidation do not populate the cache
+        let cache_key = key.serialize(WITH_SUBSPACE);
+        let generation = cache.generation();
+        cache.invalidate(&cache_key);
+        cache.insert(cache_key.clone(), Some(Arc::new(vec![1])), generation);
+        assert_eq!(cache.get(&cache_key), None);
+        cache.insert(
+            cache_key.clone(),
+            Some(Arc::new(vec![2])),
+            cache.generation(),
+        );
+        assert_eq!(cache.get(&cache_key), Some(Some(Arc::new(vec![2]))));
+
+        // Writes to cached properties are detected
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(1)
+            .with_collection(1u8)
+            .update_document(2)
+            .set(ValueClass::Property(3), vec![3])
+            .set(ValueClass::ReservedId, vec![]);
+        assert_eq!(cache.batch_keys(&batch.build()), vec![cache_key]);
+    }
+}
@@ -0,0 +1,126 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{future::Future, time::Instant};
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+/// Runs `f` with every store operation it awaits bounded by `deadline`.
+/// Reads still pending once the deadline passes are abandoned and return
+/// `Error::Timeout`, writes are only rejected if the deadline passed before
+/// they started. Nested deadlines never extend an outer one.
+pub async fn with_deadline<F: Future>(deadline: Instant, f: F) -> F::Output {
+    let deadline = current_deadline().map_or(deadline, |outer| outer.min(deadline));
+    DEADLINE.scope(deadline, f).await
+}
+
+pub fn current_deadline() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+pub(crate) async fn bounded<T>(f: impl Future<Output = crate::Result<T>>) -> crate::Result<T> {
+    if let Some(deadline) = current_deadline() {
+        tokio::time::timeout_at(deadline.into(), f)
+            .await
+            .unwrap_or_else(|_| {
+                Err(crate::Error::Timeout(
+                    "Request deadline exceeded".to_string(),
+                ))
+            })
+    } else {
+        f.await
+    }
+}
+
+// A write abandoned after it was sent to the backend might still commit, so the
+// deadline is only checked before it starts rather than reporting a timeout for a
+// write that could have succeeded.
+pub(crate) async fn bounded_write<T>(
+    f: impl Future<Output = crate::Result<T>>,
+) -> crate::Result<T> {
+    if current_deadline().map_or(false, |deadline| deadline <= Instant::now()) {
+        Err(crate::Error::Timeout(
+            "Request deadline exceeded".to_string(),
+        ))
+    } else {
+        f.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{bounded, bounded_write, current_deadline, with_deadline};
+
+    #[tokio::test]
+    async fn deadline() {
+        // Operations are unaffected without a deadline or when completing in time
+        assert_eq!(bounded(async { Ok(1) }).await, Ok(1));
+        assert_eq!(
+            with_deadline(
+                Instant::now() + Duration::from_secs(60),
+                bounded(async { Ok(2) })
+            )
+            .await,
+            Ok(2)
+        );
+
+        // Pending operations are abandoned once the deadline passes
+        let result = with_deadline(
+            Instant::now() + Duration::from_millis(50),
+            bounded(async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(3)
+            }),
+        )
+        .await;
+        assert!(matches!(result, Err(crate::Error::Timeout(_))));
+
+        // Writes are only rejected if the deadline passed before they started
+        let result = with_deadline(
+            Instant::now() + Duration::from_millis(50),
+            bounded_write(async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok(4)
+            }),
+        )
+        .await;
+        assert_eq!(result, Ok(4));
+        let result = with_deadline(Instant::now(), bounded_write(async { Ok(5) })).await;
+        assert!(matches!(result, Err(crate::Error::Timeout(_))));
+
+        // Nested deadlines never extend the outer one
+        let outer = Instant::now() + Duration::from_secs(1);
+        with_deadline(outer, async {
+            with_deadline(outer + Duration::from_secs(60), async {
+                assert_eq!(current_deadline(), Some(outer));
+            })
+            .await;
+        })
+        .await;
+        assert_eq!(current_deadline(), None);
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::{
+    write::{assert::AssertValue, Batch, BatchBuilder, Operation, ValueClass},
+    Serialize, Store, ValueKey,
+};
+
+impl Store {
+    /// Returns the current fencing epoch of an account, 0 if it was never advanced.
+    pub async fn fence_epoch(&self, account_id: u32) -> crate::Result<u64> {
+        self.get_value::<u64>(ValueKey {
+            account_id,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Fence,
+        })
+        .await
+        .map(|epoch| epoch.unwrap_or(0))
+    }
+
+    /// Increments the fencing epoch of an account and returns the new epoch. Batches
+    /// fenced with any earlier epoch are rejected from then on, which lets a node that
+    /// takes over an account lock out writes from its previous owner.
+    pub async fn advance_fence(&self, account_id: u32) -> crate::Result<u64> {
+        loop {
+            let epoch = self.fence_epoch(account_id).await?;
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .fence(epoch)
+                .set(ValueClass::Fence, (epoch + 1).serialize());
+
+            match self.write(batch.build()).await {
+                Ok(_) => return Ok(epoch + 1),
+                Err(crate::Error::StaleEpoch) => (),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Distinguishes stale fencing epochs from other assertion failures of a batch.
+    pub(crate) async fn check_fences(&self, fences: Vec<(u32, AssertValue)>) -> crate::Error {
+        for (account_id, assert_value) in fences {
+            let epoch = match self.fence_epoch(account_id).await {
+                Ok(epoch) => epoch,
+                Err(err) => return err,
+            };
+            let is_current = match assert_value {
+                AssertValue::U64(fence) => fence == epoch,
+                AssertValue::None => epoch == 0,
+                _ => true,
+            };
+            if !is_current {
+                return crate::Error::StaleEpoch;
+            }
+        }
+
+        crate::Error::AssertValueFailed
+    }
+}
+
+/// Returns the account ids and epochs the batch is fenced with.
+pub(crate) fn batch_fences(batch: &Batch) -> Vec<(u32, AssertValue)> {
+    let mut account_id = u32::MAX;
+    let mut fences = Vec::new();
+
+    for op in &batch.ops {
+        match op {
+            Operation::AccountId {
+                account_id: account_id_,
+            } => {
+                account_id = *account_id_;
+            }
+            Operation::AssertValue {
+                class: ValueClass::Fence,
+                assert_value,
+            } => {
+                fences.push((account_id, *assert_value));
+            }
+            _ => {}
+        }
+    }
+
+    fences
+}
@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use tokio::sync::broadcast;
+
+use crate::Store;
+
+/// Cache invalidation events shared between cluster nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidationEvent {
+    /// Cached principals and access tokens are stale, for a single account
+    /// or for all accounts when `account_id` is `None`.
+    Principal { account_id: Option<u32> },
+    /// Events may have been missed, every cache has to be flushed.
+    FlushAll,
+}
+
+impl InvalidationEvent {
+    pub fn serialize(&self) -> String {
+        match self {
+            InvalidationEvent::Principal {
+                account_id: Some(account_id),
+            } => format!("principal:{account_id}"),
+            InvalidationEvent::Principal { account_id: None } => "principal:*".to_string(),
+            InvalidationEvent::FlushAll => "flush".to_string(),
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.split_once(':') {
+            Some(("principal", "*")) => Some(InvalidationEvent::Principal { account_id: None }),
+            Some(("principal", account_id)) => Some(InvalidationEvent::Principal {
+                account_id: Some(account_id.parse().ok()?),
+            }),
+            None if value == "flush" => Some(InvalidationEvent::FlushAll),
+            _ => None,
+        }
+    }
+}
+
+impl Store {
+    /// Notifies other nodes that cached data is stale. This is a no-op
+    /// for backends without an invalidation channel.
+    #[allow(unreachable_patterns)]
+    #[allow(unused_variables)]
+    pub async fn publish_invalidation(&self, event: InvalidationEvent) -> crate::Result<()> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgreSQL(store) => store.publish_invalidation(event).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns a receiver for invalidation events published by any node,
+    /// or `None` if the backend has no invalidation channel configured.
+    #[allow(unreachable_patterns)]
+    pub fn subscribe_invalidations(&self) -> Option<broadcast::Receiver<InvalidationEvent>> {
+        match self {
+            #[cfg(feature = "postgres")]
+            Self::PostgreSQL(store) => store.subscribe_invalidations(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InvalidationEvent;
+
+    #[test]
+    fn invalidation_event() {
+        // Payloads are exchanged between nodes, their format must not change
+        for (event, payload) in [
+            (
+                InvalidationEvent::Principal {
+                    account_id: Some(123),
+                },
+                "principal:123",
+            ),
+            (
+                InvalidationEvent::Principal {
+                    account_id: Some(u32::MAX),
+                },
+                "principal:4294967295",
+            ),
+            (
+                InvalidationEvent::Principal { account_id: None },
+                "principal:*",
+            ),
+            (InvalidationEvent::FlushAll, "flush"),
+        ] {
+            assert_eq!(event.serialize(), payload);
+            assert_eq!(InvalidationEvent::parse(payload), Some(event));
+        }
+        for payload in [
+            "",
+            "principal",
+            "principal:",
+            "principal:abc",
+            "principal:-1",
+            "principal:4294967296",
+            "principal:1:2",
+            "flush:",
+            "flush:1",
+            "FLUSH",
+            "unknown",
+        ] {
+            assert_eq!(InvalidationEvent::parse(payload), None, "{payload:?}");
+        }
+    }
+}
@@ -21,17 +21,26 @@
  * for more details.
 */
 
-use crate::{backend::memory::MemoryStore, Row};
+use std::time::{Duration, Instant};
+
+use super::deadline::bounded;
+use crate::{backend::memory::MemoryStore, config::check_query_syntax, NamedRows, Row, Rows};
 #[allow(unused_imports)]
 use crate::{
+    is_expired, namespaced_key,
     write::{
+        assert::AssertValue,
         key::{DeserializeBigEndian, KeySerializer},
         now, BatchBuilder, Operation, ValueClass, ValueOp,
     },
-    Deserialize, IterateParams, LookupKey, LookupStore, LookupValue, QueryResult, Store, Value,
-    ValueKey, U64_LEN,
+    Deserialize, IterateParams, LookupKey, LookupStore, LookupValue, QueryResult, QueryStore,
+    Serialize, Store, Value, ValueKey, U64_LEN,
 };
 
+// Stores without native expiration keep the end of a counter's window under this namespace
+const COUNTER_EXPIRY_NS: &str = "counter-expiry";
+const MAX_COUNTER_RETRIES: usize = 10;
+
 impl LookupStore {
     #[allow(unreachable_patterns)]
     #[allow(unused_variables)]
@@ -40,23 +49,182 @@ impl LookupStore {
         query: &str,
         params: Vec<Value<'_>>,
     ) -> crate::Result<T> {
-        let result = match self {
-            #[cfg(feature = "sqlite")]
-            LookupStore::Store(Store::SQLite(store)) => store.query(query, params).await,
-            #[cfg(feature = "postgres")]
-            LookupStore::Store(Store::PostgreSQL(store)) => store.query(query, params).await,
-            #[cfg(feature = "mysql")]
-            LookupStore::Store(Store::MySQL(store)) => store.query(query, params).await,
-            _ => Err(crate::Error::InternalError(
-                "Store does not support queries".into(),
-            )),
-        };
+        let start = Instant::now();
+        let num_params = params.len();
+        let result = bounded(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                LookupStore::Store(Store::SQLite(store)) => store.query(query, params).await,
+                #[cfg(feature = "postgres")]
+                LookupStore::Store(Store::PostgreSQL(store)) => store.query(query, params).await,
+                #[cfg(feature = "mysql")]
+                LookupStore::Store(Store::MySQL(store)) => store.query(query, params).await,
+                _ => Err(crate::Error::InternalError(
+                    "Store does not support queries".into(),
+                )),
+            }
+        })
+        .await;
 
         tracing::trace!( context = "store", event = "query", query = query, result = ?result);
+        self.log_slow_query(
+            query,
+            num_params,
+            start,
+            result.as_ref().ok().map(|result| result.row_count()),
+        );
 
         result
     }
 
+    /// Executes several queries in a single transaction, returning one result
+    /// set per query. Backends able to pipeline statements send them together.
+    #[allow(unreachable_patterns)]
+    #[allow(unused_variables)]
+    pub async fn query_batch(
+        &self,
+        queries: Vec<(&str, Vec<Value<'_>>)>,
+    ) -> crate::Result<Vec<Rows>> {
+        let num_queries = queries.len();
+        let start = Instant::now();
+        let slow_query = self.slow_query_threshold().map(|_| {
+            (
+                queries
+                    .iter()
+                    .map(|(query, _)| *query)
+                    .collect::<Vec<_>>()
+                    .join("; "),
+                queries.iter().map(|(_, params)| params.len()).sum(),
+            )
+        });
+        let result = bounded(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                LookupStore::Store(Store::SQLite(store)) => store.query_batch(queries).await,
+                #[cfg(feature = "postgres")]
+                LookupStore::Store(Store::PostgreSQL(store)) => store.query_batch(queries).await,
+                #[cfg(feature = "mysql")]
+                LookupStore::Store(Store::MySQL(store)) => store.query_batch(queries).await,
+                _ => Err(crate::Error::InternalError(
+                    "Store does not support queries".into(),
+                )),
+            }
+        })
+        .await;
+
+        tracing::trace!( context = "store", event = "query-batch", queries = num_queries, result = ?result);
+        if let Some((query, num_params)) = slow_query {
+            self.log_slow_query(
+                &query,
+                num_params,
+                start,
+                result
+                    .as_ref()
+                    .ok()
+                    .map(|results| results.iter().map(|rows| rows.rows.len()).sum()),
+            );
+        }
+
+        result
+    }
+
+    /// Executes the query returning at most `limit` rows after skipping `offset` rows.
+    /// The query should include an `ORDER BY` clause, otherwise the rows included in
+    /// each page are undefined.
+    pub async fn query_page<T: QueryResult + std::fmt::Debug>(
+        &self,
+        query: &str,
+        mut params: Vec<Value<'_>>,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> crate::Result<T> {
+        if limit.is_none() && offset.is_none() {
+            return self.query(query, params).await;
+        }
+
+        // Not all backends accept an offset without a limit
+        let query = query.trim_end().trim_end_matches(';');
+        let query = if self.is_postgres() {
+            format!(
+                "{query} LIMIT ${} OFFSET ${}",
+                params.len() + 1,
+                params.len() + 2
+            )
+        } else {
+            format!("{query} LIMIT ? OFFSET ?")
+        };
+        params.push(Value::Integer(
+            limit.map_or(i64::MAX, |limit| limit.min(i64::MAX as usize) as i64),
+        ));
+        params.push(Value::Integer(
+            offset.unwrap_or(0).min(i64::MAX as usize) as i64
+        ));
+
+        self.query(&query, params).await
+    }
+
+    fn is_postgres(&self) -> bool {
+        #[cfg(feature = "postgres")]
+        {
+            matches!(self, LookupStore::Store(Store::PostgreSQL(_)))
+        }
+        #[cfg(not(feature = "postgres"))]
+        {
+            false
+        }
+    }
+
+    /// Prepares the query without executing it, so syntax errors and references to
+    /// missing tables or columns are reported by the backend. Stores that cannot
+    /// prepare statements only get a best-effort syntax check.
+    #[allow(unreachable_patterns)]
+    pub async fn validate_query(&self, query: &str) -> crate::Result<()> {
+        bounded(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                LookupStore::Store(Store::SQLite(store)) => store.prepare(query).await,
+                #[cfg(feature = "postgres")]
+                LookupStore::Store(Store::PostgreSQL(store)) => store.prepare(query).await,
+                #[cfg(feature = "mysql")]
+                LookupStore::Store(Store::MySQL(store)) => store.prepare(query).await,
+                _ => check_query_syntax(query).map_err(crate::Error::InternalError),
+            }
+        })
+        .await
+    }
+
+    #[allow(unreachable_patterns)]
+    fn slow_query_threshold(&self) -> Option<Duration> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            LookupStore::Store(Store::SQLite(store)) => store.slow_query,
+            #[cfg(feature = "postgres")]
+            LookupStore::Store(Store::PostgreSQL(store)) => store.slow_query,
+            #[cfg(feature = "mysql")]
+            LookupStore::Store(Store::MySQL(store)) => store.slow_query,
+            _ => None,
+        }
+    }
+
+    // Parameters are never logged as they may contain credentials
+    fn log_slow_query(&self, query: &str, num_params: usize, start: Instant, rows: Option<usize>) {
+        if let Some(threshold) = self.slow_query_threshold() {
+            let elapsed = start.elapsed();
+            if elapsed >= threshold {
+                tracing::warn!(
+                    context = "store",
+                    event = "slow-query",
+                    query = query,
+                    params = num_params,
+                    rows = ?rows,
+                    elapsed = elapsed.as_millis() as u64,
+                    "Query exceeded {}ms.",
+                    threshold.as_millis()
+                );
+            }
+        }
+    }
+
     pub async fn key_set(&self, key: Vec<u8>, value: LookupValue<Vec<u8>>) -> crate::Result<()> {
         match self {
             LookupStore::Store(store) => {
@@ -113,15 +281,20 @@ impl LookupStore {
                     })
                     .await
                     .map(|value| value.unwrap_or(LookupValue::None)),
-                LookupKey::Counter(key) => store
-                    .get_counter(ValueKey {
-                        account_id: 0,
-                        collection: 0,
-                        document_id: 0,
-                        class: ValueClass::Key(key),
-                    })
-                    .await
-                    .map(|num| LookupValue::Counter { num }),
+                LookupKey::Counter(key) => {
+                    if store
+                        .get_value::<u64>(counter_expiry_key(&key))
+                        .await?
+                        .map_or(false, |expires| is_expired(expires, now()))
+                    {
+                        return Ok(LookupValue::Counter { num: 0 });
+                    }
+
+                    store
+                        .get_counter(lookup_key(key))
+                        .await
+                        .map(|num| LookupValue::Counter { num })
+                }
             },
             #[cfg(feature = "redis")]
             LookupStore::Redis(store) => store.key_get(key).await,
@@ -145,21 +318,248 @@ impl LookupStore {
                         .unwrap_or(LookupValue::None)),
                 }
             }
-            LookupStore::Query(lookup) => lookup
-                .store
-                .query::<Option<Row>>(&lookup.query, vec![String::from(key).into()])
-                .await
-                .map(|row| {
-                    row.and_then(|row| row.values.into_iter().next())
+            LookupStore::Query(lookup) => {
+                lookup.first_value(String::from(key)).await.map(|value| {
+                    value
                         .map(|value| LookupValue::Value {
                             value: T::from(value),
                             expires: 0,
                         })
                         .unwrap_or(LookupValue::None)
-                }),
+                })
+            }
         }
     }
 
+    /// Adds `value` to a counter and returns the updated count. A non-zero `expires`
+    /// starts a window of that many seconds on the first increment, once it elapses
+    /// the counter reads as zero and the next increment starts a new window.
+    /// Redis expires counters natively, other stores track the end of the window
+    /// in a separate key which is checked on reads and swept by `purge_expired`.
+    pub async fn counter_incr(&self, key: Vec<u8>, value: i64, expires: u64) -> crate::Result<i64> {
+        match self {
+            LookupStore::Store(store) => {
+                if expires == 0 {
+                    let mut batch = BatchBuilder::new();
+                    batch.ops.push(Operation::Value {
+                        class: ValueClass::Key(key.clone()),
+                        op: ValueOp::Add(value),
+                    });
+                    store.write(batch.build()).await?;
+                    return store.get_counter(lookup_key(key)).await;
+                }
+
+                let expiry_key = counter_expiry_key(&key);
+                for _ in 0..MAX_COUNTER_RETRIES {
+                    let window = store.get_value::<u64>(expiry_key.clone()).await?;
+                    let now = now();
+                    let mut batch = BatchBuilder::new();
+
+                    match window {
+                        Some(window_end) if !is_expired(window_end, now) => {
+                            batch.ops.push(Operation::Value {
+                                class: ValueClass::Key(key.clone()),
+                                op: ValueOp::Add(value),
+                            });
+                        }
+                        _ => {
+                            // Start a new window, discarding the count of the previous one.
+                            // The assertion makes concurrent increments restart it only once.
+                            let current = store.get_counter(lookup_key(key.clone())).await?;
+                            batch
+                                .assert_value(
+                                    expiry_key.class.clone(),
+                                    window.map_or(AssertValue::None, AssertValue::U64),
+                                )
+                                .ops
+                                .extend([
+                                    Operation::Value {
+                                        class: ValueClass::Key(key.clone()),
+                                        op: ValueOp::Add(value - current),
+                                    },
+                                    Operation::Value {
+                                        class: expiry_key.class.clone(),
+                                        op: ValueOp::Set((now + expires).serialize()),
+                                    },
+                                ]);
+                        }
+                    }
+
+                    match store.write(batch.build()).await {
+                        Ok(_) => return store.get_counter(lookup_key(key)).await,
+                        Err(crate::Error::AssertValueFailed) => continue,
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                Err(crate::Error::AssertValueFailed)
+            }
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(store) => store.counter_incr(key, value, expires).await,
+            LookupStore::Query(_) | LookupStore::Memory(_) => Err(crate::Error::InternalError(
+                "This store does not support counter_incr".into(),
+            )),
+        }
+    }
+
+    /// Removes a value or resets a counter, including its expiration window.
+    pub async fn key_delete(&self, key: LookupKey) -> crate::Result<()> {
+        match self {
+            LookupStore::Store(store) => {
+                let mut batch = BatchBuilder::new();
+                match key {
+                    LookupKey::Key(key) => {
+                        batch.ops.push(Operation::Value {
+                            class: ValueClass::Key(key),
+                            op: ValueOp::Clear,
+                        });
+                    }
+                    LookupKey::Counter(key) => {
+                        let current = store.get_counter(lookup_key(key.clone())).await?;
+                        let expiry_key = counter_expiry_key(&key);
+                        batch.ops.extend([
+                            Operation::Value {
+                                class: ValueClass::Key(key),
+                                op: ValueOp::Add(-current),
+                            },
+                            Operation::Value {
+                                class: expiry_key.class,
+                                op: ValueOp::Clear,
+                            },
+                        ]);
+                    }
+                }
+                store.write(batch.build()).await
+            }
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(store) => store.key_delete(key).await,
+            LookupStore::Query(_) | LookupStore::Memory(_) => Err(crate::Error::InternalError(
+                "This store does not support key_delete".into(),
+            )),
+        }
+    }
+
+    /// Reads several counters at once, preserving the order of the keys.
+    /// Keys that are not counters return `None`.
+    pub async fn get_counters(&self, keys: Vec<LookupKey>) -> crate::Result<Vec<Option<i64>>> {
+        match self {
+            LookupStore::Store(store) => {
+                let mut counters = store
+                    .get_counters(
+                        keys.iter()
+                            .filter_map(|key| match key {
+                                LookupKey::Counter(key) => Some(ValueKey {
+                                    account_id: 0,
+                                    collection: 0,
+                                    document_id: 0,
+                                    class: ValueClass::Key(key.clone()),
+                                }),
+                                LookupKey::Key(_) => None,
+                            })
+                            .collect(),
+                    )
+                    .await?
+                    .into_iter();
+
+                let mut expirations = store
+                    .get_values::<u64>(
+                        keys.iter()
+                            .filter_map(|key| match key {
+                                LookupKey::Counter(key) => Some(counter_expiry_key(key)),
+                                LookupKey::Key(_) => None,
+                            })
+                            .collect(),
+                    )
+                    .await?
+                    .into_iter();
+                let now = now();
+
+                Ok(keys
+                    .iter()
+                    .map(|key| match key {
+                        LookupKey::Counter(_) => {
+                            let num = counters.next()?;
+                            Some(
+                                if expirations
+                                    .next()
+                                    .flatten()
+                                    .map_or(false, |expires| is_expired(expires, now))
+                                {
+                                    0
+                                } else {
+                                    num
+                                },
+                            )
+                        }
+                        LookupKey::Key(_) => None,
+                    })
+                    .collect())
+            }
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(store) => store.get_counters(keys).await,
+            LookupStore::Query(_) => Ok(vec![None; keys.len()]),
+            LookupStore::Memory(_) => {
+                let mut counters = Vec::with_capacity(keys.len());
+                for key in keys {
+                    counters.push(self.key_get::<String>(key).await?.counter());
+                }
+                Ok(counters)
+            }
+        }
+    }
+
+    /// Returns the subset of keys that are present in the store, preserving their order.
+    /// Values are present until they expire and counters while their count is not zero.
+    pub async fn filter_members(&self, keys: Vec<LookupKey>) -> crate::Result<Vec<LookupKey>> {
+        let members = match self {
+            LookupStore::Store(store) => {
+                let mut values = store
+                    .get_values::<LookupValue<()>>(
+                        keys.iter()
+                            .filter_map(|key| match key {
+                                LookupKey::Key(key) => Some(lookup_key(key.clone())),
+                                LookupKey::Counter(_) => None,
+                            })
+                            .collect(),
+                    )
+                    .await?
+                    .into_iter();
+
+                self.get_counters(keys.clone())
+                    .await?
+                    .into_iter()
+                    .map(|counter| match counter {
+                        Some(num) => num != 0,
+                        None => matches!(values.next().flatten(), Some(LookupValue::Value { .. })),
+                    })
+                    .collect::<Vec<_>>()
+            }
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(store) => store.filter_members(&keys).await?,
+            LookupStore::Query(lookup) => {
+                lookup
+                    .exists_batch(keys.iter().cloned().map(String::from).collect())
+                    .await?
+            }
+            LookupStore::Memory(store) => keys
+                .iter()
+                .map(|key| {
+                    let key = String::from(key.clone());
+                    match store.as_ref() {
+                        MemoryStore::List(list) => list.contains(&key),
+                        MemoryStore::Map(map) => map.contains_key(&key),
+                    }
+                })
+                .collect(),
+        };
+
+        Ok(keys
+            .into_iter()
+            .zip(members)
+            .filter_map(|(key, is_member)| if is_member { Some(key) } else { None })
+            .collect())
+    }
+
     pub async fn purge_expired(&self) -> crate::Result<()> {
         match self {
             LookupStore::Store(store) => {
@@ -178,14 +578,48 @@ impl LookupStore {
 
                 let current_time = now();
                 let mut expired_keys = Vec::new();
+                let mut expired_counters = Vec::new();
                 store
                     .iterate(IterateParams::new(from_key, to_key), |key, value| {
-                        if value.deserialize_be_u64(0)? < current_time {
-                            expired_keys.push(key.get(1..).unwrap_or_default().to_vec());
+                        let expires = value.deserialize_be_u64(0)?;
+                        if is_expired(expires, current_time) {
+                            let key = LookupKey::Key(key.get(1..).unwrap_or_default().to_vec());
+                            let counter = key
+                                .namespace()
+                                .filter(|(ns, _)| *ns == COUNTER_EXPIRY_NS)
+                                .map(|(_, counter)| counter.to_vec());
+                            match counter {
+                                Some(counter) => expired_counters.push((counter, expires)),
+                                None => expired_keys.push(key.into_bytes()),
+                            }
                         }
                         Ok(true)
                     })
                     .await?;
+
+                // Reset counters whose window elapsed, unless a new one was started meanwhile
+                for (key, expires) in expired_counters {
+                    let current = store.get_counter(lookup_key(key.clone())).await?;
+                    let expiry_key = counter_expiry_key(&key);
+                    let mut batch = BatchBuilder::new();
+                    batch
+                        .assert_value(expiry_key.class.clone(), expires)
+                        .ops
+                        .extend([
+                            Operation::Value {
+                                class: ValueClass::Key(key),
+                                op: ValueOp::Add(-current),
+                            },
+                            Operation::Value {
+                                class: expiry_key.class,
+                                op: ValueOp::Clear,
+                            },
+                        ]);
+                    match store.write(batch.build()).await {
+                        Ok(_) | Err(crate::Error::AssertValueFailed) => {}
+                        Err(err) => return Err(err),
+                    }
+                }
                 if !expired_keys.is_empty() {
                     let mut batch = BatchBuilder::new();
                     for key in expired_keys {
@@ -215,7 +649,7 @@ impl LookupStore {
 impl<T: Deserialize> Deserialize for LookupValue<T> {
     fn deserialize(bytes: &[u8]) -> crate::Result<Self> {
         bytes.deserialize_be_u64(0).and_then(|expires| {
-            Ok(if expires > now() {
+            Ok(if expires != 0 && !is_expired(expires, now()) {
                 LookupValue::Value {
                     value: T::deserialize(bytes.get(U64_LEN..).unwrap_or_default())?,
                     expires,
@@ -232,6 +666,7 @@ impl From<Value<'static>> for String {
         match value {
             Value::Text(string) => string.into_owned(),
             Value::Blob(bytes) => String::from_utf8_lossy(bytes.as_ref()).into_owned(),
+            Value::Json(json) => json.to_string(),
             Value::Bool(boolean) => boolean.to_string(),
             Value::Null => String::new(),
             Value::Integer(num) => num.to_string(),
@@ -239,3 +674,128 @@ impl From<Value<'static>> for String {
         }
     }
 }
+
+impl QueryStore {
+    /// Returns whether the query yields any rows for the key.
+    pub async fn exists(&self, key: &str) -> crate::Result<bool> {
+        if let Some(exists) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.exists.lock().get(key))
+        {
+            return Ok(exists);
+        }
+
+        let exists = self
+            .store
+            .query::<bool>(&self.query, vec![key.into()])
+            .await?;
+        if let Some(cache) = &self.cache {
+            if exists {
+                cache.exists.lock().insert_pos(key.to_string());
+            } else {
+                cache.exists.lock().insert_neg(key.to_string());
+            }
+        }
+
+        Ok(exists)
+    }
+
+    /// Returns whether the query yields any rows for each key. Keys missing from the
+    /// cache are queried together in a single batch.
+    pub async fn exists_batch(&self, keys: Vec<String>) -> crate::Result<Vec<bool>> {
+        let mut results = keys
+            .iter()
+            .map(|key| {
+                self.cache
+                    .as_ref()
+                    .and_then(|cache| cache.exists.lock().get(key))
+            })
+            .collect::<Vec<_>>();
+        let uncached = results
+            .iter()
+            .enumerate()
+            .filter_map(|(pos, exists)| if exists.is_none() { Some(pos) } else { None })
+            .collect::<Vec<_>>();
+        if uncached.is_empty() {
+            return Ok(results.into_iter().map(Option::unwrap_or_default).collect());
+        }
+
+        let rows = self
+            .store
+            .query_batch(
+                uncached
+                    .iter()
+                    .map(|pos| (self.query.as_str(), vec![keys[*pos].as_str().into()]))
+                    .collect(),
+            )
+            .await?;
+        for (pos, rows) in uncached.into_iter().zip(rows) {
+            let exists = !rows.rows.is_empty();
+            if let Some(cache) = &self.cache {
+                if exists {
+                    cache.exists.lock().insert_pos(keys[pos].clone());
+                } else {
+                    cache.exists.lock().insert_neg(keys[pos].clone());
+                }
+            }
+            results[pos] = Some(exists);
+        }
+
+        Ok(results.into_iter().map(Option::unwrap_or_default).collect())
+    }
+
+    /// Returns a page of the rows returned by the query for the key, bypassing the cache.
+    pub async fn rows_page(
+        &self,
+        key: &str,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> crate::Result<NamedRows> {
+        self.store
+            .query_page(&self.query, vec![key.into()], limit, offset)
+            .await
+    }
+
+    /// Returns the first column of the first row returned by the query for the key.
+    pub async fn first_value(&self, key: String) -> crate::Result<Option<Value<'static>>> {
+        if let Some(value) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.values.lock().get(&key))
+        {
+            return Ok(value);
+        }
+
+        let value = self
+            .store
+            .query::<Option<Row>>(&self.query, vec![key.as_str().into()])
+            .await?
+            .and_then(|row| row.values.into_iter().next());
+        if let Some(cache) = &self.cache {
+            if value.is_some() {
+                cache.values.lock().insert(key, value.clone());
+            } else {
+                cache
+                    .values
+                    .lock()
+                    .insert_with_ttl(key, None, cache.ttl_negative);
+            }
+        }
+
+        Ok(value)
+    }
+}
+
+fn lookup_key(key: Vec<u8>) -> ValueKey<ValueClass> {
+    ValueKey {
+        account_id: 0,
+        collection: 0,
+        document_id: 0,
+        class: ValueClass::Key(key),
+    }
+}
+
+fn counter_expiry_key(key: &[u8]) -> ValueKey<ValueClass> {
+    lookup_key(namespaced_key(COUNTER_EXPIRY_NS, key))
+}
@@ -22,6 +22,14 @@
 */
 
 pub mod blob;
+pub mod cache;
+pub mod deadline;
+pub mod fence;
 pub mod fts;
+pub mod invalidation;
 pub mod lookup;
+pub mod options;
+pub mod scope;
+pub mod snapshot;
 pub mod store;
+pub mod stores;
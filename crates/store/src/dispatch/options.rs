@@ -0,0 +1,59 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use utils::config::Config;
+
+use crate::write::{compression::ValueCompression, encryption::ValueEncryption, log::LogFormat};
+
+use super::cache::ValueReadCache;
+
+/// Settings shared by all backends, applied by the dispatch layer around the
+/// backend operations rather than by the backends themselves.
+pub struct StoreOptions {
+    pub read_only: AtomicBool,
+    pub compression: Option<ValueCompression>,
+    pub encryption: Option<Arc<ValueEncryption>>,
+    pub value_cache: Option<ValueReadCache>,
+    pub log_format: LogFormat,
+}
+
+impl StoreOptions {
+    pub fn parse(config: &Config, prefix: &str) -> utils::config::Result<Arc<Self>> {
+        Ok(Arc::new(StoreOptions {
+            read_only: AtomicBool::new(config.property_or_static((prefix, "read-only"), "false")?),
+            compression: ValueCompression::parse(config, prefix)?,
+            encryption: ValueEncryption::parse(config, prefix)?.map(Arc::new),
+            value_cache: ValueReadCache::parse(config, prefix)?,
+            log_format: config.property_or_static((prefix, "log-format"), "leb128")?,
+        }))
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+}
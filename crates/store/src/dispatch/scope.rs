@@ -0,0 +1,110 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+tokio::task_local! {
+    static READ_SCOPE: ReadScope;
+}
+
+#[derive(Default)]
+struct ReadScope {
+    version: Mutex<Option<(i64, Instant)>>,
+}
+
+/// Runs `f` in a read scope, where backends may serve every read it awaits
+/// from a single read version instead of obtaining a new one per read.
+/// Nested scopes share the outer one.
+pub async fn with_read_scope<F: Future>(f: F) -> F::Output {
+    if READ_SCOPE.try_with(|_| ()).is_ok() {
+        f.await
+    } else {
+        READ_SCOPE.scope(ReadScope::default(), f).await
+    }
+}
+
+/// Returns the read version of the current scope, if obtained less than `max_age` ago.
+#[cfg_attr(not(feature = "foundation"), allow(dead_code))]
+pub(crate) fn cached_read_version(max_age: Duration) -> Option<i64> {
+    READ_SCOPE
+        .try_with(|scope| {
+            (*scope.version.lock())
+                .filter(|(_, obtained)| obtained.elapsed() < max_age)
+                .map(|(version, _)| version)
+        })
+        .ok()
+        .flatten()
+}
+
+/// Stores the read version for the current scope, newer versions always win so
+/// that reads following a commit observe its changes.
+#[cfg_attr(not(feature = "foundation"), allow(dead_code))]
+pub(crate) fn set_read_version(version: i64) {
+    let _ = READ_SCOPE.try_with(|scope| {
+        let mut current = scope.version.lock();
+        if current.map_or(true, |(current, _)| version >= current) {
+            *current = Some((version, Instant::now()));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{cached_read_version, set_read_version, with_read_scope};
+
+    #[tokio::test]
+    async fn read_scope() {
+        // Versions are not kept outside a scope
+        set_read_version(10);
+        assert_eq!(cached_read_version(Duration::from_secs(60)), None);
+
+        with_read_scope(async {
+            assert_eq!(cached_read_version(Duration::from_secs(60)), None);
+            set_read_version(10);
+            assert_eq!(cached_read_version(Duration::from_secs(60)), Some(10));
+
+            // Older versions never replace newer ones
+            set_read_version(5);
+            assert_eq!(cached_read_version(Duration::from_secs(60)), Some(10));
+
+            // Nested scopes share the version
+            with_read_scope(async {
+                set_read_version(20);
+            })
+            .await;
+            assert_eq!(cached_read_version(Duration::from_secs(60)), Some(20));
+
+            // Stale versions are discarded
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert_eq!(cached_read_version(Duration::from_millis(10)), None);
+        })
+        .await;
+    }
+}
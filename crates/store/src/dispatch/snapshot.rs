@@ -0,0 +1,164 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{future::Future, pin::Pin};
+
+use roaring::RoaringBitmap;
+
+use crate::{
+    write::{
+        compression::{decompress_value, RawValue},
+        encryption::decrypt,
+        BitmapClass,
+    },
+    BitmapKey, Deserialize, ErrorContext, Key, SnapshotInner, Store, StoreSnapshot,
+    SUBSPACE_VALUES,
+};
+
+impl Store {
+    /// Runs `f` against a read-only view of the store where every read observes
+    /// the same point in time. Writes made concurrently by other tasks are not
+    /// visible until the snapshot is released.
+    ///
+    /// SQLite uses a deferred read transaction, PostgreSQL a `REPEATABLE READ`
+    /// transaction, MySQL a consistent snapshot, RocksDB a database snapshot and
+    /// FoundationDB a single transaction (bound by its five second limit).
+    pub async fn read_snapshot<F, R>(&self, f: F) -> crate::Result<R>
+    where
+        F: for<'x> FnOnce(
+            &'x mut StoreSnapshot,
+        ) -> Pin<Box<dyn Future<Output = crate::Result<R>> + Send + 'x>>,
+    {
+        let mut snapshot = self
+            .snapshot()
+            .await
+            .map_err(|err| err.with_context(ErrorContext::new("read_snapshot")))?;
+        let result = f(&mut snapshot).await;
+        let finished = snapshot.finish().await;
+
+        let result = result?;
+        finished.map(|_| result)
+    }
+
+    async fn snapshot(&self) -> crate::Result<StoreSnapshot> {
+        let inner = match self {
+            #[cfg(feature = "sqlite")]
+            Self::SQLite(store) => SnapshotInner::SQLite(store.snapshot()?),
+            #[cfg(feature = "foundation")]
+            Self::FoundationDb(store) => SnapshotInner::FoundationDb(store.snapshot()?),
+            #[cfg(feature = "postgres")]
+            Self::PostgreSQL(store) => SnapshotInner::PostgreSQL(store.snapshot().await?),
+            #[cfg(feature = "mysql")]
+            Self::MySQL(store) => SnapshotInner::MySQL(store.snapshot().await?),
+            #[cfg(feature = "rocks")]
+            Self::RocksDb(store) => SnapshotInner::RocksDb(store.snapshot().await?),
+        };
+
+        Ok(StoreSnapshot {
+            options: self.options().clone(),
+            inner,
+        })
+    }
+}
+
+impl StoreSnapshot {
+    pub async fn get_value<U>(&mut self, key: impl Key) -> crate::Result<Option<U>>
+    where
+        U: Deserialize + 'static,
+    {
+        if (self.options.compression.is_some() || self.options.encryption.is_some())
+            && key.subspace() == SUBSPACE_VALUES
+        {
+            // Values might have been stored compressed or encrypted, fetch the raw bytes first.
+            // Snapshots are read-only, values sealed with a previous key are not rotated.
+            let value_key = key.serialize(0);
+            return match self.get_value_::<RawValue>(key).await? {
+                Some(RawValue(bytes)) => {
+                    let bytes = decrypt(self.options.encryption.as_deref(), &value_key, &bytes)?;
+                    U::deserialize(&decompress_value(
+                        self.options.compression.as_ref(),
+                        &value_key,
+                        &bytes,
+                    )?)
+                    .map(Some)
+                }
+                None => Ok(None),
+            };
+        }
+
+        self.get_value_(key).await
+    }
+
+    async fn get_value_<U>(&mut self, key: impl Key) -> crate::Result<Option<U>>
+    where
+        U: Deserialize + 'static,
+    {
+        let context = ErrorContext::for_key("get_value", &key);
+        let result = match &mut self.inner {
+            #[cfg(feature = "sqlite")]
+            SnapshotInner::SQLite(snapshot) => snapshot.get_value(key).await,
+            #[cfg(feature = "foundation")]
+            SnapshotInner::FoundationDb(snapshot) => snapshot.get_value(key).await,
+            #[cfg(feature = "postgres")]
+            SnapshotInner::PostgreSQL(snapshot) => snapshot.get_value(key).await,
+            #[cfg(feature = "mysql")]
+            SnapshotInner::MySQL(snapshot) => snapshot.get_value(key).await,
+            #[cfg(feature = "rocks")]
+            SnapshotInner::RocksDb(snapshot) => snapshot.get_value(key).await,
+        };
+        result.map_err(|err| err.with_context(context))
+    }
+
+    pub async fn get_bitmap(
+        &mut self,
+        key: BitmapKey<BitmapClass>,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        let context = ErrorContext::for_key("get_bitmap", &key);
+        let result = match &mut self.inner {
+            #[cfg(feature = "sqlite")]
+            SnapshotInner::SQLite(snapshot) => snapshot.get_bitmap(key).await,
+            #[cfg(feature = "foundation")]
+            SnapshotInner::FoundationDb(snapshot) => snapshot.get_bitmap(key).await,
+            #[cfg(feature = "postgres")]
+            SnapshotInner::PostgreSQL(snapshot) => snapshot.get_bitmap(key).await,
+            #[cfg(feature = "mysql")]
+            SnapshotInner::MySQL(snapshot) => snapshot.get_bitmap(key).await,
+            #[cfg(feature = "rocks")]
+            SnapshotInner::RocksDb(snapshot) => snapshot.get_bitmap(key).await,
+        };
+        result.map_err(|err| err.with_context(context))
+    }
+
+    #[allow(unreachable_patterns)]
+    async fn finish(self) -> crate::Result<()> {
+        match self.inner {
+            #[cfg(feature = "postgres")]
+            SnapshotInner::PostgreSQL(snapshot) => snapshot.finish().await,
+            #[cfg(feature = "mysql")]
+            SnapshotInner::MySQL(snapshot) => snapshot.finish().await,
+            // SQLite rolls back on drop, FoundationDB holds no locks and
+            // RocksDB releases its snapshot once the handle is dropped
+            _ => Ok(()),
+        }
+    }
+}
@@ -21,14 +21,35 @@
  * for more details.
 */
 
-use std::ops::{BitAndAssign, Range};
+use std::{
+    borrow::Cow,
+    future::Future,
+    ops::{BitAndAssign, Range},
+    pin::Pin,
+    sync::{atomic::Ordering, Arc},
+};
 
 use roaring::RoaringBitmap;
+use xxhash_rust::xxh3::xxh3_64;
 
+use super::{
+    cache::ValueReadCache,
+    deadline::{bounded, bounded_write},
+    fence::batch_fences,
+    options::StoreOptions,
+};
 use crate::{
-    write::{key::KeySerializer, AnyKey, Batch, BitmapClass, ValueClass},
-    BitmapKey, Deserialize, IterateParams, Key, Store, ValueKey, SUBSPACE_BITMAPS,
-    SUBSPACE_INDEXES, SUBSPACE_LOGS, U32_LEN,
+    write::{
+        assert::AssertValue,
+        compression::{decompress_value, RawValue, ValueCompression},
+        encryption::{decrypt, is_encrypted, ValueEncryption},
+        key::{DeserializeBigEndian, KeySerializer},
+        log::LogFormat,
+        AnyKey, Batch, BatchBuilder, BitmapClass, Operation, ValueClass, ValueOp,
+    },
+    BitmapKey, Deserialize, DeserializeKey, ErrorContext, IterateParams, Key, ParsedKey, Store,
+    ValueKey, SUBSPACE_BITMAPS, SUBSPACE_BLOBS, SUBSPACE_COUNTERS, SUBSPACE_INDEXES, SUBSPACE_LOGS,
+    SUBSPACE_VALUES, U32_LEN, WITH_SUBSPACE,
 };
 
 #[cfg(feature = "test_mode")]
@@ -37,25 +58,164 @@ pub static ref BITMAPS: std::sync::Arc<parking_lot::Mutex<std::collections::Hash
                     std::sync::Arc::new(parking_lot::Mutex::new(std::collections::HashMap::new()));
 }
 
+// Unbounded iterations log a warning once they return this many results
+const ITERATE_WARN_RESULTS: usize = 1_000_000;
+
 impl Store {
-    pub async fn get_value<U>(&self, key: impl Key) -> crate::Result<Option<U>>
-    where
-        U: Deserialize + 'static,
-    {
+    pub(crate) fn options(&self) -> &Arc<StoreOptions> {
         match self {
             #[cfg(feature = "sqlite")]
-            Self::SQLite(store) => store.get_value(key).await,
+            Self::SQLite(store) => &store.options,
             #[cfg(feature = "foundation")]
-            Self::FoundationDb(store) => store.get_value(key).await,
+            Self::FoundationDb(store) => &store.options,
             #[cfg(feature = "postgres")]
-            Self::PostgreSQL(store) => store.get_value(key).await,
+            Self::PostgreSQL(store) => &store.options,
             #[cfg(feature = "mysql")]
-            Self::MySQL(store) => store.get_value(key).await,
+            Self::MySQL(store) => &store.options,
             #[cfg(feature = "rocks")]
-            Self::RocksDb(store) => store.get_value(key).await,
+            Self::RocksDb(store) => &store.options,
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.options().is_read_only()
+    }
+
+    /// Rejects (or allows again) all writes to the store. Reads are not affected.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.options().read_only.store(read_only, Ordering::Relaxed)
+    }
+
+    pub(crate) fn compression(&self) -> Option<&ValueCompression> {
+        self.options().compression.as_ref()
+    }
+
+    pub(crate) fn encryption(&self) -> Option<&Arc<ValueEncryption>> {
+        self.options().encryption.as_ref()
+    }
+
+    pub(crate) fn value_cache(&self) -> Option<&ValueReadCache> {
+        self.options().value_cache.as_ref()
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        self.options().log_format
+    }
+
+    #[inline(always)]
+    pub(crate) fn assert_writable(&self) -> crate::Result<()> {
+        if !self.is_read_only() {
+            Ok(())
+        } else {
+            Err(crate::Error::ReadOnly)
+        }
+    }
+
+    pub async fn get_value<U>(&self, key: impl Key) -> crate::Result<Option<U>>
+    where
+        U: Deserialize + 'static,
+    {
+        if let Some(cache) = self.value_cache().filter(|cache| cache.is_cached(&key)) {
+            let cache_key = key.serialize(WITH_SUBSPACE);
+            let value_key = key.serialize(0);
+            let bytes = if let Some(bytes) = cache.get(&cache_key) {
+                bytes
+            } else {
+                let generation = cache.generation();
+                let bytes = self
+                    .get_value_::<RawValue>(key)
+                    .await?
+                    .map(|RawValue(bytes)| Arc::new(bytes));
+                cache.insert(cache_key, bytes.clone(), generation);
+                bytes
+            };
+
+            return match bytes {
+                Some(bytes) if self.compression().is_some() || self.encryption().is_some() => {
+                    self.decode_value(&value_key, &bytes).await.map(Some)
+                }
+                Some(bytes) => U::deserialize(&bytes).map(Some),
+                None => Ok(None),
+            };
+        }
+
+        if (self.compression().is_some() || self.encryption().is_some())
+            && key.subspace() == SUBSPACE_VALUES
+        {
+            // Values might have been stored compressed or encrypted, fetch the raw bytes first
+            let value_key = key.serialize(0);
+            return match self.get_value_::<RawValue>(key).await? {
+                Some(RawValue(bytes)) => self.decode_value(&value_key, &bytes).await.map(Some),
+                None => Ok(None),
+            };
+        }
+
+        self.get_value_(key).await
+    }
+
+    /// Decrypts and decompresses a value read from the values subspace. Values sealed
+    /// with a previous encryption key are re-encrypted with the current one.
+    async fn decode_value<U: Deserialize>(&self, key: &[u8], bytes: &[u8]) -> crate::Result<U> {
+        let (bytes, rotate) = match self.encryption() {
+            Some(encryption) => encryption.decrypt(key, bytes)?,
+            None => (Cow::Borrowed(bytes), false),
+        };
+        let bytes = decompress_value(self.compression(), key, &bytes)?;
+        if rotate {
+            self.reencrypt_value(key, &bytes).await;
+        }
+        U::deserialize(&bytes)
+    }
+
+    // The value is rewritten only if it did not change since it was read. Failures are
+    // ignored, the value remains readable with the previous key.
+    async fn reencrypt_value(&self, key: &[u8], value: &[u8]) {
+        if let Ok(ParsedKey::Value(key)) = ParsedKey::deserialize(SUBSPACE_VALUES, key) {
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(key.account_id)
+                .with_collection(key.collection)
+                .update_document(key.document_id)
+                .assert_value(key.class.clone(), AssertValue::Hash(xxh3_64(value)))
+                .set(key.class, value.to_vec());
+
+            // Boxed as writes may read values themselves
+            let write: Pin<Box<dyn Future<Output = crate::Result<()>> + Send + '_>> =
+                Box::pin(self.write(batch.build()));
+            if let Err(err) = write.await {
+                tracing::debug!(
+                    context = "store",
+                    event = "error",
+                    reason = ?err,
+                    "Failed to re-encrypt value."
+                );
+            }
         }
     }
 
+    async fn get_value_<U>(&self, key: impl Key) -> crate::Result<Option<U>>
+    where
+        U: Deserialize + 'static,
+    {
+        let context = ErrorContext::for_key("get_value", &key);
+        let result = bounded(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.get_value(key).await,
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.get_value(key).await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.get_value(key).await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.get_value(key).await,
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(store) => store.get_value(key).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
+    }
+
     pub async fn get_values<U>(&self, key: Vec<impl Key>) -> crate::Result<Vec<Option<U>>>
     where
         U: Deserialize + 'static,
@@ -69,21 +229,126 @@ impl Store {
         Ok(results)
     }
 
+    /// Returns the value of a property for every document in a collection, as
+    /// `(document_id, value)` pairs ordered by document id. The values are read with a
+    /// single range scan rather than one read per document.
+    pub async fn get_collection_values<U>(
+        &self,
+        account_id: u32,
+        collection: u8,
+        field: u8,
+    ) -> crate::Result<Vec<(u32, U)>>
+    where
+        U: Deserialize + 'static,
+    {
+        let mut results = Vec::new();
+
+        self.iterate(
+            IterateParams::new(
+                ValueKey {
+                    account_id,
+                    collection,
+                    document_id: 0,
+                    class: ValueClass::Property(0),
+                },
+                ValueKey {
+                    account_id,
+                    collection,
+                    document_id: u32::MAX,
+                    class: ValueClass::Property(u8::MAX),
+                },
+            )
+            .ascending(),
+            |key, value| {
+                // The range includes the values of all properties in the collection
+                if let ParsedKey::Value(ValueKey {
+                    document_id,
+                    class: ValueClass::Property(key_field),
+                    ..
+                }) = ParsedKey::deserialize(SUBSPACE_VALUES, key)?
+                {
+                    if key_field == field {
+                        results.push((document_id, U::deserialize(value)?));
+                    }
+                }
+
+                Ok(true)
+            },
+        )
+        .await?;
+
+        // Document ids are LEB128 encoded in keys, which does not preserve their order
+        results.sort_unstable_by_key(|(document_id, _)| *document_id);
+
+        Ok(results)
+    }
+
     pub async fn get_bitmap(
         &self,
         key: BitmapKey<BitmapClass>,
     ) -> crate::Result<Option<RoaringBitmap>> {
-        match self {
-            #[cfg(feature = "sqlite")]
-            Self::SQLite(store) => store.get_bitmap(key).await,
-            #[cfg(feature = "foundation")]
-            Self::FoundationDb(store) => store.get_bitmap(key).await,
-            #[cfg(feature = "postgres")]
-            Self::PostgreSQL(store) => store.get_bitmap(key).await,
-            #[cfg(feature = "mysql")]
-            Self::MySQL(store) => store.get_bitmap(key).await,
-            #[cfg(feature = "rocks")]
-            Self::RocksDb(store) => store.get_bitmap(key).await,
+        let context = ErrorContext::for_key("get_bitmap", &key);
+        let result = bounded(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.get_bitmap(key).await,
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.get_bitmap(key).await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.get_bitmap(key).await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.get_bitmap(key).await,
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(store) => store.get_bitmap(key).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
+    }
+
+    /// Returns whether the key exists without reading its value. A bitmap key exists
+    /// when the bitmap contains the document id set as its `block_num`.
+    pub async fn exists(&self, key: impl Key) -> crate::Result<bool> {
+        let context = ErrorContext::for_key("exists", &key);
+        let result = bounded(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.key_exists(key).await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.key_exists(key).await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.key_exists(key).await,
+                // SQL backends store one row per document, others store whole bitmaps
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(_) if key.subspace() == SUBSPACE_BITMAPS => {
+                    self.bitmap_contains(key).await
+                }
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.key_exists(key).await,
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(_) if key.subspace() == SUBSPACE_BITMAPS => {
+                    self.bitmap_contains(key).await
+                }
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(store) => store.key_exists(key).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
+    }
+
+    #[allow(dead_code)]
+    async fn bitmap_contains(&self, key: impl Key) -> crate::Result<bool> {
+        if let ParsedKey::Bitmap(mut key) =
+            ParsedKey::deserialize(SUBSPACE_BITMAPS, &key.serialize(0))?
+        {
+            let document_id = key.block_num;
+            key.block_num = 0;
+            self.get_bitmap(key)
+                .await
+                .map(|bitmap| bitmap.map_or(false, |bitmap| bitmap.contains(document_id)))
+        } else {
+            Ok(false)
         }
     }
 
@@ -110,21 +375,198 @@ impl Store {
     }
 
     pub async fn iterate<T: Key>(
+        &self,
+        mut params: IterateParams<T>,
+        mut cb: impl for<'x> FnMut(&'x [u8], &'x [u8]) -> crate::Result<bool> + Sync + Send,
+    ) -> crate::Result<()> {
+        // Enforce the result budget, or warn when an unbounded scan grows too large
+        let subspace = params.begin.subspace();
+        let max_results = params.max_results.take();
+        let max_bytes = params.max_bytes.take();
+        let mut num_results = 0usize;
+        let mut num_bytes = 0usize;
+        let mut cb = move |key: &[u8], value: &[u8]| {
+            num_results += 1;
+            num_bytes += key.len() + value.len();
+            if max_results.map_or(false, |max_results| num_results > max_results) {
+                Err(crate::Error::InternalError(format!(
+                    "Iteration exceeded the maximum of {} results.",
+                    max_results.unwrap()
+                )))
+            } else if max_bytes.map_or(false, |max_bytes| num_bytes > max_bytes) {
+                Err(crate::Error::InternalError(format!(
+                    "Iteration exceeded the maximum of {} bytes.",
+                    max_bytes.unwrap()
+                )))
+            } else {
+                if num_results == ITERATE_WARN_RESULTS
+                    && max_results.is_none()
+                    && max_bytes.is_none()
+                {
+                    tracing::warn!(
+                        context = "store",
+                        event = "iterate",
+                        subspace = subspace,
+                        results = num_results,
+                        "Unbounded iteration returned a large number of results."
+                    );
+                }
+                cb(key, value)
+            }
+        };
+
+        if params.end_exclusive {
+            // Backends treat the end of the range as inclusive, skip the end key here.
+            // On descending scans the "first" flag is emulated so the scan does not stop
+            // at the end key, ascending scans can still use the backend's fast path.
+            let end = params.end.serialize(0);
+            let first = !params.ascending && std::mem::take(&mut params.first);
+            params.end_exclusive = false;
+            return self
+                .iterate_(params, move |key, value| {
+                    if key == end.as_slice() {
+                        Ok(true)
+                    } else {
+                        Ok(cb(key, value)? && !first)
+                    }
+                })
+                .await;
+        }
+
+        self.iterate_(params, cb).await
+    }
+
+    /// Iterates over a range deserializing keys and values before passing them to the
+    /// callback. A key or value that fails to deserialize stops the iteration and its
+    /// error is returned.
+    pub async fn iterate_typed<T: Key, K: DeserializeKey, V: Deserialize>(
         &self,
         params: IterateParams<T>,
+        mut cb: impl FnMut(K, V) -> crate::Result<bool> + Sync + Send,
+    ) -> crate::Result<()> {
+        let subspace = params.begin.subspace();
+        self.iterate(params, move |key, value| {
+            cb(K::deserialize_key(subspace, key)?, V::deserialize(value)?)
+        })
+        .await
+    }
+
+    /// Iterates over a range of raw keys within a single subspace. The range bounds are
+    /// given without the subspace byte, and keys are passed to the callback without it.
+    pub async fn iterate_subspace(
+        &self,
+        subspace: u8,
+        begin: impl AsRef<[u8]> + Sync + Send,
+        end: impl AsRef<[u8]> + Sync + Send,
         cb: impl for<'x> FnMut(&'x [u8], &'x [u8]) -> crate::Result<bool> + Sync + Send,
     ) -> crate::Result<()> {
+        self.iterate(
+            IterateParams::new(
+                AnyKey {
+                    subspace,
+                    key: begin,
+                },
+                AnyKey { subspace, key: end },
+            ),
+            cb,
+        )
+        .await
+    }
+
+    async fn iterate_<T: Key>(
+        &self,
+        params: IterateParams<T>,
+        mut cb: impl for<'x> FnMut(&'x [u8], &'x [u8]) -> crate::Result<bool> + Sync + Send,
+    ) -> crate::Result<()> {
+        if params.values
+            && (self.compression().is_some() || self.encryption().is_some())
+            && params.begin.subspace() == SUBSPACE_VALUES
+        {
+            let encryption = self.encryption().cloned();
+            let compression = self.compression();
+            return self
+                .iterate__(params, move |key, value| {
+                    let value = decrypt(encryption.as_deref(), key, value)?;
+                    cb(key, decompress_value(compression, key, &value)?.as_ref())
+                })
+                .await;
+        }
+
+        self.iterate__(params, cb).await
+    }
+
+    async fn iterate__<T: Key>(
+        &self,
+        params: IterateParams<T>,
+        cb: impl for<'x> FnMut(&'x [u8], &'x [u8]) -> crate::Result<bool> + Sync + Send,
+    ) -> crate::Result<()> {
+        let context = ErrorContext::for_key("iterate", &params.begin);
+        let result = bounded(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.iterate(params, cb).await,
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.iterate(params, cb).await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.iterate(params, cb).await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.iterate(params, cb).await,
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(store) => store.iterate(params, cb).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
+    }
+
+    /// Returns the approximate number of keys in a subspace, optionally restricted to an
+    /// account. Only subspaces whose keys start with the account id (bitmaps, indexes and
+    /// logs) can be filtered by account. Backends use their own statistics when available
+    /// (RocksDB key estimates, PostgreSQL and MySQL table statistics) and otherwise count
+    /// the keys in the range, which costs a scan of the index (SQL) or of the range itself
+    /// (FoundationDB, RocksDB when filtering by account). FoundationDB counts in batches
+    /// spread over as many transactions as needed, so large ranges may be off by the keys
+    /// written while they are being counted.
+    pub async fn estimate_count(
+        &self,
+        subspace: u8,
+        account_id: Option<u32>,
+    ) -> crate::Result<u64> {
+        let (begin, end) = if let Some(account_id) = account_id {
+            if !matches!(
+                subspace,
+                SUBSPACE_BITMAPS | SUBSPACE_INDEXES | SUBSPACE_LOGS
+            ) {
+                return Err(crate::Error::InternalError(format!(
+                    "Subspace {:?} cannot be counted by account.",
+                    char::from(subspace)
+                )));
+            }
+            (
+                KeySerializer::new(U32_LEN).write(account_id).finalize(),
+                Some(if let Some(next_account_id) = account_id.checked_add(1) {
+                    KeySerializer::new(U32_LEN)
+                        .write(next_account_id)
+                        .finalize()
+                } else {
+                    vec![u8::MAX; 32]
+                }),
+            )
+        } else {
+            (vec![], None)
+        };
+
         match self {
             #[cfg(feature = "sqlite")]
-            Self::SQLite(store) => store.iterate(params, cb).await,
+            Self::SQLite(store) => store.estimate_count(subspace, begin, end).await,
             #[cfg(feature = "foundation")]
-            Self::FoundationDb(store) => store.iterate(params, cb).await,
+            Self::FoundationDb(store) => store.estimate_count(subspace, begin, end).await,
             #[cfg(feature = "postgres")]
-            Self::PostgreSQL(store) => store.iterate(params, cb).await,
+            Self::PostgreSQL(store) => store.estimate_count(subspace, begin, end).await,
             #[cfg(feature = "mysql")]
-            Self::MySQL(store) => store.iterate(params, cb).await,
+            Self::MySQL(store) => store.estimate_count(subspace, begin, end).await,
             #[cfg(feature = "rocks")]
-            Self::RocksDb(store) => store.iterate(params, cb).await,
+            Self::RocksDb(store) => store.estimate_count(subspace, begin, end).await,
         }
     }
 
@@ -132,61 +574,83 @@ impl Store {
         &self,
         key: impl Into<ValueKey<ValueClass>> + Sync + Send,
     ) -> crate::Result<i64> {
-        match self {
-            #[cfg(feature = "sqlite")]
-            Self::SQLite(store) => store.get_counter(key).await,
-            #[cfg(feature = "foundation")]
-            Self::FoundationDb(store) => store.get_counter(key).await,
-            #[cfg(feature = "postgres")]
-            Self::PostgreSQL(store) => store.get_counter(key).await,
-            #[cfg(feature = "mysql")]
-            Self::MySQL(store) => store.get_counter(key).await,
-            #[cfg(feature = "rocks")]
-            Self::RocksDb(store) => store.get_counter(key).await,
-        }
+        let key = key.into();
+        let context = ErrorContext::for_key("get_counter", &key);
+        let result = bounded(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.get_counter(key).await,
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.get_counter(key).await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.get_counter(key).await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.get_counter(key).await,
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(store) => store.get_counter(key).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
+    }
+
+    /// Reads several counters at once, returning their values in the same order.
+    pub async fn get_counters(&self, keys: Vec<ValueKey<ValueClass>>) -> crate::Result<Vec<i64>> {
+        let context = ErrorContext::new("get_counters").with_subspace(SUBSPACE_COUNTERS);
+        let result = bounded(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.get_counters(keys).await,
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.get_counters(keys).await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.get_counters(keys).await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.get_counters(keys).await,
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(store) => store.get_counters(keys).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
     }
 
     pub async fn write(&self, batch: Batch) -> crate::Result<()> {
+        self.assert_writable()?;
+
+        // Bitmap changes are tracked before the batch is transformed for the backend
         #[cfg(feature = "test_mode")]
-        if std::env::var("PARANOID_WRITE").map_or(false, |v| v == "1") {
-            use crate::write::Operation;
-            let mut account_id = u32::MAX;
-            let mut collection = u8::MAX;
-            let mut document_id = u32::MAX;
-
-            let mut bitmaps = Vec::new();
-
-            for op in &batch.ops {
-                match op {
-                    Operation::AccountId {
-                        account_id: account_id_,
-                    } => {
-                        account_id = *account_id_;
-                    }
-                    Operation::Collection {
-                        collection: collection_,
-                    } => {
-                        collection = *collection_;
-                    }
-                    Operation::DocumentId {
-                        document_id: document_id_,
-                    } => {
-                        document_id = *document_id_;
-                    }
-                    Operation::Bitmap { class, set } => {
-                        let key = BitmapKey {
-                            account_id,
-                            collection,
-                            block_num: 0,
-                            class,
-                        }
-                        .serialize(0);
-                        bitmaps.push((key, class.clone(), document_id, *set));
-                    }
-                    _ => {}
+        let paranoid_bitmaps = std::env::var("PARANOID_WRITE")
+            .map_or(false, |v| v == "1")
+            .then(|| paranoid_bitmaps(&batch));
+
+        let mut batch = if let Some(compression) = self.compression() {
+            compress_batch(compression, batch)
+        } else {
+            batch
+        };
+        if let Some(encryption) = self.encryption() {
+            batch = self.encrypt_batch(encryption, batch).await?;
+        }
+        let log_format = self.log_format();
+        if log_format != LogFormat::Leb128 {
+            for op in &mut batch.ops {
+                if let Operation::Log { set, .. } = op {
+                    *set = log_format.encode(std::mem::take(set));
                 }
             }
-
+        }
+        let context =
+            ErrorContext::new("write").with_account_id(batch.ops.iter().find_map(|op| match op {
+                Operation::AccountId { account_id } => Some(*account_id),
+                _ => None,
+            }));
+        // Cached values are invalidated once the write completes, even if it failed
+        let invalidate = self
+            .value_cache()
+            .map(|cache| (cache, cache.batch_keys(&batch)));
+        let fences = batch_fences(&batch);
+        let result = bounded_write(async {
             match self {
                 #[cfg(feature = "sqlite")]
                 Self::SQLite(store) => store.write(batch).await,
@@ -198,50 +662,79 @@ impl Store {
                 Self::MySQL(store) => store.write(batch).await,
                 #[cfg(feature = "rocks")]
                 Self::RocksDb(store) => store.write(batch).await,
-            }?;
-
-            for (key, class, document_id, set) in bitmaps {
-                let mut bitmaps = BITMAPS.lock();
-                let map = bitmaps.entry(key).or_default();
-                if set {
-                    if !map.insert(document_id) {
-                        println!(
-                            concat!(
-                                "WARNING: key {:?} already contains document {} for account ",
-                                "{}, collection {}"
-                            ),
-                            class, document_id, account_id, collection
-                        );
-                    }
-                } else if !map.remove(&document_id) {
-                    println!(
-                        concat!(
-                            "WARNING: key {:?} does not contain document {} for account ",
-                            "{}, collection {}"
-                        ),
-                        class, document_id, account_id, collection
-                    );
-                }
             }
+        })
+        .await;
+        if let Some((cache, keys)) = invalidate {
+            for key in keys {
+                cache.invalidate(&key);
+            }
+        }
+        let result = match result {
+            Err(crate::Error::AssertValueFailed) if !fences.is_empty() => {
+                Err(self.check_fences(fences).await)
+            }
+            result => result.map_err(|err| err.with_context(context)),
+        };
 
-            return Ok(());
+        #[cfg(feature = "test_mode")]
+        if let (Ok(()), Some(bitmaps)) = (&result, paranoid_bitmaps) {
+            paranoid_check(bitmaps);
         }
 
-        match self {
-            #[cfg(feature = "sqlite")]
-            Self::SQLite(store) => store.write(batch).await,
-            #[cfg(feature = "foundation")]
-            Self::FoundationDb(store) => store.write(batch).await,
-            #[cfg(feature = "postgres")]
-            Self::PostgreSQL(store) => store.write(batch).await,
-            #[cfg(feature = "mysql")]
-            Self::MySQL(store) => store.write(batch).await,
-            #[cfg(feature = "rocks")]
-            Self::RocksDb(store) => store.write(batch).await,
+        result
+    }
+
+    /// Sets a value only if the key does not exist yet, returning whether it was
+    /// inserted. Concurrent callers racing on the same key see exactly one insert.
+    pub async fn put_if_absent(
+        &self,
+        key: ValueKey<ValueClass>,
+        value: Vec<u8>,
+    ) -> crate::Result<bool> {
+        self.assert_writable()?;
+
+        let value = match (self.compression(), &key.class) {
+            (Some(compression), ValueClass::Property(_)) => compression
+                .compress(key.collection, &value)
+                .unwrap_or(value),
+            _ => value,
+        };
+        let value = match (self.encryption(), &key.class) {
+            (Some(encryption), class) if encryption.is_encrypted_class(key.collection, class) => {
+                encryption.encrypt(&key.serialize(0), &value)?
+            }
+            _ => value,
+        };
+        let context = ErrorContext::for_key("put_if_absent", &key);
+        let cache_key = self
+            .value_cache()
+            .filter(|cache| cache.is_cached(&key))
+            .map(|cache| (cache, key.serialize(WITH_SUBSPACE)));
+        let result = bounded_write(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.put_if_absent(key, value).await,
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.put_if_absent(key, value).await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.put_if_absent(key, value).await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.put_if_absent(key, value).await,
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(store) => store.put_if_absent(key, value).await,
+            }
+        })
+        .await;
+        if let Some((cache, cache_key)) = cache_key {
+            cache.invalidate(&cache_key);
         }
+        result.map_err(|err| err.with_context(context))
     }
 
     pub async fn purge_bitmaps(&self) -> crate::Result<()> {
+        self.assert_writable()?;
+
         match self {
             #[cfg(feature = "sqlite")]
             Self::SQLite(store) => store.purge_bitmaps().await,
@@ -255,19 +748,171 @@ impl Store {
             Self::RocksDb(store) => store.purge_bitmaps().await,
         }
     }
-    pub(crate) async fn delete_range(&self, from: impl Key, to: impl Key) -> crate::Result<()> {
+
+    /// Purges stale bitmaps and compacts the backend's storage where supported,
+    /// which in SQLite means truncating the write-ahead log.
+    pub async fn maintain(&self) -> crate::Result<()> {
+        self.purge_bitmaps().await?;
+
+        #[allow(unreachable_patterns)]
         match self {
             #[cfg(feature = "sqlite")]
-            Self::SQLite(store) => store.delete_range(from, to).await,
-            #[cfg(feature = "foundation")]
-            Self::FoundationDb(store) => store.delete_range(from, to).await,
-            #[cfg(feature = "postgres")]
-            Self::PostgreSQL(store) => store.delete_range(from, to).await,
-            #[cfg(feature = "mysql")]
-            Self::MySQL(store) => store.delete_range(from, to).await,
+            Self::SQLite(store) => {
+                store
+                    .checkpoint(crate::backend::sqlite::CheckpointMode::Truncate)
+                    .await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Forces any writes buffered in memory to stable storage, returning once they
+    /// are durable. Backends that commit every write synchronously return immediately.
+    #[allow(unreachable_patterns)]
+    pub async fn flush(&self) -> crate::Result<()> {
+        let result = match self {
+            #[cfg(feature = "sqlite")]
+            Self::SQLite(store) => {
+                store
+                    .checkpoint(crate::backend::sqlite::CheckpointMode::Truncate)
+                    .await
+            }
             #[cfg(feature = "rocks")]
-            Self::RocksDb(store) => store.delete_range(from, to).await,
+            Self::RocksDb(store) => store.flush().await,
+            _ => Ok(()),
+        };
+        result.map_err(|err| err.with_context(ErrorContext::new("flush")))
+    }
+
+    // Hash assertions on encrypted values are checked here against the decrypted value
+    // and replaced with an assertion on the stored bytes, which the backend then
+    // verifies atomically with the write.
+    async fn encrypt_batch(
+        &self,
+        encryption: &ValueEncryption,
+        mut batch: Batch,
+    ) -> crate::Result<Batch> {
+        let mut account_id = u32::MAX;
+        let mut collection = u8::MAX;
+        let mut document_id = u32::MAX;
+        for op in &mut batch.ops {
+            match op {
+                Operation::AccountId {
+                    account_id: account_id_,
+                } => {
+                    account_id = *account_id_;
+                }
+                Operation::Collection {
+                    collection: collection_,
+                } => {
+                    collection = *collection_;
+                }
+                Operation::DocumentId {
+                    document_id: document_id_,
+                } => {
+                    document_id = *document_id_;
+                }
+                Operation::Value {
+                    class,
+                    op: ValueOp::Set(value),
+                } if encryption.is_encrypted_class(collection, class) => {
+                    let key = ValueKey {
+                        account_id,
+                        collection,
+                        document_id,
+                        class: class.clone(),
+                    };
+                    *value = encryption.encrypt(&key.serialize(0), value)?;
+                }
+                Operation::AssertValue {
+                    class,
+                    assert_value: AssertValue::Hash(hash),
+                } if encryption.is_encrypted_class(collection, class) => {
+                    let key = ValueKey {
+                        account_id,
+                        collection,
+                        document_id,
+                        class: class.clone(),
+                    };
+                    let value_key = key.serialize(0);
+                    if let Some(RawValue(bytes)) = self.get_value_::<RawValue>(key).await? {
+                        // Plaintext values are rejected here unless explicitly allowed
+                        let (value, _) = encryption.decrypt(&value_key, &bytes)?;
+                        if is_encrypted(&bytes) {
+                            if xxh3_64(&decompress_value(self.compression(), &value_key, &value)?)
+                                != *hash
+                            {
+                                return Err(crate::Error::AssertValueFailed);
+                            }
+                            *hash = xxh3_64(&bytes);
+                        }
+                    }
+                }
+                _ => {}
+            }
         }
+        Ok(batch)
+    }
+
+    pub(crate) async fn delete_range(&self, from: impl Key, to: impl Key) -> crate::Result<()> {
+        self.assert_writable()?;
+
+        let context = ErrorContext::for_key("delete_range", &from);
+        let result = bounded_write(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.delete_range(from, to).await,
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.delete_range(from, to).await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.delete_range(from, to).await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.delete_range(from, to).await,
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(store) => store.delete_range(from, to).await,
+            }
+        })
+        .await;
+        if let Some(cache) = self.value_cache() {
+            cache.clear();
+        }
+        result.map_err(|err| err.with_context(context))
+    }
+
+    /// Returns the ids of all accounts with data in the store, in ascending order.
+    /// There is no maintained list of accounts, the ids are read from the bitmaps subspace
+    /// (whose keys start with the account id) seeking once per account rather than
+    /// scanning every key.
+    pub async fn scan_accounts(&self) -> crate::Result<Vec<u32>> {
+        let mut account_ids = Vec::new();
+        let mut from_account_id = Some(0u32);
+
+        while let Some(account_id) = from_account_id.take() {
+            self.iterate(
+                IterateParams::range(
+                    AnyKey {
+                        subspace: SUBSPACE_BITMAPS,
+                        key: KeySerializer::new(U32_LEN).write(account_id).finalize(),
+                    },
+                    AnyKey {
+                        subspace: SUBSPACE_BITMAPS,
+                        key: vec![u8::MAX; 32],
+                    },
+                )
+                .values(false)
+                .first(true)
+                .build(),
+                |key, _| {
+                    let account_id = key.deserialize_be_u32(0)?;
+                    account_ids.push(account_id);
+                    from_account_id = account_id.checked_add(1);
+                    Ok(false)
+                },
+            )
+            .await?;
+        }
+
+        Ok(account_ids)
     }
 
     pub async fn purge_account(&self, account_id: u32) -> crate::Result<()> {
@@ -312,54 +957,71 @@ impl Store {
     }
 
     pub async fn get_blob(&self, key: &[u8], range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
-        match self {
-            #[cfg(feature = "sqlite")]
-            Self::SQLite(store) => store.get_blob(key, range).await,
-            #[cfg(feature = "foundation")]
-            Self::FoundationDb(store) => store.get_blob(key, range).await,
-            #[cfg(feature = "postgres")]
-            Self::PostgreSQL(store) => store.get_blob(key, range).await,
-            #[cfg(feature = "mysql")]
-            Self::MySQL(store) => store.get_blob(key, range).await,
-            #[cfg(feature = "rocks")]
-            Self::RocksDb(store) => store.get_blob(key, range).await,
-        }
+        let context = ErrorContext::new("get_blob").with_subspace(SUBSPACE_BLOBS);
+        let result = bounded(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.get_blob(key, range).await,
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.get_blob(key, range).await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.get_blob(key, range).await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.get_blob(key, range).await,
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(store) => store.get_blob(key, range).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
     }
 
     pub async fn put_blob(&self, key: &[u8], data: &[u8]) -> crate::Result<()> {
-        match self {
-            #[cfg(feature = "sqlite")]
-            Self::SQLite(store) => store.put_blob(key, data).await,
-            #[cfg(feature = "foundation")]
-            Self::FoundationDb(store) => store.put_blob(key, data).await,
-            #[cfg(feature = "postgres")]
-            Self::PostgreSQL(store) => store.put_blob(key, data).await,
-            #[cfg(feature = "mysql")]
-            Self::MySQL(store) => store.put_blob(key, data).await,
-            #[cfg(feature = "rocks")]
-            Self::RocksDb(store) => store.put_blob(key, data).await,
-        }
+        self.assert_writable()?;
+
+        let context = ErrorContext::new("put_blob").with_subspace(SUBSPACE_BLOBS);
+        let result = bounded_write(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.put_blob(key, data).await,
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.put_blob(key, data).await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.put_blob(key, data).await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.put_blob(key, data).await,
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(store) => store.put_blob(key, data).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
     }
 
     pub async fn delete_blob(&self, key: &[u8]) -> crate::Result<bool> {
-        match self {
-            #[cfg(feature = "sqlite")]
-            Self::SQLite(store) => store.delete_blob(key).await,
-            #[cfg(feature = "foundation")]
-            Self::FoundationDb(store) => store.delete_blob(key).await,
-            #[cfg(feature = "postgres")]
-            Self::PostgreSQL(store) => store.delete_blob(key).await,
-            #[cfg(feature = "mysql")]
-            Self::MySQL(store) => store.delete_blob(key).await,
-            #[cfg(feature = "rocks")]
-            Self::RocksDb(store) => store.delete_blob(key).await,
-        }
+        self.assert_writable()?;
+
+        let context = ErrorContext::new("delete_blob").with_subspace(SUBSPACE_BLOBS);
+        let result = bounded_write(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.delete_blob(key).await,
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.delete_blob(key).await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.delete_blob(key).await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.delete_blob(key).await,
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(store) => store.delete_blob(key).await,
+            }
+        })
+        .await;
+        result.map_err(|err| err.with_context(context))
     }
 
     #[cfg(feature = "test_mode")]
     pub async fn destroy(&self) {
-        use crate::{SUBSPACE_BLOBS, SUBSPACE_COUNTERS, SUBSPACE_VALUES};
-
         for subspace in [
             SUBSPACE_VALUES,
             SUBSPACE_LOGS,
@@ -395,10 +1057,7 @@ impl Store {
 
     #[cfg(feature = "test_mode")]
     pub async fn blob_expire_all(&self) {
-        use crate::{
-            write::{key::DeserializeBigEndian, BatchBuilder, BlobOp, Operation, ValueOp},
-            BlobHash, BLOB_HASH_LEN, U64_LEN,
-        };
+        use crate::{write::BlobOp, BlobHash, BLOB_HASH_LEN, U64_LEN};
 
         // Delete all temporary hashes
         let from_key = ValueKey {
@@ -455,8 +1114,6 @@ impl Store {
     pub async fn assert_is_empty(&self, blob_store: crate::BlobStore) {
         use utils::codec::leb128::Leb128Iterator;
 
-        use crate::{SUBSPACE_BLOBS, SUBSPACE_COUNTERS, SUBSPACE_VALUES};
-
         self.blob_expire_all().await;
         self.purge_blobs(blob_store).await.unwrap();
         self.purge_bitmaps().await.unwrap();
@@ -548,14 +1205,15 @@ impl Store {
                         }
                         SUBSPACE_VALUES
                             if key[0] == 3
+                                || key[0] == 9
                                 || key[0] >= 20
                                 || key.get(1..5).unwrap_or_default() == u32::MAX.to_be_bytes() =>
                         {
                             // Ignore lastId counter and ID mappings
                             return Ok(true);
                         }
-                        SUBSPACE_COUNTERS if key.len() <= 4 => {
-                            // Ignore named keys
+                        SUBSPACE_COUNTERS if key.len() <= 4 || key[0] == 9 => {
+                            // Ignore named keys and blob reference counts
                             return Ok(true);
                         }
                         SUBSPACE_INDEXES => {
@@ -617,3 +1275,102 @@ impl Store {
         }
     }
 }
+
+#[cfg(feature = "test_mode")]
+fn paranoid_bitmaps(batch: &Batch) -> Vec<(Vec<u8>, BitmapClass, u32, u8, u32, bool)> {
+    let mut account_id = u32::MAX;
+    let mut collection = u8::MAX;
+    let mut document_id = u32::MAX;
+
+    let mut bitmaps = Vec::new();
+
+    for op in &batch.ops {
+        match op {
+            Operation::AccountId {
+                account_id: account_id_,
+            } => {
+                account_id = *account_id_;
+            }
+            Operation::Collection {
+                collection: collection_,
+            } => {
+                collection = *collection_;
+            }
+            Operation::DocumentId {
+                document_id: document_id_,
+            } => {
+                document_id = *document_id_;
+            }
+            Operation::Bitmap { class, set } => {
+                let key = BitmapKey {
+                    account_id,
+                    collection,
+                    block_num: 0,
+                    class,
+                }
+                .serialize(0);
+                bitmaps.push((
+                    key,
+                    class.clone(),
+                    account_id,
+                    collection,
+                    document_id,
+                    *set,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    bitmaps
+}
+
+#[cfg(feature = "test_mode")]
+fn paranoid_check(bitmaps: Vec<(Vec<u8>, BitmapClass, u32, u8, u32, bool)>) {
+    for (key, class, account_id, collection, document_id, set) in bitmaps {
+        let mut bitmaps = BITMAPS.lock();
+        let map = bitmaps.entry(key).or_default();
+        if set {
+            if !map.insert(document_id) {
+                println!(
+                    concat!(
+                        "WARNING: key {:?} already contains document {} for account ",
+                        "{}, collection {}"
+                    ),
+                    class, document_id, account_id, collection
+                );
+            }
+        } else if !map.remove(&document_id) {
+            println!(
+                concat!(
+                    "WARNING: key {:?} does not contain document {} for account ",
+                    "{}, collection {}"
+                ),
+                class, document_id, account_id, collection
+            );
+        }
+    }
+}
+
+fn compress_batch(compression: &ValueCompression, mut batch: Batch) -> Batch {
+    let mut collection = u8::MAX;
+    for op in &mut batch.ops {
+        match op {
+            Operation::Collection {
+                collection: collection_,
+            } => {
+                collection = *collection_;
+            }
+            Operation::Value {
+                class: ValueClass::Property(_),
+                op: ValueOp::Set(value),
+            } => {
+                if let Some(compressed) = compression.compress(collection, value) {
+                    *value = compressed;
+                }
+            }
+            _ => {}
+        }
+    }
+    batch
+}
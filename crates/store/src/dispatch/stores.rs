@@ -0,0 +1,232 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    collections::hash_map::Entry,
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+
+use crate::{BlobStore, FtsStore, LookupStore, Store, Stores};
+
+use super::deadline::bounded;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    pub checks: Vec<HealthCheck>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    pub id: String,
+    pub result: Result<Duration, String>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|check| check.result.is_ok())
+    }
+}
+
+impl Stores {
+    /// Pings every configured store, reporting the latency or error of each.
+    pub async fn health(&self) -> HealthReport {
+        // The same backend is usually registered under several store kinds,
+        // ping each id only once per kind.
+        let mut results: AHashMap<String, crate::Result<Duration>> = AHashMap::new();
+
+        for (id, store) in &self.stores {
+            if let Entry::Vacant(entry) = results.entry(format!("store.{id}")) {
+                entry.insert(store.ping().await);
+            }
+        }
+        for (id, store) in &self.blob_stores {
+            if let Entry::Vacant(entry) = results.entry(format!("blob-store.{id}")) {
+                entry.insert(store.ping().await);
+            }
+        }
+        for (id, store) in &self.fts_stores {
+            if let Entry::Vacant(entry) = results.entry(format!("fts-store.{id}")) {
+                entry.insert(store.ping().await);
+            }
+        }
+        for (id, store) in &self.lookup_stores {
+            if let Entry::Vacant(entry) = results.entry(format!("lookup-store.{id}")) {
+                entry.insert(store.ping().await);
+            }
+        }
+
+        let mut checks = results
+            .into_iter()
+            .map(|(id, result)| HealthCheck {
+                id,
+                result: result.map_err(|err| err.to_string()),
+            })
+            .collect::<Vec<_>>();
+        checks.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+
+        HealthReport { checks }
+    }
+
+    /// Pings every configured store, returning the id and error of those that
+    /// could not be reached.
+    pub async fn verify(&self) -> Result<(), Vec<(String, String)>> {
+        let errors = self
+            .health()
+            .await
+            .checks
+            .into_iter()
+            .filter_map(|check| check.result.err().map(|err| (check.id, err)))
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Flushes the buffered writes of every configured store, returning the id and
+    /// error of those that could not be flushed. Called during shutdown.
+    pub async fn flush(&self) -> Result<(), Vec<(String, String)>> {
+        let mut results: AHashMap<String, crate::Result<()>> = AHashMap::new();
+
+        for (id, store) in &self.stores {
+            if let Entry::Vacant(entry) = results.entry(format!("store.{id}")) {
+                entry.insert(store.flush().await);
+            }
+        }
+        for (id, store) in &self.fts_stores {
+            if let Entry::Vacant(entry) = results.entry(format!("fts-store.{id}")) {
+                entry.insert(store.flush().await);
+            }
+        }
+        for (id, store) in &self.lookup_stores {
+            if let Entry::Vacant(entry) = results.entry(format!("lookup-store.{id}")) {
+                entry.insert(store.flush().await);
+            }
+        }
+
+        let mut errors = results
+            .into_iter()
+            .filter_map(|(id, result)| result.err().map(|err| (id, err.to_string())))
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            errors.sort_unstable();
+            Err(errors)
+        }
+    }
+}
+
+impl Store {
+    /// Performs the lightest possible round-trip to the backend and returns its latency.
+    pub async fn ping(&self) -> crate::Result<Duration> {
+        let start = Instant::now();
+        bounded(async {
+            match self {
+                #[cfg(feature = "sqlite")]
+                Self::SQLite(store) => store.ping().await,
+                #[cfg(feature = "foundation")]
+                Self::FoundationDb(store) => store.ping().await,
+                #[cfg(feature = "postgres")]
+                Self::PostgreSQL(store) => store.ping().await,
+                #[cfg(feature = "mysql")]
+                Self::MySQL(store) => store.ping().await,
+                // Embedded database, always reachable
+                #[cfg(feature = "rocks")]
+                Self::RocksDb(_) => Ok(()),
+            }
+        })
+        .await?;
+        Ok(start.elapsed())
+    }
+}
+
+impl BlobStore {
+    pub async fn ping(&self) -> crate::Result<Duration> {
+        let start = Instant::now();
+        bounded(async {
+            match self {
+                BlobStore::Store(store) => store.ping().await.map(|_| ()),
+                BlobStore::Fs(store) => store.ping().await,
+                #[cfg(feature = "s3")]
+                BlobStore::S3(store) => store.ping().await,
+            }
+        })
+        .await?;
+        Ok(start.elapsed())
+    }
+}
+
+impl FtsStore {
+    pub async fn flush(&self) -> crate::Result<()> {
+        match self {
+            FtsStore::Store(store) => store.flush().await,
+            #[cfg(feature = "elastic")]
+            FtsStore::ElasticSearch(store) => bounded(store.flush()).await,
+        }
+    }
+
+    pub async fn ping(&self) -> crate::Result<Duration> {
+        match self {
+            FtsStore::Store(store) => store.ping().await,
+            #[cfg(feature = "elastic")]
+            FtsStore::ElasticSearch(store) => {
+                let start = Instant::now();
+                bounded(store.ping()).await?;
+                Ok(start.elapsed())
+            }
+        }
+    }
+}
+
+impl LookupStore {
+    // Redis acknowledges writes once applied, and the in-memory and query
+    // stores hold nothing that needs to be persisted.
+    pub async fn flush(&self) -> crate::Result<()> {
+        match self {
+            LookupStore::Store(store) => store.flush().await,
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn ping(&self) -> crate::Result<Duration> {
+        let mut store = self;
+        while let LookupStore::Query(query) = store {
+            store = &query.store;
+        }
+
+        match store {
+            LookupStore::Store(store) => store.ping().await,
+            LookupStore::Query(_) | LookupStore::Memory(_) => Ok(Duration::ZERO),
+            #[cfg(feature = "redis")]
+            LookupStore::Redis(store) => {
+                let start = Instant::now();
+                bounded(store.ping()).await?;
+                Ok(start.elapsed())
+            }
+        }
+    }
+}
@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::{borrow::Cow, fmt::Display, sync::Arc};
+use std::{borrow::Cow, fmt::Display, sync::Arc, time::Duration};
 
 pub mod backend;
 pub mod config;
@@ -33,11 +33,13 @@ pub mod write;
 pub use ahash;
 use ahash::AHashMap;
 use backend::{fs::FsStore, memory::MemoryStore};
+use base64::{engine::general_purpose::STANDARD, Engine};
 pub use blake3;
 pub use parking_lot;
 pub use rand;
 pub use roaring;
-use write::{BitmapClass, ValueClass};
+use utils::map::lookup_cache::{LookupCache, ValueCache};
+use write::{AnyKey, BitmapClass, ValueClass};
 
 #[cfg(feature = "s3")]
 use backend::s3::S3Store;
@@ -67,6 +69,23 @@ pub trait Deserialize: Sized + Sync + Send {
     fn deserialize(bytes: &[u8]) -> crate::Result<Self>;
 }
 
+/// Deserializes a key as passed to the `Store::iterate` callback, without the subspace byte.
+pub trait DeserializeKey: Sized + Sync + Send {
+    fn deserialize_key(subspace: u8, bytes: &[u8]) -> crate::Result<Self>;
+}
+
+impl<T: Deserialize> DeserializeKey for T {
+    fn deserialize_key(_subspace: u8, bytes: &[u8]) -> crate::Result<Self> {
+        T::deserialize(bytes)
+    }
+}
+
+impl DeserializeKey for ParsedKey {
+    fn deserialize_key(subspace: u8, bytes: &[u8]) -> crate::Result<Self> {
+        ParsedKey::deserialize(subspace, bytes)
+    }
+}
+
 pub trait Serialize {
     fn serialize(self) -> Vec<u8>;
 }
@@ -78,6 +97,16 @@ pub(crate) const WITHOUT_BLOCK_NUM: u32 = 1 << 1;
 pub trait Key: Sync + Send {
     fn serialize(&self, flags: u32) -> Vec<u8>;
     fn subspace(&self) -> u8;
+
+    /// Account the key belongs to, used to give context to errors.
+    fn account_id(&self) -> Option<u32> {
+        None
+    }
+
+    /// Collection of a property value, used to decide whether it can be cached.
+    fn property_collection(&self) -> Option<u8> {
+        None
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -119,6 +148,16 @@ pub struct LogKey {
     pub change_id: u64,
 }
 
+// A serialized key parsed back into its typed form
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ParsedKey {
+    Bitmap(BitmapKey<BitmapClass>),
+    Value(ValueKey<ValueClass>),
+    Index(IndexKey<Vec<u8>>),
+    Log(LogKey),
+    Any(AnyKey<Vec<u8>>),
+}
+
 pub const BLOB_HASH_LEN: usize = 32;
 pub const U64_LEN: usize = std::mem::size_of::<u64>();
 pub const U32_LEN: usize = std::mem::size_of::<u32>();
@@ -154,6 +193,97 @@ impl Default for BlobClass {
 pub enum Error {
     InternalError(String),
     AssertValueFailed,
+    Timeout(String),
+    Unavailable(String),
+    NotFound(String),
+    ReadOnly,
+    StaleEpoch,
+    PartialCommit {
+        committed: usize,
+        total: usize,
+        reason: String,
+    },
+}
+
+/// Describes the store access that failed, without including any key or value contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub operation: &'static str,
+    pub subspace: Option<u8>,
+    pub account_id: Option<u32>,
+}
+
+impl ErrorContext {
+    pub fn new(operation: &'static str) -> Self {
+        ErrorContext {
+            operation,
+            subspace: None,
+            account_id: None,
+        }
+    }
+
+    pub fn for_key(operation: &'static str, key: &impl Key) -> Self {
+        ErrorContext {
+            operation,
+            subspace: Some(key.subspace()),
+            account_id: key.account_id(),
+        }
+    }
+
+    pub fn with_subspace(mut self, subspace: u8) -> Self {
+        self.subspace = Some(subspace);
+        self
+    }
+
+    pub fn with_account_id(mut self, account_id: Option<u32>) -> Self {
+        self.account_id = account_id;
+        self
+    }
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.operation)?;
+        if let Some(subspace) = self.subspace {
+            write!(f, " on subspace '{}'", char::from(subspace))?;
+        }
+        if let Some(account_id) = self.account_id {
+            write!(f, " for account {}", account_id)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error {
+    /// Prefixes the error message with the context of the failed operation.
+    /// Assertion failures, fencing and read-only rejections are returned unchanged as
+    /// they are not errors in the backend.
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            Error::InternalError(msg) => Error::InternalError(format!("{context}: {msg}")),
+            Error::Timeout(msg) => Error::Timeout(format!("{context}: {msg}")),
+            Error::Unavailable(msg) => Error::Unavailable(format!("{context}: {msg}")),
+            Error::NotFound(msg) => Error::NotFound(format!("{context}: {msg}")),
+            Error::AssertValueFailed => Error::AssertValueFailed,
+            Error::ReadOnly => Error::ReadOnly,
+            Error::StaleEpoch => Error::StaleEpoch,
+            Error::PartialCommit {
+                committed,
+                total,
+                reason,
+            } => Error::PartialCommit {
+                committed,
+                total,
+                reason: format!("{context}: {reason}"),
+            },
+        }
+    }
+
+    /// Returns true for failures that are likely to succeed if retried later,
+    /// such as timeouts or a backend that is temporarily down.
+    pub fn is_temporary(&self) -> bool {
+        matches!(self, Error::Timeout(_) | Error::Unavailable(_))
+    }
 }
 
 impl std::error::Error for Error {}
@@ -163,6 +293,20 @@ impl Display for Error {
         match self {
             Error::InternalError(msg) => write!(f, "Internal Error: {}", msg),
             Error::AssertValueFailed => write!(f, "Transaction failed: Hash mismatch"),
+            Error::Timeout(msg) => write!(f, "Timeout: {}", msg),
+            Error::Unavailable(msg) => write!(f, "Unavailable: {}", msg),
+            Error::NotFound(msg) => write!(f, "Not found: {}", msg),
+            Error::ReadOnly => write!(f, "Store is in read-only mode"),
+            Error::StaleEpoch => write!(f, "Write rejected: Stale fencing epoch"),
+            Error::PartialCommit {
+                committed,
+                total,
+                reason,
+            } => write!(
+                f,
+                "Batch partially written: {} of {} transactions committed: {}",
+                committed, total, reason
+            ),
         }
     }
 }
@@ -186,6 +330,13 @@ pub struct IterateParams<T: Key> {
     first: bool,
     ascending: bool,
     values: bool,
+    end_exclusive: bool,
+    max_results: Option<usize>,
+    max_bytes: Option<usize>,
+}
+
+pub struct IterateParamsBuilder<T: Key> {
+    params: IterateParams<T>,
 }
 
 #[derive(Clone, Default)]
@@ -194,6 +345,7 @@ pub struct Stores {
     pub blob_stores: AHashMap<String, BlobStore>,
     pub fts_stores: AHashMap<String, FtsStore>,
     pub lookup_stores: AHashMap<String, LookupStore>,
+    pub queries: AHashMap<String, Arc<QueryTemplate>>,
 }
 
 #[derive(Clone)]
@@ -210,6 +362,24 @@ pub enum Store {
     RocksDb(Arc<RocksDbStore>),
 }
 
+pub struct StoreSnapshot {
+    pub(crate) options: Arc<dispatch::options::StoreOptions>,
+    pub(crate) inner: SnapshotInner,
+}
+
+pub(crate) enum SnapshotInner {
+    #[cfg(feature = "sqlite")]
+    SQLite(backend::sqlite::snapshot::SqliteSnapshot),
+    #[cfg(feature = "foundation")]
+    FoundationDb(backend::foundationdb::snapshot::FdbSnapshot),
+    #[cfg(feature = "postgres")]
+    PostgreSQL(backend::postgres::snapshot::PostgresSnapshot),
+    #[cfg(feature = "mysql")]
+    MySQL(backend::mysql::snapshot::MysqlSnapshot),
+    #[cfg(feature = "rocks")]
+    RocksDb(backend::rocksdb::snapshot::RocksDbSnapshot),
+}
+
 #[derive(Clone)]
 pub enum BlobStore {
     Store(Store),
@@ -237,6 +407,25 @@ pub enum LookupStore {
 pub struct QueryStore {
     pub store: LookupStore,
     pub query: String,
+    pub cache: Option<QueryCache>,
+}
+
+/// A named query registered as `<store-id>/<query-id>`, with its number of
+/// parameters obtained from the SQL placeholders at config load.
+/// Statements are not prepared here: the SQLite, PostgreSQL and MySQL backends
+/// already cache prepared statements per connection, keyed by the query text,
+/// so each named query is prepared once per pooled connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryTemplate {
+    pub query: String,
+    pub num_params: usize,
+}
+
+/// Memoizes query results by lookup key, including lookups that returned no rows.
+pub struct QueryCache {
+    pub exists: parking_lot::Mutex<LookupCache<String>>,
+    pub values: parking_lot::Mutex<ValueCache<String, Option<Value<'static>>>>,
+    pub ttl_negative: Duration,
 }
 
 #[cfg(feature = "sqlite")]
@@ -338,6 +527,39 @@ pub enum LookupValue<T> {
     None,
 }
 
+impl<T> LookupValue<T> {
+    pub fn as_value(&self) -> Option<&T> {
+        match self {
+            LookupValue::Value { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn into_value(self) -> Option<T> {
+        match self {
+            LookupValue::Value { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn counter(&self) -> Option<i64> {
+        match self {
+            LookupValue::Counter { num } => Some(*num),
+            _ => None,
+        }
+    }
+
+    /// Returns true if this is a value whose expiration timestamp is not after `now`.
+    /// Values with `expires` set to zero do not expire.
+    pub fn is_expired(&self, now: u64) -> bool {
+        matches!(self, LookupValue::Value { expires, .. } if is_expired(*expires, now))
+    }
+}
+
+pub(crate) fn is_expired(expires: u64, now: u64) -> bool {
+    expires != 0 && expires <= now
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Value<'x> {
     Integer(i64),
@@ -345,6 +567,7 @@ pub enum Value<'x> {
     Float(f64),
     Text(Cow<'x, str>),
     Blob(Cow<'x, [u8]>),
+    Json(serde_json::Value),
     Null,
 }
 
@@ -358,18 +581,118 @@ impl<'x> Value<'x> {
             Value::Bool(b) => Cow::Owned(b.to_string()),
             Value::Float(f) => Cow::Owned(f.to_string()),
             Value::Blob(b) => String::from_utf8_lossy(b.as_ref()),
+            Value::Json(v) => Cow::Owned(v.to_string()),
             Value::Null => Cow::Borrowed(""),
         }
     }
+
+    pub fn as_json(&self) -> Option<&serde_json::Value> {
+        match self {
+            Value::Json(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the JSON value at the given RFC 6901 pointer, such as `/aliases/0`.
+    pub fn json_pointer(&self, pointer: &str) -> Option<&serde_json::Value> {
+        self.as_json()?.pointer(pointer)
+    }
+
+    /// Returns the strings contained in a JSON array, or the value itself
+    /// if it is a scalar.
+    pub fn json_strings(&self) -> Vec<Cow<'_, str>> {
+        match self {
+            Value::Json(serde_json::Value::Array(values)) => values
+                .iter()
+                .filter_map(|value| match value {
+                    serde_json::Value::String(s) => Some(Cow::Borrowed(s.as_str())),
+                    serde_json::Value::Null => None,
+                    value => Some(Cow::Owned(value.to_string())),
+                })
+                .collect(),
+            Value::Json(serde_json::Value::String(s)) => vec![Cow::Borrowed(s.as_str())],
+            Value::Null | Value::Json(serde_json::Value::Null) => vec![],
+            value => vec![value.to_str()],
+        }
+    }
+
+    pub fn into_owned(self) -> Value<'static> {
+        match self {
+            Value::Integer(i) => Value::Integer(i),
+            Value::Bool(b) => Value::Bool(b),
+            Value::Float(f) => Value::Float(f),
+            Value::Text(s) => Value::Text(s.into_owned().into()),
+            Value::Blob(b) => Value::Blob(b.into_owned().into()),
+            Value::Json(v) => Value::Json(v),
+            Value::Null => Value::Null,
+        }
+    }
+}
+
+// Namespaced keys are encoded as NAMESPACE_MARKER + namespace + NAMESPACE_MARKER + key.
+const NAMESPACE_MARKER: u8 = 0;
+
+impl LookupKey {
+    /// Builds a key prefixed with a namespace, so that keys used by different
+    /// subsystems sharing a lookup store do not collide.
+    pub fn namespaced(ns: &str, key: &[u8]) -> Self {
+        LookupKey::Key(namespaced_key(ns, key))
+    }
+
+    pub fn namespaced_counter(ns: &str, key: &[u8]) -> Self {
+        LookupKey::Counter(namespaced_key(ns, key))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            LookupKey::Key(key) | LookupKey::Counter(key) => key,
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            LookupKey::Key(key) | LookupKey::Counter(key) => key,
+        }
+    }
+
+    /// Returns the namespace and the key, if the key is namespaced.
+    pub fn namespace(&self) -> Option<(&str, &[u8])> {
+        let key = self.as_bytes().strip_prefix(&[NAMESPACE_MARKER])?;
+        let pos = key.iter().position(|&ch| ch == NAMESPACE_MARKER)?;
+        Some((std::str::from_utf8(&key[..pos]).ok()?, &key[pos + 1..]))
+    }
+}
+
+pub(crate) fn namespaced_key(ns: &str, key: &[u8]) -> Vec<u8> {
+    debug_assert!(!ns.as_bytes().contains(&NAMESPACE_MARKER));
+    let mut bytes = Vec::with_capacity(ns.len() + key.len() + 2);
+    bytes.push(NAMESPACE_MARKER);
+    bytes.extend_from_slice(ns.as_bytes());
+    bytes.push(NAMESPACE_MARKER);
+    bytes.extend_from_slice(key);
+    bytes
 }
 
 impl From<LookupKey> for String {
     fn from(value: LookupKey) -> Self {
-        let key = match value {
-            LookupKey::Key(key) | LookupKey::Counter(key) => key,
-        };
-        String::from_utf8(key)
-            .unwrap_or_else(|err| String::from_utf8_lossy(&err.into_bytes()).into_owned())
+        if let Some((ns, key)) = value.namespace() {
+            // Binary keys are hex encoded so distinct keys remain distinct
+            match std::str::from_utf8(key) {
+                Ok(key) => format!("{ns}:{key}"),
+                Err(_) => {
+                    let mut result = String::with_capacity(ns.len() + 3 + key.len() * 2);
+                    result.push_str(ns);
+                    result.push_str(":0x");
+                    for byte in key {
+                        result.push_str(&format!("{byte:02x}"));
+                    }
+                    result
+                }
+            }
+        } else {
+            String::from_utf8(value.into_bytes())
+                .unwrap_or_else(|err| String::from_utf8_lossy(&err.into_bytes()).into_owned())
+        }
     }
 }
 
@@ -389,6 +712,129 @@ pub struct NamedRows {
     pub rows: Vec<Row>,
 }
 
+impl From<Value<'_>> for serde_json::Value {
+    fn from(value: Value<'_>) -> Self {
+        match value {
+            Value::Integer(i) => serde_json::Value::Number(i.into()),
+            Value::Bool(b) => serde_json::Value::Bool(b),
+            // NaN and infinite values have no JSON representation
+            Value::Float(f) => serde_json::Number::from_f64(f)
+                .map_or(serde_json::Value::Null, serde_json::Value::Number),
+            Value::Text(s) => serde_json::Value::String(s.into_owned()),
+            Value::Blob(b) => serde_json::Value::String(STANDARD.encode(b)),
+            Value::Json(v) => v,
+            Value::Null => serde_json::Value::Null,
+        }
+    }
+}
+
+impl From<Row> for serde_json::Value {
+    fn from(row: Row) -> Self {
+        serde_json::Value::Array(row.values.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Converts each row into an object keyed by column name.
+impl From<NamedRows> for serde_json::Value {
+    fn from(rows: NamedRows) -> Self {
+        serde_json::Value::Array(
+            rows.rows
+                .into_iter()
+                .map(|row| {
+                    serde_json::Value::Object(
+                        rows.names
+                            .iter()
+                            .cloned()
+                            .zip(row.values.into_iter().map(Into::into))
+                            .collect(),
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+/// How binary values are rendered when exporting rows as text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlobEncoding {
+    #[default]
+    Base64,
+    Hex,
+}
+
+impl Rows {
+    /// Writes the rows as delimited text, one line per row.
+    pub fn to_csv(
+        &self,
+        writer: &mut impl std::io::Write,
+        delimiter: char,
+        blobs: BlobEncoding,
+    ) -> std::io::Result<()> {
+        write_csv_rows(writer, &self.rows, delimiter, blobs)
+    }
+}
+
+impl NamedRows {
+    /// Writes a header with the column names followed by the rows as delimited text.
+    pub fn to_csv(
+        &self,
+        writer: &mut impl std::io::Write,
+        delimiter: char,
+        blobs: BlobEncoding,
+    ) -> std::io::Result<()> {
+        write_csv_line(writer, self.names.iter().map(Cow::from), delimiter)?;
+        write_csv_rows(writer, &self.rows, delimiter, blobs)
+    }
+}
+
+fn write_csv_rows(
+    writer: &mut impl std::io::Write,
+    rows: &[Row],
+    delimiter: char,
+    blobs: BlobEncoding,
+) -> std::io::Result<()> {
+    for row in rows {
+        write_csv_line(
+            writer,
+            row.values.iter().map(|value| match (value, blobs) {
+                (Value::Blob(bytes), BlobEncoding::Base64) => Cow::Owned(STANDARD.encode(bytes)),
+                (Value::Blob(bytes), BlobEncoding::Hex) => Cow::Owned(
+                    bytes
+                        .iter()
+                        .map(|byte| format!("{byte:02x}"))
+                        .collect::<String>(),
+                ),
+                (value, _) => value.to_str(),
+            }),
+            delimiter,
+        )?;
+    }
+    Ok(())
+}
+
+// Fields containing the delimiter, quotes or line breaks are quoted as per RFC 4180
+fn write_csv_line<'x>(
+    writer: &mut impl std::io::Write,
+    fields: impl Iterator<Item = Cow<'x, str>>,
+    delimiter: char,
+) -> std::io::Result<()> {
+    let mut line = String::new();
+    for (pos, field) in fields.enumerate() {
+        if pos > 0 {
+            line.push(delimiter);
+        }
+        if field.contains([delimiter, '"', '\r', '\n']) {
+            line.push('"');
+            line.push_str(&field.replace('"', "\"\""));
+            line.push('"');
+        } else {
+            line.push_str(&field);
+        }
+    }
+    line.push_str("\r\n");
+    writer.write_all(line.as_bytes())
+}
+
 #[derive(Clone, Copy)]
 pub enum QueryType {
     Execute,
@@ -404,6 +850,9 @@ pub trait QueryResult: Sync + Send + 'static {
     fn from_query_all(items: impl IntoRows) -> Self;
 
     fn query_type() -> QueryType;
+
+    /// Number of rows returned or affected, as reported in statement logs.
+    fn row_count(&self) -> usize;
 }
 
 pub trait IntoRows {
@@ -432,6 +881,10 @@ impl QueryResult for Option<Row> {
     fn from_query_one(items: impl IntoRows) -> Self {
         items.into_row()
     }
+
+    fn row_count(&self) -> usize {
+        self.is_some() as usize
+    }
 }
 
 impl QueryResult for Rows {
@@ -454,6 +907,10 @@ impl QueryResult for Rows {
     fn from_query_one(_: impl IntoRows) -> Self {
         unreachable!()
     }
+
+    fn row_count(&self) -> usize {
+        self.rows.len()
+    }
 }
 
 impl QueryResult for NamedRows {
@@ -476,6 +933,10 @@ impl QueryResult for NamedRows {
     fn from_query_one(_: impl IntoRows) -> Self {
         unreachable!()
     }
+
+    fn row_count(&self) -> usize {
+        self.rows.len()
+    }
 }
 
 impl QueryResult for bool {
@@ -498,6 +959,10 @@ impl QueryResult for bool {
     fn from_query_one(_: impl IntoRows) -> Self {
         unreachable!()
     }
+
+    fn row_count(&self) -> usize {
+        *self as usize
+    }
 }
 
 impl QueryResult for usize {
@@ -520,6 +985,10 @@ impl QueryResult for usize {
     fn from_query_one(_: impl IntoRows) -> Self {
         unreachable!()
     }
+
+    fn row_count(&self) -> usize {
+        *self
+    }
 }
 
 impl<'x> From<&'x str> for Value<'x> {
@@ -588,6 +1057,12 @@ impl<'x> From<Vec<u8>> for Value<'x> {
     }
 }
 
+impl<'x> From<serde_json::Value> for Value<'x> {
+    fn from(value: serde_json::Value) -> Self {
+        Self::Json(value)
+    }
+}
+
 impl<'x> Value<'x> {
     pub fn into_string(self) -> String {
         match self {
@@ -596,6 +1071,7 @@ impl<'x> Value<'x> {
             Value::Bool(b) => b.to_string(),
             Value::Float(f) => f.to_string(),
             Value::Blob(b) => String::from_utf8_lossy(b.as_ref()).into_owned(),
+            Value::Json(v) => v.to_string(),
             Value::Null => String::new(),
         }
     }
@@ -628,7 +1104,16 @@ impl From<Rows> for Vec<String> {
         value
             .rows
             .into_iter()
-            .flat_map(|v| v.values.into_iter().map(|v| v.into_string()))
+            .flat_map(|v| v.values)
+            .flat_map(|v| match v {
+                // JSON arrays are expanded, for example a column holding a list of aliases
+                Value::Json(_) => v
+                    .json_strings()
+                    .into_iter()
+                    .map(Cow::into_owned)
+                    .collect::<Vec<_>>(),
+                v => vec![v.into_string()],
+            })
             .collect()
     }
 }
@@ -650,3 +1135,121 @@ impl From<Rows> for Vec<u32> {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{BlobEncoding, NamedRows, Row, Rows, Value};
+
+    #[test]
+    fn rows_to_json() {
+        let rows = NamedRows {
+            names: vec![
+                "name".into(),
+                "quota".into(),
+                "ratio".into(),
+                "key".into(),
+                "extra".into(),
+            ],
+            rows: vec![Row {
+                values: vec![
+                    Value::Text("john".into()),
+                    Value::Integer(1024),
+                    Value::Float(f64::NAN),
+                    Value::Blob(vec![0, 159, 146, 150].into()),
+                    Value::Null,
+                ],
+            }],
+        };
+
+        assert_eq!(
+            serde_json::Value::from(rows.clone()),
+            serde_json::json!([{
+                "name": "john",
+                "quota": 1024,
+                "ratio": null,
+                "key": "AJ+Slg==",
+                "extra": null
+            }])
+        );
+        assert_eq!(
+            serde_json::Value::from(rows.rows.into_iter().next().unwrap()),
+            serde_json::json!(["john", 1024, null, "AJ+Slg==", null])
+        );
+    }
+    #[test]
+    fn json_values() {
+        let value = Value::Json(serde_json::json!({
+            "aliases": ["john@example.org", "jdoe@example.org"],
+            "quota": 1024
+        }));
+        assert_eq!(
+            value.json_pointer("/aliases/1"),
+            Some(&serde_json::json!("jdoe@example.org"))
+        );
+        assert_eq!(value.json_pointer("/quota"), Some(&serde_json::json!(1024)));
+        assert_eq!(
+            value.to_str(),
+            r#"{"aliases":["john@example.org","jdoe@example.org"],"quota":1024}"#
+        );
+
+        let rows = Rows {
+            rows: vec![
+                Row {
+                    values: vec![Value::Json(serde_json::json!([
+                        "john@example.org",
+                        "jdoe@example.org"
+                    ]))],
+                },
+                Row {
+                    values: vec![Value::Text("sales@example.org".into())],
+                },
+            ],
+        };
+        assert_eq!(
+            Vec::<String>::from(rows),
+            vec!["john@example.org", "jdoe@example.org", "sales@example.org"]
+        );
+    }
+
+    #[test]
+    fn rows_to_csv() {
+        let rows = NamedRows {
+            names: vec!["name".into(), "description".into(), "key".into()],
+            rows: vec![
+                Row {
+                    values: vec![
+                        Value::Text("john".into()),
+                        Value::Text("Doe, \"John\"\nSales".into()),
+                        Value::Blob(vec![0, 159, 146, 150].into()),
+                    ],
+                },
+                Row {
+                    values: vec![Value::Text("jane".into()), Value::Integer(10), Value::Null],
+                },
+            ],
+        };
+
+        let mut csv = Vec::new();
+        rows.to_csv(&mut csv, ',', BlobEncoding::Base64).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            concat!(
+                "name,description,key\r\n",
+                "john,\"Doe, \"\"John\"\"\nSales\",AJ+Slg==\r\n",
+                "jane,10,\r\n"
+            )
+        );
+
+        let mut tsv = Vec::new();
+        Rows { rows: rows.rows }
+            .to_csv(&mut tsv, '\t', BlobEncoding::Hex)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(tsv).unwrap(),
+            concat!(
+                "john\t\"Doe, \"\"John\"\"\nSales\"\t009f9296\r\n",
+                "jane\t10\t\r\n"
+            )
+        );
+    }
+}
@@ -23,7 +23,13 @@
 
 use utils::codec::leb128::Leb128Iterator;
 
-use crate::{write::key::DeserializeBigEndian, Error, IterateParams, LogKey, Store, U64_LEN};
+use crate::{
+    write::{key::DeserializeBigEndian, log::LogFormat, BatchBuilder, ValueClass},
+    Error, IterateParams, LogKey, Store, ValueKey, U64_LEN,
+};
+
+// Stored in a reserved value key to record the format of the change log
+const LOG_FORMAT_PROPERTY: u8 = u8::MAX;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Change {
@@ -86,6 +92,7 @@ impl Store {
         };
 
         let mut changelog = Changes::default();
+        let log_format = self.log_format();
 
         self.iterate(
             IterateParams::new(from_key, to_key).ascending(),
@@ -96,12 +103,15 @@ impl Store {
                         changelog.from_change_id = change_id;
                     }
                     changelog.to_change_id = change_id;
-                    changelog.deserialize(value).ok_or_else(|| {
-                        Error::InternalError(format!(
-                            "Failed to deserialize changelog for [{}/{:?}]: [{:?}]",
-                            account_id, collection, query
-                        ))
-                    })?;
+                    log_format
+                        .decode(value)
+                        .and_then(|value| changelog.deserialize(&value))
+                        .ok_or_else(|| {
+                            Error::InternalError(format!(
+                                "Failed to deserialize changelog for [{}/{:?}]: [{:?}]",
+                                account_id, collection, query
+                            ))
+                        })?;
                 }
                 Ok(true)
             },
@@ -154,6 +164,68 @@ impl Store {
 
         Ok(last_change_id)
     }
+
+    /// Makes sure the configured log format matches the one the change log was
+    /// written with, recording the format when a non-native one is first used.
+    pub async fn check_log_format(&self) -> crate::Result<()> {
+        let key = ValueKey {
+            account_id: u32::MAX,
+            collection: u8::MAX,
+            document_id: u32::MAX,
+            class: ValueClass::Property(LOG_FORMAT_PROPERTY),
+        };
+        let log_format = self.log_format();
+
+        match self.get_value::<String>(key.clone()).await? {
+            Some(stored) if stored == log_format.as_str() => Ok(()),
+            Some(stored) => Err(Error::InternalError(format!(
+                "Change log was written using the {stored:?} format but {log_format:?} is configured"
+            ))),
+            None if log_format == LogFormat::Leb128 => Ok(()),
+            None => {
+                // Existing entries were written in the native format
+                let mut has_entries = false;
+                self.iterate(
+                    IterateParams::new(
+                        LogKey {
+                            account_id: 0,
+                            collection: 0,
+                            change_id: 0,
+                        },
+                        LogKey {
+                            account_id: u32::MAX,
+                            collection: u8::MAX,
+                            change_id: u64::MAX,
+                        },
+                    )
+                    .no_values()
+                    .only_first(),
+                    |_, _| {
+                        has_entries = true;
+                        Ok(false)
+                    },
+                )
+                .await?;
+
+                if has_entries {
+                    Err(Error::InternalError(format!(
+                        "Change log already contains entries in the {:?} format, {log_format:?} cannot be used",
+                        LogFormat::Leb128.as_str()
+                    )))
+                } else if !self.is_read_only() {
+                    let mut batch = BatchBuilder::new();
+                    batch
+                        .with_account_id(u32::MAX)
+                        .with_collection(u8::MAX)
+                        .update_document(u32::MAX)
+                        .set(key.class, log_format.as_str().as_bytes().to_vec());
+                    self.write(batch.build()).await
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
 }
 
 impl Changes {
@@ -29,8 +29,8 @@ pub mod sort;
 use roaring::RoaringBitmap;
 
 use crate::{
-    write::{BitmapClass, BitmapHash, TagValue},
-    BitmapKey, IterateParams, Key, Serialize,
+    write::{bitmap::BITS_PER_BLOCK_L, AnyKey, BitmapClass, BitmapHash, TagValue},
+    BitmapKey, IterateParams, IterateParamsBuilder, Key, Serialize,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -200,6 +200,13 @@ impl Comparator {
 }
 
 impl BitmapKey<BitmapClass> {
+    /// Returns the block a document id belongs to in backends that store bitmaps
+    /// as fixed size dense blocks. All block computations must go through here.
+    #[inline(always)]
+    pub fn block_for(document_id: u32) -> u32 {
+        document_id / BITS_PER_BLOCK_L
+    }
+
     pub fn document_ids(account_id: u32, collection: impl Into<u8>) -> Self {
         BitmapKey {
             account_id,
@@ -252,6 +259,16 @@ impl<T: Key> IterateParams<T> {
             first: false,
             ascending: true,
             values: true,
+            end_exclusive: false,
+            max_results: None,
+            max_bytes: None,
+        }
+    }
+
+    /// Starts building a scan over all keys in `[begin, end]`.
+    pub fn range(begin: T, end: T) -> IterateParamsBuilder<T> {
+        IterateParamsBuilder {
+            params: IterateParams::new(begin, end),
         }
     }
 
@@ -284,4 +301,87 @@ impl<T: Key> IterateParams<T> {
         self.values = false;
         self
     }
+
+    /// Fails the iteration once more than `max_results` keys have been returned.
+    pub fn set_max_results(mut self, max_results: usize) -> Self {
+        self.max_results = Some(max_results);
+        self
+    }
+
+    /// Fails the iteration once the returned keys and values add up to more than `max_bytes`.
+    pub fn set_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+}
+
+impl IterateParams<AnyKey<Vec<u8>>> {
+    /// Starts building a scan over all keys that start with the serialized `key`.
+    /// The end of the range is the prefix's successor, which is excluded from the results.
+    pub fn prefix(key: impl Key) -> IterateParamsBuilder<AnyKey<Vec<u8>>> {
+        let subspace = key.subspace();
+        let prefix = key.serialize(0);
+
+        let mut end = prefix.clone();
+        while end.last() == Some(&u8::MAX) {
+            end.pop();
+        }
+        let params = if let Some(last) = end.last_mut() {
+            *last += 1;
+            IterateParams {
+                end_exclusive: true,
+                ..IterateParams::new(
+                    AnyKey {
+                        subspace,
+                        key: prefix,
+                    },
+                    AnyKey { subspace, key: end },
+                )
+            }
+        } else {
+            // The prefix has no successor, scan up to the largest possible key
+            let mut end = prefix.clone();
+            end.extend_from_slice(&[u8::MAX; 32]);
+            IterateParams::new(
+                AnyKey {
+                    subspace,
+                    key: prefix,
+                },
+                AnyKey { subspace, key: end },
+            )
+        };
+
+        IterateParamsBuilder { params }
+    }
+}
+
+impl<T: Key> IterateParamsBuilder<T> {
+    pub fn ascending(mut self, ascending: bool) -> Self {
+        self.params.ascending = ascending;
+        self
+    }
+
+    pub fn values(mut self, values: bool) -> Self {
+        self.params.values = values;
+        self
+    }
+
+    pub fn first(mut self, first: bool) -> Self {
+        self.params.first = first;
+        self
+    }
+
+    pub fn max_results(mut self, max_results: usize) -> Self {
+        self.params.max_results = Some(max_results);
+        self
+    }
+
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.params.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn build(self) -> IterateParams<T> {
+        self.params
+    }
 }
@@ -23,6 +23,8 @@
 
 use crate::{Deserialize, U32_LEN, U64_LEN};
 
+use super::compression::{decompress, is_compressed};
+
 #[derive(Debug, Clone)]
 pub struct HashedValue<T: Deserialize> {
     pub hash: u64,
@@ -34,6 +36,8 @@ pub enum AssertValue {
     U32(u32),
     U64(u64),
     Hash(u64),
+    // Compared against the counters subspace, a missing counter is zero
+    Counter(i64),
     Some,
     None,
 }
@@ -89,7 +93,17 @@ impl AssertValue {
         match self {
             AssertValue::U32(v) => bytes.len() == U32_LEN && u32::deserialize(bytes).unwrap() == *v,
             AssertValue::U64(v) => bytes.len() == U64_LEN && u64::deserialize(bytes).unwrap() == *v,
-            AssertValue::Hash(v) => xxhash_rust::xxh3::xxh3_64(bytes) == *v,
+            AssertValue::Hash(v) => {
+                // Hashes are computed over the uncompressed value. The collection is not
+                // known here, so values that only look compressed are matched as is.
+                xxhash_rust::xxh3::xxh3_64(bytes) == *v
+                    || (is_compressed(bytes)
+                        && decompress(bytes)
+                            .map_or(false, |bytes| xxhash_rust::xxh3::xxh3_64(&bytes) == *v))
+            }
+            AssertValue::Counter(v) => {
+                bytes.len() == U64_LEN && i64::from_le_bytes(bytes.try_into().unwrap()) == *v
+            }
             AssertValue::None => false,
             AssertValue::Some => true,
         }
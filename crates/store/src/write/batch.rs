@@ -21,11 +21,19 @@
  * for more details.
 */
 
+use crate::{U32_LEN, U64_LEN};
+
 use super::{
-    assert::ToAssertValue, Batch, BatchBuilder, BitmapClass, HasFlag, IntoOperations, Operation,
-    Serialize, TagValue, ToBitmaps, ValueClass, ValueOp, F_BITMAP, F_CLEAR, F_INDEX, F_VALUE,
+    assert::{AssertValue, ToAssertValue},
+    Batch, BatchBuilder, BitmapClass, BlobOp, HasFlag, IntoOperations, Operation, Serialize,
+    TagValue, ToBitmaps, ValueClass, ValueOp, F_BITMAP, F_CLEAR, F_INDEX, F_VALUE,
 };
 
+// Subspace, account id, collection and document id
+const KEY_OVERHEAD: usize = U32_LEN * 2 + 2;
+// Bitmap key plus one dense bitmap block
+const BITMAP_OP_SIZE: usize = KEY_OVERHEAD + 16 + 128;
+
 impl BatchBuilder {
     pub fn new() -> Self {
         Self {
@@ -89,6 +97,16 @@ impl BatchBuilder {
         self
     }
 
+    /// Rejects the batch with `Error::StaleEpoch` unless the current account's fencing
+    /// epoch is still `epoch`, see `Store::advance_fence`.
+    pub fn fence(&mut self, epoch: u64) -> &mut Self {
+        if epoch > 0 {
+            self.assert_value(ValueClass::Fence, epoch)
+        } else {
+            self.assert_value(ValueClass::Fence, ())
+        }
+    }
+
     pub fn value(
         &mut self,
         field: impl Into<u8>,
@@ -151,21 +169,45 @@ impl BatchBuilder {
     }
 
     pub fn set(&mut self, class: impl Into<ValueClass>, value: impl Into<Vec<u8>>) -> &mut Self {
+        let class = class.into();
+        self.blob_ref_count(&class, 1);
         self.ops.push(Operation::Value {
-            class: class.into(),
+            class,
             op: ValueOp::Set(value.into()),
         });
         self
     }
 
     pub fn clear(&mut self, class: impl Into<ValueClass>) -> &mut Self {
+        let class = class.into();
+        self.blob_ref_count(&class, -1);
         self.ops.push(Operation::Value {
-            class: class.into(),
+            class,
             op: ValueOp::Clear,
         });
         self
     }
 
+    // Linking or unlinking a blob updates its reference count atomically
+    // in the same transaction as the link itself. The link must be absent when
+    // linking and present when unlinking, so that only real transitions are counted.
+    fn blob_ref_count(&mut self, class: &ValueClass, by: i64) {
+        if let ValueClass::Blob(BlobOp::Link { hash }) = class {
+            self.ops.push(Operation::AssertValue {
+                class: class.clone(),
+                assert_value: if by > 0 {
+                    AssertValue::None
+                } else {
+                    AssertValue::Some
+                },
+            });
+            self.ops.push(Operation::Value {
+                class: ValueClass::Blob(BlobOp::RefCount { hash: hash.clone() }),
+                op: ValueOp::Add(by),
+            });
+        }
+    }
+
     pub fn custom(&mut self, value: impl IntoOperations) -> &mut Self {
         value.build(self);
         self
@@ -222,6 +264,127 @@ impl Batch {
             _ => None,
         })
     }
+
+    /// Approximate number of bytes written by the batch.
+    pub fn estimated_size(&self) -> usize {
+        self.ops.iter().map(Operation::estimated_size).sum()
+    }
+
+    /// Splits the batch into batches of at most `max_size` estimated bytes.
+    /// Batches are only split between documents, so the operations of a single
+    /// document are still written together, and the account, collection and
+    /// document ids in effect are repeated at the start of each new batch.
+    /// Batches containing assertions or id reservations would lose their guarantees
+    /// if split and fail instead, as do documents that do not fit within `max_size`
+    /// on their own.
+    pub fn split(self, max_size: usize) -> crate::Result<Vec<Batch>> {
+        if self.estimated_size() <= max_size {
+            return Ok(vec![self]);
+        } else if !self.is_atomic() {
+            return Err(crate::Error::InternalError(
+                "Batch exceeds the maximum transaction size and contains assertions, it cannot be split.".into(),
+            ));
+        }
+
+        let mut batches = Vec::new();
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        let mut segment = Vec::new();
+        let mut segment_size = 0;
+        let mut account_id = None;
+        let mut collection = None;
+        let mut document_id = None;
+        let mut ops = self.ops.into_iter();
+
+        loop {
+            let op = ops.next();
+
+            // Each segment starts at an account, collection or document change
+            if !segment.is_empty()
+                && op.as_ref().map_or(true, |op| {
+                    matches!(
+                        op,
+                        Operation::AccountId { .. }
+                            | Operation::Collection { .. }
+                            | Operation::DocumentId { .. }
+                    )
+                })
+            {
+                if segment_size > max_size {
+                    return Err(crate::Error::InternalError(format!(
+                        "Document {} exceeds the maximum transaction size ({} > {} bytes).",
+                        document_id.unwrap_or(u32::MAX),
+                        segment_size,
+                        max_size
+                    )));
+                } else if batch_size + segment_size > max_size && batch_size > 0 {
+                    batches.push(Batch {
+                        ops: std::mem::take(&mut batch),
+                    });
+                    batch_size = 0;
+
+                    if let Some(account_id) = account_id {
+                        batch.push(Operation::AccountId { account_id });
+                    }
+                    if let Some(collection) = collection {
+                        batch.push(Operation::Collection { collection });
+                    }
+                    if let Some(document_id) = document_id {
+                        batch.push(Operation::DocumentId { document_id });
+                    }
+                }
+
+                for op in &segment {
+                    match op {
+                        Operation::AccountId { account_id: id } => account_id = Some(*id),
+                        Operation::Collection { collection: id } => collection = Some(*id),
+                        Operation::DocumentId { document_id: id } => document_id = Some(*id),
+                        _ => {}
+                    }
+                }
+                batch.append(&mut segment);
+                batch_size += segment_size;
+                segment_size = 0;
+            }
+
+            if let Some(op) = op {
+                segment_size += op.estimated_size();
+                segment.push(op);
+            } else {
+                break;
+            }
+        }
+
+        if !batch.is_empty() {
+            batches.push(Batch { ops: batch });
+        }
+
+        Ok(batches)
+    }
+}
+
+impl Operation {
+    /// Approximate number of bytes the operation adds to a transaction.
+    pub fn estimated_size(&self) -> usize {
+        match self {
+            Operation::AccountId { .. }
+            | Operation::Collection { .. }
+            | Operation::DocumentId { .. }
+            | Operation::AssertValue { .. } => 0,
+            Operation::Value { class, op } => {
+                KEY_OVERHEAD
+                    + class.serialized_size()
+                    + match op {
+                        ValueOp::Set(value) => value.len(),
+                        ValueOp::Add(_) => U64_LEN,
+                        ValueOp::Clear => 0,
+                    }
+            }
+            Operation::Index { key, .. } => KEY_OVERHEAD + key.len() + 1,
+            Operation::Bitmap { .. } => BITMAP_OP_SIZE,
+            Operation::Log { set, .. } => KEY_OVERHEAD + U64_LEN + set.len(),
+        }
+    }
 }
 
 impl Default for BatchBuilder {
@@ -229,3 +392,102 @@ impl Default for BatchBuilder {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::write::{Batch, BatchBuilder, BitmapClass, Operation, ValueClass};
+
+    fn build_batch(num_documents: u32) -> Batch {
+        let mut batch = BatchBuilder::new();
+        batch.with_account_id(1).with_collection(2);
+        for document_id in 0..num_documents {
+            batch
+                .create_document(document_id)
+                .set(ValueClass::Property(0), vec![0u8; 1000])
+                .set(ValueClass::Property(1), vec![0u8; 1000]);
+        }
+        batch.build()
+    }
+
+    #[test]
+    fn split_batch() {
+        // Small batches are returned unchanged
+        assert_eq!(build_batch(10).split(usize::MAX).unwrap().len(), 1);
+
+        let batch = build_batch(10);
+        let num_ops = batch.ops.len();
+        let max_size = batch.estimated_size() / 3;
+        let batches = batch.split(max_size).unwrap();
+        assert!(batches.len() > 1);
+
+        let mut num_documents = 0;
+        for batch in &batches {
+            assert!(batch.estimated_size() <= max_size);
+
+            // Every batch carries its account and collection, documents are not split
+            assert_eq!(batch.ops[0], Operation::AccountId { account_id: 1 });
+            assert_eq!(batch.ops[1], Operation::Collection { collection: 2 });
+            let created = batch
+                .ops
+                .iter()
+                .filter(|op| {
+                    matches!(
+                        op,
+                        Operation::Bitmap {
+                            class: BitmapClass::DocumentIds,
+                            ..
+                        }
+                    )
+                })
+                .count();
+            let values = batch
+                .ops
+                .iter()
+                .filter(|op| {
+                    matches!(
+                        op,
+                        Operation::Value {
+                            class: ValueClass::Property(_),
+                            ..
+                        }
+                    )
+                })
+                .count();
+            assert_eq!(values, created * 2);
+            num_documents += created;
+        }
+        assert_eq!(num_documents, 10);
+
+        // Account, collection and document ids are repeated in each new batch
+        assert_eq!(
+            batches.iter().map(|batch| batch.ops.len()).sum::<usize>(),
+            num_ops + (batches.len() - 1) * 3
+        );
+
+        // Documents larger than the limit and oversized assertions fail
+        assert!(build_batch(1).split(100).is_err());
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(1)
+            .with_collection(2)
+            .update_document(0)
+            .assert_value(ValueClass::Property(0), 0u64)
+            .set(ValueClass::Property(0), vec![0u8; 1000])
+            .update_document(1)
+            .set(ValueClass::Property(0), vec![0u8; 1000]);
+        assert!(batch.build().split(1500).is_err());
+
+        // Id reservations are checked on write and cannot be split either
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(1)
+            .with_collection(2)
+            .update_document(0)
+            .set(ValueClass::ReservedId, vec![0u8; 8])
+            .set(ValueClass::Property(0), vec![0u8; 1000])
+            .update_document(1)
+            .set(ValueClass::Property(0), vec![0u8; 1000]);
+        assert!(batch.build().split(1500).is_err());
+    }
+}
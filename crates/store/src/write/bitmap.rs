@@ -24,7 +24,7 @@
 use ahash::AHashSet;
 use roaring::RoaringBitmap;
 
-use crate::U64_LEN;
+use crate::{write::BitmapClass, BitmapKey, U64_LEN};
 
 pub const WORD_SIZE_BITS_L: u32 = (WORD_SIZE_L * 8) as u32;
 pub const WORD_SIZE_L: usize = std::mem::size_of::<u128>();
@@ -40,33 +40,54 @@ pub const BITS_MASK_S: u32 = BITS_PER_BLOCK_S - 1;
 
 pub struct DenseBitmap {
     pub bitmap: [u8; WORD_SIZE_L * WORDS_PER_BLOCK_L as usize],
+    block_num: Option<u32>,
 }
 
 impl DenseBitmap {
     pub fn empty() -> Self {
         Self {
             bitmap: [0; WORD_SIZE_L * WORDS_PER_BLOCK_L as usize],
+            block_num: None,
         }
     }
 
     pub fn full() -> Self {
         Self {
             bitmap: [u8::MAX; WORD_SIZE_L * WORDS_PER_BLOCK_L as usize],
+            block_num: None,
+        }
+    }
+
+    /// Empty bitmap for the block stored under a key, setting or clearing
+    /// a document id that belongs to a different block is a bug.
+    pub fn for_block(block_num: u32) -> Self {
+        Self {
+            bitmap: [0; WORD_SIZE_L * WORDS_PER_BLOCK_L as usize],
+            block_num: Some(block_num),
         }
     }
 
     pub fn set(&mut self, index: u32) {
+        self.debug_assert_block(index);
         let index = index & BITS_MASK_L;
         self.bitmap[(index / 8) as usize] |= 1 << (index & 7);
     }
 
     pub fn clear(&mut self, index: u32) {
+        self.debug_assert_block(index);
         let index = index & BITS_MASK_L;
         self.bitmap[(index / 8) as usize] &= !(1 << (index & 7));
     }
 
-    pub fn block_num(index: u32) -> u32 {
-        index / BITS_PER_BLOCK_L
+    #[inline(always)]
+    fn debug_assert_block(&self, index: u32) {
+        if let Some(block_num) = self.block_num {
+            debug_assert_eq!(
+                BitmapKey::block_for(index),
+                block_num,
+                "document id {index} does not belong to bitmap block {block_num}"
+            );
+        }
     }
 }
 
@@ -189,9 +210,10 @@ mod tests {
             let mut bitmap = RoaringBitmap::new();
             for item in range {
                 bitmap.insert(item);
+                let block_num = BitmapKey::block_for(item);
                 blocks
-                    .entry(item / BITS_PER_BLOCK_L)
-                    .or_insert_with(DenseBitmap::empty)
+                    .entry(block_num)
+                    .or_insert_with(|| DenseBitmap::for_block(block_num))
                     .set(item);
             }
             let mut bitmap_blocks = RoaringBitmap::new();
@@ -203,6 +225,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn block_boundaries() {
+        for document_id in [
+            0,
+            1,
+            BITS_PER_BLOCK_L - 1,
+            BITS_PER_BLOCK_L,
+            BITS_PER_BLOCK_L + 1,
+            BITS_PER_BLOCK_L * 2 - 1,
+            BITS_PER_BLOCK_L * 2,
+            u32::MAX,
+        ] {
+            // The block and the offset within it must add up to the document id
+            let block_num = BitmapKey::block_for(document_id);
+            assert_eq!(
+                block_num * BITS_PER_BLOCK_L + (document_id & BITS_MASK_L),
+                document_id
+            );
+
+            // Ids on either side of a boundary belong to adjacent blocks
+            if document_id & BITS_MASK_L == 0 && document_id > 0 {
+                assert_eq!(BitmapKey::block_for(document_id - 1), block_num - 1);
+            } else if document_id & BITS_MASK_L == BITS_MASK_L && document_id < u32::MAX {
+                assert_eq!(BitmapKey::block_for(document_id + 1), block_num + 1);
+            }
+
+            // Reading the block back yields the same document id
+            let mut bm = DenseBitmap::for_block(block_num);
+            bm.set(document_id);
+            let mut bitmap = RoaringBitmap::new();
+            bitmap.deserialize_block(&bm.bitmap, block_num);
+            assert_eq!(bitmap.iter().collect::<Vec<_>>(), vec![document_id]);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn set_outside_block() {
+        // A block keyed by a stale or miscomputed block number
+        let mut bm = DenseBitmap::for_block(0);
+        bm.set(BITS_PER_BLOCK_L - 1);
+        bm.set(BITS_PER_BLOCK_L);
+    }
+
     #[test]
     fn get_next_available_index() {
         let eh = AHashSet::new();
@@ -24,11 +24,13 @@
 use ahash::AHashSet;
 
 use crate::{
-    write::BatchBuilder, BlobClass, BlobHash, BlobStore, Deserialize, IterateParams, Store,
-    ValueKey, BLOB_HASH_LEN, U32_LEN, U64_LEN,
+    write::BatchBuilder, AnyKey, BlobClass, BlobHash, BlobStore, Deserialize, IterateParams,
+    Serialize, Store, ValueKey, BLOB_HASH_LEN, SUBSPACE_BLOBS, U32_LEN, U64_LEN,
 };
 
-use super::{key::DeserializeBigEndian, now, BlobOp, Operation, ValueClass, ValueOp};
+use super::{
+    assert::AssertValue, key::DeserializeBigEndian, now, BlobOp, Operation, ValueClass, ValueOp,
+};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct BlobQuota {
@@ -36,6 +38,17 @@ pub struct BlobQuota {
     pub count: usize,
 }
 
+// Reservations that expired less than this many seconds ago still protect
+// their blob from garbage collection, covering uploads that are in flight.
+pub const BLOB_GC_GRACE_PERIOD: u64 = 60 * 60;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub scanned: usize,
+    pub deleted: usize,
+    pub skipped: usize,
+}
+
 impl Store {
     pub async fn blob_exists(
         &self,
@@ -53,6 +66,21 @@ impl Store {
         .map(|v| v.is_some())
     }
 
+    pub async fn blob_ref_count(
+        &self,
+        hash: impl AsRef<BlobHash> + Sync + Send,
+    ) -> crate::Result<i64> {
+        self.get_counter(ValueKey {
+            account_id: 0,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Blob(BlobOp::RefCount {
+                hash: hash.as_ref().clone(),
+            }),
+        })
+        .await
+    }
+
     pub async fn blob_quota(&self, account_id: u32) -> crate::Result<BlobQuota> {
         let from_key = ValueKey {
             account_id,
@@ -218,7 +246,7 @@ impl Store {
                         last_hash = hash;
                     }
                 } else if last_hash != hash && !active_hashes.contains(&hash) {
-                    // Unlinked or expired blob, delete.
+                    // Unlinked or expired blob, delete once its reference count drops to zero.
                     delete_keys.push(ValueKey {
                         account_id: 0,
                         collection: 0,
@@ -232,14 +260,21 @@ impl Store {
         )
         .await?;
 
-        // Delete expired or unlinked blobs
-        for key in &delete_keys {
+        // Delete expired or unlinked blobs, the blob itself is deleted only once its
+        // commit was released.
+        let mut delete_keys_ = Vec::with_capacity(delete_keys.len());
+        for key in delete_keys {
             if let ValueClass::Blob(BlobOp::Commit { hash }) = &key.class {
-                blob_store.delete_blob(hash.as_ref()).await?;
+                if self.release_blob(hash).await? {
+                    blob_store.delete_blob(hash.as_ref()).await?;
+                }
+            } else {
+                delete_keys_.push(key);
             }
         }
+        let delete_keys = delete_keys_;
 
-        // Delete hashes
+        // Delete expired reservations
         let mut batch = BatchBuilder::new();
         let mut last_account_id = u32::MAX;
         for key in delete_keys.into_iter() {
@@ -266,6 +301,48 @@ impl Store {
         Ok(())
     }
 
+    /// Clears the commit of an unreferenced blob in a batch that asserts its reference
+    /// count did not change since it was read, so links or copies added concurrently keep
+    /// the blob alive. Returns `true` if the blob bytes can be deleted.
+    async fn release_blob(&self, hash: &BlobHash) -> crate::Result<bool> {
+        let ref_count = self.blob_ref_count(hash).await?;
+        if ref_count > 0 {
+            tracing::debug!(
+                context = "blob_purge",
+                event = "skip",
+                hash = ?hash,
+                ref_count = ref_count,
+                "Blob has no links but a positive reference count, skipping."
+            );
+            return Ok(false);
+        }
+
+        let ref_count_class = ValueClass::Blob(BlobOp::RefCount { hash: hash.clone() });
+        let mut batch = BatchBuilder::new();
+        batch.assert_value(ref_count_class.clone(), AssertValue::Counter(ref_count));
+        if ref_count < 0 {
+            // Reset negative reference counts left over by unbalanced unlinks
+            batch.add(ref_count_class, -ref_count);
+        }
+        batch.ops.push(Operation::Value {
+            class: ValueClass::Blob(BlobOp::Commit { hash: hash.clone() }),
+            op: ValueOp::Clear,
+        });
+        match self.write(batch.build()).await {
+            Ok(_) => Ok(true),
+            Err(crate::Error::AssertValueFailed) => {
+                tracing::debug!(
+                    context = "blob_purge",
+                    event = "skip",
+                    hash = ?hash,
+                    "Blob was linked while purging, skipping."
+                );
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     pub async fn blob_hash_unlink_account(&self, account_id: u32) -> crate::Result<()> {
         // Validate linked blobs
         let from_key = ValueKey {
@@ -331,10 +408,7 @@ impl Store {
                 last_collection = key.collection;
             }
             batch.update_document(key.document_id);
-            batch.ops.push(Operation::Value {
-                class: key.class,
-                op: ValueOp::Clear,
-            });
+            batch.clear(key.class);
         }
         if !batch.is_empty() {
             self.write(batch.build()).await?;
@@ -343,3 +417,250 @@ impl Store {
         Ok(())
     }
 }
+
+impl BlobStore {
+    /// Deletes unlinked blobs, including the ones written to the blob store but never
+    /// committed. Runs after `Store::purge_blobs` on each scheduled blob purge.
+    pub async fn gc(&self, store: &Store) -> crate::Result<GcStats> {
+        let mut stats = GcStats::default();
+
+        // Obtain the hashes referenced by a link or a commit
+        let mut linked_hashes = AHashSet::new();
+        let mut committed_hashes = AHashSet::new();
+        store
+            .iterate(
+                IterateParams::new(
+                    ValueKey {
+                        account_id: 0,
+                        collection: 0,
+                        document_id: 0,
+                        class: ValueClass::Blob(BlobOp::Link {
+                            hash: BlobHash::default(),
+                        }),
+                    },
+                    ValueKey {
+                        account_id: u32::MAX,
+                        collection: u8::MAX,
+                        document_id: u32::MAX,
+                        class: ValueClass::Blob(BlobOp::Link {
+                            hash: BlobHash::new_max(),
+                        }),
+                    },
+                )
+                .no_values(),
+                |key, _| {
+                    let hash = hash_from_key(key, 1)?;
+                    if key.deserialize_be_u32(key.len() - U32_LEN)? != u32::MAX {
+                        linked_hashes.insert(hash);
+                    } else {
+                        committed_hashes.insert(hash);
+                    }
+                    Ok(true)
+                },
+            )
+            .await?;
+
+        // Unlinked blobs that have been committed are candidates for deletion
+        stats.scanned = committed_hashes.len();
+        let mut candidates = committed_hashes
+            .iter()
+            .filter(|hash| !linked_hashes.contains(*hash))
+            .cloned()
+            .collect::<AHashSet<_>>();
+
+        // Blobs kept in the data store can be listed, which also finds
+        // orphaned blobs that were written but never committed.
+        if let BlobStore::Store(blob_store) = self {
+            let mut last_hash = BlobHash::default();
+            blob_store
+                .iterate(
+                    IterateParams::new(
+                        AnyKey {
+                            subspace: SUBSPACE_BLOBS,
+                            key: vec![0u8],
+                        },
+                        AnyKey {
+                            subspace: SUBSPACE_BLOBS,
+                            key: vec![u8::MAX; BLOB_HASH_LEN + U32_LEN],
+                        },
+                    )
+                    .no_values(),
+                    |key, _| {
+                        // Some backends split blobs in chunks keyed by hash + chunk number
+                        let hash = hash_from_key(key, 0)?;
+                        if hash != last_hash {
+                            if !committed_hashes.contains(&hash) {
+                                stats.scanned += 1;
+                            }
+                            if !linked_hashes.contains(&hash) {
+                                candidates.insert(hash.clone());
+                            }
+                            last_hash = hash;
+                        }
+                        Ok(true)
+                    },
+                )
+                .await?;
+        }
+        if candidates.is_empty() {
+            return Ok(stats);
+        }
+
+        // Re-read the reservations right before deleting, so that uploads started
+        // during the scan are not affected.
+        let active_hashes = store.blob_reserved_hashes(BLOB_GC_GRACE_PERIOD).await?;
+        for hash in candidates {
+            if active_hashes.contains(&hash)
+                || store.blob_is_linked(&hash).await?
+                || !store.release_blob(&hash).await?
+            {
+                stats.skipped += 1;
+                continue;
+            }
+
+            self.delete_blob(hash.as_ref()).await?;
+            stats.deleted += 1;
+        }
+
+        tracing::debug!(
+            context = "blob_gc",
+            event = "finish",
+            scanned = stats.scanned,
+            deleted = stats.deleted,
+            skipped = stats.skipped,
+            "Blob garbage collection completed."
+        );
+
+        Ok(stats)
+    }
+
+    // Blobs are content-addressed, so a copy only needs a new reference to the
+    // existing hash. No bytes are transferred regardless of the backend.
+    pub async fn copy(
+        &self,
+        store: &Store,
+        hash: &BlobHash,
+        new_link: BlobClass,
+    ) -> crate::Result<()> {
+        if self.get_blob(hash.as_ref(), 0..1).await?.is_none() {
+            return Err(crate::Error::NotFound(format!(
+                "Blob {hash:?} does not exist."
+            )));
+        }
+
+        let mut batch = BatchBuilder::new();
+        match new_link {
+            BlobClass::Reserved {
+                account_id,
+                expires,
+            } => {
+                // Copies do not count towards the quota of the destination account
+                batch.with_account_id(account_id).set(
+                    BlobOp::Reserve {
+                        hash: hash.clone(),
+                        until: expires,
+                    },
+                    0u32.serialize(),
+                );
+            }
+            BlobClass::Linked {
+                account_id,
+                collection,
+                document_id,
+            } => {
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(collection)
+                    .update_document(document_id)
+                    .set(BlobOp::Link { hash: hash.clone() }, Vec::new());
+            }
+        }
+
+        match store.write(batch.build()).await {
+            // The destination is already linked to this blob
+            Err(crate::Error::AssertValueFailed) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+impl Store {
+    async fn blob_reserved_hashes(&self, grace_period: u64) -> crate::Result<AHashSet<BlobHash>> {
+        let mut hashes = AHashSet::new();
+        let now = now();
+        self.iterate(
+            IterateParams::new(
+                ValueKey {
+                    account_id: 0,
+                    collection: 0,
+                    document_id: 0,
+                    class: ValueClass::Blob(BlobOp::Reserve {
+                        until: 0,
+                        hash: BlobHash::default(),
+                    }),
+                },
+                ValueKey {
+                    account_id: u32::MAX,
+                    collection: 0,
+                    document_id: 0,
+                    class: ValueClass::Blob(BlobOp::Reserve {
+                        until: u64::MAX,
+                        hash: BlobHash::new_max(),
+                    }),
+                },
+            )
+            .no_values(),
+            |key, _| {
+                let until = key.deserialize_be_u64(key.len() - U64_LEN)?;
+                if until.saturating_add(grace_period) > now {
+                    hashes.insert(hash_from_key(key, 1 + U32_LEN)?);
+                }
+                Ok(true)
+            },
+        )
+        .await?;
+
+        Ok(hashes)
+    }
+
+    async fn blob_is_linked(&self, hash: &BlobHash) -> crate::Result<bool> {
+        let mut is_linked = false;
+        self.iterate(
+            IterateParams::new(
+                ValueKey {
+                    account_id: 0,
+                    collection: 0,
+                    document_id: 0,
+                    class: ValueClass::Blob(BlobOp::Link { hash: hash.clone() }),
+                },
+                ValueKey {
+                    account_id: u32::MAX,
+                    collection: u8::MAX,
+                    document_id: u32::MAX,
+                    class: ValueClass::Blob(BlobOp::Link { hash: hash.clone() }),
+                },
+            )
+            .no_values(),
+            |key, _| {
+                // Skip the commit key, which shares the same prefix
+                if key.deserialize_be_u32(key.len() - U32_LEN)? != u32::MAX {
+                    is_linked = true;
+                    Ok(false)
+                } else {
+                    Ok(true)
+                }
+            },
+        )
+        .await?;
+
+        Ok(is_linked)
+    }
+}
+
+fn hash_from_key(key: &[u8], offset: usize) -> crate::Result<BlobHash> {
+    key.get(offset..offset + BLOB_HASH_LEN)
+        .and_then(|bytes| BlobHash::try_from_hash_slice(bytes).ok())
+        .ok_or_else(|| {
+            crate::Error::InternalError(format!("Invalid key {key:?} in blob hash tables"))
+        })
+}
@@ -0,0 +1,194 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+use ahash::AHashSet;
+use utils::config::Config;
+
+use crate::Deserialize;
+
+use super::key::serialized_property_collection;
+
+// Compressed values start with a marker byte followed by the zstd frame magic number,
+// which makes them distinguishable from values written before compression was enabled.
+const COMPRESSED_MARKER: u8 = 0xC5;
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+pub struct ValueCompression {
+    pub threshold: usize,
+    pub level: i32,
+    pub collections: AHashSet<u8>,
+}
+
+impl ValueCompression {
+    pub fn parse(config: &Config, prefix: &str) -> utils::config::Result<Option<Self>> {
+        let mut collections = AHashSet::new();
+        for collection in config.properties::<u8>((prefix, "compression.collections")) {
+            collections.insert(collection?.1);
+        }
+        if collections.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(ValueCompression {
+            threshold: config.property_or_static((prefix, "compression.threshold"), "4096")?,
+            level: config.property_or_static((prefix, "compression.level"), "3")?,
+            collections,
+        }))
+    }
+
+    /// Returns the compressed value if the collection has compression enabled, the value
+    /// is above the threshold and compressing it actually saves space.
+    pub fn compress(&self, collection: u8, value: &[u8]) -> Option<Vec<u8>> {
+        if value.len() < self.threshold || !self.collections.contains(&collection) {
+            return None;
+        }
+
+        let compressed = zstd::bulk::compress(value, self.level)
+            .map_err(|err| {
+                tracing::debug!(
+                    context = "store",
+                    event = "error",
+                    reason = %err,
+                    "Failed to compress value."
+                );
+            })
+            .ok()?;
+        if compressed.len() + 1 < value.len() && compressed.starts_with(&ZSTD_MAGIC) {
+            let mut bytes = Vec::with_capacity(compressed.len() + 1);
+            bytes.push(COMPRESSED_MARKER);
+            bytes.extend_from_slice(&compressed);
+            Some(bytes)
+        } else {
+            None
+        }
+    }
+
+    /// Decompresses the value if it belongs to a collection with compression enabled,
+    /// values in other collections are returned unchanged.
+    pub fn decompress<'x>(&self, key: &[u8], bytes: &'x [u8]) -> crate::Result<Cow<'x, [u8]>> {
+        if serialized_property_collection(key).map_or(false, |c| self.collections.contains(&c)) {
+            decompress(bytes)
+        } else {
+            Ok(Cow::Borrowed(bytes))
+        }
+    }
+}
+
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&COMPRESSED_MARKER)
+        && bytes.get(1..1 + ZSTD_MAGIC.len()) == Some(ZSTD_MAGIC.as_slice())
+}
+
+/// Decompresses the value if it was written compressed, otherwise returns it unchanged.
+/// Callers reading stored values should go through [`decompress_value`] instead, which
+/// only considers the collections that have compression enabled.
+pub fn decompress(bytes: &[u8]) -> crate::Result<Cow<'_, [u8]>> {
+    if is_compressed(bytes) {
+        zstd::stream::decode_all(&bytes[1..])
+            .map(Cow::Owned)
+            .map_err(|err| {
+                crate::Error::InternalError(format!("Failed to decompress value: {err}"))
+            })
+    } else {
+        Ok(Cow::Borrowed(bytes))
+    }
+}
+
+/// Decompresses a stored value if compression is enabled for its collection.
+pub fn decompress_value<'x>(
+    compression: Option<&ValueCompression>,
+    key: &[u8],
+    bytes: &'x [u8],
+) -> crate::Result<Cow<'x, [u8]>> {
+    match compression {
+        Some(compression) => compression.decompress(key, bytes),
+        None => Ok(Cow::Borrowed(bytes)),
+    }
+}
+
+pub(crate) struct RawValue(pub Vec<u8>);
+
+impl Deserialize for RawValue {
+    fn deserialize(bytes: &[u8]) -> crate::Result<Self> {
+        Ok(RawValue(bytes.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ahash::AHashSet;
+
+    use crate::{write::ValueClass, Key, ValueKey};
+
+    use super::{decompress, is_compressed, ValueCompression};
+
+    #[test]
+    fn compress_round_trip() {
+        let compression = ValueCompression {
+            threshold: 64,
+            level: 3,
+            collections: AHashSet::from_iter([1]),
+        };
+        let value = b"hello world ".repeat(100);
+
+        // Only values in enabled collections and above the threshold are compressed
+        assert!(compression.compress(0, &value).is_none());
+        assert!(compression.compress(1, &value[..32]).is_none());
+        let compressed = compression.compress(1, &value).unwrap();
+        assert!(compressed.len() < value.len());
+        assert!(is_compressed(&compressed));
+        assert_eq!(decompress(&compressed).unwrap().as_ref(), value.as_slice());
+
+        // Uncompressed values are returned as is
+        assert!(!is_compressed(&value));
+        assert_eq!(decompress(&value).unwrap().as_ref(), value.as_slice());
+
+        // Stored values are only decompressed in enabled collections, so raw values
+        // that happen to look compressed are left untouched elsewhere
+        let key = |collection| {
+            ValueKey {
+                account_id: 1,
+                collection,
+                document_id: 1,
+                class: ValueClass::Property(0),
+            }
+            .serialize(0)
+        };
+        assert_eq!(
+            compression
+                .decompress(&key(1), &compressed)
+                .unwrap()
+                .as_ref(),
+            value.as_slice()
+        );
+        assert_eq!(
+            compression
+                .decompress(&key(0), &compressed)
+                .unwrap()
+                .as_ref(),
+            compressed.as_slice()
+        );
+    }
+}
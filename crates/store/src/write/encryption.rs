@@ -0,0 +1,291 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+use std::borrow::Cow;
+
+use ahash::{AHashMap, AHashSet};
+use rand::RngCore;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use utils::config::Config;
+
+use super::{
+    key::{is_serialized_principal, serialized_property_collection},
+    DirectoryClass, ValueClass,
+};
+
+// Encrypted values start with a magic number and the version of the key that sealed them,
+// followed by a random nonce and the ChaCha20-Poly1305 ciphertext. Values written before
+// encryption was enabled lack the magic number and are only readable while
+// `encryption.allow-plaintext` is set, otherwise they could be swapped for plaintext.
+const ENCRYPTED_MAGIC: [u8; 3] = [0xE5, 0x9A, 0x3C];
+const HEADER_LEN: usize = ENCRYPTED_MAGIC.len() + 1 + NONCE_LEN;
+const KEY_CONTEXT: &str = "Stalwart Mail Server 2023 store value encryption";
+
+pub struct ValueEncryption {
+    pub collections: AHashSet<u8>,
+    pub directory: bool,
+    pub version: u8,
+    pub allow_plaintext: bool,
+    keys: AHashMap<u8, LessSafeKey>,
+}
+
+impl ValueEncryption {
+    pub fn parse(config: &Config, prefix: &str) -> utils::config::Result<Option<Self>> {
+        let mut collections = AHashSet::new();
+        for collection in config.properties::<u8>((prefix, "encryption.collections")) {
+            collections.insert(collection?.1);
+        }
+        let directory = config.property_or_static((prefix, "encryption.directory"), "false")?;
+        if collections.is_empty() && !directory {
+            return Ok(None);
+        }
+
+        // Keys are never stored in the backend, older versions are kept to read
+        // values sealed before a rotation.
+        let mut keys = AHashMap::new();
+        for (key, secret) in config.values((prefix, "encryption.key")) {
+            let version = key
+                .rsplit_once('.')
+                .and_then(|(_, version)| version.parse::<u8>().ok())
+                .ok_or_else(|| format!("Invalid encryption key version for property {key:?}."))?;
+            if secret.is_empty() {
+                return Err(format!("Property {key:?} cannot be empty."));
+            }
+            keys.insert(
+                version,
+                LessSafeKey::new(
+                    UnboundKey::new(
+                        &CHACHA20_POLY1305,
+                        &blake3::derive_key(KEY_CONTEXT, secret.as_bytes()),
+                    )
+                    .map_err(|_| format!("Invalid encryption key for property {key:?}."))?,
+                ),
+            );
+        }
+        let version = if let Some(version) = config.property((prefix, "encryption.version"))? {
+            version
+        } else if let Some(version) = keys.keys().max() {
+            *version
+        } else {
+            return Err(format!("No encryption keys configured for {prefix:?}."));
+        };
+        if !keys.contains_key(&version) {
+            return Err(format!(
+                "Encryption key version {version} for {prefix:?} is not configured."
+            ));
+        }
+
+        Ok(Some(ValueEncryption {
+            collections,
+            directory,
+            version,
+            allow_plaintext: config
+                .property_or_static((prefix, "encryption.allow-plaintext"), "false")?,
+            keys,
+        }))
+    }
+
+    pub fn is_encrypted(&self, collection: u8) -> bool {
+        self.collections.contains(&collection)
+    }
+
+    /// Principals hold account secrets and are encrypted when `encryption.directory` is set.
+    pub fn is_encrypted_class(&self, collection: u8, class: &ValueClass) -> bool {
+        match class {
+            ValueClass::Property(_) => self.is_encrypted(collection),
+            ValueClass::Directory(DirectoryClass::Principal(_)) => self.directory,
+            _ => false,
+        }
+    }
+
+    fn is_encrypted_key(&self, key: &[u8]) -> bool {
+        match serialized_property_collection(key) {
+            Some(collection) => self.is_encrypted(collection),
+            None => self.directory && is_serialized_principal(key),
+        }
+    }
+
+    /// Seals the value with the current key. The serialized key it is stored under is
+    /// authenticated as well, so ciphertexts cannot be moved between keys.
+    pub fn encrypt(&self, key: &[u8], value: &[u8]) -> crate::Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + value.len() + CHACHA20_POLY1305.tag_len());
+        bytes.extend_from_slice(&ENCRYPTED_MAGIC);
+        bytes.push(self.version);
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(value);
+
+        let mut in_out = bytes.split_off(HEADER_LEN);
+        self.keys[&self.version]
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(key),
+                &mut in_out,
+            )
+            .map_err(|_| crate::Error::InternalError("Value too large to encrypt.".to_string()))?;
+        bytes.extend_from_slice(&in_out);
+        Ok(bytes)
+    }
+
+    /// Opens a value sealed with any of the configured keys, returning the plaintext
+    /// and whether it was sealed with an older key and should be re-encrypted.
+    /// Values outside the encrypted collections and principals are returned as is.
+    pub fn decrypt<'x>(&self, key: &[u8], bytes: &'x [u8]) -> crate::Result<(Cow<'x, [u8]>, bool)> {
+        if !self.is_encrypted_key(key) {
+            return Ok((Cow::Borrowed(bytes), false));
+        } else if !is_encrypted(bytes) {
+            return if self.allow_plaintext {
+                Ok((Cow::Borrowed(bytes), false))
+            } else {
+                Err(crate::Error::InternalError(
+                    "Found unencrypted value in an encrypted collection.".to_string(),
+                ))
+            };
+        }
+
+        let version = bytes[ENCRYPTED_MAGIC.len()];
+        let cipher = self.keys.get(&version).ok_or_else(|| {
+            crate::Error::InternalError(format!(
+                "Value was encrypted with unknown key version {version}."
+            ))
+        })?;
+        let nonce = Nonce::try_assume_unique_for_key(&bytes[ENCRYPTED_MAGIC.len() + 1..HEADER_LEN])
+            .map_err(|_| crate::Error::InternalError("Invalid encryption nonce.".to_string()))?;
+        let mut in_out = bytes[HEADER_LEN..].to_vec();
+        let len = cipher
+            .open_in_place(nonce, Aad::from(key), &mut in_out)
+            .map_err(|_| crate::Error::InternalError("Failed to decrypt value.".to_string()))?
+            .len();
+        in_out.truncate(len);
+
+        Ok((Cow::Owned(in_out), version != self.version))
+    }
+}
+
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.len() >= HEADER_LEN && bytes.starts_with(&ENCRYPTED_MAGIC)
+}
+
+/// Decrypts the value if it belongs to an encrypted collection, otherwise returns it unchanged.
+pub fn decrypt<'x>(
+    encryption: Option<&ValueEncryption>,
+    key: &[u8],
+    bytes: &'x [u8],
+) -> crate::Result<Cow<'x, [u8]>> {
+    match encryption {
+        Some(encryption) => encryption.decrypt(key, bytes).map(|(bytes, _)| bytes),
+        None => Ok(Cow::Borrowed(bytes)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ahash::{AHashMap, AHashSet};
+    use ring::aead::{LessSafeKey, UnboundKey, CHACHA20_POLY1305};
+
+    use crate::{write::ValueClass, Key, ValueKey};
+
+    use super::{is_encrypted, ValueEncryption, KEY_CONTEXT};
+
+    fn cipher(secret: &str) -> LessSafeKey {
+        LessSafeKey::new(
+            UnboundKey::new(
+                &CHACHA20_POLY1305,
+                &blake3::derive_key(KEY_CONTEXT, secret.as_bytes()),
+            )
+            .unwrap(),
+        )
+    }
+
+    fn value_key(collection: u8, document_id: u32) -> Vec<u8> {
+        ValueKey {
+            account_id: 1,
+            collection,
+            document_id,
+            class: ValueClass::Property(0),
+        }
+        .serialize(0)
+    }
+
+    #[test]
+    fn encrypt_round_trip() {
+        let mut old = ValueEncryption {
+            collections: AHashSet::from_iter([1]),
+            directory: false,
+            version: 1,
+            allow_plaintext: false,
+            keys: AHashMap::from_iter([(1, cipher("old"))]),
+        };
+        let value = b"app password".to_vec();
+        let key = value_key(1, 1);
+
+        // Values are sealed to the key they are stored under
+        let encrypted = old.encrypt(&key, &value).unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert_ne!(old.encrypt(&key, &value).unwrap(), encrypted);
+        let (decrypted, rotate) = old.decrypt(&key, &encrypted).unwrap();
+        assert_eq!(decrypted.as_ref(), value.as_slice());
+        assert!(!rotate);
+        assert!(old.decrypt(&value_key(1, 2), &encrypted).is_err());
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(old.decrypt(&key, &tampered).is_err());
+
+        // Unencrypted values are rejected in encrypted collections unless allowed
+        assert!(!is_encrypted(&value));
+        assert!(old.decrypt(&key, &value).is_err());
+        old.allow_plaintext = true;
+        assert_eq!(
+            old.decrypt(&key, &value).unwrap().0.as_ref(),
+            value.as_slice()
+        );
+        old.allow_plaintext = false;
+
+        // Other collections are never decrypted
+        let other_key = value_key(2, 1);
+        assert_eq!(
+            old.decrypt(&other_key, &value).unwrap().0.as_ref(),
+            value.as_slice()
+        );
+        assert_eq!(
+            old.decrypt(&other_key, &encrypted).unwrap().0.as_ref(),
+            encrypted.as_slice()
+        );
+
+        // After a rotation, old values are readable and flagged for re-encryption
+        let new = ValueEncryption {
+            collections: AHashSet::from_iter([1]),
+            directory: false,
+            version: 2,
+            allow_plaintext: false,
+            keys: AHashMap::from_iter([(1, cipher("old")), (2, cipher("new"))]),
+        };
+        let (decrypted, rotate) = new.decrypt(&key, &encrypted).unwrap();
+        assert_eq!(decrypted.as_ref(), value.as_slice());
+        assert!(rotate);
+        let encrypted = new.encrypt(&key, &value).unwrap();
+        assert!(!new.decrypt(&key, &encrypted).unwrap().1);
+        assert!(old.decrypt(&key, &encrypted).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+use utils::config::Config;
+
+// Authenticated values end with a keyed BLAKE3 MAC followed by a magic number, which makes
+// them distinguishable from values written before integrity checks were enabled.
+const MAC_LEN: usize = blake3::OUT_LEN;
+const MAC_MAGIC: [u8; 4] = [0xA7, 0x5E, 0x41, 0x1C];
+const MAC_KEY_CONTEXT: &str = "Stalwart Mail Server 2023 store value integrity";
+
+pub struct ValueIntegrity {
+    key: [u8; blake3::KEY_LEN],
+    pub accept_unsigned: bool,
+}
+
+impl ValueIntegrity {
+    pub fn parse(config: &Config, prefix: &str) -> utils::config::Result<Option<Self>> {
+        let secret = if let Some(secret) = config.value((prefix, "integrity.key")) {
+            secret
+        } else {
+            return Ok(None);
+        };
+        if secret.is_empty() {
+            return Err(format!("Property {prefix}.integrity.key cannot be empty."));
+        }
+
+        let accept_unsigned = match config
+            .value((prefix, "integrity.unsigned"))
+            .unwrap_or("reject")
+        {
+            "accept" => true,
+            "reject" => false,
+            value => {
+                return Err(format!(
+                    "Invalid value {value:?} for property {prefix}.integrity.unsigned."
+                ))
+            }
+        };
+
+        Ok(Some(ValueIntegrity {
+            key: blake3::derive_key(MAC_KEY_CONTEXT, secret.as_bytes()),
+            accept_unsigned,
+        }))
+    }
+
+    /// Appends the MAC of the value, which is bound to the key it is stored under.
+    pub fn sign(&self, key: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(value.len() + MAC_LEN + MAC_MAGIC.len());
+        bytes.extend_from_slice(value);
+        bytes.extend_from_slice(self.mac(key, value).as_bytes());
+        bytes.extend_from_slice(&MAC_MAGIC);
+        bytes
+    }
+
+    /// Verifies and strips the MAC of a value read from the backend. Values without a MAC
+    /// are returned unchanged or rejected depending on the configured policy.
+    pub fn verify(&self, key: &[u8], mut bytes: Vec<u8>) -> crate::Result<Vec<u8>> {
+        if let Some(value_len) = signed_len(&bytes) {
+            // Comparing against a blake3::Hash is constant time
+            if self.mac(key, &bytes[..value_len]) == bytes[value_len..value_len + MAC_LEN] {
+                bytes.truncate(value_len);
+                Ok(bytes)
+            } else {
+                Err(crate::Error::InternalError(
+                    "Value failed integrity verification.".to_string(),
+                ))
+            }
+        } else if self.accept_unsigned {
+            Ok(bytes)
+        } else {
+            Err(crate::Error::InternalError(
+                "Value is missing its integrity MAC.".to_string(),
+            ))
+        }
+    }
+
+    fn mac(&self, key: &[u8], value: &[u8]) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new_keyed(&self.key);
+        hasher.update(&(key.len() as u64).to_be_bytes());
+        hasher.update(key);
+        hasher.update(value);
+        hasher.finalize()
+    }
+}
+
+fn signed_len(bytes: &[u8]) -> Option<usize> {
+    bytes
+        .len()
+        .checked_sub(MAC_LEN + MAC_MAGIC.len())
+        .filter(|_| bytes.ends_with(&MAC_MAGIC))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ValueIntegrity;
+
+    #[test]
+    fn sign_and_verify() {
+        let integrity = ValueIntegrity {
+            key: blake3::derive_key(super::MAC_KEY_CONTEXT, b"secret"),
+            accept_unsigned: false,
+        };
+        let value = b"hello world".to_vec();
+
+        // Signed values round trip under the same key only
+        let signed = integrity.sign(b"key", &value);
+        assert_ne!(signed, value);
+        assert_eq!(integrity.verify(b"key", signed.clone()).unwrap(), value);
+        assert!(integrity.verify(b"other", signed.clone()).is_err());
+        assert_eq!(
+            integrity.verify(b"", integrity.sign(b"", b"")).unwrap(),
+            b""
+        );
+
+        // Tampered values are rejected
+        let mut tampered = signed.clone();
+        tampered[0] ^= 1;
+        assert!(integrity.verify(b"key", tampered).is_err());
+        let mut tampered = signed;
+        tampered[value.len()] ^= 1;
+        assert!(integrity.verify(b"key", tampered).is_err());
+
+        // Unsigned values are handled according to the policy
+        assert!(integrity.verify(b"key", value.clone()).is_err());
+        let integrity = ValueIntegrity {
+            accept_unsigned: true,
+            ..integrity
+        };
+        assert_eq!(integrity.verify(b"key", value.clone()).unwrap(), value);
+    }
+}
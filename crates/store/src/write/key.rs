@@ -22,15 +22,15 @@
 */
 
 use std::convert::TryInto;
-use utils::codec::leb128::Leb128_;
+use utils::codec::leb128::{Leb128Reader, Leb128_};
 
 use crate::{
-    BitmapKey, IndexKey, IndexKeyPrefix, Key, LogKey, ValueKey, BLOB_HASH_LEN, SUBSPACE_BITMAPS,
-    SUBSPACE_INDEXES, SUBSPACE_LOGS, SUBSPACE_VALUES, U32_LEN, U64_LEN, WITHOUT_BLOCK_NUM,
-    WITH_SUBSPACE,
+    BitmapKey, BlobHash, IndexKey, IndexKeyPrefix, Key, LogKey, ParsedKey, ValueKey, BLOB_HASH_LEN,
+    SUBSPACE_BITMAPS, SUBSPACE_COUNTERS, SUBSPACE_INDEXES, SUBSPACE_LOGS, SUBSPACE_VALUES, U32_LEN,
+    U64_LEN, WITHOUT_BLOCK_NUM, WITH_SUBSPACE,
 };
 
-use super::{AnyKey, BitmapClass, BlobOp, DirectoryClass, TagValue, ValueClass};
+use super::{AnyKey, BitmapClass, BitmapHash, BlobOp, DirectoryClass, TagValue, ValueClass};
 
 pub struct KeySerializer {
     pub buf: Vec<u8>,
@@ -187,6 +187,10 @@ impl Key for IndexKeyPrefix {
     fn subspace(&self) -> u8 {
         SUBSPACE_INDEXES
     }
+
+    fn account_id(&self) -> Option<u32> {
+        Some(self.account_id)
+    }
 }
 
 impl IndexKeyPrefix {
@@ -200,6 +204,10 @@ impl Key for LogKey {
         SUBSPACE_LOGS
     }
 
+    fn account_id(&self) -> Option<u32> {
+        Some(self.account_id)
+    }
+
     fn serialize(&self, flags: u32) -> Vec<u8> {
         {
             if (flags & WITH_SUBSPACE) != 0 {
@@ -220,6 +228,21 @@ impl<T: AsRef<ValueClass> + Sync + Send> Key for ValueKey<T> {
         SUBSPACE_VALUES
     }
 
+    fn account_id(&self) -> Option<u32> {
+        match self.class.as_ref() {
+            ValueClass::Directory(_)
+            | ValueClass::Blob(BlobOp::Commit { .. } | BlobOp::RefCount { .. }) => None,
+            _ => Some(self.account_id),
+        }
+    }
+
+    fn property_collection(&self) -> Option<u8> {
+        match self.class.as_ref() {
+            ValueClass::Property(_) => Some(self.collection),
+            _ => None,
+        }
+    }
+
     fn serialize(&self, flags: u32) -> Vec<u8> {
         let serializer = if (flags & WITH_SUBSPACE) != 0 {
             KeySerializer::new(self.class.as_ref().serialized_size() + 2).write(self.subspace())
@@ -256,6 +279,7 @@ impl<T: AsRef<ValueClass> + Sync + Send> Key for ValueKey<T> {
                 .write(*seq)
                 .write(self.account_id)
                 .write(self.document_id),
+            ValueClass::Fence => serializer.write(8u8).write(self.account_id),
             ValueClass::Blob(op) => match op {
                 BlobOp::Reserve { hash, until } => serializer
                     .write(6u8)
@@ -274,6 +298,7 @@ impl<T: AsRef<ValueClass> + Sync + Send> Key for ValueKey<T> {
                     .write(self.account_id)
                     .write(self.collection)
                     .write(self.document_id),
+                BlobOp::RefCount { hash } => serializer.write(9u8).write::<&[u8]>(hash.as_ref()),
             },
             ValueClass::Directory(directory) => match directory {
                 DirectoryClass::NameToId(name) => serializer.write(20u8).write(name.as_slice()),
@@ -301,11 +326,29 @@ impl<T: AsRef<ValueClass> + Sync + Send> Key for ValueKey<T> {
     }
 }
 
+/// Returns the collection of a property value from its key serialized without the
+/// subspace, or `None` for any other class of value.
+pub(crate) fn serialized_property_collection(key: &[u8]) -> Option<u8> {
+    match key {
+        [0, _, _, _, _, collection, ..] => Some(*collection),
+        _ => None,
+    }
+}
+
+/// Returns true if the key, serialized without the subspace, belongs to a directory principal.
+pub(crate) fn is_serialized_principal(key: &[u8]) -> bool {
+    key.first() == Some(&22)
+}
+
 impl<T: AsRef<[u8]> + Sync + Send> Key for IndexKey<T> {
     fn subspace(&self) -> u8 {
         SUBSPACE_INDEXES
     }
 
+    fn account_id(&self) -> Option<u32> {
+        Some(self.account_id)
+    }
+
     fn serialize(&self, flags: u32) -> Vec<u8> {
         let key = self.key.as_ref();
         {
@@ -325,20 +368,24 @@ impl<T: AsRef<[u8]> + Sync + Send> Key for IndexKey<T> {
     }
 }
 
+const BM_DOCUMENT_IDS: u8 = 0;
+const BM_TAG: u8 = 1 << 6;
+const BM_TEXT: u8 = 1 << 7;
+
+const TAG_ID: u8 = 0;
+const TAG_TEXT: u8 = 1 << 0;
+const TAG_STATIC: u8 = 1 << 1;
+
 impl<T: AsRef<BitmapClass> + Sync + Send> Key for BitmapKey<T> {
     fn subspace(&self) -> u8 {
         SUBSPACE_BITMAPS
     }
 
-    fn serialize(&self, flags: u32) -> Vec<u8> {
-        const BM_DOCUMENT_IDS: u8 = 0;
-        const BM_TAG: u8 = 1 << 6;
-        const BM_TEXT: u8 = 1 << 7;
-
-        const TAG_ID: u8 = 0;
-        const TAG_TEXT: u8 = 1 << 0;
-        const TAG_STATIC: u8 = 1 << 1;
+    fn account_id(&self) -> Option<u32> {
+        Some(self.account_id)
+    }
 
+    fn serialize(&self, flags: u32) -> Vec<u8> {
         let serializer = match self.class.as_ref() {
             BitmapClass::DocumentIds => if (flags & WITH_SUBSPACE) != 0 {
                 KeySerializer::new(U32_LEN + 3).write(SUBSPACE_BITMAPS)
@@ -435,8 +482,10 @@ impl ValueClass {
             ValueClass::Blob(op) => match op {
                 BlobOp::Reserve { .. } => BLOB_HASH_LEN + U64_LEN + U32_LEN + 1,
                 BlobOp::Commit { .. } | BlobOp::Link { .. } => BLOB_HASH_LEN + U32_LEN * 2 + 2,
+                BlobOp::RefCount { .. } => BLOB_HASH_LEN + 1,
             },
             ValueClass::IndexEmail { .. } => U64_LEN * 2,
+            ValueClass::Fence => U32_LEN + 1,
         }
     }
 }
@@ -474,3 +523,404 @@ impl From<BlobOp> for ValueClass {
         ValueClass::Blob(value)
     }
 }
+
+impl ParsedKey {
+    /// Parses a key as returned by `Store::iterate`, that is, without the leading subspace byte.
+    pub fn deserialize(subspace: u8, bytes: &[u8]) -> crate::Result<Self> {
+        let mut key = KeyReader { bytes, pos: 0 };
+        let parsed = match subspace {
+            SUBSPACE_BITMAPS => ParsedKey::Bitmap(key.bitmap_key()?),
+            SUBSPACE_VALUES | SUBSPACE_COUNTERS => ParsedKey::Value(key.value_key()?),
+            SUBSPACE_INDEXES => ParsedKey::Index(key.index_key()?),
+            SUBSPACE_LOGS => ParsedKey::Log(LogKey {
+                account_id: key.u32()?,
+                collection: key.u8()?,
+                change_id: key.u64()?,
+            }),
+            _ => ParsedKey::Any(AnyKey {
+                subspace,
+                key: key.remaining(0)?.to_vec(),
+            }),
+        };
+
+        if key.pos == bytes.len() {
+            Ok(parsed)
+        } else {
+            Err(crate::Error::InternalError(format!(
+                "Unexpected trailing bytes in key {bytes:?} of subspace {:?}.",
+                char::from(subspace)
+            )))
+        }
+    }
+}
+
+impl Key for ParsedKey {
+    fn serialize(&self, flags: u32) -> Vec<u8> {
+        match self {
+            ParsedKey::Bitmap(key) => key.serialize(flags),
+            ParsedKey::Value(key) => key.serialize(flags),
+            ParsedKey::Index(key) => key.serialize(flags),
+            ParsedKey::Log(key) => key.serialize(flags),
+            ParsedKey::Any(key) => key.serialize(flags),
+        }
+    }
+
+    fn subspace(&self) -> u8 {
+        match self {
+            ParsedKey::Bitmap(key) => key.subspace(),
+            ParsedKey::Value(key) => key.subspace(),
+            ParsedKey::Index(key) => key.subspace(),
+            ParsedKey::Log(key) => key.subspace(),
+            ParsedKey::Any(key) => key.subspace(),
+        }
+    }
+}
+
+struct KeyReader<'x> {
+    bytes: &'x [u8],
+    pos: usize,
+}
+
+impl<'x> KeyReader<'x> {
+    fn take(&mut self, len: usize) -> crate::Result<&'x [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| self.error())?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    // Returns all bytes except the last `keep`
+    fn remaining(&mut self, keep: usize) -> crate::Result<&'x [u8]> {
+        let len = self
+            .bytes
+            .len()
+            .checked_sub(self.pos + keep)
+            .ok_or_else(|| self.error())?;
+        self.take(len)
+    }
+
+    fn u8(&mut self) -> crate::Result<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u32(&mut self) -> crate::Result<u32> {
+        self.take(U32_LEN)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> crate::Result<u64> {
+        self.take(U64_LEN)
+            .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn leb128(&mut self) -> crate::Result<u32> {
+        let (value, len) = self
+            .bytes
+            .get(self.pos..)
+            .and_then(|bytes| bytes.read_leb128::<u32>())
+            .ok_or_else(|| self.error())?;
+        self.pos += len;
+        Ok(value)
+    }
+
+    fn blob_hash(&mut self) -> crate::Result<BlobHash> {
+        self.take(BLOB_HASH_LEN)
+            .map(|bytes| BlobHash(bytes.try_into().unwrap()))
+    }
+
+    fn bitmap_key(&mut self) -> crate::Result<BitmapKey<BitmapClass>> {
+        let account_id = self.u32()?;
+        let collection = self.u8()?;
+        let typ = self.u8()?;
+        let class = if typ & BM_TEXT != 0 {
+            let field = self.u8()?;
+            BitmapClass::Text {
+                field,
+                token: BitmapHash {
+                    hash: self.take(8)?.try_into().unwrap(),
+                    len: typ & !BM_TEXT,
+                },
+            }
+        } else if typ & BM_TAG != 0 {
+            let field = self.u8()?;
+            let value = match typ & !BM_TAG {
+                TAG_ID => TagValue::Id(self.leb128()?),
+                TAG_TEXT => TagValue::Text(self.remaining(U32_LEN)?.to_vec()),
+                TAG_STATIC => TagValue::Static(self.u8()?),
+                _ => return Err(self.error()),
+            };
+            BitmapClass::Tag { field, value }
+        } else if typ == BM_DOCUMENT_IDS {
+            BitmapClass::DocumentIds
+        } else {
+            return Err(self.error());
+        };
+
+        Ok(BitmapKey {
+            account_id,
+            collection,
+            class,
+            block_num: self.u32()?,
+        })
+    }
+
+    fn index_key(&mut self) -> crate::Result<IndexKey<Vec<u8>>> {
+        Ok(IndexKey {
+            account_id: self.u32()?,
+            collection: self.u8()?,
+            field: self.u8()?,
+            key: self.remaining(U32_LEN)?.to_vec(),
+            document_id: self.u32()?,
+        })
+    }
+
+    fn value_key(&mut self) -> crate::Result<ValueKey<ValueClass>> {
+        let mut key = ValueKey {
+            account_id: 0,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::ReservedId,
+        };
+
+        match self.u8()? {
+            0 => {
+                key.account_id = self.u32()?;
+                key.collection = self.u8()?;
+                key.document_id = self.leb128()?;
+                key.class = ValueClass::Property(self.u8()?);
+            }
+            1 => {
+                key.account_id = self.u32()?;
+                key.collection = self.u8()?;
+                key.document_id = self.leb128()?;
+                key.class = ValueClass::TermIndex;
+            }
+            2 => {
+                key.class = ValueClass::Acl(self.u32()?);
+                key.account_id = self.u32()?;
+                key.collection = self.u8()?;
+                key.document_id = self.u32()?;
+            }
+            3 => {
+                key.account_id = self.u32()?;
+                key.collection = self.u8()?;
+                key.document_id = self.u32()?;
+            }
+            4 => {
+                key.class = ValueClass::Key(self.remaining(0)?.to_vec());
+            }
+            5 => {
+                key.class = ValueClass::IndexEmail(self.u64()?);
+                key.account_id = self.u32()?;
+                key.document_id = self.u32()?;
+            }
+            6 => {
+                key.account_id = self.u32()?;
+                key.class = ValueClass::Blob(BlobOp::Reserve {
+                    hash: self.blob_hash()?,
+                    until: self.u64()?,
+                });
+            }
+            7 => {
+                let hash = self.blob_hash()?;
+                key.account_id = self.u32()?;
+                key.collection = self.u8()?;
+                key.document_id = self.u32()?;
+                key.class = if key.account_id == u32::MAX
+                    && key.collection == 0
+                    && key.document_id == u32::MAX
+                {
+                    key.account_id = 0;
+                    key.document_id = 0;
+                    ValueClass::Blob(BlobOp::Commit { hash })
+                } else {
+                    ValueClass::Blob(BlobOp::Link { hash })
+                };
+            }
+            8 => {
+                key.account_id = self.u32()?;
+                key.class = ValueClass::Fence;
+            }
+            9 => {
+                key.class = ValueClass::Blob(BlobOp::RefCount {
+                    hash: self.blob_hash()?,
+                });
+            }
+            20 => {
+                key.class = DirectoryClass::NameToId(self.remaining(0)?.to_vec()).into();
+            }
+            21 => {
+                key.class = DirectoryClass::EmailToId(self.remaining(0)?.to_vec()).into();
+            }
+            22 => {
+                key.class = DirectoryClass::Principal(self.leb128()?).into();
+            }
+            23 => {
+                key.class = DirectoryClass::Domain(self.remaining(0)?.to_vec()).into();
+            }
+            24 => {
+                key.class = DirectoryClass::UsedQuota(self.leb128()?).into();
+            }
+            25 => {
+                key.class = DirectoryClass::MemberOf {
+                    principal_id: self.u32()?,
+                    member_of: self.u32()?,
+                }
+                .into();
+            }
+            26 => {
+                key.class = DirectoryClass::Members {
+                    principal_id: self.u32()?,
+                    has_member: self.u32()?,
+                }
+                .into();
+            }
+            _ => return Err(self.error()),
+        }
+
+        Ok(key)
+    }
+
+    fn error(&self) -> crate::Error {
+        crate::Error::InternalError(format!(
+            "Failed to parse key {:?} at position {}.",
+            self.bytes, self.pos
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        write::{BitmapClass, BitmapHash, BlobOp, DirectoryClass, TagValue, ValueClass},
+        BitmapKey, BlobHash, IndexKey, Key, LogKey, ParsedKey, ValueKey, WITH_SUBSPACE,
+    };
+
+    #[test]
+    fn parse_key_round_trip() {
+        let hash = BlobHash::from(b"blob".as_slice());
+        let mut keys = vec![
+            ParsedKey::Log(LogKey {
+                account_id: 1,
+                collection: 2,
+                change_id: u64::MAX - 3,
+            }),
+            ParsedKey::Index(IndexKey {
+                account_id: 1,
+                collection: 2,
+                document_id: 3,
+                field: 4,
+                key: b"index value".to_vec(),
+            }),
+        ];
+        for class in [
+            BitmapClass::DocumentIds,
+            BitmapClass::Tag {
+                field: 1,
+                value: TagValue::Id(300),
+            },
+            BitmapClass::Tag {
+                field: 1,
+                value: TagValue::Text(b"tag".to_vec()),
+            },
+            BitmapClass::Tag {
+                field: 1,
+                value: TagValue::Static(7),
+            },
+            BitmapClass::Text {
+                field: 1 | 1 << 7,
+                token: BitmapHash::new("token"),
+            },
+        ] {
+            keys.push(ParsedKey::Bitmap(BitmapKey {
+                account_id: 1,
+                collection: 2,
+                class,
+                block_num: 3,
+            }));
+        }
+        for (account_id, collection, document_id, class) in [
+            (1, 2, 300, ValueClass::Property(4)),
+            (1, 2, 300, ValueClass::TermIndex),
+            (1, 2, 3, ValueClass::Acl(4)),
+            (1, 2, 3, ValueClass::ReservedId),
+            (0, 0, 0, ValueClass::Key(b"lookup key".to_vec())),
+            (1, 0, 3, ValueClass::IndexEmail(4)),
+            (1, 0, 0, ValueClass::Fence),
+            (
+                1,
+                0,
+                0,
+                ValueClass::Blob(BlobOp::Reserve {
+                    hash: hash.clone(),
+                    until: 4,
+                }),
+            ),
+            (
+                0,
+                0,
+                0,
+                ValueClass::Blob(BlobOp::Commit { hash: hash.clone() }),
+            ),
+            (
+                0,
+                0,
+                0,
+                ValueClass::Blob(BlobOp::RefCount { hash: hash.clone() }),
+            ),
+            (1, 2, 3, ValueClass::Blob(BlobOp::Link { hash })),
+            (0, 0, 0, DirectoryClass::NameToId(b"john".to_vec()).into()),
+            (
+                0,
+                0,
+                0,
+                DirectoryClass::EmailToId(b"john@example.org".to_vec()).into(),
+            ),
+            (0, 0, 0, DirectoryClass::Principal(300).into()),
+            (
+                0,
+                0,
+                0,
+                DirectoryClass::Domain(b"example.org".to_vec()).into(),
+            ),
+            (0, 0, 0, DirectoryClass::UsedQuota(300).into()),
+            (
+                0,
+                0,
+                0,
+                DirectoryClass::MemberOf {
+                    principal_id: 1,
+                    member_of: 2,
+                }
+                .into(),
+            ),
+            (
+                0,
+                0,
+                0,
+                DirectoryClass::Members {
+                    principal_id: 1,
+                    has_member: 2,
+                }
+                .into(),
+            ),
+        ] {
+            keys.push(ParsedKey::Value(ValueKey {
+                account_id,
+                collection,
+                document_id,
+                class,
+            }));
+        }
+
+        for key in keys {
+            let bytes = key.serialize(WITH_SUBSPACE);
+            let parsed = ParsedKey::deserialize(bytes[0], &bytes[1..])
+                .unwrap_or_else(|err| panic!("failed to parse {key:?}: {err}"));
+            assert_eq!(parsed, key);
+            assert_eq!(parsed.serialize(WITH_SUBSPACE), bytes);
+        }
+    }
+}
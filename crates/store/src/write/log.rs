@@ -21,8 +21,14 @@
  * for more details.
 */
 
+use std::{borrow::Cow, fmt::Display};
+
 use ahash::AHashSet;
-use utils::{codec::leb128::Leb128Vec, map::vec_map::VecMap};
+use utils::{
+    codec::leb128::{Leb128Iterator, Leb128Vec},
+    config::utils::{AsKey, ParseValue},
+    map::vec_map::VecMap,
+};
 
 use crate::Serialize;
 
@@ -178,3 +184,203 @@ impl Serialize for Changes {
         buf
     }
 }
+
+/// Encoding of the change log entries stored in the logs subspace.
+///
+/// `Leb128` is the native format: the number of inserts, updates, child updates
+/// and deletes followed by the ids of each list, all as LEB128 integers.
+/// `Cbor` stores the same four lists as a CBOR array of four arrays of unsigned
+/// integers (RFC 8949), which external tools can decode with any CBOR library.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Leb128,
+    Cbor,
+}
+
+impl LogFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogFormat::Leb128 => "leb128",
+            LogFormat::Cbor => "cbor",
+        }
+    }
+
+    /// Converts a change log entry in the native format into this format.
+    pub(crate) fn encode(self, bytes: Vec<u8>) -> Vec<u8> {
+        match self {
+            LogFormat::Leb128 => bytes,
+            LogFormat::Cbor => match leb128_to_lists(&bytes) {
+                Some(lists) => {
+                    let mut buf = Vec::with_capacity(bytes.len() + 8);
+                    cbor_write_header(&mut buf, CBOR_ARRAY, lists.len() as u64);
+                    for list in lists {
+                        cbor_write_header(&mut buf, CBOR_ARRAY, list.len() as u64);
+                        for id in list {
+                            cbor_write_header(&mut buf, CBOR_UINT, id);
+                        }
+                    }
+                    buf
+                }
+                None => bytes,
+            },
+        }
+    }
+
+    /// Converts a change log entry stored in this format into the native format.
+    pub(crate) fn decode(self, bytes: &[u8]) -> Option<Cow<'_, [u8]>> {
+        match self {
+            LogFormat::Leb128 => Some(Cow::Borrowed(bytes)),
+            LogFormat::Cbor => {
+                let mut bytes = bytes.iter();
+                if cbor_read_header(&mut bytes, CBOR_ARRAY)? != 4 {
+                    return None;
+                }
+                let mut lists: [Vec<u64>; 4] = Default::default();
+                for list in &mut lists {
+                    let len = cbor_read_header(&mut bytes, CBOR_ARRAY)?;
+                    for _ in 0..len {
+                        list.push(cbor_read_header(&mut bytes, CBOR_UINT)?);
+                    }
+                }
+                if bytes.next().is_some() {
+                    return None;
+                }
+
+                let mut buf = Vec::with_capacity(
+                    lists.iter().map(|list| list.len() + 1).sum::<usize>()
+                        * std::mem::size_of::<u64>(),
+                );
+                for list in &lists {
+                    buf.push_leb128(list.len());
+                }
+                for list in lists {
+                    for id in list {
+                        buf.push_leb128(id);
+                    }
+                }
+                Some(Cow::Owned(buf))
+            }
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl ParseValue for LogFormat {
+    fn parse_value(key: impl AsKey, value: &str) -> utils::config::Result<Self> {
+        match value {
+            "leb128" => Ok(LogFormat::Leb128),
+            "cbor" => Ok(LogFormat::Cbor),
+            _ => Err(format!(
+                "Invalid value for log format {key:?}: {value:?}",
+                key = key.as_key(),
+            )),
+        }
+    }
+}
+
+const CBOR_UINT: u8 = 0;
+const CBOR_ARRAY: u8 = 4;
+
+fn leb128_to_lists(bytes: &[u8]) -> Option<[Vec<u64>; 4]> {
+    let mut bytes = bytes.iter();
+    let mut lists: [Vec<u64>; 4] = Default::default();
+    let lens: [usize; 4] = [
+        bytes.next_leb128()?,
+        bytes.next_leb128()?,
+        bytes.next_leb128()?,
+        bytes.next_leb128()?,
+    ];
+    for (list, len) in lists.iter_mut().zip(lens) {
+        for _ in 0..len {
+            list.push(bytes.next_leb128()?);
+        }
+    }
+    Some(lists)
+}
+
+fn cbor_write_header(buf: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    if value < 24 {
+        buf.push(major | value as u8);
+    } else if value <= u8::MAX as u64 {
+        buf.push(major | 24);
+        buf.push(value as u8);
+    } else if value <= u16::MAX as u64 {
+        buf.push(major | 25);
+        buf.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value <= u32::MAX as u64 {
+        buf.push(major | 26);
+        buf.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        buf.push(major | 27);
+        buf.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn cbor_read_header<'x>(bytes: &mut impl Iterator<Item = &'x u8>, major: u8) -> Option<u64> {
+    let byte = *bytes.next()?;
+    if byte >> 5 != major {
+        return None;
+    }
+    let len = match byte & 0x1f {
+        value @ 0..=23 => return Some(value as u64),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return None,
+    };
+    let mut value = 0u64;
+    for _ in 0..len {
+        value = (value << 8) | *bytes.next()? as u64;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Serialize;
+
+    use super::{Changes, LogFormat};
+
+    #[test]
+    fn log_format_cbor() {
+        let mut changes = Changes::default();
+        changes.inserts.insert(1);
+        changes.updates.insert(300);
+        changes.deletes.insert(u64::MAX);
+        let native = changes.serialize();
+
+        let cbor = LogFormat::Cbor.encode(native.clone());
+        assert_ne!(cbor, native);
+        // Array of four arrays, the first one holding the single insert
+        assert_eq!(&cbor[..3], &[0x84, 0x81, 0x01]);
+        assert_eq!(
+            LogFormat::Cbor.decode(&cbor).unwrap().as_ref(),
+            native.as_slice()
+        );
+
+        // Empty entries
+        let native = Changes::default().serialize();
+        let cbor = LogFormat::Cbor.encode(native.clone());
+        assert_eq!(cbor, vec![0x84, 0x80, 0x80, 0x80, 0x80]);
+        assert_eq!(
+            LogFormat::Cbor.decode(&cbor).unwrap().as_ref(),
+            native.as_slice()
+        );
+
+        // Invalid entries
+        assert!(LogFormat::Cbor.decode(&[0x83, 0x80, 0x80, 0x80]).is_none());
+        assert!(LogFormat::Cbor.decode(&[0x84, 0x80, 0x80, 0x80]).is_none());
+        assert_eq!(
+            LogFormat::Leb128.encode(native.clone()),
+            LogFormat::Leb128.decode(&native).unwrap().as_ref()
+        );
+    }
+}
@@ -42,7 +42,10 @@ pub mod assign_id;
 pub mod batch;
 pub mod bitmap;
 pub mod blob;
+pub mod compression;
+pub mod encryption;
 pub mod hash;
+pub mod integrity;
 pub mod key;
 pub mod log;
 pub mod purge;
@@ -137,6 +140,7 @@ pub enum ValueClass {
     Directory(DirectoryClass),
     Blob(BlobOp),
     IndexEmail(u64),
+    Fence,
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
@@ -163,6 +167,8 @@ pub enum BlobOp {
     Reserve { hash: BlobHash, until: u64 },
     Commit { hash: BlobHash },
     Link { hash: BlobHash },
+    // Number of links to a blob, kept in the counters subspace
+    RefCount { hash: BlobHash },
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
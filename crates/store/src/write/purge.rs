@@ -64,7 +64,10 @@ impl PurgeSchedule {
                 let result = match &self.store {
                     PurgeStore::Bitmaps(store) => store.purge_bitmaps().await,
                     PurgeStore::Blobs { store, blob_store } => {
-                        store.purge_blobs(blob_store.clone()).await
+                        match store.purge_blobs(blob_store.clone()).await {
+                            Ok(_) => blob_store.gc(store).await.map(|_| ()),
+                            Err(err) => Err(err),
+                        }
                     }
                     PurgeStore::Lookup(store) => store.purge_expired().await,
                 };
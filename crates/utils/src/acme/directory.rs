@@ -0,0 +1,259 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use reqwest::{
+    header::{CONTENT_TYPE, LOCATION},
+    Client, Response,
+};
+use ring::signature::EcdsaKeyPair;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+
+use super::{jose, AcmeError};
+
+pub(crate) struct Account {
+    key_pair: EcdsaKeyPair,
+    directory: Directory,
+    kid: String,
+    client: Client,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Order {
+    pub status: OrderStatus,
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    pub certificate: Option<String>,
+    pub error: Option<Problem>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OrderStatus {
+    Pending,
+    Ready,
+    Processing,
+    Valid,
+    Invalid,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Auth {
+    pub identifier: Identifier,
+    pub status: AuthStatus,
+    pub challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Identifier {
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AuthStatus {
+    Pending,
+    Valid,
+    Invalid,
+    Deactivated,
+    Expired,
+    Revoked,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Challenge {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub url: String,
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Problem {
+    #[serde(rename = "type")]
+    pub typ: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl Account {
+    pub async fn create(
+        directory_url: &str,
+        contact: &[String],
+        key: &[u8],
+    ) -> Result<Self, AcmeError> {
+        let client = Client::builder().timeout(Duration::from_secs(30)).build()?;
+        let directory: Directory = parse(client.get(directory_url).send().await?).await?;
+        let key_pair = jose::load_key(key)?;
+
+        // Registering an existing key returns the URL of the existing account
+        let body = jose::sign(
+            &key_pair,
+            None,
+            nonce(&client, &directory.new_nonce).await?,
+            &directory.new_account,
+            &json!({
+                "termsOfServiceAgreed": true,
+                "contact": contact,
+            })
+            .to_string(),
+        )?;
+        let response = post(&client, &directory.new_account, body).await?;
+        let kid = location(&response)?;
+
+        Ok(Account {
+            key_pair,
+            directory,
+            kid,
+            client,
+        })
+    }
+
+    pub fn key_pair(&self) -> &EcdsaKeyPair {
+        &self.key_pair
+    }
+
+    pub async fn new_order(&self, domains: &[String]) -> Result<(String, Order), AcmeError> {
+        let identifiers = domains
+            .iter()
+            .map(|domain| json!({"type": "dns", "value": domain}))
+            .collect::<Vec<_>>();
+        let response = self
+            .request(
+                &self.directory.new_order,
+                &json!({ "identifiers": identifiers }).to_string(),
+            )
+            .await?;
+        let url = location(&response)?;
+        Ok((url, parse(response).await?))
+    }
+
+    pub async fn order(&self, url: &str) -> Result<Order, AcmeError> {
+        parse(self.request(url, "").await?).await
+    }
+
+    pub async fn auth(&self, url: &str) -> Result<Auth, AcmeError> {
+        parse(self.request(url, "").await?).await
+    }
+
+    pub async fn challenge_ready(&self, url: &str) -> Result<(), AcmeError> {
+        self.request(url, "{}").await.map(|_| ())
+    }
+
+    pub async fn finalize(&self, url: &str, csr: &[u8]) -> Result<Order, AcmeError> {
+        parse(
+            self.request(url, &json!({ "csr": jose::base64_url(csr) }).to_string())
+                .await?,
+        )
+        .await
+    }
+
+    pub async fn certificate(&self, url: &str) -> Result<String, AcmeError> {
+        self.request(url, "")
+            .await?
+            .text()
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn request(&self, url: &str, payload: &str) -> Result<Response, AcmeError> {
+        let body = jose::sign(
+            &self.key_pair,
+            Some(&self.kid),
+            nonce(&self.client, &self.directory.new_nonce).await?,
+            url,
+            payload,
+        )?;
+        post(&self.client, url, body).await
+    }
+}
+
+async fn nonce(client: &Client, url: &str) -> Result<String, AcmeError> {
+    let response = check_status(client.head(url).send().await?).await?;
+    response
+        .headers()
+        .get("replay-nonce")
+        .and_then(|nonce| nonce.to_str().ok())
+        .map(|nonce| nonce.to_string())
+        .ok_or_else(|| AcmeError::Protocol("Missing Replay-Nonce header".into()))
+}
+
+async fn post(client: &Client, url: &str, body: String) -> Result<Response, AcmeError> {
+    check_status(
+        client
+            .post(url)
+            .header(CONTENT_TYPE, "application/jose+json")
+            .body(body)
+            .send()
+            .await?,
+    )
+    .await
+}
+
+async fn check_status(response: Response) -> Result<Response, AcmeError> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else {
+        let body = response.text().await.unwrap_or_default();
+        Err(AcmeError::Protocol(
+            match serde_json::from_str::<Problem>(&body) {
+                Ok(problem) => problem.to_string(),
+                Err(_) => body,
+            } + &format!(" (status {status})"),
+        ))
+    }
+}
+
+async fn parse<T: DeserializeOwned>(response: Response) -> Result<T, AcmeError> {
+    serde_json::from_slice(&check_status(response).await?.bytes().await?).map_err(Into::into)
+}
+
+fn location(response: &Response) -> Result<String, AcmeError> {
+    response
+        .headers()
+        .get(LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .map(|location| location.to_string())
+        .ok_or_else(|| AcmeError::Protocol("Missing Location header".into()))
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            self.typ.as_deref().unwrap_or("unknown"),
+            self.detail.as_deref().unwrap_or("no details")
+        )
+    }
+}
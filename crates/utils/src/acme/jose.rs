@@ -0,0 +1,145 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::{
+    digest::{digest, Digest, SHA256},
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde::Serialize;
+
+use super::AcmeError;
+
+#[derive(Debug, Serialize)]
+struct Body {
+    protected: String,
+    payload: String,
+    signature: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Protected<'x> {
+    alg: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jwk: Option<Jwk>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<&'x str>,
+    nonce: String,
+    url: &'x str,
+}
+
+#[derive(Debug, Serialize)]
+struct Jwk {
+    alg: &'static str,
+    crv: &'static str,
+    kty: &'static str,
+    #[serde(rename = "use")]
+    u: &'static str,
+    x: String,
+    y: String,
+}
+
+pub(crate) fn load_key(pkcs8: &[u8]) -> Result<EcdsaKeyPair, AcmeError> {
+    EcdsaKeyPair::from_pkcs8(
+        &ECDSA_P256_SHA256_FIXED_SIGNING,
+        pkcs8,
+        &SystemRandom::new(),
+    )
+    .map_err(|err| AcmeError::Crypto(format!("Invalid account key: {err}")))
+}
+
+pub(crate) fn sign(
+    key: &EcdsaKeyPair,
+    kid: Option<&str>,
+    nonce: String,
+    url: &str,
+    payload: &str,
+) -> Result<String, AcmeError> {
+    // Requests are signed with the JWK until the account URL is known
+    let protected = Protected {
+        alg: "ES256",
+        jwk: if kid.is_none() {
+            Some(Jwk::new(key))
+        } else {
+            None
+        },
+        kid,
+        nonce,
+        url,
+    };
+    let protected = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&protected)?);
+    let payload = URL_SAFE_NO_PAD.encode(payload);
+    let signature = key
+        .sign(
+            &SystemRandom::new(),
+            format!("{protected}.{payload}").as_bytes(),
+        )
+        .map_err(|_| AcmeError::Crypto("Failed to sign request".into()))?;
+
+    serde_json::to_string(&Body {
+        protected,
+        payload,
+        signature: URL_SAFE_NO_PAD.encode(signature.as_ref()),
+    })
+    .map_err(Into::into)
+}
+
+pub(crate) fn base64_url(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub(crate) fn key_authorization(key: &EcdsaKeyPair, token: &str) -> String {
+    format!("{token}.{}", Jwk::new(key).thumbprint())
+}
+
+pub(crate) fn key_authorization_sha256(key: &EcdsaKeyPair, token: &str) -> Digest {
+    digest(&SHA256, key_authorization(key, token).as_bytes())
+}
+
+impl Jwk {
+    fn new(key: &EcdsaKeyPair) -> Self {
+        // Public key is an uncompressed point: 0x04 || x || y
+        let (x, y) = key.public_key().as_ref()[1..].split_at(32);
+        Jwk {
+            alg: "ES256",
+            crv: "P-256",
+            kty: "EC",
+            u: "sig",
+            x: URL_SAFE_NO_PAD.encode(x),
+            y: URL_SAFE_NO_PAD.encode(y),
+        }
+    }
+
+    fn thumbprint(&self) -> String {
+        // RFC 7638 thumbprint over the required members in lexicographic order
+        URL_SAFE_NO_PAD.encode(digest(
+            &SHA256,
+            format!(
+                r#"{{"crv":"{}","kty":"{}","x":"{}","y":"{}"}}"#,
+                self.crv, self.kty, self.x, self.y
+            )
+            .as_bytes(),
+        ))
+    }
+}
@@ -0,0 +1,525 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    fmt::Display,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ahash::AHashMap;
+use rcgen::{
+    CertificateParams, CustomExtension, DistinguishedName, KeyPair, PKCS_ECDSA_P256_SHA256,
+};
+use rustls::{crypto::ring::sign::any_supported_type, sign::CertifiedKey};
+use rustls_pemfile::{read_one, Item};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio::sync::watch;
+use x509_parser::{certificate::X509Certificate, prelude::FromDer};
+
+use crate::config::{certificate::Certificate, Config};
+
+use self::directory::{Account, AuthStatus, Order, OrderStatus};
+
+mod directory;
+mod jose;
+
+pub const ACME_TLS_ALPN_NAME: &[u8] = b"acme-tls/1";
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MIN_RENEW_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+
+#[derive(Debug)]
+pub struct AcmeProvider {
+    pub id: String,
+    pub directory_url: String,
+    pub domains: Vec<String>,
+    pub contact: Vec<String>,
+    pub challenge: ChallengeType,
+    pub renew_before: Duration,
+    pub cache: PathBuf,
+    pub cert: Arc<Certificate>,
+    account_key: Option<Vec<u8>>,
+    tls_alpn_challenges: Mutex<AHashMap<String, Arc<CertifiedKey>>>,
+    http_challenges: Mutex<AHashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeType {
+    TlsAlpn01,
+    Http01,
+}
+
+#[derive(Debug)]
+pub enum AcmeError {
+    Http(reqwest::Error),
+    Json(serde_json::Error),
+    Io(std::io::Error),
+    Crypto(String),
+    Protocol(String),
+}
+
+// Missing, expired and soon to expire certificates are renewed right away
+fn renew_in(valid_for: Option<Duration>, renew_before: Duration) -> Duration {
+    valid_for.map_or(Duration::ZERO, |valid_for| {
+        valid_for.saturating_sub(renew_before)
+    })
+}
+
+// Certificates issued with a lifetime shorter than `renew_before` would be due again
+// immediately, they wait for half of their remaining lifetime, up to a day, instead.
+fn next_renewal(valid_for: Option<Duration>, renew_before: Duration) -> Duration {
+    match valid_for {
+        Some(valid_for) if valid_for <= renew_before => (valid_for / 2).min(MIN_RENEW_INTERVAL),
+        valid_for => renew_in(valid_for, renew_before),
+    }
+}
+
+impl AcmeProvider {
+    pub fn tls_alpn_challenge(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        self.tls_alpn_challenges
+            .lock()
+            .ok()?
+            .get(&name.to_lowercase())
+            .cloned()
+    }
+
+    pub fn http_challenge(&self, token: &str) -> Option<String> {
+        self.http_challenges.lock().ok()?.get(token).cloned()
+    }
+
+    pub fn spawn(self: Arc<Self>, mut shutdown_rx: watch::Receiver<bool>) {
+        tokio::spawn(async move {
+            let mut renew_in = self.renew_in();
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(renew_in) => {
+                        let result = self.renew().await;
+                        self.clear_challenges();
+                        renew_in = match result {
+                            Ok(_) => {
+                                tracing::info!(
+                                    context = "acme",
+                                    event = "renew",
+                                    id = self.id,
+                                    domains = ?self.domains,
+                                    "Obtained new certificate."
+                                );
+                                next_renewal(self.valid_for(), self.renew_before)
+                            }
+                            Err(err) => {
+                                tracing::error!(
+                                    context = "acme",
+                                    event = "error",
+                                    id = self.id,
+                                    domains = ?self.domains,
+                                    "Failed to obtain certificate: {}",
+                                    err
+                                );
+                                RETRY_INTERVAL
+                            }
+                        };
+                    },
+                    _ = shutdown_rx.changed() => {
+                        break;
+                    }
+                };
+            }
+        });
+    }
+
+    pub fn renew_in(&self) -> Duration {
+        renew_in(self.valid_for(), self.renew_before)
+    }
+
+    fn valid_for(&self) -> Option<Duration> {
+        self.expires_at()
+            .and_then(|expires_at| expires_at.duration_since(SystemTime::now()).ok())
+    }
+
+    fn expires_at(&self) -> Option<SystemTime> {
+        let key = self.cert.certified_key()?;
+        let (_, cert) = X509Certificate::from_der(key.end_entity_cert().ok()?.as_ref()).ok()?;
+        u64::try_from(cert.validity().not_after.timestamp())
+            .ok()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    async fn renew(&self) -> Result<(), AcmeError> {
+        let account =
+            Account::create(&self.directory_url, &self.contact, &self.account_key()?).await?;
+        let (order_url, order) = account.new_order(&self.domains).await?;
+
+        // Publish the responses to all pending challenges
+        for auth_url in &order.authorizations {
+            let auth = account.auth(auth_url).await?;
+            match auth.status {
+                AuthStatus::Pending => (),
+                AuthStatus::Valid => continue,
+                status => {
+                    return Err(AcmeError::Protocol(format!(
+                        "Authorization for {:?} is {status:?}",
+                        auth.identifier.value
+                    )))
+                }
+            }
+            let challenge = auth
+                .challenges
+                .iter()
+                .find(|challenge| challenge.typ == self.challenge.as_str())
+                .ok_or_else(|| {
+                    AcmeError::Protocol(format!(
+                        "No {} challenge offered for {:?}",
+                        self.challenge.as_str(),
+                        auth.identifier.value
+                    ))
+                })?;
+            let token = challenge.token.as_deref().ok_or_else(|| {
+                AcmeError::Protocol(format!("Missing challenge token for {:?}", challenge.url))
+            })?;
+            match self.challenge {
+                ChallengeType::TlsAlpn01 => {
+                    let key = challenge_certificate(
+                        &auth.identifier.value,
+                        jose::key_authorization_sha256(account.key_pair(), token).as_ref(),
+                    )?;
+                    if let Ok(mut challenges) = self.tls_alpn_challenges.lock() {
+                        challenges.insert(auth.identifier.value.to_lowercase(), Arc::new(key));
+                    }
+                }
+                ChallengeType::Http01 => {
+                    if let Ok(mut challenges) = self.http_challenges.lock() {
+                        challenges.insert(
+                            token.to_string(),
+                            jose::key_authorization(account.key_pair(), token),
+                        );
+                    }
+                }
+            }
+            account.challenge_ready(&challenge.url).await?;
+        }
+
+        // Wait until all authorizations are validated
+        let order = wait_for_order(&account, &order_url, order, OrderStatus::Pending).await?;
+        if order.status != OrderStatus::Ready {
+            return Err(order_error(order));
+        }
+
+        // Submit a CSR for a new key pair
+        let mut params = CertificateParams::new(self.domains.clone());
+        params.distinguished_name = DistinguishedName::new();
+        params.alg = &PKCS_ECDSA_P256_SHA256;
+        let cert = rcgen::Certificate::from_params(params).map_err(AcmeError::crypto)?;
+        let order = account
+            .finalize(
+                &order.finalize,
+                &cert.serialize_request_der().map_err(AcmeError::crypto)?,
+            )
+            .await?;
+        let order = wait_for_order(&account, &order_url, order, OrderStatus::Processing).await?;
+        let cert_url = match order.certificate {
+            Some(cert_url) if order.status == OrderStatus::Valid => cert_url,
+            _ => return Err(order_error(order)),
+        };
+        let cert_pem = account.certificate(&cert_url).await?;
+
+        // Store the certificate and reload it. Both files are fully written before either
+        // one is replaced, and a pair left mismatched by a crash in between is rejected
+        // on load, which triggers a new renewal.
+        std::fs::create_dir_all(&self.cache)?;
+        let key_path = self.cache_path("key");
+        let pem_path = self.cache_path("pem");
+        let key_temp = write_temp(&key_path, cert.serialize_private_key_pem().as_bytes(), true)?;
+        let pem_temp = write_temp(&pem_path, cert_pem.as_bytes(), false)?;
+        std::fs::rename(key_temp, key_path)?;
+        std::fs::rename(pem_temp, pem_path)?;
+        if self.cert.reload() {
+            Ok(())
+        } else {
+            Err(AcmeError::Crypto(
+                "Failed to load the issued certificate".into(),
+            ))
+        }
+    }
+
+    fn account_key(&self) -> Result<Vec<u8>, AcmeError> {
+        let pem = if let Some(account_key) = &self.account_key {
+            account_key.clone()
+        } else {
+            // Generate a new account key on first use
+            let path = self.cache.join(format!("account-{}.key", self.id));
+            if !path.exists() {
+                let key = KeyPair::generate(&PKCS_ECDSA_P256_SHA256).map_err(AcmeError::crypto)?;
+                std::fs::create_dir_all(&self.cache)?;
+                std::fs::rename(
+                    write_temp(&path, key.serialize_pem().as_bytes(), true)?,
+                    &path,
+                )?;
+            }
+            std::fs::read(&path)?
+        };
+
+        match read_one(&mut Cursor::new(&pem))? {
+            Some(Item::Pkcs8Key(key)) => Ok(key.secret_pkcs8_der().to_vec()),
+            _ => Err(AcmeError::Crypto(format!(
+                "Account key for ACME provider {:?} is not a PKCS#8 private key",
+                self.id
+            ))),
+        }
+    }
+
+    fn cache_path(&self, extension: &str) -> PathBuf {
+        self.cache.join(format!("cert-{}.{extension}", self.id))
+    }
+
+    fn clear_challenges(&self) {
+        if let Ok(mut challenges) = self.tls_alpn_challenges.lock() {
+            challenges.clear();
+        }
+        if let Ok(mut challenges) = self.http_challenges.lock() {
+            challenges.clear();
+        }
+    }
+}
+
+/// Writes `contents` next to `path` so it can be moved into place with a rename,
+/// restricting access to the owner when the file holds a private key.
+fn write_temp(path: &Path, contents: &[u8], private: bool) -> std::io::Result<PathBuf> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    // Leftovers from an interrupted write are replaced, as the mode only applies on creation
+    match std::fs::remove_file(&temp_path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+        _ => (),
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let mut file = options.open(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    Ok(temp_path)
+}
+
+async fn wait_for_order(
+    account: &Account,
+    url: &str,
+    mut order: Order,
+    while_status: OrderStatus,
+) -> Result<Order, AcmeError> {
+    for _ in 0..POLL_ATTEMPTS {
+        if order.status != while_status {
+            return Ok(order);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+        order = account.order(url).await?;
+    }
+
+    Err(AcmeError::Protocol(format!(
+        "Timed out waiting for order {url:?} to leave {while_status:?} status"
+    )))
+}
+
+fn order_error(order: Order) -> AcmeError {
+    AcmeError::Protocol(match order.error {
+        Some(problem) => format!("Order is {:?}: {problem}", order.status),
+        None => format!("Order is {:?}", order.status),
+    })
+}
+
+fn challenge_certificate(domain: &str, key_auth: &[u8]) -> Result<CertifiedKey, AcmeError> {
+    // Self-signed certificate carrying the acmeIdentifier extension (RFC 8737)
+    let mut params = CertificateParams::new(vec![domain.to_string()]);
+    params.alg = &PKCS_ECDSA_P256_SHA256;
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(key_auth)];
+    let cert = rcgen::Certificate::from_params(params).map_err(AcmeError::crypto)?;
+
+    Ok(CertifiedKey {
+        cert: vec![CertificateDer::from(
+            cert.serialize_der().map_err(AcmeError::crypto)?,
+        )],
+        key: any_supported_type(&PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            cert.serialize_private_key_der(),
+        )))
+        .map_err(AcmeError::crypto)?,
+        ocsp: None,
+    })
+}
+
+impl ChallengeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChallengeType::TlsAlpn01 => "tls-alpn-01",
+            ChallengeType::Http01 => "http-01",
+        }
+    }
+}
+
+impl Config {
+    pub fn parse_acme_providers(
+        &self,
+    ) -> crate::config::Result<AHashMap<String, Arc<AcmeProvider>>> {
+        let mut providers = AHashMap::new();
+
+        for id in self.sub_keys("acme") {
+            let domains = self
+                .values(("acme", id, "domains"))
+                .map(|(_, domain)| domain.trim().to_lowercase())
+                .collect::<Vec<_>>();
+            if domains.is_empty() {
+                return Err(format!("No domains configured for ACME provider {id:?}."));
+            }
+            let cache = PathBuf::from(self.value_require(("acme", id, "cache"))?);
+
+            // Load the last issued certificate from the cache, if any
+            let provider = AcmeProvider {
+                id: id.to_string(),
+                directory_url: self.value_require(("acme", id, "directory"))?.to_string(),
+                contact: self
+                    .values(("acme", id, "contact"))
+                    .map(|(_, contact)| contact.to_string())
+                    .collect(),
+                challenge: match self
+                    .value(("acme", id, "challenge"))
+                    .unwrap_or("tls-alpn-01")
+                {
+                    "tls-alpn-01" => ChallengeType::TlsAlpn01,
+                    "http-01" => ChallengeType::Http01,
+                    challenge => {
+                        return Err(format!(
+                            "Unsupported ACME challenge type {challenge:?} for provider {id:?}."
+                        ))
+                    }
+                },
+                renew_before: self
+                    .property(("acme", id, "renew-before"))?
+                    .unwrap_or(Duration::from_secs(30 * 24 * 60 * 60)),
+                cert: Arc::new(Certificate::from_files(
+                    format!("acme:{id}"),
+                    cache.join(format!("cert-{id}.pem")),
+                    cache.join(format!("cert-{id}.key")),
+                )),
+                account_key: if self.value(("acme", id, "account-key")).is_some() {
+                    Some(self.file_contents(("acme", id, "account-key"))?)
+                } else {
+                    None
+                },
+                cache,
+                domains,
+                tls_alpn_challenges: Mutex::new(AHashMap::new()),
+                http_challenges: Mutex::new(AHashMap::new()),
+            };
+
+            providers.insert(id.to_string(), Arc::new(provider));
+        }
+
+        Ok(providers)
+    }
+}
+
+impl AcmeError {
+    fn crypto(err: impl Display) -> Self {
+        AcmeError::Crypto(err.to_string())
+    }
+}
+
+impl From<reqwest::Error> for AcmeError {
+    fn from(err: reqwest::Error) -> Self {
+        AcmeError::Http(err)
+    }
+}
+
+impl From<serde_json::Error> for AcmeError {
+    fn from(err: serde_json::Error) -> Self {
+        AcmeError::Json(err)
+    }
+}
+
+impl From<std::io::Error> for AcmeError {
+    fn from(err: std::io::Error) -> Self {
+        AcmeError::Io(err)
+    }
+}
+
+impl Display for AcmeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AcmeError::Http(err) => write!(f, "HTTP error: {err}"),
+            AcmeError::Json(err) => write!(f, "JSON error: {err}"),
+            AcmeError::Io(err) => write!(f, "I/O error: {err}"),
+            AcmeError::Crypto(err) => write!(f, "Cryptography error: {err}"),
+            AcmeError::Protocol(err) => write!(f, "ACME error: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{next_renewal, renew_in, MIN_RENEW_INTERVAL};
+
+    #[test]
+    fn renewal_schedule() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let renew_before = 30 * day;
+
+        // Renewals are due `renew_before` ahead of the expiration
+        assert_eq!(renew_in(Some(90 * day), renew_before), 60 * day);
+        assert_eq!(next_renewal(Some(90 * day), renew_before), 60 * day);
+
+        // Missing, expired or soon to expire certificates are renewed right away
+        for valid_for in [
+            None,
+            Some(Duration::ZERO),
+            Some(10 * day),
+            Some(renew_before),
+        ] {
+            assert_eq!(renew_in(valid_for, renew_before), Duration::ZERO);
+        }
+        assert_eq!(next_renewal(None, renew_before), Duration::ZERO);
+
+        // Short-lived certificates that were just issued are not renewed back to back
+        assert_eq!(
+            next_renewal(Some(7 * day), renew_before),
+            MIN_RENEW_INTERVAL
+        );
+        assert_eq!(
+            next_renewal(Some(Duration::from_secs(3600)), renew_before),
+            Duration::from_secs(1800)
+        );
+    }
+}
@@ -21,38 +21,565 @@
  * for more details.
 */
 
-use std::{io::Cursor, sync::Arc};
+use std::{
+    io::Cursor,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use ahash::AHashMap;
+use p12::PFX;
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
 use rustls::{
-    server::{ClientHello, ResolvesServerCert, ResolvesServerCertUsingSni},
+    client::verify_server_name,
+    crypto::ring::{default_provider, sign::any_supported_type, Ticketer},
+    server::{
+        danger::ClientCertVerifier, ClientHello, ParsedCertificate, ProducesTickets,
+        ResolvesServerCert,
+    },
     sign::CertifiedKey,
     version::{TLS12, TLS13},
+    ServerConfig, SignatureAlgorithm, SignatureScheme, SupportedCipherSuite,
     SupportedProtocolVersion,
 };
 use rustls_pemfile::{certs, read_one, Item};
-use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use x509_parser::{
+    certificate::X509Certificate,
+    extensions::GeneralName,
+    oid_registry::{
+        OID_KEY_TYPE_EC_PUBLIC_KEY, OID_PKCS1_RSAENCRYPTION, OID_PKCS1_SHA256WITHRSA,
+        OID_PKCS1_SHA384WITHRSA, OID_PKCS1_SHA512WITHRSA, OID_SIG_ECDSA_WITH_SHA256,
+        OID_SIG_ECDSA_WITH_SHA384, OID_SIG_ED25519,
+    },
+    prelude::FromDer,
+};
+
+use crate::acme::{AcmeProvider, ACME_TLS_ALPN_NAME};
 
 use super::Config;
 
 pub static TLS13_VERSION: &[&SupportedProtocolVersion] = &[&TLS13];
 pub static TLS12_VERSION: &[&SupportedProtocolVersion] = &[&TLS12];
 
+/// Builds a server config for a listener, every protocol policy of the listener
+/// shares its certificate resolver and only differs in the settings passed here.
+/// An empty `ciphers` list enables all the cipher suites supported by the provider.
+pub fn build_server_config(
+    versions: &[&'static SupportedProtocolVersion],
+    ciphers: &[SupportedCipherSuite],
+    client_verifier: Arc<dyn ClientCertVerifier>,
+    cert_resolver: Arc<CertificateResolver>,
+    protocols: &[&[u8]],
+) -> Result<ServerConfig, rustls::Error> {
+    let mut provider = default_provider();
+    if !ciphers.is_empty() {
+        provider.cipher_suites = ciphers.to_vec();
+    }
+
+    let mut config = ServerConfig::builder_with_provider(provider.into())
+        .with_protocol_versions(versions)?
+        .with_client_cert_verifier(client_verifier)
+        .with_cert_resolver(cert_resolver);
+    config.alpn_protocols = protocols.iter().map(|protocol| protocol.to_vec()).collect();
+
+    Ok(config)
+}
+
 #[derive(Debug)]
 pub struct CertificateResolver {
-    pub resolver: Option<ResolvesServerCertUsingSni>,
-    pub default_cert: Option<Arc<CertifiedKey>>,
+    pub sni: AHashMap<String, Arc<Certificate>>,
+    pub default_cert: Option<Arc<Certificate>>,
+    pub acme: Option<Arc<AcmeProvider>>,
+}
+
+#[derive(Debug)]
+pub struct Certificate {
+    pub id: String,
+    key: RwLock<Option<Arc<CertifiedKey>>>,
+    source: Config,
+    modified: Mutex<Option<SystemTime>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientCertificate {
+    pub subject: Option<String>,
+    pub emails: Vec<String>,
+    pub dns_names: Vec<String>,
 }
 
 impl ResolvesServerCert for CertificateResolver {
     fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
-        self.resolver
-            .as_ref()
-            .and_then(|r| r.resolve(hello))
-            .or_else(|| self.default_cert.clone())
+        // Answer TLS-ALPN-01 challenges with the validation certificate
+        if let (Some(acme), Some(name)) = (&self.acme, hello.server_name()) {
+            if hello.alpn().map_or(false, |mut alpn| {
+                alpn.any(|proto| proto == ACME_TLS_ALPN_NAME)
+            }) {
+                return acme.tls_alpn_challenge(name);
+            }
+        }
+
+        // Certificates obtained through ACME might not have been issued yet
+        hello
+            .server_name()
+            .and_then(|name| self.sni_certificate(name))
+            .and_then(|cert| cert.certified_key())
+            .or_else(|| {
+                self.default_cert
+                    .as_ref()
+                    .and_then(|cert| cert.certified_key())
+            })
+    }
+}
+
+impl CertificateResolver {
+    pub fn add_sni(&mut self, name: &str, cert: Arc<Certificate>) -> Result<(), rustls::Error> {
+        // Wildcards are only allowed as the complete leftmost label (RFC 6125),
+        // the certificate is checked against a name the wildcard would match.
+        let server_name = if let Some(domain) = name.strip_prefix("*.") {
+            if !domain.contains('.') {
+                return Err(rustls::Error::General(
+                    "Wildcard names require at least two labels".into(),
+                ));
+            }
+            ServerName::try_from(format!("wildcard.{domain}"))
+        } else {
+            ServerName::try_from(name.to_string())
+        }
+        .map_err(|_| rustls::Error::General("Bad DNS name".into()))?;
+        if let Some(key) = cert.certified_key() {
+            key.end_entity_cert()
+                .and_then(ParsedCertificate::try_from)
+                .and_then(|parsed| verify_server_name(&parsed, &server_name))?;
+        }
+        self.sni.insert(name.to_lowercase(), cert);
+        Ok(())
+    }
+
+    // Exact names take precedence over wildcards, which match a single label only
+    pub fn sni_certificate(&self, name: &str) -> Option<&Arc<Certificate>> {
+        let name = name.to_lowercase();
+        self.sni.get(&name).or_else(|| {
+            name.split_once('.')
+                .filter(|(label, domain)| !label.is_empty() && domain.contains('.'))
+                .and_then(|(_, domain)| self.sni.get(&format!("*.{domain}")))
+        })
+    }
+}
+
+impl Certificate {
+    pub fn from_files(id: impl Into<String>, cert: PathBuf, private_key: PathBuf) -> Self {
+        // Missing or invalid files leave the certificate empty until reloaded
+        let id = id.into();
+        let source = Config {
+            keys: [
+                (
+                    format!("certificate.{id}.cert"),
+                    format!("file://{}", cert.display()),
+                ),
+                (
+                    format!("certificate.{id}.private-key"),
+                    format!("file://{}", private_key.display()),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        Certificate {
+            key: RwLock::new(source.rustls_certified_key(&id).ok().map(Arc::new)),
+            modified: Mutex::new(source.files_modified()),
+            source,
+            id,
+        }
+    }
+
+    pub fn self_signed(hostname: &str) -> super::Result<Self> {
+        let cert =
+            rcgen::generate_simple_self_signed(vec![hostname.to_string()]).map_err(|err| {
+                format!("Failed to generate self-signed certificate for {hostname:?}: {err}")
+            })?;
+        let certified_key = CertifiedKey {
+            cert: vec![CertificateDer::from(cert.serialize_der().map_err(
+                |err| {
+                    format!("Failed to serialize self-signed certificate for {hostname:?}: {err}")
+                },
+            )?)],
+            key: any_supported_type(&PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+                cert.serialize_private_key_der(),
+            )))
+            .map_err(|err| {
+                format!("Failed to sign self-signed certificate for {hostname:?}: {err}")
+            })?,
+            ocsp: None,
+        };
+
+        tracing::warn!(
+            context = "tls",
+            event = "self-signed",
+            hostname = hostname,
+            "No certificate configured, using an insecure in-memory self-signed certificate."
+        );
+
+        Ok(Certificate {
+            id: format!("self-signed:{hostname}"),
+            key: RwLock::new(Some(Arc::new(certified_key))),
+            source: Config::default(),
+            modified: Mutex::new(None),
+        })
+    }
+
+    pub fn certified_key(&self) -> Option<Arc<CertifiedKey>> {
+        self.key.read().ok().and_then(|key| key.clone())
+    }
+
+    pub fn days_until_expiry(&self) -> Option<i64> {
+        self.certified_key()
+            .and_then(|key| key.cert.first().and_then(days_until_expiry))
+    }
+
+    pub fn is_watched(&self) -> bool {
+        self.source.files_modified().is_some()
+    }
+
+    pub fn reload_if_changed(&self) -> bool {
+        // Check whether any of the certificate files changed on disk
+        let modified = self.source.files_modified();
+        if let Ok(mut last_modified) = self.modified.lock() {
+            if modified <= *last_modified {
+                return false;
+            }
+            *last_modified = modified;
+        } else {
+            return false;
+        }
+
+        self.reload()
+    }
+
+    pub fn reload(&self) -> bool {
+        // Replace the certified key only if the new pair loads successfully
+        match self.source.rustls_certified_key(&self.id) {
+            Ok(certified_key) => {
+                if let Ok(mut key) = self.key.write() {
+                    *key = Some(Arc::new(certified_key));
+                }
+                tracing::info!(
+                    context = "tls",
+                    event = "reload",
+                    id = self.id,
+                    "Reloaded certificate."
+                );
+                true
+            }
+            Err(err) => {
+                tracing::error!(
+                    context = "tls",
+                    event = "error",
+                    id = self.id,
+                    "Failed to reload certificate, keeping previous one: {}",
+                    err
+                );
+                false
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TicketRotator {
+    rotation: Duration,
+    state: Mutex<TicketRotatorState>,
+}
+
+#[derive(Debug)]
+struct TicketRotatorState {
+    current: Arc<dyn ProducesTickets>,
+    previous: Option<Arc<dyn ProducesTickets>>,
+    next_rotation: Instant,
+}
+
+impl TicketRotator {
+    pub fn new(rotation: Duration) -> Result<Self, rustls::Error> {
+        Ok(TicketRotator {
+            rotation,
+            state: Mutex::new(TicketRotatorState {
+                current: Ticketer::new()?,
+                previous: None,
+                next_rotation: Instant::now() + rotation,
+            }),
+        })
+    }
+
+    fn ticketers(&self) -> Option<(Arc<dyn ProducesTickets>, Option<Arc<dyn ProducesTickets>>)> {
+        let mut state = self.state.lock().ok()?;
+        let now = Instant::now();
+        if now >= state.next_rotation {
+            // Replace the ticket keys, keeping the previous ones only if they
+            // were rotated out less than one rotation interval ago.
+            let next = Ticketer::new().ok()?;
+            let previous = std::mem::replace(&mut state.current, next);
+            state.previous = if now < state.next_rotation + self.rotation {
+                Some(previous)
+            } else {
+                None
+            };
+            state.next_rotation = now + self.rotation;
+        }
+        Some((state.current.clone(), state.previous.clone()))
+    }
+}
+
+impl ProducesTickets for TicketRotator {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        // Tickets are accepted for no longer than two rotation intervals
+        std::cmp::min(self.rotation.as_secs().saturating_mul(2), u32::MAX as u64) as u32
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        self.ticketers()?.0.encrypt(plain)
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        let (current, previous) = self.ticketers()?;
+        current
+            .decrypt(cipher)
+            .or_else(|| previous.and_then(|previous| previous.decrypt(cipher)))
+    }
+}
+
+impl ClientCertificate {
+    pub fn parse(cert: &CertificateDer<'_>) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(cert.as_ref()).ok()?;
+        let mut client_cert = ClientCertificate {
+            subject: cert
+                .subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(|cn| cn.to_string()),
+            ..Default::default()
+        };
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::RFC822Name(email) => {
+                        client_cert.emails.push(email.to_lowercase());
+                    }
+                    GeneralName::DNSName(name) => {
+                        client_cert.dns_names.push(name.to_lowercase());
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        Some(client_cert)
+    }
+
+    // Names to map to a principal, in order of preference
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.emails
+            .iter()
+            .chain(self.dns_names.iter())
+            .map(|name| name.as_str())
+            .chain(self.subject.as_deref())
     }
 }
 
 impl Config {
+    pub fn parse_certificate(&self, cert_id: &str) -> super::Result<Certificate> {
+        // Keep a copy of the certificate properties so it can be reloaded later
+        let prefix = format!("certificate.{cert_id}.");
+        let source = Config {
+            keys: self
+                .keys
+                .iter()
+                .filter(|(key, _)| key.starts_with(&prefix))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        };
+        let modified = source.files_modified();
+
+        Ok(Certificate {
+            id: cert_id.to_string(),
+            key: RwLock::new(Some(Arc::new(source.rustls_certified_key(cert_id)?))),
+            source,
+            modified: Mutex::new(modified),
+        })
+    }
+
+    pub fn rustls_certified_key(&self, cert_id: &str) -> super::Result<CertifiedKey> {
+        let (cert, key) = if self.value(("certificate", cert_id, "pkcs12")).is_some() {
+            self.rustls_pkcs12(
+                cert_id,
+                self.value(("certificate", cert_id, "password"))
+                    .unwrap_or_default(),
+            )?
+        } else {
+            (
+                self.rustls_certificate(cert_id)?,
+                self.rustls_private_key(cert_id)?,
+            )
+        };
+
+        let certified_key = CertifiedKey {
+            cert: self.check_certificate_chain(cert_id, cert)?,
+            key: any_supported_type(&key)
+                .map_err(|err| format!("Failed to sign certificate id {cert_id:?}: {err}"))?,
+            ocsp: None,
+        };
+
+        // Catch mismatched key pairs now rather than at the first handshake
+        verify_key_pair(&certified_key).map_err(|err| {
+            format!("Private key does not match certificate id {cert_id:?}: {err}")
+        })?;
+
+        // Unusual validity periods are not an error, the warning is just skipped
+        if let Some(days) = certified_key.cert.first().and_then(days_until_expiry) {
+            let window = self
+                .property_or_static::<Duration>(("certificate", cert_id, "expiry-warning"), "30d")?
+                .as_secs()
+                / 86400;
+            if days < 0 {
+                tracing::warn!(
+                    context = "tls",
+                    event = "expired",
+                    id = cert_id,
+                    days = days,
+                    "Certificate has expired."
+                );
+            } else if days as u64 <= window {
+                tracing::warn!(
+                    context = "tls",
+                    event = "expiring",
+                    id = cert_id,
+                    days = days,
+                    "Certificate expires in {} days.",
+                    days
+                );
+            }
+        }
+
+        Ok(certified_key)
+    }
+
+    pub fn rustls_pkcs12(
+        &self,
+        cert_id: &str,
+        password: &str,
+    ) -> super::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+        let pfx = PFX::parse(&self.file_contents(("certificate", cert_id, "pkcs12"))?).map_err(
+            |err| {
+                format!("Failed to parse PKCS#12 bundle in \"certificate.{cert_id}.pkcs12\": {err}")
+            },
+        )?;
+        if !pfx.verify_mac(password) {
+            return Err(format!(
+                "Invalid password for PKCS#12 bundle in \"certificate.{cert_id}.pkcs12\"."
+            ));
+        }
+
+        // Obtain certificate chain, placing the leaf certificate first
+        let mut certs = pfx
+            .cert_x509_bags(password)
+            .map_err(|err| {
+                format!("Failed to read certificates in \"certificate.{cert_id}.pkcs12\": {err}")
+            })?
+            .into_iter()
+            .map(CertificateDer::from)
+            .collect::<Vec<_>>();
+        if certs.is_empty() {
+            return Err(format!(
+                "No certificates found in \"certificate.{cert_id}.pkcs12\"."
+            ));
+        }
+        if let Some(leaf_pos) = leaf_position(&certs) {
+            certs.swap(0, leaf_pos);
+        }
+
+        // Obtain private key
+        let mut keys = pfx.key_bags(password).map_err(|err| {
+            format!("Failed to read private keys in \"certificate.{cert_id}.pkcs12\": {err}")
+        })?;
+        match keys.len() {
+            1 => Ok((
+                certs,
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(keys.pop().unwrap())),
+            )),
+            0 => Err(format!(
+                "No private keys found in \"certificate.{cert_id}.pkcs12\"."
+            )),
+            n => Err(format!(
+                "Found {n} private keys in \"certificate.{cert_id}.pkcs12\", expected only one."
+            )),
+        }
+    }
+
+    // Misordered chains are accepted by some clients and rejected by others,
+    // so they are reported when loading the certificate.
+    fn check_certificate_chain(
+        &self,
+        cert_id: &str,
+        certs: Vec<CertificateDer<'static>>,
+    ) -> super::Result<Vec<CertificateDer<'static>>> {
+        let reject = match self
+            .value(("certificate", cert_id, "chain.check"))
+            .unwrap_or("warn")
+        {
+            "disable" => return Ok(certs),
+            "warn" => false,
+            "reject" => true,
+            value => {
+                return Err(format!(
+                    "Invalid value {value:?} for property \"certificate.{cert_id}.chain.check\", expected disable, warn or reject."
+                ))
+            }
+        };
+        let certs = if self
+            .property_or_static::<bool>(("certificate", cert_id, "chain.reorder"), "false")?
+        {
+            order_chain(certs)
+        } else {
+            certs
+        };
+
+        if let Err(err) = verify_chain_order(&certs) {
+            if reject {
+                return Err(format!(
+                    "Invalid certificate chain for certificate id {cert_id:?}: {err}"
+                ));
+            } else {
+                tracing::warn!(
+                    context = "tls",
+                    event = "invalid-chain",
+                    id = cert_id,
+                    "Certificate chain is not ordered from leaf to root: {}",
+                    err
+                );
+            }
+        }
+
+        Ok(certs)
+    }
+
+    fn files_modified(&self) -> Option<SystemTime> {
+        self.keys
+            .values()
+            .filter_map(|value| value.strip_prefix("file://"))
+            .filter_map(|path| {
+                std::fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            })
+            .max()
+    }
+
     pub fn rustls_certificate(&self, cert_id: &str) -> super::Result<Vec<CertificateDer<'static>>> {
         let certs = certs(&mut Cursor::new(self.file_contents((
             "certificate",
@@ -97,3 +624,342 @@ impl Config {
         }
     }
 }
+
+fn verify_key_pair(certified_key: &CertifiedKey) -> Result<(), String> {
+    let leaf = certified_key
+        .cert
+        .first()
+        .ok_or_else(|| "no certificates found".to_string())?;
+    let (_, cert) = X509Certificate::from_der(leaf.as_ref())
+        .map_err(|err| format!("failed to parse leaf certificate: {err}"))?;
+    let public_key = cert.public_key();
+
+    // Compare the key algorithms
+    let key_algorithm = certified_key.key.algorithm();
+    let cert_algorithm = match &public_key.algorithm.algorithm {
+        oid if *oid == OID_PKCS1_RSAENCRYPTION => SignatureAlgorithm::RSA,
+        oid if *oid == OID_KEY_TYPE_EC_PUBLIC_KEY => SignatureAlgorithm::ECDSA,
+        oid if *oid == OID_SIG_ED25519 => SignatureAlgorithm::ED25519,
+        oid => {
+            return Err(format!(
+                "unsupported certificate public key algorithm {oid}"
+            ))
+        }
+    };
+    if key_algorithm != cert_algorithm {
+        return Err(format!(
+            "{key_algorithm:?} private key used with a {cert_algorithm:?} certificate"
+        ));
+    }
+
+    // Sign a test message and verify it using the certificate's public key
+    let signer = certified_key
+        .key
+        .choose_scheme(&[
+            SignatureScheme::ED25519,
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ])
+        .ok_or_else(|| format!("unsupported {key_algorithm:?} private key"))?;
+    let verify_algorithm: &'static dyn VerificationAlgorithm = match signer.scheme() {
+        SignatureScheme::ED25519 => &signature::ED25519,
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &signature::ECDSA_P256_SHA256_ASN1,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &signature::ECDSA_P384_SHA384_ASN1,
+        SignatureScheme::RSA_PSS_SHA256 => &signature::RSA_PSS_2048_8192_SHA256,
+        SignatureScheme::RSA_PKCS1_SHA256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+        scheme => return Err(format!("unsupported signature scheme {scheme:?}")),
+    };
+    let message = b"stalwart key pair validation";
+    let signature = signer
+        .sign(message)
+        .map_err(|err| format!("failed to sign with private key: {err}"))?;
+    UnparsedPublicKey::new(
+        verify_algorithm,
+        public_key.subject_public_key.data.as_ref(),
+    )
+    .verify(message, &signature)
+    .map_err(|_| "private key does not belong to the leaf certificate".to_string())
+}
+
+fn days_until_expiry(cert: &CertificateDer<'_>) -> Option<i64> {
+    let (_, cert) = X509Certificate::from_der(cert.as_ref()).ok()?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs()) as i64;
+    Some((cert.validity().not_after.timestamp() - now).div_euclid(86400))
+}
+
+fn leaf_position(certs: &[CertificateDer<'_>]) -> Option<usize> {
+    // The leaf certificate is the one that did not issue any other certificate in the chain
+    let certs = certs
+        .iter()
+        .map(|cert| {
+            X509Certificate::from_der(cert.as_ref())
+                .ok()
+                .map(|(_, cert)| cert)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    certs.iter().position(|cert| {
+        !certs.iter().any(|other| {
+            !std::ptr::eq(cert, other) && other.issuer().as_raw() == cert.subject().as_raw()
+        })
+    })
+}
+
+// Returns whether `cert` names `issuer` as its issuer and carries a valid signature
+// from it. Signatures made with algorithms that cannot be verified are assumed valid.
+fn is_issued_by(cert: &X509Certificate<'_>, issuer: &X509Certificate<'_>) -> bool {
+    if cert.issuer().as_raw() != issuer.subject().as_raw() {
+        return false;
+    }
+
+    let algorithms: &[&'static dyn VerificationAlgorithm] =
+        match &cert.signature_algorithm.algorithm {
+            oid if *oid == OID_PKCS1_SHA256WITHRSA => &[&signature::RSA_PKCS1_2048_8192_SHA256],
+            oid if *oid == OID_PKCS1_SHA384WITHRSA => &[&signature::RSA_PKCS1_2048_8192_SHA384],
+            oid if *oid == OID_PKCS1_SHA512WITHRSA => &[&signature::RSA_PKCS1_2048_8192_SHA512],
+            oid if *oid == OID_SIG_ECDSA_WITH_SHA256 => &[
+                &signature::ECDSA_P256_SHA256_ASN1,
+                &signature::ECDSA_P384_SHA256_ASN1,
+            ],
+            oid if *oid == OID_SIG_ECDSA_WITH_SHA384 => &[
+                &signature::ECDSA_P384_SHA384_ASN1,
+                &signature::ECDSA_P256_SHA384_ASN1,
+            ],
+            oid if *oid == OID_SIG_ED25519 => &[&signature::ED25519],
+            _ => return true,
+        };
+    algorithms.iter().any(|algorithm| {
+        UnparsedPublicKey::new(
+            *algorithm,
+            issuer.public_key().subject_public_key.data.as_ref(),
+        )
+        .verify(
+            cert.tbs_certificate.as_ref(),
+            cert.signature_value.data.as_ref(),
+        )
+        .is_ok()
+    })
+}
+
+fn verify_chain_order(certs: &[CertificateDer<'_>]) -> Result<(), String> {
+    let parsed = certs
+        .iter()
+        .map(|cert| {
+            X509Certificate::from_der(cert.as_ref())
+                .map(|(_, cert)| cert)
+                .map_err(|err| format!("failed to parse certificate: {err}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The last certificate may be issued by a root that is not part of the chain
+    for (pos, pair) in parsed.windows(2).enumerate() {
+        if !is_issued_by(&pair[0], &pair[1]) {
+            return Err(format!(
+                "certificate {} ({}) is not issued by certificate {} ({})",
+                pos,
+                pair[0].subject(),
+                pos + 1,
+                pair[1].subject()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Places the leaf first followed by each certificate's issuer. Certificates that
+// are not part of the chain are kept at the end.
+fn order_chain(certs: Vec<CertificateDer<'static>>) -> Vec<CertificateDer<'static>> {
+    let parsed = if let Some(parsed) = certs
+        .iter()
+        .map(|cert| {
+            X509Certificate::from_der(cert.as_ref())
+                .ok()
+                .map(|(_, cert)| cert)
+        })
+        .collect::<Option<Vec<_>>>()
+    {
+        parsed
+    } else {
+        return certs;
+    };
+    let mut pos = if let Some(pos) = leaf_position(&certs) {
+        pos
+    } else {
+        return certs;
+    };
+
+    let mut order = vec![pos];
+    while let Some(issuer_pos) = (0..parsed.len()).find(|issuer_pos| {
+        !order.contains(issuer_pos) && is_issued_by(&parsed[pos], &parsed[*issuer_pos])
+    }) {
+        order.push(issuer_pos);
+        pos = issuer_pos;
+    }
+    order.extend((0..certs.len()).filter(|pos| !order.contains(pos)));
+
+    let mut certs = certs.into_iter().map(Some).collect::<Vec<_>>();
+    order
+        .into_iter()
+        .filter_map(|pos| certs[pos].take())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ahash::AHashMap;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+    use rustls::{
+        crypto::ring::cipher_suite::TLS13_AES_256_GCM_SHA384, server::NoClientAuth, ALL_VERSIONS,
+    };
+    use rustls_pki_types::CertificateDer;
+
+    use super::{
+        days_until_expiry, order_chain, verify_chain_order, CertificateResolver, TLS12_VERSION,
+        TLS13_VERSION,
+    };
+
+    fn ca(name: &str) -> Certificate {
+        let mut params = CertificateParams::new(vec![]);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        Certificate::from_params(params).unwrap()
+    }
+
+    #[test]
+    fn certificate_chain_order() {
+        let root = ca("Root CA");
+        let intermediate = ca("Intermediate CA");
+        let leaf =
+            Certificate::from_params(CertificateParams::new(vec!["mail.example.org".to_string()]))
+                .unwrap();
+        let leaf_der = CertificateDer::from(leaf.serialize_der_with_signer(&intermediate).unwrap());
+        let intermediate_der =
+            CertificateDer::from(intermediate.serialize_der_with_signer(&root).unwrap());
+        let root_der = CertificateDer::from(root.serialize_der().unwrap());
+
+        let ordered = vec![leaf_der.clone(), intermediate_der.clone(), root_der.clone()];
+        assert!(verify_chain_order(&ordered).is_ok());
+        assert!(verify_chain_order(&ordered[..2]).is_ok());
+
+        let misordered = vec![intermediate_der.clone(), root_der.clone(), leaf_der.clone()];
+        assert!(verify_chain_order(&misordered).is_err());
+        assert!(verify_chain_order(&[leaf_der.clone(), root_der.clone()]).is_err());
+        assert_eq!(order_chain(misordered), ordered);
+
+        // Same names but signed by a different key
+        let impostor = CertificateDer::from(
+            ca("Intermediate CA")
+                .serialize_der_with_signer(&root)
+                .unwrap(),
+        );
+        assert!(verify_chain_order(&[leaf_der, impostor]).is_err());
+    }
+
+    #[test]
+    fn build_server_config() {
+        let cert_resolver = Arc::new(CertificateResolver {
+            sni: AHashMap::new(),
+            default_cert: None,
+            acme: None,
+        });
+
+        let config = super::build_server_config(
+            ALL_VERSIONS,
+            &[],
+            Arc::new(NoClientAuth),
+            cert_resolver.clone(),
+            &[b"h2", b"http/1.1"],
+        )
+        .unwrap();
+        assert_eq!(
+            config.alpn_protocols,
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+        assert!(super::build_server_config(
+            TLS13_VERSION,
+            &[TLS13_AES_256_GCM_SHA384],
+            Arc::new(NoClientAuth),
+            cert_resolver.clone(),
+            &[],
+        )
+        .unwrap()
+        .alpn_protocols
+        .is_empty());
+
+        // Cipher suites must be usable with the enabled protocol versions
+        assert!(super::build_server_config(
+            TLS12_VERSION,
+            &[TLS13_AES_256_GCM_SHA384],
+            Arc::new(NoClientAuth),
+            cert_resolver,
+            &[],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn certificate_expiry() {
+        let mut params = CertificateParams::new(vec!["mail.example.org".to_string()]);
+        params.not_after = rcgen::date_time_ymd(2000, 1, 1);
+        let expired = CertificateDer::from(
+            Certificate::from_params(params)
+                .unwrap()
+                .serialize_der()
+                .unwrap(),
+        );
+        assert!(days_until_expiry(&expired).unwrap() < 0);
+
+        let mut params = CertificateParams::new(vec!["mail.example.org".to_string()]);
+        params.not_after = rcgen::date_time_ymd(9999, 1, 1);
+        let valid = CertificateDer::from(
+            Certificate::from_params(params)
+                .unwrap()
+                .serialize_der()
+                .unwrap(),
+        );
+        assert!(days_until_expiry(&valid).unwrap() > 365);
+        assert_eq!(
+            days_until_expiry(&CertificateDer::from(vec![1, 2, 3])),
+            None
+        );
+    }
+
+    #[test]
+    fn sni_wildcard_match() {
+        let mut resolver = CertificateResolver {
+            sni: AHashMap::new(),
+            default_cert: None,
+            acme: None,
+        };
+        for name in ["*.example.org", "mail.example.org", "*.org"] {
+            resolver.sni.insert(
+                name.to_string(),
+                Arc::new(super::Certificate::self_signed(name).unwrap()),
+            );
+        }
+
+        for (name, expected) in [
+            ("mail.example.org", Some("self-signed:mail.example.org")),
+            ("MAIL.Example.org", Some("self-signed:mail.example.org")),
+            ("imap.example.org", Some("self-signed:*.example.org")),
+            ("a.b.example.org", None),
+            ("example.org", None),
+            (".example.org", None),
+            ("example.com", None),
+        ] {
+            assert_eq!(
+                resolver.sni_certificate(name).map(|cert| cert.id.as_str()),
+                expected,
+                "{name}"
+            );
+        }
+    }
+}
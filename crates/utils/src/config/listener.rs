@@ -21,29 +21,35 @@
  * for more details.
 */
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{io::Cursor, net::SocketAddr, sync::Arc, time::Duration};
 
+use ahash::AHashMap;
 use rustls::{
-    crypto::ring::{
-        cipher_suite::{
-            TLS13_AES_128_GCM_SHA256, TLS13_AES_256_GCM_SHA384, TLS13_CHACHA20_POLY1305_SHA256,
-            TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256, TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
-            TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256, TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
-            TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384, TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
-        },
-        default_provider,
-        sign::any_supported_type,
+    crypto::ring::cipher_suite::{
+        TLS13_AES_128_GCM_SHA256, TLS13_AES_256_GCM_SHA384, TLS13_CHACHA20_POLY1305_SHA256,
+        TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256, TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+        TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256, TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+        TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384, TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
     },
-    server::ResolvesServerCertUsingSni,
-    sign::CertifiedKey,
-    ServerConfig, SupportedCipherSuite, ALL_VERSIONS,
+    server::{
+        danger::ClientCertVerifier, NoClientAuth, NoServerSessionStorage, ServerSessionMemoryCache,
+        WebPkiClientVerifier,
+    },
+    RootCertStore, SupportedCipherSuite, ALL_VERSIONS,
 };
+use rustls_pemfile::certs;
 use tokio::net::TcpSocket;
 
-use crate::UnwrapFailure;
+use crate::{
+    acme::{AcmeProvider, ChallengeType, ACME_TLS_ALPN_NAME},
+    UnwrapFailure,
+};
 
 use super::{
-    certificate::{CertificateResolver, TLS12_VERSION, TLS13_VERSION},
+    certificate::{
+        build_server_config, Certificate, CertificateResolver, TicketRotator, TLS12_VERSION,
+        TLS13_VERSION,
+    },
     utils::{AsKey, ParseKey, ParseValue},
     Config, Listener, Server, ServerProtocol, Servers,
 };
@@ -51,8 +57,10 @@ use super::{
 impl Config {
     pub fn parse_servers(&self) -> super::Result<Servers> {
         let mut servers: Vec<Server> = Vec::new();
+        let mut certificates = AHashMap::new();
+        let acme_providers = self.parse_acme_providers()?;
         for (internal_id, id) in self.sub_keys("server.listener").enumerate() {
-            let mut server = self.parse_server(id)?;
+            let mut server = self.parse_server(id, &mut certificates, &acme_providers)?;
             if !servers.iter().any(|s| s.id == server.id) {
                 server.internal_id = internal_id as u16;
                 servers.push(server);
@@ -62,15 +70,31 @@ impl Config {
         }
 
         if !servers.is_empty() {
-            Ok(Servers { inner: servers })
+            Ok(Servers {
+                inner: servers,
+                certificates: certificates.into_values().collect(),
+                acme_providers: acme_providers.into_values().collect(),
+                reload_interval: if self.property("server.tls.reload.enable")?.unwrap_or(true) {
+                    self.property("server.tls.reload.interval")?
+                        .unwrap_or(Duration::from_secs(60))
+                        .into()
+                } else {
+                    None
+                },
+            })
         } else {
             Err("No server directives found in config file.".to_string())
         }
     }
 
-    fn parse_server(&self, id: &str) -> super::Result<Server> {
+    fn parse_server(
+        &self,
+        id: &str,
+        certificates: &mut AHashMap<String, Arc<Certificate>>,
+        acme_providers: &AHashMap<String, Arc<AcmeProvider>>,
+    ) -> super::Result<Server> {
         // Build TLS config
-        let (tls, tls_implicit) = if self
+        let (tls, tls_policies, tls_acme, tls_implicit) = if self
             .property_or_default(("server.listener", id, "tls.enable"), "server.tls.enable")?
             .unwrap_or(false)
         {
@@ -92,6 +116,14 @@ impl Config {
                 }
             }
 
+            let versions = if tls_v3 == tls_v2 {
+                ALL_VERSIONS
+            } else if tls_v3 {
+                TLS13_VERSION
+            } else {
+                TLS12_VERSION
+            };
+
             // Parse cipher suites
             let mut ciphers: Vec<SupportedCipherSuite> = Vec::new();
             for (key, protocol) in
@@ -100,85 +132,188 @@ impl Config {
                 ciphers.push(protocol.parse_key(key)?);
             }
 
-            // Obtain default certificate
-            let cert_id = self
-                .value_or_default(
-                    ("server.listener", id, "tls.certificate"),
-                    "server.tls.certificate",
-                )
-                .ok_or_else(|| format!("Undefined certificate id for listener {id:?}."))?;
-            let cert = self.rustls_certificate(cert_id)?;
-            let pki = self.rustls_private_key(cert_id)?;
+            // Obtain ACME provider
+            let acme = if let Some(acme_id) =
+                self.value_or_default(("server.listener", id, "tls.acme"), "server.tls.acme")
+            {
+                Some(acme_providers.get(acme_id).cloned().ok_or_else(|| {
+                    format!("Undefined ACME provider {acme_id:?} for listener {id:?}.")
+                })?)
+            } else {
+                None
+            };
 
-            // Add SNI certificates
-            let mut resolver = ResolvesServerCertUsingSni::new();
-            let mut has_sni = false;
+            // Obtain default certificate, which is optional when using ACME
+            let cert_id = self.value_or_default(
+                ("server.listener", id, "tls.certificate"),
+                "server.tls.certificate",
+            );
+            let mut cert_resolver = CertificateResolver {
+                sni: AHashMap::new(),
+                default_cert: match (cert_id, &acme) {
+                    (Some(cert_id), _) => self.certificate(cert_id, certificates)?.into(),
+                    (None, Some(acme)) => acme.cert.clone().into(),
+                    (None, None) if self.property("certificate.self-signed")?.unwrap_or(false) => {
+                        // Opt-in fallback for first boot, never enabled by default
+                        let hostname = self
+                            .value_or_default(
+                                ("server.listener", id, "hostname"),
+                                "server.hostname",
+                            )
+                            .ok_or("Hostname directive not found.")?;
+                        let cert_id = format!("self-signed:{hostname}");
+                        if let Some(cert) = certificates.get(&cert_id) {
+                            cert.clone().into()
+                        } else {
+                            let cert = Arc::new(Certificate::self_signed(hostname)?);
+                            certificates.insert(cert_id, cert.clone());
+                            cert.into()
+                        }
+                    }
+                    (None, None) => {
+                        return Err(format!("Undefined certificate id for listener {id:?}."))
+                    }
+                },
+                acme: None,
+            };
+
+            // Add ACME certificates, which are validated only once issued
+            if let Some(acme) = &acme {
+                for domain in &acme.domains {
+                    cert_resolver.sni.insert(domain.clone(), acme.cert.clone());
+                }
+            }
+
+            // Add SNI certificates, broken ones are skipped unless they are also the default
+            let skip_broken = match self.value("certificate.on-error").unwrap_or("skip-broken") {
+                "skip-broken" => true,
+                "fail-fast" => false,
+                value => {
+                    return Err(format!(
+                        "Invalid value {value:?} for property \"certificate.on-error\", expected fail-fast or skip-broken."
+                    ))
+                }
+            };
+            let mut sni_policies = Vec::new();
             for (key, value) in
                 self.values_or_default(("server.listener", id, "tls.sni"), "server.tls.sni")
             {
                 if let Some(prefix) = key.strip_suffix(".subject") {
-                    has_sni = true;
-                    resolver
-                        .add(
-                            value,
-                            match self.value((prefix, "certificate")) {
-                                Some(sni_cert_id) if sni_cert_id != cert_id => CertifiedKey {
-                                    cert: self.rustls_certificate(sni_cert_id)?,
-                                    key: any_supported_type(&self.rustls_private_key(sni_cert_id)?)
-                                        .map_err(|err| {
-                                            format!(
-                                                "Failed to sign SNI certificate for {key:?}: {err}",
-                                            )
-                                        })?,
-                                    ocsp: None,
-                                },
-                                _ => CertifiedKey {
-                                    cert: cert.clone(),
-                                    key:
-                                        any_supported_type(&pki).map_err(|err| {
-                                            format!(
-                                                "Failed to sign SNI certificate for {key:?}: {err}",
-                                            )
-                                        })?,
-                                    ocsp: None,
-                                },
+                    let sni_cert_id = self
+                        .value((prefix, "certificate"))
+                        .or(cert_id)
+                        .ok_or_else(|| format!("Undefined certificate id for {prefix:?}."))?;
+                    if let Err(err) = self
+                        .certificate(sni_cert_id, certificates)
+                        .and_then(|cert| {
+                            cert_resolver.add_sni(value, cert).map_err(|err| {
+                                format!("Failed to add SNI certificate for {key:?}: {err}")
+                            })
+                        })
+                    {
+                        if skip_broken && Some(sni_cert_id) != cert_id {
+                            tracing::error!(
+                                context = "tls",
+                                event = "error",
+                                id = sni_cert_id,
+                                listener = id,
+                                "Skipping broken SNI certificate for {:?}: {}",
+                                value,
+                                err
+                            );
+                            continue;
+                        } else {
+                            return Err(err);
+                        }
+                    }
+
+                    // Parse SNI protocol policy
+                    let min_version = self.value((prefix, "min-version"));
+                    let mut sni_ciphers: Vec<SupportedCipherSuite> = Vec::new();
+                    for (key, protocol) in self.values((prefix, "ciphers")) {
+                        sni_ciphers.push(protocol.parse_key(key)?);
+                    }
+                    if min_version.is_some() || !sni_ciphers.is_empty() {
+                        sni_policies.push((
+                            value.to_lowercase(),
+                            match min_version {
+                                None => versions,
+                                Some("TLSv1.2" | "0x0303") => ALL_VERSIONS,
+                                Some("TLSv1.3" | "0x0304") => TLS13_VERSION,
+                                Some(protocol) => {
+                                    return Err(format!(
+                                        "Unsupported TLS protocol {protocol:?} found in key \"{prefix}.min-version\"",
+                                    ))
+                                }
                             },
-                        )
-                        .map_err(|err| {
-                            format!("Failed to add SNI certificate for {key:?}: {err}")
-                        })?;
+                            sni_ciphers,
+                        ));
+                    }
                 }
             }
 
-            // Add default certificate
-            let default_cert = Some(Arc::new(CertifiedKey {
-                cert,
-                key: any_supported_type(&pki)
-                    .map_err(|err| format!("Failed to sign certificate id {cert_id:?}: {err}"))?,
-                ocsp: None,
-            }));
-
-            // Build cert provider
-            let mut provider = default_provider();
-            if !ciphers.is_empty() {
-                provider.cipher_suites = ciphers;
-            }
+            // Build client certificate verifier
+            let client_verifier: Arc<dyn ClientCertVerifier> = match self
+                .value_or_default(
+                    ("server.listener", id, "tls.client-auth.mode"),
+                    "server.tls.client-auth.mode",
+                )
+                .unwrap_or("none")
+            {
+                "none" => Arc::new(NoClientAuth),
+                mode @ ("optional" | "required") => {
+                    let ca_key = if self
+                        .value(("server.listener", id, "tls.client-auth.ca"))
+                        .is_some()
+                    {
+                        format!("server.listener.{id}.tls.client-auth.ca")
+                    } else {
+                        "server.tls.client-auth.ca".to_string()
+                    };
+                    let mut roots = RootCertStore::empty();
+                    for cert in certs(&mut Cursor::new(self.file_contents(ca_key.as_str())?)) {
+                        roots
+                            .add(cert.map_err(|err| {
+                                format!(
+                                    "Failed to read client CA certificates in {ca_key:?}: {err}"
+                                )
+                            })?)
+                            .map_err(|err| {
+                                format!("Failed to add client CA certificate in {ca_key:?}: {err}")
+                            })?;
+                    }
+                    let mut builder = WebPkiClientVerifier::builder(roots.into());
+                    if mode == "optional" {
+                        builder = builder.allow_unauthenticated();
+                    }
+                    builder.build().map_err(|err| {
+                        format!(
+                            "Failed to build client certificate verifier for listener {id:?}: {err}"
+                        )
+                    })?
+                }
+                mode => {
+                    return Err(format!(
+                        "Invalid client authentication mode {mode:?} for listener {id:?}."
+                    ))
+                }
+            };
 
-            // Build server config
-            let mut config = ServerConfig::builder_with_provider(provider.into())
-                .with_protocol_versions(if tls_v3 == tls_v2 {
-                    ALL_VERSIONS
-                } else if tls_v3 {
-                    TLS13_VERSION
-                } else {
-                    TLS12_VERSION
-                })
-                .map_err(|err| format!("Failed to build TLS config: {err}"))?
-                .with_no_client_auth()
-                .with_cert_resolver(Arc::new(CertificateResolver {
-                    resolver: if has_sni { resolver.into() } else { None },
-                    default_cert,
-                }));
+            // Build cert resolver, shared by all protocol policies
+            let tls_alpn_acme = acme
+                .as_ref()
+                .filter(|acme| acme.challenge == ChallengeType::TlsAlpn01)
+                .cloned();
+            cert_resolver.acme = tls_alpn_acme.clone();
+            let cert_resolver = Arc::new(cert_resolver);
+            let mut config = build_server_config(
+                versions,
+                &ciphers,
+                client_verifier.clone(),
+                cert_resolver.clone(),
+                &[],
+            )
+            .map_err(|err| format!("Failed to build TLS config: {err}"))?;
 
             //config.key_log = Arc::new(KeyLogger::default());
             config.ignore_client_order = self
@@ -187,8 +322,84 @@ impl Config {
                     "server.tls.ignore-client-order",
                 )?
                 .unwrap_or(true);
+
+            // Session resumption
+            if self
+                .property_or_default(
+                    ("server.listener", id, "tls.session.tickets"),
+                    "server.tls.session.tickets",
+                )?
+                .unwrap_or(true)
+            {
+                let rotation = self
+                    .property_or_default::<Duration>(
+                        ("server.listener", id, "tls.session.ticket-rotation"),
+                        "server.tls.session.ticket-rotation",
+                    )?
+                    .unwrap_or(Duration::from_secs(6 * 60 * 60));
+                if rotation.as_secs() == 0 {
+                    return Err(format!(
+                        "Ticket rotation interval for listener {id:?} must be at least one second."
+                    ));
+                }
+                config.ticketer = Arc::new(TicketRotator::new(rotation).map_err(|err| {
+                    format!("Failed to build TLS session ticketer for listener {id:?}: {err}")
+                })?);
+            }
+            config.session_storage = match self
+                .property_or_default::<usize>(
+                    ("server.listener", id, "tls.session.cache-size"),
+                    "server.tls.session.cache-size",
+                )?
+                .unwrap_or(256)
+            {
+                0 => Arc::new(NoServerSessionStorage {}),
+                size => ServerSessionMemoryCache::new(size),
+            };
+
+            // Build SNI protocol policies
+            let mut tls_policies = AHashMap::with_capacity(sni_policies.len());
+            for (subject, versions, sni_ciphers) in sni_policies {
+                let mut policy_config = build_server_config(
+                    versions,
+                    if !sni_ciphers.is_empty() {
+                        &sni_ciphers
+                    } else {
+                        &ciphers
+                    },
+                    client_verifier.clone(),
+                    cert_resolver.clone(),
+                    &[],
+                )
+                .map_err(|err| format!("Failed to build TLS config for SNI {subject:?}: {err}"))?;
+                policy_config.ignore_client_order = config.ignore_client_order;
+                policy_config.ticketer = config.ticketer.clone();
+                policy_config.session_storage = config.session_storage.clone();
+                tls_policies.insert(subject, Arc::new(policy_config));
+            }
+
+            // Build TLS-ALPN-01 challenge config
+            let tls_acme = if tls_alpn_acme.is_some() {
+                // ACME servers never present a client certificate
+                let acme_config = build_server_config(
+                    versions,
+                    &ciphers,
+                    Arc::new(NoClientAuth),
+                    cert_resolver.clone(),
+                    &[ACME_TLS_ALPN_NAME],
+                )
+                .map_err(|err| {
+                    format!("Failed to build ACME TLS config for listener {id:?}: {err}")
+                })?;
+                Some(Arc::new(acme_config))
+            } else {
+                None
+            };
+
             (
                 config.into(),
+                tls_policies,
+                tls_acme,
                 self.property_or_default(
                     ("server.listener", id, "tls.implicit"),
                     "server.tls.implicit",
@@ -196,7 +407,7 @@ impl Config {
                 .unwrap_or(true),
             )
         } else {
-            (None, false)
+            (None, AHashMap::new(), None, false)
         };
 
         // Build listeners
@@ -304,7 +515,10 @@ impl Config {
             protocol,
             listeners,
             tls,
+            tls_policies,
+            tls_acme,
             tls_implicit,
+            acme_providers: acme_providers.values().cloned().collect(),
         })
     }
 }
@@ -345,6 +559,23 @@ impl ParseValue for SocketAddr {
     }
 }
 
+impl Config {
+    fn certificate(
+        &self,
+        cert_id: &str,
+        certificates: &mut AHashMap<String, Arc<Certificate>>,
+    ) -> super::Result<Arc<Certificate>> {
+        // Certificates are shared between listeners so they are only reloaded once
+        if let Some(cert) = certificates.get(cert_id) {
+            Ok(cert.clone())
+        } else {
+            let cert = Arc::new(self.parse_certificate(cert_id)?);
+            certificates.insert(cert_id.to_string(), cert.clone());
+            Ok(cert)
+        }
+    }
+}
+
 impl ParseValue for SupportedCipherSuite {
     fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
         Ok(match value {
@@ -33,16 +33,18 @@ use std::{
     collections::BTreeMap,
     fmt::Display,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
     time::Duration,
 };
 
 use ahash::{AHashMap, AHashSet};
+
 use rustls::ServerConfig;
 use tokio::net::TcpSocket;
 
-use crate::{failed, UnwrapFailure};
+use crate::{acme::AcmeProvider, failed, UnwrapFailure};
 
-use self::utils::ParseValue;
+use self::{certificate::Certificate, utils::ParseValue};
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Config {
@@ -58,12 +60,18 @@ pub struct Server {
     pub protocol: ServerProtocol,
     pub listeners: Vec<Listener>,
     pub tls: Option<ServerConfig>,
+    pub tls_policies: AHashMap<String, Arc<ServerConfig>>,
+    pub tls_acme: Option<Arc<ServerConfig>>,
     pub tls_implicit: bool,
     pub max_connections: u64,
+    pub acme_providers: Vec<Arc<AcmeProvider>>,
 }
 
 pub struct Servers {
     pub inner: Vec<Server>,
+    pub certificates: Vec<Arc<Certificate>>,
+    pub acme_providers: Vec<Arc<AcmeProvider>>,
+    pub reload_interval: Option<Duration>,
 }
 
 #[derive(Debug)]
@@ -457,7 +457,9 @@ impl ParseValue for Canonicalization {
 
 impl ParseValue for IpLookupStrategy {
     fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
-        Ok(match value.to_lowercase().as_str() {
+        // Accept both ipv4_only and ipv4-only
+        let strategy = value.trim().to_lowercase().replace('_', "-");
+        Ok(match strategy.as_str() {
             "ipv4-only" => IpLookupStrategy::Ipv4Only,
             "ipv6-only" => IpLookupStrategy::Ipv6Only,
             //"ipv4-and-ipv6" => IpLookupStrategy::Ipv4AndIpv6,
@@ -465,7 +467,10 @@ impl ParseValue for IpLookupStrategy {
             "ipv4-then-ipv6" => IpLookupStrategy::Ipv4thenIpv6,
             _ => {
                 return Err(format!(
-                    "Invalid IP lookup strategy {:?} for property {:?}.",
+                    concat!(
+                        "Invalid IP lookup strategy {:?} for property {:?}, expected one of ",
+                        "ipv4-only, ipv6-only, ipv4-then-ipv6 or ipv6-then-ipv4."
+                    ),
                     value,
                     key.as_key()
                 ))
@@ -638,7 +643,9 @@ impl AsKey for (&str, &str, &str, &str) {
 mod tests {
     use std::net::IpAddr;
 
-    use crate::config::Config;
+    use mail_auth::IpLookupStrategy;
+
+    use crate::config::{utils::ParseValue, Config};
 
     #[test]
     fn toml_utils() {
@@ -698,4 +705,25 @@ ip = "a:b::1:1"
             "a:b::1:1".parse::<IpAddr>().unwrap()
         );
     }
+
+    #[test]
+    fn parse_ip_strategy() {
+        for (value, expected) in [
+            ("ipv4-only", IpLookupStrategy::Ipv4Only),
+            ("IPv6_Only", IpLookupStrategy::Ipv6Only),
+            ("ipv4_then_ipv6", IpLookupStrategy::Ipv4thenIpv6),
+            ("Ipv6-Then-Ipv4", IpLookupStrategy::Ipv6thenIpv4),
+        ] {
+            let strategy = IpLookupStrategy::parse_value("strategy", value).unwrap();
+            assert_eq!(
+                std::mem::discriminant(&strategy),
+                std::mem::discriminant(&expected),
+                "{value}"
+            );
+        }
+
+        let err = IpLookupStrategy::parse_value("strategy", "ipv4-than-ipv6").unwrap_err();
+        assert!(err.contains("\"ipv4-than-ipv6\""), "{err}");
+        assert!(err.contains("ipv6-then-ipv4"), "{err}");
+    }
 }
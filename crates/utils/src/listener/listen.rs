@@ -23,22 +23,24 @@
 
 use std::{net::IpAddr, sync::Arc};
 
-use rustls::crypto::ring::cipher_suite::TLS13_AES_128_GCM_SHA256;
+use rustls::{crypto::ring::cipher_suite::TLS13_AES_128_GCM_SHA256, server::Acceptor};
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::{TcpListener, TcpStream},
     sync::watch,
 };
-use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tokio_rustls::{server::TlsStream, LazyConfigAcceptor};
 use tracing::Span;
 
 use crate::{
+    acme::ACME_TLS_ALPN_NAME,
     config::{Config, Listener, Server, ServerProtocol, Servers},
     failed,
     listener::SessionData,
     UnwrapFailure,
 };
 
-use super::{limiter::ConcurrencyLimiter, ServerInstance, SessionManager};
+use super::{limiter::ConcurrencyLimiter, ServerInstance, SessionManager, TlsAcceptor};
 
 impl Server {
     pub fn spawn(self, manager: impl SessionManager, shutdown_rx: watch::Receiver<bool>) {
@@ -53,10 +55,19 @@ impl Server {
             listener_id: self.internal_id,
             protocol: self.protocol,
             hostname: self.hostname,
-            tls_acceptor: self.tls.map(|config| TlsAcceptor::from(Arc::new(config))),
+            tls_acceptor: self.tls.map(|config| {
+                let config = Arc::new(config);
+                TlsAcceptor {
+                    acceptor: config.clone().into(),
+                    default: config,
+                    policies: self.tls_policies,
+                    acme: self.tls_acme,
+                }
+            }),
             is_tls_implicit: self.tls_implicit,
             limiter: ConcurrencyLimiter::new(self.max_connections),
             shutdown_rx,
+            acme_providers: self.acme_providers,
         });
 
         // Spawn listeners
@@ -223,6 +234,35 @@ impl Servers {
             spawn(server, shutdown_rx.clone());
         }
 
+        // Spawn ACME certificate renewals
+        for provider in self.acme_providers {
+            provider.spawn(shutdown_rx.clone());
+        }
+
+        // Spawn certificate reloader
+        let certificates = self
+            .certificates
+            .into_iter()
+            .filter(|cert| cert.is_watched())
+            .collect::<Vec<_>>();
+        if let (Some(interval), false) = (self.reload_interval, certificates.is_empty()) {
+            let mut shutdown_rx = shutdown_rx.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval) => {
+                            for cert in &certificates {
+                                cert.reload_if_changed();
+                            }
+                        },
+                        _ = shutdown_rx.changed() => {
+                            break;
+                        }
+                    };
+                }
+            });
+        }
+
         (shutdown_tx, shutdown_rx)
     }
 }
@@ -235,6 +275,35 @@ impl Listener {
     }
 }
 
+impl TlsAcceptor {
+    pub async fn accept<IO>(&self, stream: IO) -> std::io::Result<TlsStream<IO>>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        if self.policies.is_empty() && self.acme.is_none() {
+            return self.acceptor.accept(stream).await;
+        }
+
+        // Select the protocol policy matching the SNI in the ClientHello
+        let start = LazyConfigAcceptor::new(Acceptor::default(), stream).await?;
+        let hello = start.client_hello();
+        let config = if let Some(acme) = self.acme.as_ref().filter(|_| {
+            hello.alpn().map_or(false, |mut alpn| {
+                alpn.any(|proto| proto == ACME_TLS_ALPN_NAME)
+            })
+        }) {
+            acme
+        } else {
+            hello
+                .server_name()
+                .and_then(|name| self.policies.get(&name.to_lowercase()))
+                .unwrap_or(&self.default)
+        }
+        .clone();
+        start.into_stream(config).await
+    }
+}
+
 impl ServerInstance {
     pub async fn tls_accept(
         &self,
@@ -23,14 +23,15 @@
 
 use std::{net::IpAddr, sync::Arc};
 
+use ahash::AHashMap;
+use rustls::ServerConfig;
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     net::TcpStream,
     sync::watch,
 };
-use tokio_rustls::TlsAcceptor;
 
-use crate::config::ServerProtocol;
+use crate::{acme::AcmeProvider, config::ServerProtocol};
 
 use self::limiter::{ConcurrencyLimiter, InFlight};
 
@@ -47,6 +48,14 @@ pub struct ServerInstance {
     pub is_tls_implicit: bool,
     pub limiter: ConcurrencyLimiter,
     pub shutdown_rx: watch::Receiver<bool>,
+    pub acme_providers: Vec<Arc<AcmeProvider>>,
+}
+
+pub struct TlsAcceptor {
+    pub acceptor: tokio_rustls::TlsAcceptor,
+    pub default: Arc<ServerConfig>,
+    pub policies: AHashMap<String, Arc<ServerConfig>>,
+    pub acme: Option<Arc<ServerConfig>>,
 }
 
 pub struct SessionData<T: AsyncRead + AsyncWrite + Unpin + 'static> {
@@ -0,0 +1,267 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    borrow::Borrow,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+#[allow(clippy::type_complexity)]
+#[derive(Debug)]
+pub struct LookupCache<T: Hash + Eq> {
+    cache_pos: lru_cache::LruCache<T, Instant, ahash::RandomState>,
+    cache_neg: lru_cache::LruCache<T, Instant, ahash::RandomState>,
+    ttl_pos: Duration,
+    ttl_neg: Duration,
+    early_refresh: Option<EarlyRefresh>,
+    stats: LookupCacheStats,
+}
+
+/// TTL cache for values, such as the groups an account belongs to.
+#[derive(Debug)]
+pub struct ValueCache<K: Hash + Eq, V> {
+    cache: lru_cache::LruCache<K, (V, Instant), ahash::RandomState>,
+    ttl: Duration,
+    stats: LookupCacheStats,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LookupCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub positive_inserts: u64,
+    pub negative_inserts: u64,
+    pub positive_entries: usize,
+    pub negative_entries: usize,
+    /// Entries removed because the cache was full or their TTL expired.
+    pub evictions: u64,
+}
+
+/// Probabilistic early expiration (XFetch) parameters.
+#[derive(Debug, Clone, Copy)]
+pub struct EarlyRefresh {
+    /// Values above 1.0 favour earlier refreshes, below 1.0 later ones.
+    pub beta: f64,
+    /// Expected time it takes to query the backend.
+    pub compute_time: Duration,
+}
+
+impl<K: Hash + Eq, V: Clone> ValueCache<K, V> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            cache: lru_cache::LruCache::with_hasher(capacity, ahash::RandomState::new()),
+            ttl,
+            stats: LookupCacheStats::default(),
+        }
+    }
+
+    pub fn get<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        if let Some((value, valid_until)) = self.cache.get_mut(key) {
+            if *valid_until >= Instant::now() {
+                self.stats.hits += 1;
+                return Some(value.clone());
+            } else {
+                self.cache.remove(key);
+                self.stats.evictions += 1;
+            }
+        }
+
+        self.stats.misses += 1;
+        None
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.insert_with_ttl(key, value, self.ttl);
+    }
+
+    /// Inserts a value that expires after `ttl` instead of the cache default.
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: Duration) {
+        if self.cache.len() == self.cache.capacity() && !self.cache.contains_key(&key) {
+            self.stats.evictions += 1;
+        }
+        self.stats.positive_inserts += 1;
+        self.cache.insert(key, (value, Instant::now() + ttl));
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.cache.remove(key);
+    }
+
+    /// Removes the entries for which `f` returns `false`.
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &V) -> bool)
+    where
+        K: Clone,
+    {
+        let keys = self
+            .cache
+            .iter()
+            .filter(|(key, (value, _))| !f(key, value))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in keys {
+            self.cache.remove(&key);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    pub fn stats(&self) -> LookupCacheStats {
+        LookupCacheStats {
+            positive_entries: self.cache.len(),
+            ..self.stats
+        }
+    }
+}
+
+impl<T: Hash + Eq> LookupCache<T> {
+    pub fn new(capacity: usize, ttl_pos: Duration, ttl_neg: Duration) -> Self {
+        Self {
+            cache_pos: lru_cache::LruCache::with_hasher(capacity, ahash::RandomState::new()),
+            cache_neg: lru_cache::LruCache::with_hasher(capacity, ahash::RandomState::new()),
+            ttl_pos,
+            ttl_neg,
+            early_refresh: None,
+            stats: LookupCacheStats::default(),
+        }
+    }
+
+    pub fn with_early_refresh(mut self, early_refresh: Option<EarlyRefresh>) -> Self {
+        self.early_refresh = early_refresh;
+        self
+    }
+
+    pub fn get<Q: ?Sized>(&mut self, name: &Q) -> Option<bool>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.get_(name, self.early_refresh)
+    }
+
+    /// Returns the cached value ignoring early expiration.
+    pub fn peek<Q: ?Sized>(&mut self, name: &Q) -> Option<bool>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.get_(name, None)
+    }
+
+    fn get_<Q: ?Sized>(&mut self, name: &Q, early_refresh: Option<EarlyRefresh>) -> Option<bool>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let now = Instant::now();
+
+        // Check positive cache
+        if let Some(valid_until) = self.cache_pos.get_mut(name) {
+            if *valid_until >= now {
+                return if !is_early_expired(*valid_until, now, early_refresh) {
+                    self.stats.hits += 1;
+                    Some(true)
+                } else {
+                    self.stats.misses += 1;
+                    None
+                };
+            } else {
+                self.cache_pos.remove(name);
+                self.stats.evictions += 1;
+            }
+        }
+
+        // Check negative cache
+        if let Some(valid_until) = self.cache_neg.get_mut(name) {
+            if *valid_until >= now {
+                if !is_early_expired(*valid_until, now, early_refresh) {
+                    self.stats.hits += 1;
+                    return Some(false);
+                }
+            } else {
+                self.cache_neg.remove(name);
+                self.stats.evictions += 1;
+            }
+        }
+
+        self.stats.misses += 1;
+        None
+    }
+
+    pub fn insert_pos(&mut self, item: T) {
+        if self.cache_pos.len() == self.cache_pos.capacity() && !self.cache_pos.contains_key(&item)
+        {
+            self.stats.evictions += 1;
+        }
+        self.stats.positive_inserts += 1;
+        self.cache_pos.insert(item, Instant::now() + self.ttl_pos);
+    }
+
+    pub fn insert_neg(&mut self, item: T) {
+        if self.cache_neg.len() == self.cache_neg.capacity() && !self.cache_neg.contains_key(&item)
+        {
+            self.stats.evictions += 1;
+        }
+        self.stats.negative_inserts += 1;
+        self.cache_neg.insert(item, Instant::now() + self.ttl_neg);
+    }
+
+    pub fn clear(&mut self) {
+        self.cache_pos.clear();
+        self.cache_neg.clear();
+    }
+
+    pub fn stats(&self) -> LookupCacheStats {
+        LookupCacheStats {
+            positive_entries: self.cache_pos.len(),
+            negative_entries: self.cache_neg.len(),
+            ..self.stats
+        }
+    }
+}
+
+// XFetch: an entry is considered expired when now - compute_time * beta * ln(rand) >= expiry,
+// so that the probability of an early refresh increases as the entry approaches its TTL.
+fn is_early_expired(
+    valid_until: Instant,
+    now: Instant,
+    early_refresh: Option<EarlyRefresh>,
+) -> bool {
+    if let Some(early_refresh) = early_refresh {
+        let rand = rand::random::<f64>().max(f64::MIN_POSITIVE);
+        let gap = early_refresh.compute_time.as_secs_f64() * early_refresh.beta * -rand.ln();
+        now + Duration::from_secs_f64(gap.clamp(0.0, 86400.0 * 365.0)) >= valid_until
+    } else {
+        false
+    }
+}
@@ -22,6 +22,7 @@
 */
 
 pub mod bitmap;
+pub mod lookup_cache;
 pub mod mutex_map;
 pub mod ttl_dashmap;
 pub mod vec_map;
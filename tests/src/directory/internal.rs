@@ -26,7 +26,7 @@ use directory::{
         lookup::DirectoryStore, manage::ManageDirectory, PrincipalField, PrincipalUpdate,
         PrincipalValue,
     },
-    DirectoryError, ManagementError, Principal, QueryBy, Type,
+    DirectoryError, ManagementError, Principal, QueryBy, QueryResult, Type,
 };
 use jmap_proto::types::collection::Collection;
 use mail_send::Credentials;
@@ -177,19 +177,25 @@ async fn internal_directory() {
                 ..Default::default()
             })
         );
-        assert_eq!(
-            store
-                .query(
-                    QueryBy::Credentials(&Credentials::new(
-                        "jane".to_string(),
-                        "wrong_password".to_string()
-                    )),
-                    true
-                )
-                .await
-                .unwrap(),
-            None
-        );
+        for (username, secret, expected) in [
+            ("jane", "wrong_password", QueryResult::InvalidCredentials),
+            ("nobody", "my_secret", QueryResult::NotFound),
+        ] {
+            assert_eq!(
+                store
+                    .query_detailed(
+                        QueryBy::Credentials(&Credentials::new(
+                            username.to_string(),
+                            secret.to_string()
+                        )),
+                        true
+                    )
+                    .await
+                    .unwrap(),
+                expected,
+                "failed for {username}/{secret}"
+            );
+        }
 
         // Duplicate email address should fail
         assert_eq!(
@@ -23,7 +23,9 @@
 
 use std::fmt::Debug;
 
-use directory::{backend::internal::manage::ManageDirectory, Principal, QueryBy, Type};
+use directory::{
+    backend::internal::manage::ManageDirectory, Principal, QueryBy, QueryResult, Type,
+};
 use mail_send::Credentials;
 
 use crate::directory::{map_account_ids, DirectoryTest, IntoSortedPrincipal};
@@ -99,17 +101,25 @@ async fn ldap_directory() {
         }
         .into_sorted()
     );
-    assert!(handle
-        .query(
-            QueryBy::Credentials(&Credentials::Plain {
-                username: "bill".to_string(),
-                secret: "invalid".to_string()
-            }),
-            true
-        )
-        .await
-        .unwrap()
-        .is_none());
+    for (username, secret, expected) in [
+        ("bill", "invalid", QueryResult::InvalidCredentials),
+        ("nobody", "password", QueryResult::NotFound),
+    ] {
+        assert_eq!(
+            handle
+                .query_detailed(
+                    QueryBy::Credentials(&Credentials::Plain {
+                        username: username.to_string(),
+                        secret: secret.to_string()
+                    }),
+                    true
+                )
+                .await
+                .unwrap(),
+            expected,
+            "failed for {username}/{secret}"
+        );
+    }
 
     // Get user by name
     assert_eq!(
@@ -148,6 +158,32 @@ async fn ldap_directory() {
         }
     );
 
+    // Batched queries preserve the order of the requests
+    assert_eq!(
+        handle
+            .query_many(vec![
+                QueryBy::Name("sales"),
+                QueryBy::Name("unknown"),
+                QueryBy::Name("jane"),
+            ])
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|principal| principal.map(|principal| (principal.id, principal.name)))
+            .collect::<Vec<_>>(),
+        vec![
+            Some((
+                base_store.get_account_id("sales").await.unwrap().unwrap(),
+                "sales".to_string()
+            )),
+            None,
+            Some((
+                base_store.get_account_id("jane").await.unwrap().unwrap(),
+                "jane".to_string()
+            )),
+        ]
+    );
+
     // Ids by email
     compare_sorted(
         handle.email_to_ids("jane@example.org").await.unwrap(),
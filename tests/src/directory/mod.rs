@@ -29,14 +29,18 @@ pub mod sql;
 
 use ::smtp::core::Lookup;
 use directory::{
-    backend::internal::manage::ManageDirectory, core::config::ConfigDirectory, AddressMapping,
-    Directories, Principal,
+    backend::internal::manage::ManageDirectory,
+    core::{
+        cache::{CachedDirectory, CachedLookup, EarlyRefresh, LookupCache},
+        config::ConfigDirectory,
+    },
+    AddressMapping, Directories, Principal,
 };
 use mail_send::Credentials;
 use rustls::ServerConfig;
 use rustls_pemfile::{certs, pkcs8_private_keys};
 use rustls_pki_types::PrivateKeyDer;
-use std::{borrow::Cow, io::BufReader, path::PathBuf, sync::Arc};
+use std::{borrow::Cow, io::BufReader, path::PathBuf, sync::Arc, time::Duration};
 use store::{config::ConfigStore, LookupStore, Store, Stores};
 use tokio_rustls::TlsAcceptor;
 
@@ -74,6 +78,7 @@ secret = "secret"
 email = "address"
 quota = "quota"
 type = "type"
+active = "active"
 
 [store."rocksdb"]
 type = "rocksdb"
@@ -87,7 +92,7 @@ type = "sqlite"
 path = "{TMP}/auth.db"
 
 [store."sqlite".query]
-name = "SELECT name, type, secret, description, quota FROM accounts WHERE name = ? AND active = true"
+name = "SELECT name, type, secret, description, quota, active FROM accounts WHERE name = ?"
 members = "SELECT member_of FROM group_members WHERE name = ?"
 recipients = "SELECT name FROM emails WHERE address = ? ORDER BY name ASC"
 emails = "SELECT address FROM emails WHERE name = ? AND type != 'list' ORDER BY type DESC, address ASC"
@@ -112,6 +117,7 @@ secret = "secret"
 email = "address"
 quota = "quota"
 type = "type"
+active = "active"
 
 [store."postgresql"]
 type = "postgresql"
@@ -122,7 +128,7 @@ user = "postgres"
 password = "mysecretpassword"
 
 [store."postgresql".query]
-name = "SELECT name, type, secret, description, quota FROM accounts WHERE name = $1 AND active = true"
+name = "SELECT name, type, secret, description, quota, active FROM accounts WHERE name = $1"
 members = "SELECT member_of FROM group_members WHERE name = $1"
 recipients = "SELECT name FROM emails WHERE address = $1 ORDER BY name ASC"
 emails = "SELECT address FROM emails WHERE name = $1 AND type != 'list' ORDER BY type DESC, address ASC"
@@ -147,6 +153,7 @@ secret = "secret"
 email = "address"
 quota = "quota"
 type = "type"
+active = "active"
 
 [store."mysql"]
 type = "mysql"
@@ -157,7 +164,7 @@ user = "root"
 password = "password"
 
 [store."mysql".query]
-name = "SELECT name, type, secret, description, quota FROM accounts WHERE name = ? AND active = true"
+name = "SELECT name, type, secret, description, quota, active FROM accounts WHERE name = ?"
 members = "SELECT member_of FROM group_members WHERE name = ?"
 recipients = "SELECT name FROM emails WHERE address = ? ORDER BY name ASC"
 emails = "SELECT address FROM emails WHERE name = ? AND type != 'list' ORDER BY type DESC, address ASC"
@@ -600,6 +607,314 @@ async fn lookup_local() {
     }
 }
 
+#[test]
+fn member_of_cache() {
+    let config = utils::config::Config::new(
+        "[directory.\"test\".cache]\nentries = 10\nttl.member-of = \"1s\"\n",
+    )
+    .unwrap();
+    let cache = CachedDirectory::try_from_config(&config, ("directory", "test"))
+        .unwrap()
+        .unwrap();
+
+    // Cached memberships are returned until invalidated
+    assert_eq!(cache.get_member_of(1), None);
+    cache.set_member_of(1, vec![10, 11]);
+    cache.set_member_of(2, vec![10]);
+    assert_eq!(cache.get_member_of(1), Some(vec![10, 11]));
+    cache.invalidate_member_of(1);
+    assert_eq!(cache.get_member_of(1), None);
+    assert_eq!(cache.get_member_of(2), Some(vec![10]));
+    cache.invalidate_all_member_of();
+    assert_eq!(cache.get_member_of(2), None);
+
+    // Entries expire after their TTL
+    cache.set_member_of(3, vec![]);
+    assert_eq!(cache.get_member_of(3), Some(vec![]));
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    assert_eq!(cache.get_member_of(3), None);
+
+    let stats = cache.stats().member_of;
+    assert_eq!(stats.hits, 3);
+    assert_eq!(stats.positive_inserts, 3);
+    assert_eq!(stats.evictions, 1);
+}
+
+#[test]
+fn principal_cache() {
+    let config = utils::config::Config::new("[directory.\"test\".cache]\nentries = 10\n").unwrap();
+    let cache = CachedDirectory::try_from_config(&config, ("directory", "test"))
+        .unwrap()
+        .unwrap();
+    let principal = |id: u32, name: &str| Principal::<u32> {
+        id,
+        name: name.to_string(),
+        ..Default::default()
+    };
+
+    // Invalidating a principal only discards the entries that resolved to it
+    cache.set_principal("john", Some(principal(1, "john")));
+    cache.set_principal("john@example.org", Some(principal(1, "john")));
+    cache.set_principal("jane", Some(principal(2, "jane")));
+    cache.set_principal("unknown", None);
+    cache.invalidate_principal(1);
+    assert_eq!(cache.get_principal("john"), None);
+    assert_eq!(cache.get_principal("john@example.org"), None);
+    assert_eq!(
+        cache.get_principal("jane"),
+        Some(Some(principal(2, "jane")))
+    );
+    assert_eq!(cache.get_principal("unknown"), Some(None));
+}
+
+#[tokio::test]
+async fn single_flight_lookups() {
+    let config = utils::config::Config::new("[directory.\"test\".cache]\nentries = 10\n").unwrap();
+    let cache = CachedDirectory::try_from_config(&config, ("directory", "test"))
+        .unwrap()
+        .unwrap();
+
+    // The first miss queries the backend, concurrent misses for the same key await its result
+    let lookup = match cache.lookup_rcpt("john@example.org") {
+        CachedLookup::Vacant(lookup) => lookup,
+        _ => panic!("Expected a vacant lookup"),
+    };
+    let waiters = (0..3)
+        .map(|_| match cache.lookup_rcpt("john@EXAMPLE.org") {
+            CachedLookup::Pending(pending) => pending,
+            _ => panic!("Expected a pending lookup"),
+        })
+        .collect::<Vec<_>>();
+
+    // Other keys are not blocked
+    assert!(matches!(
+        cache.lookup_rcpt("jane@example.org"),
+        CachedLookup::Vacant(_)
+    ));
+
+    cache.set_rcpt("john@example.org", true);
+    lookup.complete(true);
+    for pending in waiters {
+        assert_eq!(pending.await, Ok(true));
+    }
+    assert!(matches!(
+        cache.lookup_rcpt("john@example.org"),
+        CachedLookup::Cached(true)
+    ));
+
+    // Waiters are cancelled when the query fails and the next lookup retries it
+    let lookup = match cache.lookup_rcpt("jane@example.org") {
+        CachedLookup::Vacant(lookup) => lookup,
+        _ => panic!("Expected a vacant lookup"),
+    };
+    let pending = match cache.lookup_rcpt("jane@example.org") {
+        CachedLookup::Pending(pending) => pending,
+        _ => panic!("Expected a pending lookup"),
+    };
+    drop(lookup);
+    assert!(pending.await.is_err());
+    assert!(matches!(
+        cache.lookup_rcpt("jane@example.org"),
+        CachedLookup::Vacant(_)
+    ));
+
+    // Domains are coalesced separately from recipients
+    let _lookup = cache.lookup_domain("example.org");
+    assert!(matches!(
+        cache.lookup_domain("EXAMPLE.org"),
+        CachedLookup::Pending(_)
+    ));
+    assert!(matches!(
+        cache.lookup_rcpt("example.org"),
+        CachedLookup::Vacant(_)
+    ));
+}
+
+#[test]
+fn lookup_cache_early_refresh() {
+    let hour = Duration::from_secs(3600);
+
+    // Entries expire at their TTL by default
+    let mut cache = LookupCache::<String>::new(10, hour, hour);
+    cache.insert_pos("john@example.org".to_string());
+    cache.insert_neg("jane@example.org".to_string());
+    for _ in 0..100 {
+        assert_eq!(cache.get("john@example.org"), Some(true));
+        assert_eq!(cache.get("jane@example.org"), Some(false));
+    }
+
+    // Entries far from their expiry are never refreshed early
+    let mut cache =
+        LookupCache::<String>::new(10, hour, hour).with_early_refresh(Some(EarlyRefresh {
+            beta: 1.0,
+            compute_time: Duration::from_secs(1),
+        }));
+    cache.insert_pos("john@example.org".to_string());
+    for _ in 0..100 {
+        assert_eq!(cache.get("john@example.org"), Some(true));
+    }
+
+    // Entries about to expire are refreshed early by some callers,
+    // while others keep being served the valid entry
+    let second = Duration::from_secs(1);
+    let mut cache =
+        LookupCache::<String>::new(10, second, second).with_early_refresh(Some(EarlyRefresh {
+            beta: 1.0,
+            compute_time: Duration::from_secs(86400),
+        }));
+    cache.insert_pos("john@example.org".to_string());
+    cache.insert_neg("jane@example.org".to_string());
+    for key in ["john@example.org", "jane@example.org"] {
+        let refreshes = (0..100).filter(|_| cache.get(key).is_none()).count();
+        assert!(refreshes > 90, "only {refreshes} early refreshes for {key}");
+    }
+    assert_eq!(cache.peek("john@example.org"), Some(true));
+    assert_eq!(cache.peek("jane@example.org"), Some(false));
+    let stats = cache.stats();
+    assert_eq!(stats.positive_entries, 1);
+    assert_eq!(stats.negative_entries, 1);
+}
+
+#[tokio::test]
+async fn catch_all_cache() {
+    let config = utils::config::Config::new(
+        r#"
+    [directory."test"]
+    type = "memory"
+
+    [directory."test".options]
+    catch-all = true
+    subaddressing = false
+
+    [directory."test".cache]
+    entries = 100
+
+    [[directory."test".principals]]
+    name = "postmaster"
+    email = ["postmaster@example.org", "@example.org"]
+
+    [[directory."test".principals]]
+    name = "john"
+    email = "john@example.net"
+    "#,
+    )
+    .unwrap();
+    let stores = config.parse_stores().await.unwrap();
+    let directories = config.parse_directory(&stores, None).await.unwrap();
+    let directory = directories.directories.get("test").unwrap();
+
+    // Learning that a domain is catch-all caches it
+    assert!(directory.rcpt("unknown1@example.org").await.unwrap());
+    let stats = directory.cache_stats().unwrap().catch_all;
+    assert_eq!((stats.positive_inserts, stats.hits), (1, 0));
+
+    // Further unknown local parts are resolved from the cached flag
+    for address in ["unknown2@example.org", "unknown3@example.org"] {
+        assert!(directory.rcpt(address).await.unwrap());
+    }
+    let stats = directory.cache_stats().unwrap();
+    assert_eq!(
+        (stats.catch_all.positive_inserts, stats.catch_all.hits),
+        (1, 2)
+    );
+    assert_eq!(stats.rcpts.positive_entries, 1);
+
+    // Domains without a catch-all address are cached as such,
+    // their addresses are still looked up one by one
+    assert!(!directory.rcpt("unknown@example.net").await.unwrap());
+    assert_eq!(
+        directory.cache_stats().unwrap().catch_all.negative_inserts,
+        1
+    );
+    assert!(directory.rcpt("john@example.net").await.unwrap());
+    assert!(!directory.rcpt("other@example.net").await.unwrap());
+    assert_eq!(directory.cache_stats().unwrap().rcpts.positive_entries, 2);
+}
+
+#[test]
+fn address_normalization() {
+    let cache = |settings: &str| {
+        CachedDirectory::try_from_config(
+            &utils::config::Config::new(&format!(
+                "[directory.\"test\".cache]\nentries = 10\n{settings}"
+            ))
+            .unwrap(),
+            ("directory", "test"),
+        )
+        .unwrap()
+        .unwrap()
+    };
+
+    // Domains are lowercased and converted to ASCII, local parts keep their case by default
+    let case_sensitive = cache("");
+    for (address, expected) in [
+        ("john@example.org", "john@example.org"),
+        ("john@EXAMPLE.org", "john@example.org"),
+        ("John.Doe@Example.ORG", "John.Doe@example.org"),
+        ("jöhn@bücher.example", "jöhn@xn--bcher-kva.example"),
+        ("JÖHN@BÜCHER.example", "JÖHN@xn--bcher-kva.example"),
+        ("john", "john"),
+    ] {
+        assert_eq!(case_sensitive.normalize_address(address), expected);
+    }
+
+    // Local parts are lowercased when case sensitivity is disabled
+    let case_insensitive = cache("case-sensitive = false\n");
+    for (address, expected) in [
+        ("John.Doe@Example.ORG", "john.doe@example.org"),
+        ("JÖHN@BÜCHER.example", "jöhn@xn--bcher-kva.example"),
+        ("JOHN", "john"),
+    ] {
+        assert_eq!(case_insensitive.normalize_address(address), expected);
+    }
+
+    // Equivalent addresses share a cache entry
+    case_sensitive.set_rcpt("john@EXAMPLE.org", true);
+    assert_eq!(case_sensitive.get_rcpt("john@example.org"), Some(true));
+    assert_eq!(case_sensitive.get_rcpt("JOHN@example.org"), None);
+    case_insensitive.set_rcpt("john@example.org", false);
+    assert_eq!(case_insensitive.get_rcpt("JOHN@EXAMPLE.ORG"), Some(false));
+    case_sensitive.set_domain("BÜCHER.example", true);
+    assert_eq!(
+        case_sensitive.get_domain("xn--bcher-kva.example"),
+        Some(true)
+    );
+    assert_eq!(case_sensitive.get_domain("bücher.EXAMPLE"), Some(true));
+}
+
+#[tokio::test]
+async fn verify_secret_formats() {
+    for (stored_secret, expected) in [
+        ("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=", true),
+        ("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9h=", false),
+        (
+            "{SHA512}sQnzu7wkTrgkQZF+0G1hi5AI3Qmzvv0bXgc5THBqi7mAsdd4Xll27ASbRt9fEyavWi6m0QP9B8lThf+rDKy8hg==",
+            true,
+        ),
+        (
+            "$2y$05$bvIG6Nmid91Mu9RcmmWZfO5HJIMCT8riNW0hEp8f6/FuA2/mHZFpe",
+            true,
+        ),
+        ("{PLAIN}password", true),
+        ("password", true),
+        ("passwor", false),
+        // Unknown formats fail closed
+        ("$unknown$password", false),
+        ("{UNKNOWN}password", false),
+        ("$argon2id$password", false),
+    ] {
+        let principal = Principal::<u32> {
+            secrets: vec![stored_secret.to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            principal.verify_secret("password").await,
+            expected,
+            "failed for {stored_secret}"
+        );
+    }
+}
+
 #[test]
 fn address_mappings() {
     const MAPPINGS: &str = r#"
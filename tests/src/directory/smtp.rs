@@ -188,6 +188,7 @@ async fn smtp_directory() {
         }
     }
     assert!(!requests.is_empty());
+    let num_cached = requests.len() as u64;
     for (result, item, expected_result) in requests {
         assert_eq!(
             result.await.unwrap(),
@@ -195,6 +196,12 @@ async fn smtp_directory() {
             "Failed for {item:?}"
         );
     }
+
+    // Verify cache statistics
+    let stats = handle.cache_stats().unwrap();
+    assert!(stats.rcpts.hits >= num_cached, "{stats:?}");
+    assert!(stats.rcpts.positive_entries > 0, "{stats:?}");
+    assert!(stats.rcpts.negative_entries > 0, "{stats:?}");
 }
 
 pub fn spawn_mock_lmtp_server(max_concurrency: u64) -> watch::Sender<bool> {
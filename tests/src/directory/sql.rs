@@ -22,7 +22,9 @@
 */
 
 use ahash::AHashMap;
-use directory::{backend::internal::manage::ManageDirectory, Principal, QueryBy, Type};
+use directory::{
+    backend::internal::manage::ManageDirectory, Principal, QueryBy, QueryResult, Type,
+};
 use mail_send::Credentials;
 use smtp::core::Lookup;
 use store::{LookupStore, Store};
@@ -195,6 +197,38 @@ async fn sql_directory() {
             .unwrap()
             .is_none());
 
+        // Disabled accounts are reported only once the credentials are verified
+        store
+            .create_test_user("mike", "fghij", "Mike Disabled")
+            .await;
+        store.set_test_active("mike", false).await;
+        for (username, secret, expected) in [
+            ("mike", "fghij", QueryResult::Disabled),
+            ("mike", "wrong", QueryResult::InvalidCredentials),
+            ("john", "wrong", QueryResult::InvalidCredentials),
+            ("nobody", "12345", QueryResult::NotFound),
+        ] {
+            assert_eq!(
+                handle
+                    .query_detailed(
+                        QueryBy::Credentials(&Credentials::Plain {
+                            username: username.to_string(),
+                            secret: secret.to_string()
+                        }),
+                        false
+                    )
+                    .await
+                    .unwrap(),
+                expected,
+                "failed for {username}/{secret}"
+            );
+        }
+        assert!(handle
+            .query(QueryBy::Name("mike"), false)
+            .await
+            .unwrap()
+            .is_none());
+
         // Get user by name
         assert_eq!(
             handle
@@ -230,6 +264,39 @@ async fn sql_directory() {
             }
         );
 
+        // Batched queries preserve the order of the requests and skip disabled accounts
+        assert_eq!(
+            handle
+                .query_many(vec![
+                    QueryBy::Name("sales"),
+                    QueryBy::Name("unknown"),
+                    QueryBy::Name("mike"),
+                    QueryBy::Name("jane"),
+                ])
+                .await
+                .unwrap(),
+            vec![
+                Some(Principal {
+                    id: base_store.get_account_id("sales").await.unwrap().unwrap(),
+                    name: "sales".to_string(),
+                    description: "Sales Team".to_string().into(),
+                    typ: Type::Group,
+                    ..Default::default()
+                }),
+                None,
+                None,
+                Some(Principal {
+                    id: base_store.get_account_id("jane").await.unwrap().unwrap(),
+                    name: "jane".to_string(),
+                    description: "Jane Doe".to_string().into(),
+                    typ: Type::Individual,
+                    secrets: vec!["abcde".to_string()],
+                    emails: vec!["jane@example.org".to_string()],
+                    ..Default::default()
+                }),
+            ]
+        );
+
         // Ids by email
         assert_eq!(
             handle.email_to_ids("jane@example.org").await.unwrap(),
@@ -438,6 +505,20 @@ impl DirectoryStore {
             .unwrap();
     }
 
+    pub async fn set_test_active(&self, login: &str, active: bool) {
+        self.store
+            .query::<usize>(
+                if self.is_postgresql() {
+                    "UPDATE accounts SET active = $1 where name = $2"
+                } else {
+                    "UPDATE accounts SET active = ? where name = ?"
+                },
+                vec![active.into(), login.into()],
+            )
+            .await
+            .unwrap();
+    }
+
     pub async fn add_to_group(&self, login: &str, group: &str) {
         self.store
             .query::<usize>(
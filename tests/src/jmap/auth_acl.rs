@@ -778,13 +778,27 @@ pub async fn test(params: &mut JMAPTest) {
         .directory
         .remove_from_group("jdoe@example.com", "sales@example.com")
         .await;
-    server.sessions.clear();
+    let john_access_token = server
+        .get_cached_access_token(john_id.document_id())
+        .await
+        .unwrap();
+    server.evict_all_principals();
+    assert!(server.sessions.is_empty());
+    assert!(!server.is_access_token_current(&john_access_token));
     assert_forbidden(
         john_client
             .set_default_account_id(&sales_id.to_string())
             .email_get(&email_id, [Property::Subject].into())
             .await,
     );
+    let john_access_token = server
+        .get_cached_access_token(john_id.document_id())
+        .await
+        .unwrap();
+    assert!(server.is_access_token_current(&john_access_token));
+    assert!(!john_access_token
+        .member_of
+        .contains(&sales_id.document_id()));
 
     // Destroy test account data
     for id in [john_id, bill_id, jane_id, sales_id] {
@@ -24,6 +24,7 @@
 use std::{sync::Arc, time::Duration};
 
 use directory::backend::internal::manage::ManageDirectory;
+use jmap::auth::rate_limit::RemoteAddress;
 use jmap_client::{
     client::{Client, Credentials},
     core::set::{SetError, SetErrorType},
@@ -35,6 +36,60 @@ use crate::jmap::{assert_is_empty, mailbox::destroy_all_mailboxes};
 
 use super::JMAPTest;
 
+#[test]
+fn remote_address_fingerprint() {
+    let addr = |ip: &str| RemoteAddress::IpAddress(ip.parse().unwrap());
+    let fingerprint = |addr: &RemoteAddress| match addr {
+        RemoteAddress::Fingerprinted(_, fingerprint) => *fingerprint,
+        _ => panic!("Expected a fingerprinted address, got {addr:?}"),
+    };
+    let ua = Some("Mozilla/5.0");
+
+    // Clients without a user agent are only limited by their address
+    for user_agent in [None, Some("")] {
+        let fp_addr = addr("192.168.1.1").with_fingerprint(user_agent);
+        assert_eq!(fp_addr, addr("192.168.1.1"));
+        assert_eq!(fp_addr.limiter_keys(), vec![addr("192.168.1.1")]);
+    }
+
+    // The fingerprint is shared within a network but not across networks or user agents
+    let fp = fingerprint(&addr("192.168.1.1").with_fingerprint(ua));
+    assert_eq!(fp, fingerprint(&addr("192.168.1.200").with_fingerprint(ua)));
+    assert_ne!(fp, fingerprint(&addr("192.168.2.1").with_fingerprint(ua)));
+    assert_ne!(fp, fingerprint(&addr("10.0.0.1").with_fingerprint(ua)));
+    assert_ne!(
+        fp,
+        fingerprint(&addr("192.168.1.1").with_fingerprint(Some("curl/8.0")))
+    );
+    let fp = fingerprint(&addr("2001:db8:0:1::1").with_fingerprint(ua));
+    assert_eq!(
+        fp,
+        fingerprint(&addr("2001:db8:0:2::1").with_fingerprint(ua))
+    );
+    assert_ne!(fp, fingerprint(&addr("2001:db8:1::1").with_fingerprint(ua)));
+
+    // Fingerprinted clients are limited by both their address and their fingerprint
+    let fp_addr = addr("192.168.1.1").with_fingerprint(ua);
+    assert_eq!(
+        fp_addr.limiter_keys(),
+        vec![
+            addr("192.168.1.1"),
+            RemoteAddress::Fingerprint(fingerprint(&fp_addr))
+        ]
+    );
+
+    // Forwarded addresses that can't be parsed still get a fingerprint of their own
+    let fwd = |ip: &str| RemoteAddress::IpAddressFwd(ip.to_string()).with_fingerprint(ua);
+    assert_ne!(
+        fingerprint(&fwd("unknown-1")),
+        fingerprint(&fwd("unknown-2"))
+    );
+    assert_eq!(
+        fingerprint(&fwd("192.168.1.1")),
+        fingerprint(&addr("192.168.1.1").with_fingerprint(ua))
+    );
+}
+
 pub async fn test(params: &mut JMAPTest) {
     println!("Running Authorization tests...");
 
@@ -114,6 +169,28 @@ pub async fn test(params: &mut JMAPTest) {
             .unwrap();
     }
 
+    // Accounts should be locked after too many failures, even if the right password is used
+    server.rate_limit_unauth.clear();
+    for (secret, status) in [
+        ("abcde", 401),
+        ("abcde", 401),
+        ("abcde", 403),
+        ("12345", 403),
+    ] {
+        assert!(matches!(
+            Client::new()
+                .credentials(Credentials::basic("jdoe@example.com", secret))
+                .accept_invalid_certs(true)
+                .connect("https://127.0.0.1:8899")
+                .await,
+            Err(jmap_client::Error::Problem(err)) if err.status() == Some(status)));
+    }
+    server
+        .directory
+        .clear_lockout("jdoe@example.com")
+        .await
+        .unwrap();
+
     // Login with the correct credentials
     let client = Client::new()
         .credentials(Credentials::basic("jdoe@example.com", "12345"))
@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use directory::backend::internal::manage::ManageDirectory;
+use jmap::auth::{geoip::GeoLocation, rate_limit::RemoteAddress, SessionKey};
+use utils::map::ttl_dashmap::TtlMap;
+
+use super::JMAPTest;
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running session location tests...");
+
+    let server = params.server.clone();
+    params
+        .directory
+        .create_test_user_with_email("geo@example.com", "secret", "Geo User")
+        .await;
+    let account_id = server
+        .store
+        .get_or_create_account_id("geo@example.com")
+        .await
+        .unwrap();
+    let access_token = Arc::new(server.get_access_token(account_id).await.unwrap());
+    let location = |country: &str, asn: u32| GeoLocation {
+        country: Some(country.to_string()),
+        city: None,
+        asn: Some(asn),
+        as_org: None,
+    };
+
+    // Concurrent sessions of the same account keep their own location
+    for (token, ip, country, asn) in [
+        ("geo-session-1", "192.0.2.1", "DE", 3320),
+        ("geo-session-2", "198.51.100.1", "US", 7922),
+    ] {
+        let location = server.track_location(
+            &access_token,
+            &RemoteAddress::IpAddress(ip.parse().unwrap()),
+            location(country, asn),
+        );
+        server.cache_session(
+            SessionKey::Token(token.to_string()),
+            &access_token,
+            Some(location),
+        );
+    }
+    for (token, country, asn) in [("geo-session-1", "DE", 3320), ("geo-session-2", "US", 7922)] {
+        let session = server
+            .sessions
+            .get_with_ttl(&SessionKey::Token(token.to_string()))
+            .unwrap();
+        assert_eq!(session.account_id, account_id);
+        assert_eq!(session.location.as_deref(), Some(&location(country, asn)));
+    }
+
+    // The last location seen is used to detect new locations
+    assert_eq!(
+        server.locations.get_with_ttl(&account_id).as_deref(),
+        Some(&location("US", 7922))
+    );
+
+    // Evicting the principal drops its sessions and locations
+    server.evict_principal(account_id);
+    for token in ["geo-session-1", "geo-session-2"] {
+        assert!(server
+            .sessions
+            .get_with_ttl(&SessionKey::Token(token.to_string()))
+            .is_none());
+    }
+    assert!(server.locations.get_with_ttl(&account_id).is_none());
+}
@@ -0,0 +1,128 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::Duration,
+};
+
+use reqwest::{header, StatusCode};
+
+use super::JMAPTest;
+
+const SESSION_URL: &str = "https://127.0.0.1:8899/.well-known/jmap";
+const TRUSTED_HEADER: &str = "X-Authenticated-User";
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running trusted header authentication tests...");
+
+    params
+        .directory
+        .create_test_user_with_email("proxy@example.com", "secret", "Proxy User")
+        .await;
+    params
+        .directory
+        .create_test_user_with_email("direct@example.com", "secret", "Direct User")
+        .await;
+
+    // Requests from a trusted proxy are authenticated by the header
+    let trusted = client(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    assert_eq!(
+        get_session(
+            trusted
+                .get(SESSION_URL)
+                .header(TRUSTED_HEADER, "proxy@example.com")
+        )
+        .await,
+        (StatusCode::OK, Some("proxy@example.com".to_string()))
+    );
+
+    // Missing, empty or unknown principals are not authenticated
+    for request in [
+        trusted.get(SESSION_URL),
+        trusted.get(SESSION_URL).header(TRUSTED_HEADER, ""),
+        trusted
+            .get(SESSION_URL)
+            .header(TRUSTED_HEADER, "nobody@example.com"),
+    ] {
+        assert_eq!(get_session(request).await, (StatusCode::UNAUTHORIZED, None));
+    }
+
+    // Hosts outside the trusted proxy list can not spoof the header
+    // (connecting from 127.0.0.2 relies on Linux routing the whole loopback range)
+    let untrusted = client(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)));
+    assert_eq!(
+        get_session(
+            untrusted
+                .get(SESSION_URL)
+                .header(TRUSTED_HEADER, "proxy@example.com")
+        )
+        .await,
+        (StatusCode::UNAUTHORIZED, None)
+    );
+
+    // The Authorization header takes precedence over the trusted header
+    assert_eq!(
+        get_session(
+            trusted
+                .get(SESSION_URL)
+                .header(TRUSTED_HEADER, "proxy@example.com")
+                .basic_auth("direct@example.com", Some("secret"))
+        )
+        .await,
+        (StatusCode::OK, Some("direct@example.com".to_string()))
+    );
+    assert_eq!(
+        get_session(
+            trusted
+                .get(SESSION_URL)
+                .header(TRUSTED_HEADER, "proxy@example.com")
+                .header(header::AUTHORIZATION, "Bearer invalid")
+        )
+        .await,
+        (StatusCode::UNAUTHORIZED, None)
+    );
+}
+
+async fn get_session(request: reqwest::RequestBuilder) -> (StatusCode, Option<String>) {
+    let response = request.send().await.unwrap();
+    let status = response.status();
+    let username = serde_json::from_slice::<serde_json::Value>(&response.bytes().await.unwrap())
+        .ok()
+        .and_then(|session| {
+            session
+                .get("username")
+                .and_then(|username| username.as_str())
+                .map(|username| username.to_string())
+        });
+    (status, username)
+}
+
+fn client(local_address: IpAddr) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_millis(500))
+        .danger_accept_invalid_certs(true)
+        .local_address(local_address)
+        .build()
+        .unwrap()
+}
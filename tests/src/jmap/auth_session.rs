@@ -0,0 +1,240 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use reqwest::{header, Method, StatusCode};
+use serde::Deserialize;
+
+use super::JMAPTest;
+
+const BASE_URL: &str = "https://127.0.0.1:8899";
+const ECHO_REQUEST: &str =
+    r#"{"using":["urn:ietf:params:jmap:core"],"methodCalls":[["Core/echo",{},"c1"]]}"#;
+
+#[derive(Debug, Deserialize)]
+struct SessionResponse {
+    #[serde(rename = "csrfToken")]
+    csrf_token: String,
+    #[serde(rename = "expiresIn")]
+    expires_in: u64,
+}
+
+struct Session {
+    token: String,
+    csrf_token: String,
+}
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running session cookie tests...");
+
+    params
+        .directory
+        .create_test_user_with_email("cookie@example.com", "secret", "Cookie Monster")
+        .await;
+
+    // Invalid credentials do not issue a session
+    assert_eq!(
+        send(
+            client()
+                .post(format!("{BASE_URL}/auth/session"))
+                .form(&[("email", "cookie@example.com"), ("password", "wrong")])
+        )
+        .await
+        .0,
+        StatusCode::UNAUTHORIZED
+    );
+
+    // Read requests are authenticated by the cookie alone
+    let session = login().await;
+    assert_eq!(get_session(&session.token).await, StatusCode::OK);
+
+    // Tampered tokens are rejected
+    let mut tampered = session.token.clone().into_bytes();
+    let pos = tampered.len() / 2;
+    tampered[pos] = if tampered[pos] == b'A' { b'B' } else { b'A' };
+    assert_eq!(
+        get_session(&String::from_utf8(tampered).unwrap()).await,
+        StatusCode::UNAUTHORIZED
+    );
+
+    // State changing requests require the double-submitted CSRF token
+    let wrong_csrf = "0".repeat(session.csrf_token.len());
+    for (header, cookie) in [
+        (None, Some(session.csrf_token.as_str())),
+        (Some(session.csrf_token.as_str()), None),
+        (Some(wrong_csrf.as_str()), Some(wrong_csrf.as_str())),
+        (Some(session.csrf_token.as_str()), Some(wrong_csrf.as_str())),
+    ] {
+        assert_eq!(
+            post_jmap(&session.token, header, cookie).await,
+            StatusCode::FORBIDDEN,
+            "header: {header:?}, cookie: {cookie:?}"
+        );
+    }
+    assert_eq!(
+        post_jmap(
+            &session.token,
+            Some(&session.csrf_token),
+            Some(&session.csrf_token)
+        )
+        .await,
+        StatusCode::OK
+    );
+
+    // CSRF tokens are bound to their session
+    let other_session = login().await;
+    assert_ne!(other_session.csrf_token, session.csrf_token);
+    assert_eq!(
+        post_jmap(
+            &other_session.token,
+            Some(&session.csrf_token),
+            Some(&session.csrf_token)
+        )
+        .await,
+        StatusCode::FORBIDDEN
+    );
+
+    // Logging out revokes every session of the account
+    let (status, cookies) = send(
+        client()
+            .request(Method::DELETE, format!("{BASE_URL}/auth/session"))
+            .header(
+                header::COOKIE,
+                format!("stalwart_session={}", session.token),
+            ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(
+        cookies
+            .iter()
+            .any(|cookie| cookie.starts_with("stalwart_session=;") && cookie.contains("Max-Age=0")),
+        "{cookies:?}"
+    );
+    assert_eq!(get_session(&session.token).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        get_session(&other_session.token).await,
+        StatusCode::UNAUTHORIZED
+    );
+
+    // New sessions are not affected by earlier logouts
+    let session = login().await;
+    assert_eq!(get_session(&session.token).await, StatusCode::OK);
+
+    // Sessions expire
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(get_session(&session.token).await, StatusCode::UNAUTHORIZED);
+}
+
+async fn login() -> Session {
+    let response = client()
+        .post(format!("{BASE_URL}/auth/session"))
+        .form(&[("email", "cookie@example.com"), ("password", "secret")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookies = set_cookies(response.headers());
+    let response: SessionResponse =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(response.expires_in, 2);
+
+    let session_cookie = cookies
+        .iter()
+        .find(|cookie| cookie.starts_with("stalwart_session="))
+        .expect("missing session cookie");
+    for attribute in ["HttpOnly", "Secure", "SameSite=Strict"] {
+        assert!(session_cookie.contains(attribute), "{session_cookie}");
+    }
+    let csrf_cookie = cookies
+        .iter()
+        .find(|cookie| cookie.starts_with("stalwart_csrf="))
+        .expect("missing CSRF cookie");
+    assert!(!csrf_cookie.contains("HttpOnly"), "{csrf_cookie}");
+    assert_eq!(cookie_value(csrf_cookie), response.csrf_token);
+
+    Session {
+        token: cookie_value(session_cookie).to_string(),
+        csrf_token: response.csrf_token,
+    }
+}
+
+async fn get_session(token: &str) -> StatusCode {
+    send(
+        client()
+            .get(format!("{BASE_URL}/.well-known/jmap"))
+            .header(header::COOKIE, format!("stalwart_session={token}")),
+    )
+    .await
+    .0
+}
+
+async fn post_jmap(
+    token: &str,
+    csrf_header: Option<&str>,
+    csrf_cookie: Option<&str>,
+) -> StatusCode {
+    let mut cookies = format!("stalwart_session={token}");
+    if let Some(csrf_cookie) = csrf_cookie {
+        cookies.push_str(&format!("; stalwart_csrf={csrf_cookie}"));
+    }
+    let mut request = client()
+        .post(format!("{BASE_URL}/jmap"))
+        .header(header::COOKIE, cookies)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(ECHO_REQUEST);
+    if let Some(csrf_header) = csrf_header {
+        request = request.header("X-CSRF-Token", csrf_header);
+    }
+    send(request).await.0
+}
+
+async fn send(request: reqwest::RequestBuilder) -> (StatusCode, Vec<String>) {
+    let response = request.send().await.unwrap();
+    (response.status(), set_cookies(response.headers()))
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_millis(500))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+}
+
+fn set_cookies(headers: &header::HeaderMap) -> Vec<String> {
+    headers
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+        .collect()
+}
+
+fn cookie_value(cookie: &str) -> &str {
+    cookie
+        .split_once('=')
+        .and_then(|(_, value)| value.split(';').next())
+        .unwrap()
+}
@@ -42,7 +42,10 @@ use crate::{add_test_certs, directory::DirectoryStore, store::TempDir};
 
 pub mod auth_acl;
 pub mod auth_limits;
+pub mod auth_location;
 pub mod auth_oauth;
+pub mod auth_proxy;
+pub mod auth_session;
 pub mod blob;
 pub mod crypto;
 pub mod delivery;
@@ -204,6 +207,17 @@ account = "1000/1m"
 authentication = "100/2s"
 anonymous = "100/1m"
 
+[jmap.auth.lockout]
+attempts = 3
+
+[jmap.auth.trusted-header]
+name = "X-Authenticated-User"
+proxies = ["127.0.0.1"]
+
+[jmap.auth.session-cookie]
+enable = true
+expiry = "2s"
+
 [jmap.event-source]
 throttle = "500ms"
 
@@ -302,6 +316,9 @@ pub async fn jmap_tests() {
     auth_acl::test(&mut params).await;
     auth_limits::test(&mut params).await;
     auth_oauth::test(&mut params).await;
+    auth_session::test(&mut params).await;
+    auth_proxy::test(&mut params).await;
+    auth_location::test(&mut params).await;
     event_source::test(&mut params).await;
     push_subscription::test(&mut params).await;
     sieve_script::test(&mut params).await;
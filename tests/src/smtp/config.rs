@@ -33,24 +33,31 @@ use std::{
 use store::{
     backend::memory::{LookupList, MemoryStore},
     config::ConfigStore,
-    LookupStore,
+    LookupStore, QueryTemplate,
 };
 use tokio::net::TcpSocket;
 
-use utils::config::{Config, DynValue, KeyLookup, Listener, Rate, Server, ServerProtocol};
+use utils::{
+    acme::ChallengeType,
+    config::{
+        certificate::{Certificate, ClientCertificate},
+        Config, DynValue, KeyLookup, Listener, Rate, Server, ServerProtocol, Servers,
+    },
+};
 
 use ahash::AHashMap;
 
 use smtp::{
     config::{
-        condition::ConfigCondition, if_block::ConfigIf, throttle::ConfigThrottle, Condition,
-        ConditionMatch, Conditions, ConfigContext, EnvelopeKey, IfBlock, IfThen, IpAddrMask,
-        StringMatch, Throttle, THROTTLE_AUTH_AS, THROTTLE_REMOTE_IP, THROTTLE_SENDER_DOMAIN,
+        condition::ConfigCondition, if_block::ConfigIf, queue::ConfigQueue,
+        resolver::ConfigResolver, throttle::ConfigThrottle, Condition, ConditionMatch, Conditions,
+        ConfigContext, EnvelopeKey, IfBlock, IfThen, IpAddrMask, StringMatch, Throttle,
+        THROTTLE_AUTH_AS, THROTTLE_REMOTE_IP, THROTTLE_SENDER_DOMAIN,
     },
     core::Lookup,
 };
 
-use super::add_test_certs;
+use super::{add_test_certs, make_temp_dir};
 
 struct TestEnvelope {
     pub local_ip: IpAddr,
@@ -81,15 +88,29 @@ fn parse_conditions() {
         ..Default::default()
     }];
     let mut context = ConfigContext::new(&servers);
+    let query = "SELECT address FROM list WHERE address = ?";
     let list = LookupStore::Query(Arc::new(store::QueryStore {
         store: MemoryStore::List(LookupList::default()).into(),
-        query: "abc".into(),
+        query: query.into(),
+        cache: None,
     }));
     context
         .stores
         .lookup_stores
         .insert("test-list".to_string(), list.clone());
 
+    // Queries used as lookups must be registered and take a single parameter
+    assert!(config.parse_conditions(&context).is_err());
+    context.stores.queries.insert(
+        "test-list".to_string(),
+        Arc::new(QueryTemplate::new(format!("{query} AND type = ?"))),
+    );
+    assert!(config.parse_conditions(&context).is_err());
+    context.stores.queries.insert(
+        "test-list".to_string(),
+        Arc::new(QueryTemplate::new(query.to_string())),
+    );
+
     let mut conditions = config.parse_conditions(&context).unwrap();
     let expected_rules = AHashMap::from_iter([
         (
@@ -421,6 +442,72 @@ fn parse_throttle() {
     );
 }
 
+#[test]
+fn parse_queue_limits() {
+    let toml = concat!(
+        "[server]\nhostname = \"mx.example.org\"\n",
+        "[queue]\npath = \"/tmp/queue\"\n",
+    );
+    assert!(Config::new(toml)
+        .unwrap()
+        .parse_queue(&ConfigContext::new(&[]))
+        .is_ok());
+
+    for (limit, expected_err) in [
+        ("mx = 0", "\"queue.outbound.limits.mx\" must be at least 1"),
+        (
+            "multihomed = [{if = \"rcpt-domain\", eq = \"example.org\", then = 0}, {else = 2}]",
+            "\"queue.outbound.limits.multihomed\" must be at least 1",
+        ),
+    ] {
+        let err = Config::new(&format!("{toml}[queue.outbound.limits]\n{limit}\n"))
+            .unwrap()
+            .parse_queue(&ConfigContext::new(&[]))
+            .err()
+            .unwrap();
+        assert!(err.contains(expected_err), "{err}");
+    }
+}
+
+#[test]
+fn parse_resolver_ttl() {
+    let toml = "[resolver]\ntype = \"custom\"\naddress = \"127.0.0.1\"\n";
+
+    // Cached TTLs are clamped to a 30 second floor by default
+    let (_, opts) = Config::new(toml)
+        .unwrap()
+        .parse_resolver_options("resolver")
+        .unwrap();
+    assert_eq!(opts.positive_min_ttl, Some(Duration::from_secs(30)));
+    assert_eq!(opts.positive_max_ttl, Some(Duration::from_secs(86400)));
+    assert_eq!(opts.negative_min_ttl, Some(Duration::from_secs(30)));
+    assert_eq!(opts.negative_max_ttl, Some(Duration::from_secs(3600)));
+
+    let (_, opts) = Config::new(&format!(
+        concat!(
+            "{}[resolver.ttl.positive]\nmin = \"1s\"\nmax = \"1h\"\n",
+            "[resolver.ttl.negative]\nmin = \"10s\"\nmax = \"5m\"\n"
+        ),
+        toml
+    ))
+    .unwrap()
+    .parse_resolver_options("resolver")
+    .unwrap();
+    assert_eq!(opts.positive_min_ttl, Some(Duration::from_secs(1)));
+    assert_eq!(opts.positive_max_ttl, Some(Duration::from_secs(3600)));
+    assert_eq!(opts.negative_min_ttl, Some(Duration::from_secs(10)));
+    assert_eq!(opts.negative_max_ttl, Some(Duration::from_secs(300)));
+
+    let err = Config::new(&format!(
+        "{toml}[resolver.ttl.positive]\nmin = \"2h\"\nmax = \"1h\"\n"
+    ))
+    .unwrap()
+    .parse_resolver_options("resolver")
+    .err()
+    .unwrap();
+    assert!(err.contains("resolver.ttl.positive.min"), "{err}");
+}
+
 #[test]
 fn parse_servers() {
     let mut file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -434,6 +521,43 @@ fn parse_servers() {
     // Parse servers
     let config = Config::new(&toml).unwrap();
     let servers = config.parse_servers().unwrap().inner;
+
+    // Session ticket configuration
+    for (server, expected_lifetime) in servers.iter().zip([Some(12 * 60 * 60), None, Some(7200)]) {
+        let ticketer = &server.tls.as_ref().unwrap().ticketer;
+        assert_eq!(
+            ticketer.enabled().then(|| ticketer.lifetime()),
+            expected_lifetime,
+            "failed for {}",
+            server.id
+        );
+    }
+
+    // Client certificates
+    let cert = rustls_pemfile::certs(&mut std::io::Cursor::new(
+        fs::read(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("resources")
+                .join("smtp")
+                .join("certs")
+                .join("tls_cert.pem"),
+        )
+        .unwrap(),
+    ))
+    .next()
+    .unwrap()
+    .unwrap();
+    let client_cert = ClientCertificate::parse(&cert).unwrap();
+    assert_eq!(client_cert.subject.as_deref(), Some("localhost"));
+    assert_eq!(client_cert.names().collect::<Vec<_>>(), vec!["localhost"]);
+
+    // SNI protocol policies
+    assert!(servers[0].tls_policies.is_empty());
+    assert_eq!(
+        servers[2].tls_policies.keys().collect::<Vec<_>>(),
+        vec!["secure.example.org"]
+    );
+
     let expected_servers = vec![
         Server {
             id: "smtp".to_string(),
@@ -450,8 +574,11 @@ fn parse_servers() {
                 nodelay: true,
             }],
             tls: None,
+            tls_policies: AHashMap::new(),
+            tls_acme: None,
             tls_implicit: false,
             max_connections: 8192,
+            acme_providers: vec![],
         },
         Server {
             id: "smtps".to_string(),
@@ -478,8 +605,11 @@ fn parse_servers() {
                 },
             ],
             tls: None,
+            tls_policies: AHashMap::new(),
+            tls_acme: None,
             tls_implicit: true,
             max_connections: 1024,
+            acme_providers: vec![],
         },
         Server {
             id: "submission".to_string(),
@@ -496,8 +626,11 @@ fn parse_servers() {
                 nodelay: true,
             }],
             tls: None,
+            tls_policies: AHashMap::new(),
+            tls_acme: None,
             tls_implicit: true,
             max_connections: 8192,
+            acme_providers: vec![],
         },
     ];
 
@@ -554,6 +687,264 @@ fn parse_servers() {
     }
 }
 
+#[test]
+fn reload_certificate() {
+    let temp_dir = make_temp_dir("smtp_reload_cert_test", true);
+    let mut cert_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    cert_path.push("resources");
+    cert_path.push("smtp");
+    cert_path.push("certs");
+    let cert = fs::read(cert_path.join("tls_cert.pem")).unwrap();
+    let pk = fs::read(cert_path.join("tls_privatekey.pem")).unwrap();
+    let cert_file = temp_dir.temp_dir.join("cert.pem");
+    let pk_file = temp_dir.temp_dir.join("pk.pem");
+    fs::write(&cert_file, &cert).unwrap();
+    fs::write(&pk_file, &pk).unwrap();
+
+    let config = Config::new(&format!(
+        "[certificate.\"default\"]\ncert = \"file://{}\"\nprivate-key = \"file://{}\"\n",
+        cert_file.display(),
+        pk_file.display()
+    ))
+    .unwrap();
+    let certificate = config.parse_certificate("default").unwrap();
+    let original_key = certificate.certified_key().unwrap();
+    assert!(certificate.is_watched());
+    assert!(!certificate.reload_if_changed());
+
+    // A malformed certificate must be rejected, keeping the previous one
+    let touch = |path: &PathBuf, contents: &[u8], offset: u64| {
+        fs::write(path, contents).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + Duration::from_secs(offset))
+            .unwrap();
+    };
+    touch(&cert_file, b"invalid", 10);
+    assert!(!certificate.reload_if_changed());
+    assert!(Arc::ptr_eq(
+        &original_key,
+        &certificate.certified_key().unwrap()
+    ));
+
+    // Valid certificates are reloaded
+    touch(&cert_file, &cert, 20);
+    assert!(certificate.reload_if_changed());
+    assert!(!Arc::ptr_eq(
+        &original_key,
+        &certificate.certified_key().unwrap()
+    ));
+    assert!(!certificate.reload_if_changed());
+}
+
+#[test]
+fn certificate_key_mismatch() {
+    let mut cert_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    cert_path.push("resources");
+    cert_path.push("smtp");
+    cert_path.push("certs");
+    let config = |pk: &str| {
+        Config::new(&format!(
+            "[certificate.\"default\"]\ncert = \"file://{}\"\nprivate-key = \"file://{}\"\n",
+            cert_path.join("tls_cert.pem").display(),
+            cert_path.join(pk).display()
+        ))
+        .unwrap()
+    };
+
+    assert!(config("tls_privatekey.pem")
+        .rustls_certified_key("default")
+        .is_ok());
+    for (pk, expected_err) in [
+        ("tls_other_privatekey.pem", "does not belong"),
+        (
+            "tls_ed25519_privatekey.pem",
+            "ED25519 private key used with a RSA certificate",
+        ),
+    ] {
+        let err = config(pk).rustls_certified_key("default").unwrap_err();
+        assert!(err.contains("\"default\""), "{err}");
+        assert!(err.contains(expected_err), "{err}");
+    }
+}
+
+#[test]
+fn self_signed_certificate() {
+    let cert = Certificate::self_signed("mx.example.org").unwrap();
+    assert!(!cert.is_watched());
+    let client_cert =
+        ClientCertificate::parse(cert.certified_key().unwrap().end_entity_cert().unwrap()).unwrap();
+    assert_eq!(client_cert.dns_names, vec!["mx.example.org"]);
+
+    // Listeners without a certificate are only allowed when opted in
+    let toml = concat!(
+        "[server]\nhostname = \"mx.example.org\"\n",
+        "[server.listener.\"smtp\"]\nbind = \"127.0.0.1:9925\"\nprotocol = \"smtp\"\n",
+        "[server.tls]\nenable = true\n",
+    );
+    assert!(Config::new(toml).unwrap().parse_servers().is_err());
+    assert!(
+        Config::new(&format!("{toml}[certificate]\nself-signed = true\n"))
+            .unwrap()
+            .parse_servers()
+            .unwrap()
+            .inner[0]
+            .tls
+            .is_some()
+    );
+}
+
+#[test]
+fn listener_certificate_override() {
+    // Listeners pin their own default certificate, regardless of the one in server.tls
+    let toml = add_test_certs(concat!(
+        "[server]\nhostname = \"mx.example.org\"\n",
+        "[server.listener.\"smtp\"]\nbind = \"127.0.0.1:9925\"\nprotocol = \"smtp\"\n",
+        "[server.listener.\"submission\"]\nbind = \"127.0.0.1:9991\"\nprotocol = \"smtp\"\n",
+        "tls.certificate = \"submission\"\n",
+        "[server.tls]\nenable = true\ncertificate = \"default\"\n",
+        "[certificate.\"default\"]\ncert = \"file://{CERT}\"\nprivate-key = \"file://{PK}\"\n",
+        "[certificate.\"submission\"]\ncert = \"file://{CERT}\"\nprivate-key = \"file://{PK}\"\n",
+    ));
+    let servers = Config::new(&toml).unwrap().parse_servers().unwrap();
+    let mut cert_ids = servers
+        .certificates
+        .iter()
+        .map(|cert| cert.id.as_str())
+        .collect::<Vec<_>>();
+    cert_ids.sort_unstable();
+    assert_eq!(cert_ids, vec!["default", "submission"]);
+    for (server_id, cert_id) in [("smtp", "default"), ("submission", "submission")] {
+        assert_eq!(default_cert_id(&servers, server_id), cert_id);
+    }
+
+    // The listener certificate is used even if the shared default is unusable
+    let toml = toml.replace(
+        "[server.listener.\"smtp\"]\nbind = \"127.0.0.1:9925\"\nprotocol = \"smtp\"\n",
+        "",
+    );
+    let toml = toml.replacen(
+        "private-key = \"file://",
+        "private-key = \"file:///missing",
+        1,
+    );
+    let servers = Config::new(&toml).unwrap().parse_servers().unwrap();
+    assert_eq!(
+        servers
+            .certificates
+            .iter()
+            .map(|cert| cert.id.as_str())
+            .collect::<Vec<_>>(),
+        vec!["submission"]
+    );
+    assert_eq!(default_cert_id(&servers, "submission"), "submission");
+}
+
+// The certificate resolver is only reachable through the rustls configuration
+fn default_cert_id(servers: &Servers, server_id: &str) -> String {
+    let resolver = format!(
+        "{:?}",
+        servers
+            .inner
+            .iter()
+            .find(|server| server.id == server_id)
+            .unwrap()
+            .tls
+            .as_ref()
+            .unwrap()
+            .cert_resolver
+    );
+    resolver
+        .split_once("default_cert: Some(Certificate { id: \"")
+        .and_then(|(_, cert)| cert.split_once('"'))
+        .map(|(cert_id, _)| cert_id.to_string())
+        .unwrap_or_else(|| panic!("No default certificate for {server_id:?}: {resolver}"))
+}
+
+#[test]
+fn parse_acme() {
+    let temp_dir = make_temp_dir("smtp_acme_test", true);
+    let config = Config::new(&format!(
+        concat!(
+            "[acme.\"letsencrypt\"]\n",
+            "directory = \"https://acme.example.org/directory\"\n",
+            "contact = [\"mailto:admin@example.org\"]\n",
+            "domains = [\"Mail.example.org\", \"mx.example.org\"]\n",
+            "challenge = \"http-01\"\n",
+            "cache = \"{}\"\n",
+            "renew-before = \"10d\"\n",
+        ),
+        temp_dir.temp_dir.display()
+    ))
+    .unwrap();
+    let providers = config.parse_acme_providers().unwrap();
+    let provider = providers.get("letsencrypt").unwrap();
+    assert_eq!(provider.domains, vec!["mail.example.org", "mx.example.org"]);
+    assert_eq!(provider.challenge, ChallengeType::Http01);
+    assert_eq!(provider.renew_before, Duration::from_secs(10 * 86400));
+    assert_eq!(provider.http_challenge("token"), None);
+
+    // No certificate has been issued yet, so renewal is due immediately
+    assert!(provider.cert.certified_key().is_none());
+    assert_eq!(provider.renew_in(), Duration::ZERO);
+
+    // Previously issued certificates are loaded from the cache
+    let mut cert_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    cert_path.push("resources");
+    cert_path.push("smtp");
+    cert_path.push("certs");
+    fs::copy(
+        cert_path.join("tls_cert.pem"),
+        temp_dir.temp_dir.join("cert-letsencrypt.pem"),
+    )
+    .unwrap();
+    fs::copy(
+        cert_path.join("tls_privatekey.pem"),
+        temp_dir.temp_dir.join("cert-letsencrypt.key"),
+    )
+    .unwrap();
+    assert!(provider.cert.reload());
+    assert!(provider.cert.certified_key().is_some());
+
+    // Expired certificates are renewed immediately
+    assert_eq!(provider.renew_in(), Duration::ZERO);
+
+    // Invalid challenge types are rejected
+    assert!(Config::new(
+        "[acme.\"test\"]\ndirectory = \"https://acme.example.org\"\ndomains = [\"example.org\"]\ncache = \"/tmp\"\nchallenge = \"dns-01\"\n"
+    )
+    .unwrap()
+    .parse_acme_providers()
+    .is_err());
+}
+
+#[test]
+fn parse_pkcs12() {
+    let mut cert_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    cert_path.push("resources");
+    cert_path.push("smtp");
+    cert_path.push("certs");
+    let config = Config::new(&format!(
+        "[certificate.\"default\"]\npkcs12 = \"file://{}\"\npassword = \"secret\"\n",
+        cert_path.join("tls_bundle.p12").display()
+    ))
+    .unwrap();
+
+    let (certs, _) = config.rustls_pkcs12("default", "secret").unwrap();
+    assert_eq!(
+        certs,
+        rustls_pemfile::certs(&mut std::io::Cursor::new(
+            fs::read(cert_path.join("tls_cert.pem")).unwrap()
+        ))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+    );
+    assert!(config.rustls_pkcs12("default", "wrong").is_err());
+    assert!(config.parse_certificate("default").is_ok());
+}
+
 #[tokio::test]
 async fn eval_if() {
     let mut file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -23,12 +23,15 @@
 
 use directory::core::config::ConfigDirectory;
 use smtp_proto::{AUTH_LOGIN, AUTH_PLAIN};
-use store::Stores;
+use store::{config::ConfigStore, Stores};
 use utils::config::{Config, DynValue};
 
-use crate::smtp::{
-    session::{TestSession, VerifyResponse},
-    ParseTestConfig, TestConfig,
+use crate::{
+    smtp::{
+        session::{TestSession, VerifyResponse},
+        ParseTestConfig, TestConfig,
+    },
+    store::TempDir,
 };
 use smtp::{
     config::{ConfigContext, EnvelopeKey, IfBlock},
@@ -175,3 +178,75 @@ async fn auth() {
         .cmd("AUTH PLAIN AGpvaG4Ac2VjcmV0", "503 5.5.1")
         .await;
 }
+
+const LOCKOUT: &str = r#"
+[store."lockout"]
+type = "sqlite"
+path = "{TMP}/lockout.db"
+
+[jmap.auth.lockout]
+attempts = 2
+store = "lockout"
+"#;
+
+#[tokio::test]
+async fn auth_lockout() {
+    let temp_dir = TempDir::new("smtp_auth_lockout_tests", true);
+    let config = Config::new(&format!(
+        "{DIRECTORY}{}",
+        LOCKOUT.replace("{TMP}", &temp_dir.path.to_string_lossy())
+    ))
+    .unwrap();
+    let mut core = SMTP::test();
+    let mut ctx = ConfigContext::new(&[]);
+    ctx.stores = config.parse_stores().await.unwrap();
+    ctx.directory = config.parse_directory(&ctx.stores, None).await.unwrap();
+
+    let config = &mut core.session.config.auth;
+    config.require = IfBlock::new(true);
+    config.directory = "'local'"
+        .parse_if::<Option<DynValue<EnvelopeKey>>>(&ctx)
+        .map_if_block(&ctx.directory.directories, "", "")
+        .unwrap();
+    config.errors_max = IfBlock::new(10);
+    config.errors_wait = "'1ms'".parse_if(&ctx);
+    config.mechanisms = IfBlock::new(AUTH_PLAIN | AUTH_LOGIN);
+    config.must_match_sender = IfBlock::new(true);
+
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.stream.tls = true;
+    session.ehlo("mx.foobar.org").await;
+
+    // The account is locked once the failure threshold is reached
+    session
+        .cmd("AUTH PLAIN AGpvaG4AY2hpbWljaGFuZ2Fz", "535 5.7.8")
+        .await;
+    session
+        .cmd("AUTH PLAIN AGpvaG4AY2hpbWljaGFuZ2Fz", "454 4.7.0")
+        .await;
+
+    // Locked accounts are rejected even with the right password
+    session
+        .cmd("AUTH PLAIN AGpvaG4Ac2VjcmV0", "454 4.7.0")
+        .await;
+
+    // Other accounts are not affected
+    session
+        .cmd("AUTH PLAIN AGphbmUAcDRzc3cwcmQ=", "235 2.7.0")
+        .await;
+
+    // Clearing the lockout allows the account to log in again
+    ctx.directory
+        .directories
+        .get("local")
+        .unwrap()
+        .clear_lockout("john")
+        .await
+        .unwrap();
+    session.data.authenticated_as.clear();
+    session
+        .cmd("AUTH PLAIN AGpvaG4Ac2VjcmV0", "235 2.7.0")
+        .await;
+}
@@ -25,8 +25,11 @@ use std::time::Duration;
 
 use directory::core::config::ConfigDirectory;
 use smtp_proto::{AUTH_LOGIN, AUTH_PLAIN};
-use store::config::ConfigStore;
-use utils::config::{Config, DynValue};
+use store::{config::ConfigStore, parking_lot::Mutex, QueryCache, QueryStore};
+use utils::{
+    config::{Config, DynValue},
+    map::lookup_cache::{LookupCache, ValueCache},
+};
 
 use crate::{
     directory::DirectoryStore,
@@ -137,6 +140,110 @@ async fn lookup_sql() {
             .unwrap();
     }
 
+    // Multiple result sets in a single call
+    let results = handle
+        .store
+        .query_batch(vec![
+            (
+                "SELECT description FROM domains WHERE name = ?",
+                vec!["foobar.org".into()],
+            ),
+            ("SELECT addr FROM allowed_ips", vec![]),
+            (
+                "SELECT name FROM domains WHERE name = ?",
+                vec!["unknown.org".into()],
+            ),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(
+        results[0].rows[0].values,
+        vec![store::Value::from("Main domain")]
+    );
+    assert_eq!(
+        results[1].rows[0].values,
+        vec![store::Value::from("10.0.0.50")]
+    );
+    assert!(results[2].rows.is_empty());
+
+    // Paginated queries
+    for (limit, offset, expected) in [
+        (Some(1), None, vec!["foobar.net"]),
+        (Some(1), Some(1), vec!["foobar.org"]),
+        (None, Some(1), vec!["foobar.org"]),
+        (Some(5), Some(2), vec![]),
+    ] {
+        let rows = handle
+            .store
+            .query_page::<store::Rows>(
+                "SELECT name FROM domains WHERE name LIKE ? ORDER BY name;",
+                vec!["foobar.%".into()],
+                limit,
+                offset,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            rows.rows
+                .into_iter()
+                .flat_map(|row| row.values)
+                .collect::<Vec<_>>(),
+            expected
+                .into_iter()
+                .map(store::Value::from)
+                .collect::<Vec<_>>(),
+            "limit {limit:?}, offset {offset:?}"
+        );
+    }
+
+    // Cached query lookups, including negative results
+    let cached = QueryStore {
+        store: handle.store.clone(),
+        query: "SELECT addr FROM allowed_ips WHERE addr = ? LIMIT 1".to_string(),
+        cache: Some(QueryCache {
+            exists: Mutex::new(LookupCache::new(
+                10,
+                Duration::from_secs(3600),
+                Duration::from_secs(3600),
+            )),
+            values: Mutex::new(ValueCache::new(10, Duration::from_secs(3600))),
+            ttl_negative: Duration::from_secs(3600),
+        }),
+    };
+    assert!(cached.exists("10.0.0.50").await.unwrap());
+    assert!(!cached.exists("10.0.0.99").await.unwrap());
+    assert_eq!(
+        cached.first_value("10.0.0.50".to_string()).await.unwrap(),
+        Some(store::Value::from("10.0.0.50"))
+    );
+    for query in [
+        "DELETE FROM allowed_ips WHERE addr = '10.0.0.50'",
+        "INSERT INTO allowed_ips (addr) VALUES ('10.0.0.99')",
+    ] {
+        handle
+            .store
+            .query::<usize>(query, Vec::new())
+            .await
+            .unwrap();
+    }
+    assert!(cached.exists("10.0.0.50").await.unwrap());
+    assert!(!cached.exists("10.0.0.99").await.unwrap());
+    assert_eq!(
+        cached.first_value("10.0.0.50".to_string()).await.unwrap(),
+        Some(store::Value::from("10.0.0.50"))
+    );
+    for query in [
+        "DELETE FROM allowed_ips WHERE addr = '10.0.0.99'",
+        "INSERT INTO allowed_ips (addr) VALUES ('10.0.0.50')",
+    ] {
+        handle
+            .store
+            .query::<usize>(query, Vec::new())
+            .await
+            .unwrap();
+    }
+
     // Enable AUTH
     let config = &mut core.session.config.auth;
     config.directory = r"'sql'"
@@ -21,23 +21,91 @@
  * for more details.
 */
 
-use std::time::{Duration, Instant};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant},
+};
 
-use mail_auth::{IpLookupStrategy, MX};
+use mail_auth::{hickory_resolver::proto::rr::RecordType, IpLookupStrategy, Resolver, MX};
 
-use ::smtp::{config::IfBlock, core::SMTP, outbound::NextHop};
+use ::smtp::{
+    config::IfBlock,
+    core::{rng::SelectionRng, NamedResolver, SMTP},
+    outbound::NextHop,
+};
 use mail_parser::DateTime;
 use smtp::{
-    config::AggregateFrequency,
+    config::{AggregateFrequency, RelayHost, RequireOptional},
     outbound::{
-        lookup::ToNextHop,
+        dane::DnssecStatus,
+        lookup::{
+            select_source_ips, RandomSourceIp, RoundRobinSourceIp, SourceIpSelector, SrvRecord,
+            ToNextHop, ToSrvNextHop,
+        },
         mta_sts::{Mode, MxPattern, Policy},
     },
     queue::RecipientDomain,
 };
+use utils::config::ServerProtocol;
 
 use crate::smtp::TestConfig;
 
+struct FixedSourceIp(usize, usize);
+
+impl SourceIpSelector for FixedSourceIp {
+    fn select(&self, num_ipv4: usize, num_ipv6: usize) -> (usize, usize) {
+        assert!(num_ipv4 > 1 || num_ipv6 > 1);
+        (self.0, self.1)
+    }
+}
+
+#[test]
+fn source_ip_selection() {
+    let ipv4 = [
+        Ipv4Addr::new(10, 0, 0, 1),
+        Ipv4Addr::new(10, 0, 0, 2),
+        Ipv4Addr::new(10, 0, 0, 3),
+    ];
+    let ipv6 = [Ipv6Addr::new(0xa, 0xb, 0, 0, 0, 0, 0, 1)];
+
+    // Selectors are only consulted when there is more than one address
+    assert_eq!(
+        select_source_ips(&[], &[], &FixedSourceIp(5, 5)),
+        (None, None)
+    );
+    assert_eq!(
+        select_source_ips(&ipv4[..1], &ipv6, &FixedSourceIp(5, 5)),
+        (Some(IpAddr::V4(ipv4[0])), Some(IpAddr::V6(ipv6[0])))
+    );
+    for (pos, ip) in ipv4.iter().enumerate() {
+        assert_eq!(
+            select_source_ips(&ipv4, &ipv6, &FixedSourceIp(pos, 5)),
+            (Some(IpAddr::V4(*ip)), Some(IpAddr::V6(ipv6[0])))
+        );
+    }
+
+    // Round-robin advances once per selection, for both families
+    let round_robin = RoundRobinSourceIp::default();
+    for seq in 0..ipv4.len() * 2 {
+        assert_eq!(
+            select_source_ips(&ipv4, &ipv6, &round_robin),
+            (
+                Some(IpAddr::V4(ipv4[seq % ipv4.len()])),
+                Some(IpAddr::V6(ipv6[0]))
+            )
+        );
+    }
+    assert_eq!(round_robin.select(2, 4), (0, 2));
+
+    // Random selection stays within the pool
+    let random = RandomSourceIp::default();
+    for _ in 0..100 {
+        let (pos_ipv4, pos_ipv6) = random.select(ipv4.len(), 1);
+        assert!(pos_ipv4 < ipv4.len());
+        assert_eq!(pos_ipv6, 0);
+    }
+}
+
 #[tokio::test]
 async fn lookup_ip() {
     let ipv6 = vec![
@@ -87,6 +155,64 @@ async fn lookup_ip() {
         .remote_ips
         .contains(&"172.168.0.100".parse().unwrap()));
 
+    // The configured selector chooses the source addresses
+    core.queue.source_ip = Box::new(FixedSourceIp(2, 3));
+    let resolve_result = core
+        .resolve_host(
+            &NextHop::MX("mx.foobar.org"),
+            &RecipientDomain::new("envelope"),
+            2,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        (resolve_result.source_ipv4, resolve_result.source_ipv6),
+        (Some(IpAddr::V4(ipv4[2])), Some(IpAddr::V6(ipv6[3])))
+    );
+
+    // Source addresses are selected reproducibly with a seeded source
+    let mut selected = Vec::new();
+    for _ in 0..2 {
+        core.queue.source_ip = Box::new(RandomSourceIp(SelectionRng::seeded(1)));
+        let mut source_ips = Vec::new();
+        for _ in 0..8 {
+            source_ips.push(
+                core.resolve_host(
+                    &NextHop::MX("mx.foobar.org"),
+                    &RecipientDomain::new("envelope"),
+                    2,
+                )
+                .await
+                .unwrap()
+                .source_ipv4,
+            );
+        }
+        selected.push(source_ips);
+    }
+    assert_eq!(selected[0], selected[1]);
+
+    // Round-robin selection cycles through the whole pool
+    core.queue.source_ip = Box::<RoundRobinSourceIp>::default();
+    let mut source_ips = Vec::new();
+    for _ in 0..ipv4.len() * 2 {
+        source_ips.push(
+            core.resolve_host(
+                &NextHop::MX("mx.foobar.org"),
+                &RecipientDomain::new("envelope"),
+                2,
+            )
+            .await
+            .unwrap()
+            .source_ipv4
+            .unwrap(),
+        );
+    }
+    assert_eq!(source_ips[..ipv4.len()], source_ips[ipv4.len()..]);
+    for ip in &ipv4 {
+        assert!(source_ips[..ipv4.len()].contains(&std::net::IpAddr::V4(*ip)));
+    }
+    core.queue.source_ip = Box::<RandomSourceIp>::default();
+
     // Ipv6 strategy
     core.queue.config.ip_strategy = IfBlock::new(IpLookupStrategy::Ipv6thenIpv4);
     let resolve_result = core
@@ -104,6 +230,181 @@ async fn lookup_ip() {
     assert!(resolve_result
         .remote_ips
         .contains(&"e:f::a".parse().unwrap()));
+
+    // Disabled address families are never looked up, regardless of the strategy
+    core.queue.config.ip_strategy = IfBlock::new(IpLookupStrategy::Ipv4thenIpv6);
+    core.queue.config.disable_ipv4 = true;
+    let resolve_result = core
+        .resolve_host(
+            &NextHop::MX("mx.foobar.org"),
+            &RecipientDomain::new("envelope"),
+            2,
+        )
+        .await
+        .unwrap();
+    assert_eq!(resolve_result.source_ipv4, None);
+    assert!(resolve_result.remote_ips.iter().all(|ip| ip.is_ipv6()));
+    core.queue.config.disable_ipv4 = false;
+    core.queue.config.disable_ipv6 = true;
+    let resolve_result = core
+        .resolve_host(
+            &NextHop::MX("mx.foobar.org"),
+            &RecipientDomain::new("envelope"),
+            2,
+        )
+        .await
+        .unwrap();
+    assert_eq!(resolve_result.source_ipv6, None);
+    assert!(resolve_result.remote_ips.iter().all(|ip| ip.is_ipv4()));
+    core.queue.config.disable_ipv6 = false;
+
+    // Recently failed addresses are tried last, recently successful ones first
+    core.queue.config.ip_strategy = IfBlock::new(IpLookupStrategy::Ipv4Only);
+    core.record_ip_feedback("172.168.0.100".parse().unwrap(), false);
+    let resolve_result = core
+        .resolve_host(
+            &NextHop::MX("mx.foobar.org"),
+            &RecipientDomain::new("envelope"),
+            2,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        resolve_result.remote_ips,
+        vec![
+            "172.168.0.101".parse::<std::net::IpAddr>().unwrap(),
+            "172.168.0.100".parse().unwrap()
+        ]
+    );
+    core.record_ip_feedback("172.168.0.100".parse().unwrap(), true);
+    let resolve_result = core
+        .resolve_host(
+            &NextHop::MX("mx.foobar.org"),
+            &RecipientDomain::new("envelope"),
+            2,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        resolve_result.remote_ips[0],
+        "172.168.0.100".parse::<std::net::IpAddr>().unwrap()
+    );
+
+    // Named resolver selected by domain pattern
+    core.resolvers.named.push(NamedResolver {
+        id: "internal".to_string(),
+        domains: vec!["*.internal.org".to_string()],
+        resolver: Resolver::new_cloudflare().unwrap(),
+        ecs: None,
+    });
+    core.resolvers.named[0].resolver.ipv4_add(
+        "mx.internal.org",
+        vec!["10.1.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.queue.config.ip_strategy = IfBlock::new(IpLookupStrategy::Ipv4Only);
+    let resolve_result = core
+        .resolve_host(
+            &NextHop::MX("mx.internal.org"),
+            &RecipientDomain::new("envelope"),
+            2,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        resolve_result.remote_ips,
+        vec!["10.1.0.1".parse::<std::net::IpAddr>().unwrap()]
+    );
+    assert!(std::ptr::eq(
+        core.resolvers.dns_for("internal.org"),
+        &core.resolvers.dns
+    ));
+}
+
+#[tokio::test]
+async fn lookup_dnssec_status() {
+    let mut core = SMTP::test();
+    let envelope = RecipientDomain::new("foobar.org");
+    core.queue.config.ip_strategy = IfBlock::new(IpLookupStrategy::Ipv4thenIpv6);
+    core.resolvers.dns.mx_add(
+        "foobar.org",
+        vec![MX {
+            exchanges: vec!["mx.foobar.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dns.ipv4_add(
+        "mx.foobar.org",
+        vec!["172.168.0.100".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dnssec_add(
+        "foobar.org",
+        RecordType::MX,
+        DnssecStatus::Secure,
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dnssec_add(
+        "mx.foobar.org",
+        RecordType::A,
+        DnssecStatus::Insecure,
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dnssec_add(
+        "mx.foobar.org",
+        RecordType::AAAA,
+        DnssecStatus::Secure,
+        Instant::now() + Duration::from_secs(10),
+    );
+
+    // Statuses are only looked up when DANE is enabled
+    core.queue.config.tls.dane = IfBlock::new(RequireOptional::Disable);
+    assert_eq!(
+        core.mx_lookup("foobar.org", &envelope)
+            .await
+            .unwrap()
+            .dnssec,
+        DnssecStatus::Unknown
+    );
+
+    // The status of the record type used for delivery is returned
+    core.queue.config.tls.dane = IfBlock::new(RequireOptional::Optional);
+    assert_eq!(
+        core.mx_lookup("foobar.org", &envelope)
+            .await
+            .unwrap()
+            .dnssec,
+        DnssecStatus::Secure
+    );
+    assert_eq!(
+        core.resolve_host(&NextHop::MX("mx.foobar.org"), &envelope, 2)
+            .await
+            .unwrap()
+            .dnssec,
+        DnssecStatus::Insecure
+    );
+
+    // Expired statuses are looked up again
+    core.resolvers.dnssec_add(
+        "mx.foobar.org",
+        RecordType::A,
+        DnssecStatus::Secure,
+        Instant::now() - Duration::from_secs(1),
+    );
+    assert_eq!(
+        core.resolve_host(&NextHop::MX("mx.foobar.org"), &envelope, 2)
+            .await
+            .unwrap()
+            .dnssec,
+        DnssecStatus::Unknown
+    );
+    assert_eq!(
+        core.resolvers
+            .dnssec_status("mx.foobar.org", RecordType::AAAA)
+            .await,
+        DnssecStatus::Secure
+    );
 }
 
 #[test]
@@ -131,18 +432,119 @@ fn to_remote_hosts() {
             preference: 10,
         },
     ];
-    let hosts = mx.to_remote_hosts("domain", 7).unwrap();
+    let rng = SelectionRng::default();
+    let hosts = rng
+        .with(|rng| mx.to_remote_hosts("domain", 7, rng))
+        .unwrap();
     assert_eq!(hosts.len(), 7);
     for host in hosts {
         if let NextHop::MX(host) = host {
             assert!((*host.as_bytes().last().unwrap() - b'0') <= 8);
         }
     }
+
+    // Seeded sources shuffle exchanges of equal preference in the same order
+    let shuffle = |seed| {
+        SelectionRng::seeded(seed)
+            .with(|rng| mx.to_remote_hosts("domain", 10, rng))
+            .map(|hosts| format!("{hosts:?}"))
+            .unwrap()
+    };
+    assert_eq!(shuffle(1), shuffle(1));
+    assert!((2..32).any(|seed| shuffle(seed) != shuffle(1)));
+
     let mx = vec![MX {
         exchanges: vec![".".to_string()],
         preference: 0,
     }];
-    assert!(mx.to_remote_hosts("domain", 10).is_none());
+    assert!(rng
+        .with(|rng| mx.to_remote_hosts("domain", 10, rng))
+        .is_none());
+}
+
+#[test]
+fn to_srv_hosts() {
+    let relay = RelayHost {
+        address: "example.org".to_string(),
+        port: 587,
+        protocol: ServerProtocol::Smtp,
+        auth: None,
+        tls_implicit: false,
+        tls_allow_invalid_certs: false,
+        srv: Some("_submission._tcp".to_string()),
+    };
+    let srv = vec![
+        SrvRecord {
+            priority: 20,
+            weight: 0,
+            port: 2525,
+            target: "backup.example.org.".to_string(),
+        },
+        SrvRecord {
+            priority: 10,
+            weight: 60,
+            port: 587,
+            target: "mx1.example.org.".to_string(),
+        },
+        SrvRecord {
+            priority: 10,
+            weight: 40,
+            port: 587,
+            target: "mx2.example.org.".to_string(),
+        },
+        SrvRecord {
+            priority: 10,
+            weight: 0,
+            port: 587,
+            target: "mx3.example.org.".to_string(),
+        },
+    ];
+
+    let rng = SelectionRng::default();
+    for _ in 0..10 {
+        let hosts = rng.with(|rng| srv.to_srv_hosts(&relay, 10, rng)).unwrap();
+        assert_eq!(hosts.len(), 4);
+
+        // Records with a lower priority must always be tried first
+        let mut names = Vec::new();
+        for host in &hosts {
+            if let NextHop::Srv(_, srv) = host {
+                names.push(srv.target.as_str());
+            } else {
+                panic!("Unexpected next hop {host:?}");
+            }
+        }
+        assert_eq!(names.last().unwrap(), &"backup.example.org.");
+        assert!(names[..3].contains(&"mx1.example.org."));
+        assert!(names[..3].contains(&"mx2.example.org."));
+        assert!(names[..3].contains(&"mx3.example.org."));
+    }
+
+    // Limit number of hosts
+    assert_eq!(
+        rng.with(|rng| srv.to_srv_hosts(&relay, 2, rng))
+            .unwrap()
+            .len(),
+        2
+    );
+
+    // Weighted selection is reproducible with a seeded source
+    let select = |seed| {
+        SelectionRng::seeded(seed)
+            .with(|rng| srv.to_srv_hosts(&relay, 10, rng))
+            .map(|hosts| format!("{hosts:?}"))
+            .unwrap()
+    };
+    assert_eq!(select(1), select(1));
+
+    // Service not available
+    let srv = vec![SrvRecord {
+        priority: 0,
+        weight: 0,
+        port: 0,
+        target: ".".to_string(),
+    }];
+    assert!(rng.with(|rng| srv.to_srv_hosts(&relay, 10, rng)).is_none());
 }
 
 #[test]
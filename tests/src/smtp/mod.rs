@@ -28,7 +28,10 @@ use dashmap::DashMap;
 use directory::Directory;
 use mail_auth::{
     common::lru::{DnsCache, LruCache},
-    hickory_resolver::config::{ResolverConfig, ResolverOpts},
+    hickory_resolver::{
+        config::{ResolverConfig, ResolverOpts},
+        AsyncResolver,
+    },
     IpLookupStrategy, Resolver,
 };
 use mail_send::smtp::tls::build_tls_connector;
@@ -41,10 +44,10 @@ use smtp::{
         if_block::ConfigIf, queue::ConfigQueue, scripts::SieveContext, session::ConfigSession,
         throttle::ConfigThrottle, AggregateReport, ArcAuthConfig, Auth, ConfigContext, Connect,
         Data, DkimAuthConfig, DmarcAuthConfig, Dsn, Ehlo, EnvelopeKey, Extensions, IfBlock,
-        IpRevAuthConfig, Mail, MailAuthConfig, Milter, QueueConfig, QueueOutboundSourceIp,
-        QueueOutboundTimeout, QueueOutboundTls, QueueQuotas, QueueThrottle, Rcpt, Report,
-        ReportAnalysis, ReportConfig, SessionConfig, SessionThrottle, SpfAuthConfig, Throttle,
-        VerifyStrategy,
+        IpRevAuthConfig, Mail, MailAuthConfig, Milter, QueueConfig, QueueDnsRetry,
+        QueueOutboundSourceIp, QueueOutboundTimeout, QueueOutboundTls, QueueQuotas, QueueThrottle,
+        Rcpt, Report, ReportAnalysis, ReportConfig, SessionConfig, SessionThrottle,
+        SourceIpSelection, SpfAuthConfig, Throttle, VerifyStrategy,
     },
     core::{
         throttle::ThrottleKeyHasherBuilder, QueueCore, ReportCore, Resolvers, SessionCore,
@@ -169,19 +172,25 @@ impl TestConfig for SMTP {
             queue: QueueCore::test(),
             resolvers: Resolvers {
                 dns: Resolver::new_system_conf().unwrap(),
+                named: vec![],
                 dnssec: DnssecResolver::with_capacity(
                     ResolverConfig::cloudflare(),
                     ResolverOpts::default(),
                 )
                 .unwrap(),
+                srv: AsyncResolver::tokio(ResolverConfig::cloudflare(), ResolverOpts::default()),
+                ecs: None,
                 cache: smtp::core::DnsCache {
                     tlsa: LruCache::with_capacity(100),
                     mta_sts: LruCache::with_capacity(100),
+                    srv: LruCache::with_capacity(100),
+                    dnssec: LruCache::with_capacity(100),
                 },
             },
             mail_auth: MailAuthConfig::test(),
             report: ReportCore::test(),
             sieve: SieveCore::test(),
+            rng: smtp::core::rng::SelectionRng::default(),
             delivery_tx: mpsc::channel(1).0,
         }
     }
@@ -287,6 +296,8 @@ impl TestConfig for QueueCore {
             ),
             tx: mpsc::channel(1024).0,
             id_seq: 0.into(),
+            source_ip: SourceIpSelection::Random.selector(),
+            ip_feedback: LruCache::with_capacity(100),
             connectors: TlsConnectors {
                 pki_verify: build_tls_connector(false),
                 dummy_verify: build_tls_connector(true),
@@ -301,6 +312,10 @@ impl TestConfig for QueueConfig {
             path: Default::default(),
             hash: IfBlock::new(10),
             retry: IfBlock::new(vec![Duration::from_secs(10)]),
+            retry_dns: QueueDnsRetry {
+                server_failure: IfBlock::new(vec![Duration::from_secs(5)]),
+                unreachable: IfBlock::new(vec![Duration::from_secs(10)]),
+            },
             notify: IfBlock::new(vec![Duration::from_secs(20)]),
             expire: IfBlock::new(Duration::from_secs(10)),
             hostname: IfBlock::new("mx.example.org".to_string()),
@@ -310,8 +325,12 @@ impl TestConfig for QueueConfig {
             source_ip: QueueOutboundSourceIp {
                 ipv4: IfBlock::new(vec![]),
                 ipv6: IfBlock::new(vec![]),
+                selection: SourceIpSelection::Random,
             },
             ip_strategy: IfBlock::new(IpLookupStrategy::Ipv4thenIpv6),
+            disable_ipv4: false,
+            disable_ipv6: false,
+            ip_feedback_ttl: Duration::from_secs(5 * 60),
             tls: QueueOutboundTls {
                 dane: IfBlock::new(smtp::config::RequireOptional::Optional),
                 mta_sts: IfBlock::new(smtp::config::RequireOptional::Optional),
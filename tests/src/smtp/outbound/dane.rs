@@ -231,12 +231,17 @@ async fn dane_test() {
 
     let r = Resolvers {
         dns: Resolver::new_cloudflare().unwrap(),
+        named: vec![],
         dnssec: DnssecResolver {
             resolver: AsyncResolver::tokio(conf, opts),
         },
+        srv: AsyncResolver::tokio(ResolverConfig::cloudflare(), ResolverOpts::default()),
+        ecs: None,
         cache: smtp::core::DnsCache {
             tlsa: LruCache::with_capacity(10),
             mta_sts: LruCache::with_capacity(10),
+            srv: LruCache::with_capacity(10),
+            dnssec: LruCache::with_capacity(10),
         },
     };
 
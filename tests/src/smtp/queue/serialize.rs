@@ -31,8 +31,8 @@ use smtp_proto::{Response, MAIL_REQUIRETLS, MAIL_SMTPUTF8, RCPT_CONNEG, RCPT_NOT
 use smtp::{
     core::SMTP,
     queue::{
-        Domain, Error, ErrorDetails, HostResponse, Message, Recipient, Schedule, Status,
-        RCPT_STATUS_CHANGED,
+        DnsErrorDetails, DnsErrorKind, Domain, Error, ErrorDetails, HostResponse, Message,
+        Recipient, Schedule, Status, RCPT_STATUS_CHANGED,
     },
 };
 
@@ -173,6 +173,31 @@ async fn queue_serialize() {
         &Message::from_path(message.path.clone()).await.unwrap(),
     );
 
+    // DNS lookup errors
+    message.domains[0].status = Status::TemporaryFailure(Error::DnsLookupError(DnsErrorDetails {
+        kind: DnsErrorKind::ServerFailure,
+        rcode: Some(2),
+        details: ErrorDetails {
+            entity: "domain.org".to_string(),
+            details: "Server failure (Server Failure)".to_string(),
+        },
+    }));
+    message.domains[0].changed = true;
+    message.domains[1].status = Status::TemporaryFailure(Error::DnsLookupError(DnsErrorDetails {
+        kind: DnsErrorKind::Unreachable,
+        rcode: None,
+        details: ErrorDetails {
+            entity: "mx.domain.org".to_string(),
+            details: "no connections available".to_string(),
+        },
+    }));
+    message.domains[1].changed = true;
+    message.save_changes().await;
+    assert_msg_eq(
+        &message,
+        &Message::from_path(message.path.clone()).await.unwrap(),
+    );
+
     // Remove
     message.remove().await;
     assert!(!message.path.exists());
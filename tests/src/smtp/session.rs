@@ -372,6 +372,7 @@ impl TestServerInstance for ServerInstance {
             is_tls_implicit: false,
             limiter: ConcurrencyLimiter::new(100),
             shutdown_rx,
+            acme_providers: vec![],
         }
     }
 }
@@ -24,7 +24,7 @@
 use ahash::AHashMap;
 use store::{
     config::ConfigStore,
-    write::{blob::BlobQuota, now, BatchBuilder, BlobOp},
+    write::{assert::AssertValue, blob::BlobQuota, now, BatchBuilder, BlobOp},
     BlobClass, BlobHash, BlobStore, Serialize,
 };
 use utils::config::Config;
@@ -417,6 +417,164 @@ pub async fn blob_tests() {
                     ^ ct
             );
         }
+
+        // Link the same blob from a second account, the reference count should increase
+        let hash = BlobHash::from(b"456".as_slice());
+        assert_eq!(store.blob_ref_count(&hash).await.unwrap(), 1);
+        store
+            .write(
+                BatchBuilder::new()
+                    .with_account_id(2)
+                    .with_collection(0)
+                    .update_document(0)
+                    .set(BlobOp::Link { hash: hash.clone() }, vec![])
+                    .build_batch(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.blob_ref_count(&hash).await.unwrap(), 2);
+
+        // Setting an existing link or clearing a missing one must not change the count
+        for batch in [
+            BatchBuilder::new()
+                .with_account_id(2)
+                .with_collection(0)
+                .update_document(0)
+                .set(BlobOp::Link { hash: hash.clone() }, vec![])
+                .build_batch(),
+            BatchBuilder::new()
+                .with_account_id(2)
+                .with_collection(0)
+                .update_document(1)
+                .clear(BlobOp::Link { hash: hash.clone() })
+                .build_batch(),
+        ] {
+            assert!(matches!(
+                store.write(batch).await,
+                Err(store::Error::AssertValueFailed)
+            ));
+        }
+        assert_eq!(store.blob_ref_count(&hash).await.unwrap(), 2);
+
+        // Replacing a link with itself is a valid transition
+        store
+            .write(
+                BatchBuilder::new()
+                    .with_account_id(2)
+                    .with_collection(0)
+                    .update_document(0)
+                    .clear(BlobOp::Link { hash: hash.clone() })
+                    .set(BlobOp::Link { hash: hash.clone() }, vec![])
+                    .build_batch(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.blob_ref_count(&hash).await.unwrap(), 2);
+
+        // Reference counts can be asserted atomically with a write
+        for (ref_count, is_ok) in [(1, false), (2, true)] {
+            assert_eq!(
+                store
+                    .write(
+                        BatchBuilder::new()
+                            .assert_value(
+                                BlobOp::RefCount { hash: hash.clone() },
+                                AssertValue::Counter(ref_count),
+                            )
+                            .build_batch(),
+                    )
+                    .await
+                    .is_ok(),
+                is_ok
+            );
+        }
+
+        // Unlink it from accountId 0, the blob should survive the purge
+        store
+            .write(
+                BatchBuilder::new()
+                    .with_account_id(0)
+                    .with_collection(0)
+                    .update_document(1)
+                    .clear(BlobOp::Link { hash: hash.clone() })
+                    .build_batch(),
+            )
+            .await
+            .unwrap();
+        store.purge_blobs(blob_store.clone()).await.unwrap();
+        assert_eq!(store.blob_ref_count(&hash).await.unwrap(), 1);
+        assert!(store.blob_exists(&hash).await.unwrap());
+        assert!(blob_store
+            .get_blob(hash.as_ref(), 0..u32::MAX)
+            .await
+            .unwrap()
+            .is_some());
+
+        // Copying a blob adds a new link to the same hash without duplicating it,
+        // copying it again to the same destination is a no-op
+        let copy_class = BlobClass::Linked {
+            account_id: 3,
+            collection: 0,
+            document_id: 0,
+        };
+        blob_store
+            .copy(&store, &hash, copy_class.clone())
+            .await
+            .unwrap();
+        blob_store
+            .copy(&store, &hash, copy_class.clone())
+            .await
+            .unwrap();
+        assert!(store.blob_has_access(&hash, copy_class).await.unwrap());
+        assert_eq!(store.blob_ref_count(&hash).await.unwrap(), 2);
+        store.blob_hash_unlink_account(3).await.unwrap();
+        assert_eq!(store.blob_ref_count(&hash).await.unwrap(), 1);
+        assert!(matches!(
+            blob_store
+                .copy(
+                    &store,
+                    &BlobHash::from(b"missing".as_slice()),
+                    BlobClass::Linked {
+                        account_id: 3,
+                        collection: 0,
+                        document_id: 0,
+                    }
+                )
+                .await,
+            Err(store::Error::NotFound(_))
+        ));
+
+        // Remove the last link, the blob should now be deleted
+        store.blob_hash_unlink_account(2).await.unwrap();
+        assert_eq!(store.blob_ref_count(&hash).await.unwrap(), 0);
+        store.purge_blobs(blob_store.clone()).await.unwrap();
+        assert!(!store.blob_exists(&hash).await.unwrap());
+        assert!(blob_store
+            .get_blob(hash.as_ref(), 0..u32::MAX)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Blobs that were written but never committed are removed by the garbage collector,
+        // while reserved blobs are kept.
+        let hash = BlobHash::from(b"orphan".as_slice());
+        blob_store.put_blob(hash.as_ref(), b"orphan").await.unwrap();
+        let stats = blob_store.gc(&store).await.unwrap();
+        assert_eq!(stats.deleted, 1, "{stats:?}");
+        assert!(blob_store
+            .get_blob(hash.as_ref(), 0..u32::MAX)
+            .await
+            .unwrap()
+            .is_none());
+        for blob in [b"efg", b"hij"] {
+            let hash = BlobHash::from(blob.as_slice());
+            assert!(store.blob_exists(&hash).await.unwrap());
+            assert!(blob_store
+                .get_blob(hash.as_ref(), 0..u32::MAX)
+                .await
+                .unwrap()
+                .is_some());
+        }
     }
     temp_dir.delete();
 }
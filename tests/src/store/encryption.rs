@@ -0,0 +1,92 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use store::{
+    config::ConfigStore,
+    write::{BatchBuilder, DirectoryClass, ValueClass},
+    Store, ValueKey,
+};
+use utils::config::Config;
+
+use super::TempDir;
+
+const CONFIG: &str = r#"
+[store."encrypted"]
+type = "sqlite"
+path = "{TMP}/sqlite.db"
+
+[store."encrypted".encryption]
+collections = [1]
+directory = true
+key.1 = "store-encryption-test"
+
+[store."plain"]
+type = "sqlite"
+path = "{TMP}/sqlite.db"
+"#;
+
+#[tokio::test]
+pub async fn store_encryption() {
+    let temp_dir = TempDir::new("store_encryption_tests", true);
+    let config = Config::new(&CONFIG.replace("{TMP}", &temp_dir.path.to_string_lossy())).unwrap();
+    let stores = config.parse_stores().await.unwrap();
+    let encrypted = stores.stores.get("encrypted").unwrap().clone();
+    let plain = stores.stores.get("plain").unwrap().clone();
+
+    let principal_key = ValueKey::from(DirectoryClass::Principal(1));
+    let property_key = |collection: u8| ValueKey {
+        account_id: 1,
+        collection,
+        document_id: 1,
+        class: ValueClass::Property(0),
+    };
+    let mut batch = BatchBuilder::new();
+    batch
+        .set(DirectoryClass::Principal(1), b"principal secret".to_vec())
+        .with_account_id(1)
+        .with_collection(1)
+        .create_document(1)
+        .set(ValueClass::Property(0), b"mailbox secret".to_vec())
+        .with_collection(2)
+        .create_document(1)
+        .set(ValueClass::Property(0), b"public value".to_vec());
+    encrypted.write(batch.build()).await.unwrap();
+
+    // Principals and encrypted collections are sealed in the backend
+    for (key, value, is_sealed) in [
+        (principal_key, "principal secret", true),
+        (property_key(1), "mailbox secret", true),
+        (property_key(2), "public value", false),
+    ] {
+        assert_eq!(get(&encrypted, key.clone()).await, value);
+        let raw = get(&plain, key).await;
+        assert_eq!(raw != value, is_sealed, "{value}");
+        assert!(!is_sealed || !raw.contains(value), "{value}");
+    }
+
+    temp_dir.delete();
+}
+
+async fn get(store: &Store, key: ValueKey<ValueClass>) -> String {
+    store.get_value::<String>(key).await.unwrap().unwrap()
+}
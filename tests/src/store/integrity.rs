@@ -0,0 +1,219 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use store::{
+    config::ConfigStore, write::integrity::ValueIntegrity, BlobHash, LookupKey, LookupValue,
+};
+use utils::config::Config;
+
+const CONFIG: &str = r#"
+[store."s3-signed"]
+type = "s3"
+access-key = "minioadmin"
+secret-key = "minioadmin"
+region = "eu-central-1"
+endpoint = "http://localhost:9000"
+bucket = "tmp"
+integrity.key = "secret"
+
+[store."s3-accept"]
+type = "s3"
+access-key = "minioadmin"
+secret-key = "minioadmin"
+region = "eu-central-1"
+endpoint = "http://localhost:9000"
+bucket = "tmp"
+integrity.key = "secret"
+integrity.unsigned = "accept"
+
+[store."s3-raw"]
+type = "s3"
+access-key = "minioadmin"
+secret-key = "minioadmin"
+region = "eu-central-1"
+endpoint = "http://localhost:9000"
+bucket = "tmp"
+
+[store."redis-signed"]
+type = "redis"
+url = "redis://127.0.0.1"
+integrity.key = "secret"
+
+[store."redis-accept"]
+type = "redis"
+url = "redis://127.0.0.1"
+integrity.key = "secret"
+integrity.unsigned = "accept"
+
+[store."redis-raw"]
+type = "redis"
+url = "redis://127.0.0.1"
+"#;
+
+#[tokio::test]
+pub async fn integrity_tests() {
+    let config = Config::new(CONFIG).unwrap();
+    let stores = config.parse_stores().await.unwrap();
+    let signer = ValueIntegrity::parse(&config, "store.s3-signed")
+        .unwrap()
+        .unwrap();
+
+    println!("Testing S3 blob integrity...");
+    let signed = stores.blob_stores.get("s3-signed").unwrap();
+    let accept = stores.blob_stores.get("s3-accept").unwrap();
+    let raw = stores.blob_stores.get("s3-raw").unwrap();
+    const DATA: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit. Fusce erat nisl, dignissim a porttitor id, varius nec arcu. Sed mauris.";
+    let key = BlobHash::from(DATA);
+    let other_key = BlobHash::from(b"other".as_slice());
+
+    // Blobs are stored with their MAC, which is stripped on read
+    signed.put_blob(key.as_slice(), DATA).await.unwrap();
+    let signed_blob = signer.sign(key.as_slice(), DATA);
+    assert_eq!(
+        raw.get_blob(key.as_slice(), 0..u32::MAX).await.unwrap(),
+        Some(signed_blob.clone())
+    );
+    for range in [0..u32::MAX, 11..57, DATA.len() as u32 - 10..u32::MAX] {
+        assert_eq!(
+            signed
+                .get_blob(key.as_slice(), range.clone())
+                .await
+                .unwrap()
+                .unwrap(),
+            &DATA[range.start as usize..std::cmp::min(range.end as usize, DATA.len())]
+        );
+    }
+
+    // Tampered blobs are rejected, even when the requested range was not modified
+    let mut tampered = signed_blob.clone();
+    tampered[DATA.len() - 1] ^= 1;
+    raw.put_blob(key.as_slice(), &tampered).await.unwrap();
+    assert!(signed.get_blob(key.as_slice(), 0..u32::MAX).await.is_err());
+    assert!(signed.get_blob(key.as_slice(), 0..10).await.is_err());
+    assert!(accept.get_blob(key.as_slice(), 0..u32::MAX).await.is_err());
+
+    // MACs are bound to the key the blob is stored under
+    raw.put_blob(other_key.as_slice(), &signed_blob)
+        .await
+        .unwrap();
+    assert!(signed
+        .get_blob(other_key.as_slice(), 0..u32::MAX)
+        .await
+        .is_err());
+
+    // Unsigned blobs are handled according to the configured policy
+    raw.put_blob(key.as_slice(), DATA).await.unwrap();
+    assert!(signed.get_blob(key.as_slice(), 0..u32::MAX).await.is_err());
+    assert_eq!(
+        accept
+            .get_blob(key.as_slice(), 11..57)
+            .await
+            .unwrap()
+            .unwrap(),
+        &DATA[11..57]
+    );
+
+    for key in [key, other_key] {
+        raw.delete_blob(key.as_slice()).await.unwrap();
+    }
+
+    println!("Testing Redis value integrity...");
+    let signed = stores.lookup_stores.get("redis-signed").unwrap();
+    let accept = stores.lookup_stores.get("redis-accept").unwrap();
+    let raw = stores.lookup_stores.get("redis-raw").unwrap();
+    let key = b"integrity".to_vec();
+    let value = b"hello world".to_vec();
+
+    // Values are stored with their MAC, which is stripped on read
+    signed
+        .key_set(
+            key.clone(),
+            LookupValue::Value {
+                value: value.clone(),
+                expires: 0,
+            },
+        )
+        .await
+        .unwrap();
+    assert!(matches!(signed
+        .key_get::<String>(LookupKey::Key(key.clone()))
+        .await
+        .unwrap(), LookupValue::Value { value,.. } if value == "hello world"));
+    assert!(matches!(raw
+        .key_get::<String>(LookupKey::Key(key.clone()))
+        .await
+        .unwrap(), LookupValue::Value { value,.. } if value.starts_with("hello world") && value != "hello world"));
+
+    // Tampered values, values signed for another key and unsigned values
+    let mut tampered = signer.sign(&key, &value);
+    tampered[0] = b'j';
+    for (stored, is_accepted) in [
+        (tampered, false),
+        (signer.sign(b"other", &value), false),
+        (value.clone(), true),
+    ] {
+        raw.key_set(
+            key.clone(),
+            LookupValue::Value {
+                value: stored,
+                expires: 0,
+            },
+        )
+        .await
+        .unwrap();
+        assert!(signed
+            .key_get::<String>(LookupKey::Key(key.clone()))
+            .await
+            .is_err());
+        assert_eq!(
+            accept
+                .key_get::<String>(LookupKey::Key(key.clone()))
+                .await
+                .is_ok(),
+            is_accepted
+        );
+    }
+
+    // Counters are updated in place and are not signed
+    let key = b"integrity-counter".to_vec();
+    raw.key_set(
+        key.clone(),
+        LookupValue::Value {
+            value: b"0".to_vec(),
+            expires: 0,
+        },
+    )
+    .await
+    .unwrap();
+    signed
+        .key_set(key.clone(), LookupValue::Counter { num: 2 })
+        .await
+        .unwrap();
+    assert_eq!(
+        signed
+            .key_get::<String>(LookupKey::Counter(key))
+            .await
+            .unwrap(),
+        LookupValue::Counter { num: 2 }
+    );
+}
@@ -0,0 +1,141 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use store::{dispatch::invalidation::InvalidationEvent, LookupStore, Row, Value};
+use tokio::sync::broadcast;
+use utils::config::Config;
+
+const CHANNEL: &str = "stalwart_test_invalidation";
+
+// Two stores sharing a database and channel act as two cluster nodes
+const CONFIG: &str = r#"
+[store."node1"]
+type = "postgresql"
+host = "localhost"
+port = 5432
+database = "stalwart"
+user = "postgres"
+password = "mysecretpassword"
+invalidation.enable = true
+invalidation.channel = "stalwart_test_invalidation"
+
+[store."node2"]
+type = "postgresql"
+host = "localhost"
+port = 5432
+database = "stalwart"
+user = "postgres"
+password = "mysecretpassword"
+invalidation.enable = true
+invalidation.channel = "stalwart_test_invalidation"
+"#;
+
+pub async fn test() {
+    println!("Running PostgreSQL invalidation channel tests...");
+    let config = Config::new(CONFIG).unwrap();
+    let stores = config.parse_stores().await.unwrap();
+    let node1 = stores.stores.get("node1").unwrap().clone();
+    let node2 = stores.stores.get("node2").unwrap().clone();
+    let sql = LookupStore::from(node1.clone());
+    let mut events1 = node1.subscribe_invalidations().unwrap();
+    let mut events2 = node2.subscribe_invalidations().unwrap();
+    wait_for_listeners(&sql, 2).await;
+
+    // Events published on one node reach every node, including the publisher
+    for event in [
+        InvalidationEvent::Principal {
+            account_id: Some(1),
+        },
+        InvalidationEvent::Principal {
+            account_id: Some(u32::MAX),
+        },
+        InvalidationEvent::Principal { account_id: None },
+        InvalidationEvent::FlushAll,
+    ] {
+        node1.publish_invalidation(event).await.unwrap();
+        assert_eq!(next_event(&mut events1).await, event);
+        assert_eq!(next_event(&mut events2).await, event);
+    }
+
+    // Malformed payloads are skipped without closing the listener
+    for payload in ["", "principal:", "principal:abc", "principal:-1", "flush:1"] {
+        sql.query::<usize>(&format!("NOTIFY {CHANNEL}, '{payload}'"), vec![])
+            .await
+            .unwrap();
+    }
+    let event = InvalidationEvent::Principal {
+        account_id: Some(2),
+    };
+    node2.publish_invalidation(event).await.unwrap();
+    assert_eq!(next_event(&mut events1).await, event);
+    assert_eq!(next_event(&mut events2).await, event);
+
+    // Dropping the listener connections makes both nodes flush their caches
+    // once they reconnect, as notifications sent meanwhile are lost
+    sql.query::<usize>(
+        &format!(
+            "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE query = 'LISTEN \"{CHANNEL}\"'"
+        ),
+        vec![],
+    )
+    .await
+    .unwrap();
+    assert_eq!(next_event(&mut events1).await, InvalidationEvent::FlushAll);
+    assert_eq!(next_event(&mut events2).await, InvalidationEvent::FlushAll);
+    wait_for_listeners(&sql, 2).await;
+
+    // Reconnected listeners keep receiving events
+    let event = InvalidationEvent::Principal {
+        account_id: Some(3),
+    };
+    node1.publish_invalidation(event).await.unwrap();
+    assert_eq!(next_event(&mut events1).await, event);
+    assert_eq!(next_event(&mut events2).await, event);
+}
+
+async fn next_event(events: &mut broadcast::Receiver<InvalidationEvent>) -> InvalidationEvent {
+    tokio::time::timeout(Duration::from_secs(30), events.recv())
+        .await
+        .expect("Timed out waiting for invalidation event")
+        .unwrap()
+}
+
+async fn wait_for_listeners(sql: &LookupStore, expected: i64) {
+    for _ in 0..300 {
+        let listeners = sql
+            .query::<Option<Row>>(
+                "SELECT COUNT(*) FROM pg_stat_activity WHERE query = $1",
+                vec![Value::from(format!("LISTEN \"{CHANNEL}\""))],
+            )
+            .await
+            .unwrap()
+            .and_then(|row| row.values.into_iter().next());
+        if listeners == Some(Value::Integer(expected)) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Invalidation listeners did not connect");
+}
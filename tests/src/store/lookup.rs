@@ -122,5 +122,196 @@ pub async fn lookup_tests() {
                 .await
                 .unwrap()
         );
+
+        // Test namespaced keys
+        for (ns, value) in [("greylist", "1"), ("reputation", "2")] {
+            store
+                .key_set(
+                    LookupKey::namespaced(ns, b"xyz").into_bytes(),
+                    LookupValue::Value {
+                        value: value.as_bytes().to_vec(),
+                        expires: 0,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+        for (ns, value) in [("greylist", "1"), ("reputation", "2")] {
+            assert!(matches!(store
+                .key_get::<String>(LookupKey::namespaced(ns, b"xyz"))
+                .await
+                .unwrap(), LookupValue::Value { value: value_,.. } if value_ == value));
+        }
+        assert_eq!(
+            LookupValue::None,
+            store
+                .key_get::<String>(LookupKey::namespaced("rate", b"xyz"))
+                .await
+                .unwrap()
+        );
+        store
+            .key_set(
+                LookupKey::namespaced_counter("rate", b"abc").into_bytes(),
+                LookupValue::Counter { num: 5 },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            LookupValue::Counter { num: 5 },
+            store
+                .key_get::<String>(LookupKey::namespaced_counter("rate", b"abc"))
+                .await
+                .unwrap()
+        );
+
+        // Test batched counter reads
+        assert_eq!(
+            store
+                .get_counters(vec![
+                    LookupKey::Counter(key.clone()),
+                    LookupKey::Key(b"xyz".to_vec()),
+                    LookupKey::namespaced_counter("rate", b"abc"),
+                    LookupKey::Counter(b"unknown".to_vec()),
+                ])
+                .await
+                .unwrap(),
+            vec![Some(3), None, Some(5), Some(0)]
+        );
+
+        // Test batched membership
+        assert_eq!(
+            store
+                .filter_members(vec![
+                    LookupKey::namespaced("greylist", b"xyz"),
+                    LookupKey::Key(b"unknown".to_vec()),
+                    LookupKey::Counter(key.clone()),
+                    LookupKey::Counter(b"unknown".to_vec()),
+                    LookupKey::namespaced("reputation", b"xyz"),
+                ])
+                .await
+                .unwrap()
+                .iter()
+                .map(|key| key.as_bytes().to_vec())
+                .collect::<Vec<_>>(),
+            vec![
+                LookupKey::namespaced("greylist", b"xyz").into_bytes(),
+                key.clone(),
+                LookupKey::namespaced("reputation", b"xyz").into_bytes(),
+            ]
+        );
+
+        // Test counter expiry
+        let key = LookupKey::namespaced_counter("ttl", b"abc");
+        assert_eq!(
+            store
+                .counter_incr(key.as_bytes().to_vec(), 2, 1)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            store
+                .counter_incr(key.as_bytes().to_vec(), 3, 1)
+                .await
+                .unwrap(),
+            5
+        );
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        assert_eq!(
+            LookupValue::Counter { num: 0 },
+            store.key_get::<String>(key.clone()).await.unwrap()
+        );
+        assert_eq!(
+            store.get_counters(vec![key.clone()]).await.unwrap(),
+            vec![Some(0)]
+        );
+        assert_eq!(
+            store
+                .counter_incr(key.as_bytes().to_vec(), 1, 1)
+                .await
+                .unwrap(),
+            1
+        );
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        store.purge_expired().await.unwrap();
+        assert_eq!(
+            LookupValue::Counter { num: 0 },
+            store.key_get::<String>(key).await.unwrap()
+        );
+
+        // Test deletion, a deleted counter starts a new window
+        let key = LookupKey::namespaced_counter("del", b"abc");
+        store
+            .counter_incr(key.as_bytes().to_vec(), 4, 60)
+            .await
+            .unwrap();
+        store.key_delete(key.clone()).await.unwrap();
+        assert_eq!(
+            LookupValue::Counter { num: 0 },
+            store.key_get::<String>(key.clone()).await.unwrap()
+        );
+        assert_eq!(
+            store
+                .counter_incr(key.as_bytes().to_vec(), 1, 60)
+                .await
+                .unwrap(),
+            1
+        );
+        let key = LookupKey::namespaced("del", b"xyz");
+        store
+            .key_set(
+                key.as_bytes().to_vec(),
+                LookupValue::Value {
+                    value: b"1".to_vec(),
+                    expires: 60,
+                },
+            )
+            .await
+            .unwrap();
+        store.key_delete(key.clone()).await.unwrap();
+        assert_eq!(
+            LookupValue::None,
+            store.key_get::<String>(key).await.unwrap()
+        );
     }
+
+    // Namespaced keys are readable when converted to strings
+    assert_eq!(
+        String::from(LookupKey::namespaced("greylist", b"1.2.3.4")),
+        "greylist:1.2.3.4"
+    );
+    assert_eq!(
+        String::from(LookupKey::namespaced("bayes", &[0xff, 0x00])),
+        "bayes:0xff00"
+    );
+    assert_eq!(String::from(LookupKey::Key(b"plain".to_vec())), "plain");
+    assert_eq!(
+        LookupKey::namespaced("a", b"bc").namespace(),
+        Some(("a", &b"bc"[..]))
+    );
+    assert_ne!(
+        LookupKey::namespaced("a", b"bc").into_bytes(),
+        LookupKey::namespaced("ab", b"c").into_bytes()
+    );
+
+    // Value accessors
+    let value = LookupValue::Value {
+        value: "abc".to_string(),
+        expires: 100,
+    };
+    assert_eq!(value.as_value().map(String::as_str), Some("abc"));
+    assert_eq!(value.counter(), None);
+    assert!(!value.is_expired(99));
+    assert!(value.is_expired(100));
+    assert_eq!(value.into_value().as_deref(), Some("abc"));
+    let value = LookupValue::<String>::Value {
+        value: "abc".to_string(),
+        expires: 0,
+    };
+    assert!(!value.is_expired(u64::MAX));
+    let value = LookupValue::<String>::Counter { num: 3 };
+    assert_eq!(value.counter(), Some(3));
+    assert_eq!(value.as_value(), None);
+    assert!(!value.is_expired(u64::MAX));
+    assert_eq!(LookupValue::<String>::None.into_value(), None);
 }
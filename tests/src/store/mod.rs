@@ -23,6 +23,9 @@
 
 pub mod assign_id;
 pub mod blob;
+pub mod encryption;
+pub mod integrity;
+pub mod invalidation;
 pub mod lookup;
 pub mod ops;
 pub mod query;
@@ -98,12 +101,23 @@ pub async fn store_tests() {
         .clone();
 
     println!("Testing store {}...", store_id);
+    store.ping().await.expect("Store is unreachable");
+    let report = stores.health().await;
+    let check = report
+        .checks
+        .iter()
+        .find(|check| check.id == format!("store.{store_id}"))
+        .expect("Missing health check");
+    assert!(check.result.is_ok(), "{:?}", check.result);
     if insert {
         store.destroy().await;
     }
     ops::test(store.clone()).await;
     query::test(store.clone(), FtsStore::Store(store.clone()), insert).await;
     assign_id::test(store).await;
+    if store_id == "postgresql" {
+        invalidation::test().await;
+    }
 
     if insert {
         temp_dir.delete();
@@ -21,15 +21,37 @@
  * for more details.
 */
 
+use std::{collections::HashSet, time::Duration};
+
 use store::{
-    write::{BatchBuilder, ValueClass},
-    Store, ValueKey,
+    roaring::RoaringBitmap,
+    write::{
+        bitmap::BITS_PER_BLOCK_L, key::DeserializeBigEndian, BatchBuilder, BitmapClass, Operation,
+        ValueClass,
+    },
+    BitmapKey, IterateParams, LogKey, ParsedKey, Store, ValueKey, SUBSPACE_LOGS, SUBSPACE_VALUES,
+    U64_LEN,
 };
 
 // FDB max value
 const MAX_VALUE_SIZE: usize = 100000;
 
 pub async fn test(db: Store) {
+    test_iterate_prefix(db.clone()).await;
+    test_iterate_limits(db.clone()).await;
+    test_iterate_byte_order(db.clone()).await;
+    test_bitmap_blocks(db.clone()).await;
+    test_read_only(db.clone()).await;
+    test_read_snapshot(db.clone()).await;
+    test_oversized_batch(db.clone()).await;
+    test_put_if_absent(db.clone()).await;
+    test_iterate_typed(db.clone()).await;
+    test_collection_values(db.clone()).await;
+    test_scan_accounts(db.clone()).await;
+    test_fencing(db.clone()).await;
+    test_flush(db.clone()).await;
+    test_exists(db.clone()).await;
+
     for (test_num, value) in [
         vec![b'A'; 0],
         vec![b'A'; 1],
@@ -142,4 +164,780 @@ pub async fn test(db: Store) {
         // Make sure everything is deleted
         db.assert_is_empty(db.clone().into()).await;
     }
+
+    // Overwriting a large value with a smaller one must not leave trailing chunks behind
+    let key = ValueKey {
+        account_id: 0,
+        collection: 0,
+        document_id: 0,
+        class: ValueClass::Property(1),
+    };
+    for value in [
+        vec![b'I'; MAX_VALUE_SIZE * 3],
+        vec![b'J'; MAX_VALUE_SIZE * 2 + 1],
+        vec![b'K'; MAX_VALUE_SIZE],
+    ] {
+        db.write(
+            BatchBuilder::new()
+                .with_account_id(0)
+                .with_collection(0)
+                .update_document(0)
+                .set(ValueClass::Property(1), value.as_slice())
+                .build_batch(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            db.get_value::<String>(key.clone()).await.unwrap(),
+            Some(String::from_utf8(value).unwrap())
+        );
+    }
+    db.write(
+        BatchBuilder::new()
+            .with_account_id(0)
+            .with_collection(0)
+            .update_document(0)
+            .clear(ValueClass::Property(1))
+            .build_batch(),
+    )
+    .await
+    .unwrap();
+    db.assert_is_empty(db.clone().into()).await;
+}
+
+async fn test_iterate_prefix(db: Store) {
+    let keys: [&[u8]; 6] = [b"pfw", b"pfx", b"pfx\x00", b"pfx\x01a", b"pfx\xff", b"pfy"];
+    let mut batch = BatchBuilder::new();
+    for key in keys {
+        batch.set(ValueClass::Key(key.to_vec()), key.to_vec());
+    }
+    db.write(batch.build()).await.unwrap();
+
+    // Only keys starting with the prefix are returned, in both directions
+    let expected = keys[1..5]
+        .iter()
+        .map(|key| key.to_vec())
+        .collect::<Vec<_>>();
+    for ascending in [true, false] {
+        for first in [false, true] {
+            let mut results = Vec::new();
+            db.iterate(
+                IterateParams::prefix(ValueKey::from(ValueClass::Key(b"pfx".to_vec())))
+                    .ascending(ascending)
+                    .values(true)
+                    .first(first)
+                    .build(),
+                |key, value| {
+                    assert_eq!(&key[1..], value);
+                    results.push(value.to_vec());
+                    Ok(true)
+                },
+            )
+            .await
+            .unwrap();
+
+            let expected = match (ascending, first) {
+                (true, false) => expected.clone(),
+                (false, false) => expected.iter().rev().cloned().collect(),
+                (true, true) => vec![expected.first().unwrap().clone()],
+                (false, true) => vec![expected.last().unwrap().clone()],
+            };
+            assert_eq!(results, expected, "ascending: {ascending}, first: {first}");
+        }
+    }
+
+    // Raw subspace iteration returns logical keys without the subspace byte
+    let mut results = Vec::new();
+    db.iterate_subspace(SUBSPACE_VALUES, b"\x04pfx", b"\x04pfx\xff", |key, value| {
+        assert_eq!(&key[1..], value);
+        results.push(value.to_vec());
+        Ok(true)
+    })
+    .await
+    .unwrap();
+    assert_eq!(results, expected);
+
+    let mut batch = BatchBuilder::new();
+    for key in keys {
+        batch.clear(ValueClass::Key(key.to_vec()));
+    }
+    db.write(batch.build()).await.unwrap();
+}
+
+async fn test_iterate_limits(db: Store) {
+    let keys = (0..10u8)
+        .map(|i| [b'l', b'i', b'm', i].to_vec())
+        .collect::<Vec<_>>();
+    let mut batch = BatchBuilder::new();
+    for key in &keys {
+        batch.set(ValueClass::Key(key.clone()), key.clone());
+    }
+    db.write(batch.build()).await.unwrap();
+
+    // Each result is the prefixed key (5 bytes) plus its value (4 bytes)
+    for (max_results, max_bytes, is_ok) in [
+        (Some(10), None, true),
+        (Some(9), None, false),
+        (None, Some(90), true),
+        (None, Some(89), false),
+        (None, None, true),
+    ] {
+        let mut params =
+            IterateParams::prefix(ValueKey::from(ValueClass::Key(b"lim".to_vec()))).values(true);
+        if let Some(max_results) = max_results {
+            params = params.max_results(max_results);
+        }
+        if let Some(max_bytes) = max_bytes {
+            params = params.max_bytes(max_bytes);
+        }
+        let mut results = 0;
+        let result = db
+            .iterate(params.build(), |_, _| {
+                results += 1;
+                Ok(true)
+            })
+            .await;
+        assert_eq!(
+            result.is_ok(),
+            is_ok,
+            "max_results: {max_results:?}, max_bytes: {max_bytes:?}"
+        );
+        if is_ok {
+            assert_eq!(results, keys.len());
+        }
+    }
+
+    let mut batch = BatchBuilder::new();
+    for key in keys {
+        batch.clear(ValueClass::Key(key));
+    }
+    db.write(batch.build()).await.unwrap();
+}
+
+async fn test_iterate_byte_order(db: Store) {
+    // Change ids whose big endian encoding only sorts correctly when compared as bytes
+    const ACCOUNT_ID: u32 = 1234;
+    let change_ids = [
+        0x7f,
+        0x80,
+        0xff,
+        0x100,
+        0xff00,
+        0x7fff_ffff_ffff_ffff,
+        0x8000_0000_0000_0000,
+        0xff00_0000_0000_0000,
+    ];
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(ACCOUNT_ID);
+    for change_id in change_ids.iter().rev() {
+        batch.ops.push(Operation::Log {
+            change_id: *change_id,
+            collection: 0,
+            set: vec![0xff, 0x00],
+        });
+    }
+    db.write(batch.build()).await.unwrap();
+
+    for ascending in [true, false] {
+        let mut results = Vec::new();
+        db.iterate(
+            IterateParams::new(
+                LogKey {
+                    account_id: ACCOUNT_ID,
+                    collection: 0,
+                    change_id: 0,
+                },
+                LogKey {
+                    account_id: ACCOUNT_ID,
+                    collection: 0,
+                    change_id: u64::MAX,
+                },
+            )
+            .set_ascending(ascending),
+            |key, _| {
+                results.push(key.deserialize_be_u64(key.len() - U64_LEN)?);
+                Ok(true)
+            },
+        )
+        .await
+        .unwrap();
+
+        let expected = if ascending {
+            change_ids.to_vec()
+        } else {
+            change_ids.iter().rev().copied().collect()
+        };
+        assert_eq!(results, expected, "ascending: {ascending}");
+    }
+
+    assert_eq!(
+        db.estimate_count(SUBSPACE_LOGS, Some(ACCOUNT_ID))
+            .await
+            .unwrap(),
+        change_ids.len() as u64
+    );
+    assert_eq!(
+        db.get_last_change_id(ACCOUNT_ID, 0u8).await.unwrap(),
+        Some(0xff00_0000_0000_0000)
+    );
+
+    db.purge_account(ACCOUNT_ID).await.unwrap();
+}
+
+async fn test_bitmap_blocks(db: Store) {
+    // Document ids spanning several bitmap blocks, including both sides of each boundary
+    const ACCOUNT_ID: u32 = 1235;
+    let mut expected = RoaringBitmap::new();
+    for block_num in [0u32, 1, 2, 7, 4096] {
+        let block_start = block_num * BITS_PER_BLOCK_L;
+        for document_id in [
+            block_start,
+            block_start + 1,
+            block_start + BITS_PER_BLOCK_L / 2,
+            block_start + BITS_PER_BLOCK_L - 1,
+        ] {
+            expected.insert(document_id);
+        }
+        if block_start > 0 {
+            expected.insert(block_start - 1);
+        }
+    }
+    assert_eq!(
+        expected
+            .iter()
+            .map(BitmapKey::block_for)
+            .collect::<HashSet<_>>(),
+        HashSet::from([0, 1, 2, 6, 7, 4095, 4096])
+    );
+
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(ACCOUNT_ID).with_collection(0);
+    for document_id in &expected {
+        batch.create_document(document_id);
+    }
+    db.write(batch.build()).await.unwrap();
+
+    assert_eq!(
+        db.get_bitmap(BitmapKey::document_ids(ACCOUNT_ID, 0u8))
+            .await
+            .unwrap(),
+        Some(expected.clone())
+    );
+
+    // Removing every other document must not affect neighbouring blocks
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(ACCOUNT_ID).with_collection(0);
+    let removed = expected.iter().step_by(2).collect::<Vec<_>>();
+    for document_id in &removed {
+        batch.delete_document(*document_id);
+        expected.remove(*document_id);
+    }
+    db.write(batch.build()).await.unwrap();
+
+    assert_eq!(
+        db.get_bitmap(BitmapKey::document_ids(ACCOUNT_ID, 0u8))
+            .await
+            .unwrap(),
+        Some(expected)
+    );
+
+    db.purge_account(ACCOUNT_ID).await.unwrap();
+}
+
+async fn test_read_only(db: Store) {
+    let key = ValueKey {
+        account_id: 1236,
+        collection: 0,
+        document_id: 0,
+        class: ValueClass::Property(0),
+    };
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(1236)
+        .with_collection(0)
+        .update_document(0)
+        .set(ValueClass::Property(0), "value");
+    db.write(batch.build()).await.unwrap();
+
+    // Writes are rejected while reads still succeed
+    db.set_read_only(true);
+    assert!(db.is_read_only());
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(1236)
+        .with_collection(0)
+        .update_document(0)
+        .clear(ValueClass::Property(0));
+    assert_eq!(db.write(batch.build()).await, Err(store::Error::ReadOnly));
+    assert_eq!(db.purge_account(1236).await, Err(store::Error::ReadOnly));
+    assert_eq!(
+        db.get_value::<String>(key.clone()).await.unwrap(),
+        Some("value".to_string())
+    );
+
+    // Writes are accepted again once the flag is cleared
+    db.set_read_only(false);
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(1236)
+        .with_collection(0)
+        .update_document(0)
+        .clear(ValueClass::Property(0));
+    db.write(batch.build()).await.unwrap();
+    assert_eq!(db.get_value::<String>(key).await.unwrap(), None);
+}
+
+async fn test_read_snapshot(db: Store) {
+    const ACCOUNT_ID: u32 = 1238;
+    let key = ValueKey {
+        account_id: ACCOUNT_ID,
+        collection: 0,
+        document_id: 0,
+        class: ValueClass::Property(0),
+    };
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(ACCOUNT_ID)
+        .with_collection(0)
+        .create_document(0)
+        .set(ValueClass::Property(0), "before");
+    db.write(batch.build()).await.unwrap();
+
+    let writer = db.clone();
+    let (first, second, bitmap) = db
+        .read_snapshot(move |snapshot| {
+            Box::pin(async move {
+                let first = snapshot.get_value::<String>(key.clone()).await?;
+
+                // Concurrent writes are not visible inside the snapshot
+                let mut batch = BatchBuilder::new();
+                batch
+                    .with_account_id(ACCOUNT_ID)
+                    .with_collection(0)
+                    .update_document(0)
+                    .set(ValueClass::Property(0), "after");
+                writer.write(batch.build()).await?;
+
+                let second = snapshot.get_value::<String>(key).await?;
+                let bitmap = snapshot
+                    .get_bitmap(BitmapKey::document_ids(ACCOUNT_ID, 0u8))
+                    .await?;
+                Ok((first, second, bitmap))
+            })
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(first.as_deref(), Some("before"));
+    assert_eq!(second.as_deref(), Some("before"));
+    assert_eq!(bitmap, Some(RoaringBitmap::from_iter([0u32])));
+    assert_eq!(
+        db.get_value::<String>(ValueKey {
+            account_id: ACCOUNT_ID,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Property(0),
+        })
+        .await
+        .unwrap()
+        .as_deref(),
+        Some("after")
+    );
+
+    // Abandoned snapshots must not hold on to their transaction
+    let key = ValueKey {
+        account_id: ACCOUNT_ID,
+        collection: 0,
+        document_id: 0,
+        class: ValueClass::Property(0),
+    };
+    for _ in 0..8 {
+        let key = key.clone();
+        assert!(tokio::time::timeout(
+            Duration::from_millis(100),
+            db.read_snapshot(move |snapshot| {
+                Box::pin(async move {
+                    snapshot.get_value::<String>(key).await?;
+                    std::future::pending::<()>().await;
+                    Ok(())
+                })
+            }),
+        )
+        .await
+        .is_err());
+    }
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(ACCOUNT_ID)
+        .with_collection(0)
+        .update_document(0)
+        .set(ValueClass::Property(0), "cancelled");
+    db.write(batch.build()).await.unwrap();
+    assert_eq!(
+        db.get_value::<String>(key).await.unwrap().as_deref(),
+        Some("cancelled")
+    );
+
+    db.purge_account(ACCOUNT_ID).await.unwrap();
+}
+
+async fn test_oversized_batch(db: Store) {
+    // Larger than the FoundationDB transaction limit, written in several transactions
+    const ACCOUNT_ID: u32 = 1239;
+    const NUM_DOCUMENTS: u32 = 120;
+    let build_batch = |with_assertion: bool| {
+        let mut batch = BatchBuilder::new();
+        batch.with_account_id(ACCOUNT_ID).with_collection(0);
+        for document_id in 0..NUM_DOCUMENTS {
+            batch.create_document(document_id).set(
+                ValueClass::Property(0),
+                vec![b'A' + (document_id % 26) as u8; MAX_VALUE_SIZE],
+            );
+            if with_assertion && document_id == 0 {
+                batch.assert_value(ValueClass::Property(1), ());
+            }
+        }
+        batch.build()
+    };
+    let batch = build_batch(false);
+    assert!(batch.estimated_size() > 10_000_000);
+
+    // Stores that have to split the batch refuse assertions before writing anything
+    match db.write(build_batch(true)).await {
+        Ok(()) => db.purge_account(ACCOUNT_ID).await.unwrap(),
+        Err(err) => {
+            assert!(matches!(err, store::Error::InternalError(_)), "{err}");
+            assert_eq!(
+                db.get_bitmap(BitmapKey::document_ids(ACCOUNT_ID, 0u8))
+                    .await
+                    .unwrap(),
+                None
+            );
+        }
+    }
+
+    db.write(batch).await.unwrap();
+
+    assert_eq!(
+        db.get_bitmap(BitmapKey::document_ids(ACCOUNT_ID, 0u8))
+            .await
+            .unwrap(),
+        Some(RoaringBitmap::from_iter(0..NUM_DOCUMENTS))
+    );
+    for document_id in 0..NUM_DOCUMENTS {
+        assert_eq!(
+            db.get_value::<String>(ValueKey {
+                account_id: ACCOUNT_ID,
+                collection: 0,
+                document_id,
+                class: ValueClass::Property(0),
+            })
+            .await
+            .unwrap(),
+            Some(String::from(char::from(b'A' + (document_id % 26) as u8)).repeat(MAX_VALUE_SIZE)),
+            "document {document_id}"
+        );
+    }
+
+    db.purge_account(ACCOUNT_ID).await.unwrap();
+}
+
+async fn test_put_if_absent(db: Store) {
+    const ACCOUNT_ID: u32 = 1240;
+    let key = |document_id: u32| ValueKey {
+        account_id: ACCOUNT_ID,
+        collection: 0,
+        document_id,
+        class: ValueClass::Property(0),
+    };
+
+    // Only the first insert succeeds, the stored value is kept
+    assert!(db.put_if_absent(key(0), b"first".to_vec()).await.unwrap());
+    assert!(!db.put_if_absent(key(0), b"second".to_vec()).await.unwrap());
+    assert_eq!(
+        db.get_value::<String>(key(0)).await.unwrap().as_deref(),
+        Some("first")
+    );
+
+    // Concurrent inserts of the same key result in a single insert
+    let mut handles = Vec::new();
+    for num in 0..5 {
+        let db = db.clone();
+        handles.push(tokio::spawn(async move {
+            db.put_if_absent(key(1), format!("value {num}").into_bytes())
+                .await
+                .unwrap()
+        }));
+    }
+    let mut inserted = 0;
+    for handle in handles {
+        if handle.await.unwrap() {
+            inserted += 1;
+        }
+    }
+    assert_eq!(inserted, 1);
+
+    db.purge_account(ACCOUNT_ID).await.unwrap();
+}
+
+async fn test_iterate_typed(db: Store) {
+    const ACCOUNT_ID: u32 = 1237;
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(ACCOUNT_ID);
+    for change_id in 1..=3u64 {
+        batch.ops.push(Operation::Log {
+            change_id,
+            collection: 0,
+            set: format!("change {change_id}").into_bytes(),
+        });
+    }
+    db.write(batch.build()).await.unwrap();
+
+    let from_key = LogKey {
+        account_id: ACCOUNT_ID,
+        collection: 0,
+        change_id: 0,
+    };
+    let to_key = LogKey {
+        account_id: ACCOUNT_ID,
+        collection: 0,
+        change_id: u64::MAX,
+    };
+
+    // Keys and values are passed to the callback already deserialized
+    let mut results = Vec::new();
+    db.iterate_typed(
+        IterateParams::new(from_key, to_key),
+        |key: ParsedKey, value: String| {
+            match key {
+                ParsedKey::Log(key) => results.push((key.change_id, value)),
+                key => panic!("unexpected key {key:?}"),
+            }
+            Ok(true)
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        results,
+        (1..=3u64)
+            .map(|change_id| (change_id, format!("change {change_id}")))
+            .collect::<Vec<_>>()
+    );
+
+    // Values that fail to deserialize stop the iteration
+    let mut count = 0;
+    assert!(db
+        .iterate_typed(
+            IterateParams::new(from_key, to_key),
+            |_: ParsedKey, _: u32| {
+                count += 1;
+                Ok(true)
+            },
+        )
+        .await
+        .is_err());
+    assert_eq!(count, 0);
+
+    db.purge_account(ACCOUNT_ID).await.unwrap();
+}
+
+async fn test_collection_values(db: Store) {
+    const ACCOUNT_ID: u32 = 1238;
+    let document_ids = [0u32, 1, 127, 128, 300, 70000];
+    let mut batch = BatchBuilder::new();
+    batch.with_account_id(ACCOUNT_ID).with_collection(1);
+    for &document_id in document_ids.iter().rev() {
+        batch
+            .create_document(document_id)
+            .set(ValueClass::Property(0), format!("other {document_id}"))
+            .set(ValueClass::Property(1), format!("value {document_id}"));
+    }
+    batch
+        .with_collection(2)
+        .create_document(5)
+        .set(ValueClass::Property(1), "other collection");
+    db.write(batch.build()).await.unwrap();
+
+    // Only the requested property of the collection is returned
+    assert_eq!(
+        db.get_collection_values::<String>(ACCOUNT_ID, 1, 1)
+            .await
+            .unwrap(),
+        document_ids
+            .iter()
+            .map(|&document_id| (document_id, format!("value {document_id}")))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        db.get_collection_values::<String>(ACCOUNT_ID, 3, 1)
+            .await
+            .unwrap(),
+        vec![]
+    );
+
+    db.purge_account(ACCOUNT_ID).await.unwrap();
+}
+
+async fn test_scan_accounts(db: Store) {
+    let account_ids = [1239u32, 1240, 70000];
+    let mut batch = BatchBuilder::new();
+    for &account_id in &account_ids {
+        batch
+            .with_account_id(account_id)
+            .with_collection(0)
+            .create_document(1)
+            .with_collection(1)
+            .create_document(2);
+    }
+    db.write(batch.build()).await.unwrap();
+
+    // Each account is listed once and in order
+    let scanned = db.scan_accounts().await.unwrap();
+    assert!(scanned.windows(2).all(|ids| ids[0] < ids[1]));
+    for account_id in account_ids {
+        assert!(scanned.contains(&account_id), "missing {account_id}");
+    }
+
+    for account_id in account_ids {
+        db.purge_account(account_id).await.unwrap();
+    }
+    let scanned = db.scan_accounts().await.unwrap();
+    assert!(account_ids
+        .iter()
+        .all(|account_id| !scanned.contains(account_id)));
+}
+
+async fn test_fencing(db: Store) {
+    const ACCOUNT_ID: u32 = 1241;
+    let fenced_write = |epoch: u64, value: &'static str| {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(ACCOUNT_ID)
+            .fence(epoch)
+            .with_collection(0)
+            .update_document(0)
+            .set(ValueClass::Property(0), value);
+        batch.build()
+    };
+
+    // Accounts start at epoch 0
+    assert_eq!(db.fence_epoch(ACCOUNT_ID).await.unwrap(), 0);
+    db.write(fenced_write(0, "epoch 0")).await.unwrap();
+
+    // Writes with the current epoch succeed, older epochs are rejected
+    assert_eq!(db.advance_fence(ACCOUNT_ID).await.unwrap(), 1);
+    db.write(fenced_write(1, "epoch 1")).await.unwrap();
+    assert_eq!(db.advance_fence(ACCOUNT_ID).await.unwrap(), 2);
+    assert!(matches!(
+        db.write(fenced_write(1, "stale")).await,
+        Err(store::Error::StaleEpoch)
+    ));
+    assert_eq!(
+        db.get_value::<String>(ValueKey {
+            account_id: ACCOUNT_ID,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Property(0),
+        })
+        .await
+        .unwrap(),
+        Some("epoch 1".to_string())
+    );
+
+    // Other assertion failures are still reported as such
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(ACCOUNT_ID)
+        .fence(2)
+        .with_collection(0)
+        .update_document(0)
+        .assert_value(ValueClass::Property(0), 0u64)
+        .set(ValueClass::Property(0), "mismatch");
+    assert!(matches!(
+        db.write(batch.build()).await,
+        Err(store::Error::AssertValueFailed)
+    ));
+
+    db.purge_account(ACCOUNT_ID).await.unwrap();
+    db.write({
+        let mut batch = BatchBuilder::new();
+        batch.with_account_id(ACCOUNT_ID).clear(ValueClass::Fence);
+        batch.build()
+    })
+    .await
+    .unwrap();
+}
+
+async fn test_flush(db: Store) {
+    const ACCOUNT_ID: u32 = 1242;
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(ACCOUNT_ID)
+        .with_collection(0)
+        .create_document(0)
+        .set(ValueClass::Property(0), "flushed");
+    db.write(batch.build()).await.unwrap();
+
+    // Flushing is idempotent and does not alter the data
+    db.flush().await.unwrap();
+    db.flush().await.unwrap();
+    assert_eq!(
+        db.get_value::<String>(ValueKey {
+            account_id: ACCOUNT_ID,
+            collection: 0,
+            document_id: 0,
+            class: ValueClass::Property(0),
+        })
+        .await
+        .unwrap()
+        .as_deref(),
+        Some("flushed")
+    );
+
+    db.purge_account(ACCOUNT_ID).await.unwrap();
+}
+
+async fn test_exists(db: Store) {
+    const ACCOUNT_ID: u32 = 1243;
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(ACCOUNT_ID)
+        .with_collection(0)
+        .create_document(3)
+        .set(ValueClass::Property(0), "present");
+    db.write(batch.build()).await.unwrap();
+
+    for (document_id, field, expected) in [(3, 0, true), (3, 1, false), (4, 0, false)] {
+        assert_eq!(
+            db.exists(ValueKey {
+                account_id: ACCOUNT_ID,
+                collection: 0,
+                document_id,
+                class: ValueClass::Property(field),
+            })
+            .await
+            .unwrap(),
+            expected,
+            "document {document_id}, field {field}"
+        );
+    }
+
+    // Bitmap keys exist when the bitmap contains the document
+    for (document_id, expected) in [(3, true), (4, false)] {
+        assert_eq!(
+            db.exists(BitmapKey {
+                account_id: ACCOUNT_ID,
+                collection: 0,
+                class: BitmapClass::DocumentIds,
+                block_num: document_id,
+            })
+            .await
+            .unwrap(),
+            expected,
+            "document {document_id}"
+        );
+    }
+
+    db.purge_account(ACCOUNT_ID).await.unwrap();
 }